@@ -2,14 +2,25 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
 use clap::Args;
-use miette::{IntoDiagnostic, Result};
+use miette::{IntoDiagnostic, Result, WrapErr};
+use spfs::proto::database_service_server::DatabaseServiceServer;
+use spfs::proto::payload_service_server::PayloadServiceServer;
+use spfs::proto::repository_server::RepositoryServer;
+use spfs::proto::tag_service_server::TagServiceServer;
 use spfs_cli_common as cli;
 
 /// Start an spfs server
 ///
 /// The server can be used as a remote repository by
-/// its clients, communicating over gRPC and http
+/// its clients, communicating over gRPC and http. Both
+/// servers answer health checks once the repository is
+/// open: the standard gRPC health service and GET /healthz
 #[derive(Debug, Args)]
 pub struct CmdServer {
     #[clap(flatten)]
@@ -20,8 +31,10 @@ pub struct CmdServer {
     remote: Option<String>,
 
     /// The external root url that clients can use to connect to this server
-    #[clap(long = "payloads-root", default_value = "http://localhost")]
-    payloads_root: url::Url,
+    ///
+    /// Defaults to http://localhost, or https://localhost when serving over TLS
+    #[clap(long = "payloads-root")]
+    payloads_root: Option<url::Url>,
 
     /// The address to listen on for grpc requests
     #[clap(
@@ -33,80 +46,401 @@ pub struct CmdServer {
     /// The address to listen on for http requests
     #[clap(default_value = "0.0.0.0:7787")]
     http_address: std::net::SocketAddr,
+
+    /// Listen for grpc requests on this unix socket instead of the grpc address
+    #[clap(long, value_name = "PATH")]
+    grpc_unix_socket: Option<PathBuf>,
+
+    /// Listen for http requests on this unix socket instead of the http address
+    ///
+    /// Unless --payloads-root is also given, clients are directed
+    /// to download payloads from this socket
+    #[clap(long, value_name = "PATH")]
+    http_unix_socket: Option<PathBuf>,
+
+    /// Limit the number of tag write operations processed at once
+    ///
+    /// Writes over this limit wait for an earlier one to complete.
+    /// Reads are not limited.
+    #[clap(long)]
+    max_concurrent_writes: Option<usize>,
+
+    /// Reject tag writes over --max-concurrent-writes instead of queueing them
+    #[clap(long, requires = "max_concurrent_writes")]
+    reject_excess_writes: bool,
+
+    /// Serve both gRPC and http over TLS using this PEM certificate chain
+    #[clap(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// The PEM private key for the certificate given by --tls-cert
+    #[clap(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Require clients to present this bearer token on every request
+    ///
+    /// Health checks do not require the token.
+    #[clap(long, env = "SPFS_SERVER_AUTH_TOKEN", hide_env_values = true)]
+    auth_token: Option<String>,
+
+    /// Reject all writes to the repository
+    ///
+    /// Tags, objects and payloads can still be read and downloaded.
+    #[clap(long)]
+    read_only: bool,
+
+    /// On shutdown, wait this many seconds for open http connections
+    /// to finish their current requests before closing them
+    #[clap(long, value_name = "SECONDS", default_value_t = 30)]
+    shutdown_grace: u64,
 }
 
 impl CmdServer {
     pub async fn run(&mut self, config: &spfs::Config) -> Result<i32> {
         let repo = spfs::config::open_repository_from_string(config, self.remote.as_ref()).await?;
-        let repo = std::sync::Arc::new(repo);
-
-        let payload_service =
-            spfs::server::PayloadService::new(repo.clone(), self.payloads_root.clone());
-        let grpc_future = tonic::transport::Server::builder()
-            .add_service(spfs::server::Repository::new_srv())
-            .add_service(spfs::server::TagService::new_srv(repo.clone()))
-            .add_service(spfs::server::DatabaseService::new_srv(repo))
-            .add_service(payload_service.clone().into_srv())
-            .serve_with_shutdown(self.grpc_address, async {
+        let repo = Arc::new(repo);
+        let tls = self.load_tls_identity()?;
+
+        let payloads_root = match (&self.payloads_root, &self.http_unix_socket, &tls) {
+            (Some(root), _, _) => root.clone(),
+            (None, Some(socket), _) => spfs::storage::rpc::unix_socket_url(socket)?,
+            (None, None, Some(_)) => url::Url::parse("https://localhost").into_diagnostic()?,
+            (None, None, None) => url::Url::parse("http://localhost").into_diagnostic()?,
+        };
+        let auth = match &self.auth_token {
+            Some(token) => spfs::server::BearerAuth::with_token(token),
+            None => spfs::server::BearerAuth::disabled(),
+        };
+        let payload_service = spfs::server::PayloadService::new(repo.clone(), payloads_root)
+            .with_auth(auth.clone())
+            .with_read_only(self.read_only);
+        let mut tag_service = spfs::server::TagService::new(repo.clone())
+            .with_reject_excess_writes(self.reject_excess_writes)
+            .with_read_only(self.read_only);
+        if let Some(limit) = self.max_concurrent_writes {
+            tag_service = tag_service.with_max_concurrent_writes(limit);
+        }
+        // either server stopping, for any reason, asks the other to stop
+        // as well so that the process can exit
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        tokio::task::spawn({
+            let shutdown_tx = shutdown_tx.clone();
+            async move {
                 if let Err(err) = tokio::signal::ctrl_c().await {
                     tracing::error!(?err, "Failed to setup graceful shutdown handler");
-                };
-                tracing::info!("shutting down gRPC server...");
-            });
-        let http_listener = tokio::net::TcpListener::bind(self.http_address)
-            .await
-            .into_diagnostic()?;
-        let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel();
-        tokio::task::spawn(async move {
-            if let Err(err) = tokio::signal::ctrl_c().await {
-                tracing::error!(?err, "failed to setup graceful shutdown handler");
-            } else {
-                tracing::info!("shutting down HTTP server...");
-                shutdown_tx.send(()).ok();
+                } else {
+                    shutdown_tx.send_replace(true);
+                }
             }
         });
+
+        let mut grpc_server = tonic::transport::Server::builder();
+        if let Some(tls) = &tls {
+            let identity = tonic::transport::Identity::from_pem(&tls.cert_pem, &tls.key_pem);
+            grpc_server = grpc_server
+                .tls_config(tonic::transport::ServerTlsConfig::new().identity(identity))
+                .into_diagnostic()
+                .wrap_err("Failed to configure TLS for the gRPC server")?;
+        }
+        let tls_acceptor = tls.as_ref().map(TlsIdentity::acceptor).transpose()?;
+        let grpc_router = grpc_server
+            .add_service(RepositoryServer::with_interceptor(
+                spfs::server::Repository::new(),
+                auth.clone(),
+            ))
+            .add_service(spfs::server::HealthService::new_srv())
+            .add_service(TagServiceServer::with_interceptor(
+                tag_service,
+                auth.clone(),
+            ))
+            .add_service(DatabaseServiceServer::with_interceptor(
+                spfs::server::DatabaseService::new(repo).with_read_only(self.read_only),
+                auth.clone(),
+            ))
+            .add_service(PayloadServiceServer::with_interceptor(
+                payload_service.clone(),
+                auth,
+            ));
+        let grpc_shutdown = {
+            let shutdown = shutdown_requested(shutdown_rx.clone());
+            async move {
+                shutdown.await;
+                tracing::info!("shutting down gRPC server...");
+            }
+        };
+        let grpc_future: Pin<Box<dyn Future<Output = _>>> = match &self.grpc_unix_socket {
+            #[cfg(unix)]
+            Some(socket) => {
+                let incoming =
+                    tokio_stream::wrappers::UnixListenerStream::new(bind_unix_socket(socket)?);
+                Box::pin(grpc_router.serve_with_incoming_shutdown(incoming, grpc_shutdown))
+            }
+            #[cfg(not(unix))]
+            Some(_) => miette::bail!("Unix sockets are not supported on this platform"),
+            None => Box::pin(grpc_router.serve_with_shutdown(self.grpc_address, grpc_shutdown)),
+        };
+        let http_listener = match &self.http_unix_socket {
+            #[cfg(unix)]
+            Some(socket) => HttpListener::Unix(bind_unix_socket(socket)?),
+            #[cfg(not(unix))]
+            Some(_) => miette::bail!("Unix sockets are not supported on this platform"),
+            None => HttpListener::Tcp(
+                tokio::net::TcpListener::bind(self.http_address)
+                    .await
+                    .into_diagnostic()?,
+            ),
+        };
+        let mut http_shutdown = std::pin::pin!(shutdown_requested(shutdown_rx.clone()));
+        let shutdown_grace = std::time::Duration::from_secs(self.shutdown_grace);
         let http_future = async move {
+            let mut connections = tokio::task::JoinSet::new();
             loop {
                 let conn = tokio::select! {
                     conn = http_listener.accept() => conn,
-                    _ = &mut shutdown_rx => {
+                    _ = &mut http_shutdown => {
+                        tracing::info!("shutting down HTTP server...");
                         break;
                     }
                 };
                 let stream = match conn {
-                    Ok((stream, _)) => {
-                        tracing::debug!("Accepted connection from {:?}", stream.peer_addr());
-                        stream
-                    }
+                    Ok(stream) => stream,
                     Err(err) => {
                         tracing::error!("Error accepting connection: {:?}", err);
                         continue;
                     }
                 };
-                let io = hyper_util::rt::TokioIo::new(stream);
+                // forget about connections that have already closed
+                while connections.try_join_next().is_some() {}
                 let service = payload_service.clone();
-                tokio::task::spawn(async move {
-                    if let Err(err) = hyper::server::conn::http1::Builder::new()
-                        .serve_connection(io, service)
-                        .await
-                    {
-                        tracing::error!("Error serving connection: {:?}", err);
-                    }
-                });
+                let tls_acceptor = tls_acceptor.clone();
+                let shutdown = shutdown_rx.clone();
+                match stream {
+                    HttpStream::Tcp(stream) => connections.spawn(serve_http_stream(
+                        stream,
+                        service,
+                        tls_acceptor,
+                        shutdown,
+                    )),
+                    #[cfg(unix)]
+                    HttpStream::Unix(stream) => connections.spawn(serve_http_stream(
+                        stream,
+                        service,
+                        tls_acceptor,
+                        shutdown,
+                    )),
+                };
+            }
+            // new connections are no longer accepted, but the open
+            // ones are given a chance to finish what they are sending
+            if !connections.is_empty() {
+                tracing::info!(
+                    "waiting up to {shutdown_grace:?} for {} http connections to finish...",
+                    connections.len()
+                );
+            }
+            let drain = async { while connections.join_next().await.is_some() {} };
+            if tokio::time::timeout(shutdown_grace, drain).await.is_err() {
+                tracing::warn!(
+                    "closing {} http connections that did not finish in time",
+                    connections.len()
+                );
+                connections.shutdown().await;
             }
             Result::<(), miette::Report>::Ok(())
         };
-        tracing::info!("listening on: {}, {}", self.grpc_address, self.http_address);
+        let grpc_listen = match &self.grpc_unix_socket {
+            Some(socket) => socket.display().to_string(),
+            None => self.grpc_address.to_string(),
+        };
+        let http_listen = match &self.http_unix_socket {
+            Some(socket) => socket.display().to_string(),
+            None => self.http_address.to_string(),
+        };
+        tracing::info!("listening on: {grpc_listen}, {http_listen}");
 
-        // TODO: stop the other server when one fails so that
-        // the process can exit
-        let (grpc_result, http_result) = tokio::join!(grpc_future, http_future);
-        if let Err(err) = grpc_result {
-            tracing::error!("gRPC server failed: {:?}", err);
+        let grpc_future = async {
+            let result = grpc_future.await.into_diagnostic();
+            let requested = shutdown_tx.send_replace(true);
+            (result, requested)
+        };
+        let http_future = async {
+            let result = http_future.await;
+            let requested = shutdown_tx.send_replace(true);
+            (result, requested)
+        };
+        let ((grpc_result, grpc_requested), (http_result, http_requested)) =
+            tokio::join!(grpc_future, http_future);
+        let mut code = 0;
+        match grpc_result {
+            Err(err) => {
+                tracing::error!("gRPC server failed: {:?}", err);
+                code = 1;
+            }
+            Ok(()) if !grpc_requested => {
+                tracing::error!("gRPC server stopped unexpectedly");
+                code = 1;
+            }
+            Ok(()) => {}
         }
-        if let Err(err) = http_result {
-            tracing::error!("http server failed: {:?}", err);
+        match http_result {
+            Err(err) => {
+                tracing::error!("http server failed: {:?}", err);
+                code = 1;
+            }
+            Ok(()) if !http_requested => {
+                tracing::error!("http server stopped unexpectedly");
+                code = 1;
+            }
+            Ok(()) => {}
         }
-        Ok(0)
+        Ok(code)
+    }
+
+    fn load_tls_identity(&self) -> Result<Option<TlsIdentity>> {
+        let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) else {
+            return Ok(None);
+        };
+        let cert_pem = std::fs::read(cert)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read TLS certificate: {cert:?}"))?;
+        let key_pem = std::fs::read(key)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read TLS key: {key:?}"))?;
+        Ok(Some(TlsIdentity { cert_pem, key_pem }))
+    }
+}
+
+/// The PEM encoded certificate chain and private key to serve with
+struct TlsIdentity {
+    cert_pem: Vec<u8>,
+    key_pem: Vec<u8>,
+}
+
+impl TlsIdentity {
+    /// Create an acceptor for http connections using this identity
+    fn acceptor(&self) -> Result<tokio_rustls::TlsAcceptor> {
+        use tokio_rustls::rustls;
+
+        let certs = rustls_pemfile::certs(&mut self.cert_pem.as_slice())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .into_diagnostic()
+            .wrap_err("Failed to parse TLS certificate")?;
+        let key = rustls_pemfile::private_key(&mut self.key_pem.as_slice())
+            .into_diagnostic()
+            .wrap_err("Failed to parse TLS key")?
+            .ok_or_else(|| miette::miette!("No private key found in the TLS key file"))?;
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .into_diagnostic()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .into_diagnostic()
+        .wrap_err("Invalid TLS certificate or key")?;
+        Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
     }
 }
+
+/// Listens for http connections over tcp or a unix socket
+enum HttpListener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+/// A connection accepted by an [`HttpListener`]
+enum HttpStream {
+    Tcp(tokio::net::TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl HttpListener {
+    async fn accept(&self) -> std::io::Result<HttpStream> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                tracing::debug!("Accepted connection from {peer:?}");
+                Ok(HttpStream::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                tracing::debug!("Accepted connection on unix socket");
+                Ok(HttpStream::Unix(stream))
+            }
+        }
+    }
+}
+
+/// Bind a unix socket, replacing any stale socket left at the same path.
+#[cfg(unix)]
+fn bind_unix_socket(path: &std::path::Path) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            miette::bail!("Refusing to replace a file that is not a socket: {path:?}");
+        }
+        std::fs::remove_file(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to remove stale socket: {path:?}"))?;
+    }
+    tokio::net::UnixListener::bind(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to bind unix socket: {path:?}"))
+}
+
+/// Serve a single accepted http connection, after a TLS handshake if enabled.
+async fn serve_http_stream<S>(
+    stream: S,
+    service: spfs::server::PayloadService,
+    tls_acceptor: Option<tokio_rustls::TlsAcceptor>,
+    shutdown: tokio::sync::watch::Receiver<bool>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    // the handshake happens here rather than in the accept
+    // loop so that a slow client cannot hold up others
+    match tls_acceptor {
+        None => serve_http_connection(stream, service, shutdown).await,
+        Some(acceptor) => match acceptor.accept(stream).await {
+            Ok(stream) => serve_http_connection(stream, service, shutdown).await,
+            Err(err) => tracing::error!("TLS handshake failed: {:?}", err),
+        },
+    }
+}
+
+/// Serve payloads over a single accepted http connection.
+///
+/// Once shutdown is requested, the connection is closed after
+/// the response that is currently being sent, if any.
+async fn serve_http_connection<S>(
+    stream: S,
+    service: spfs::server::PayloadService,
+    shutdown: tokio::sync::watch::Receiver<bool>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let io = hyper_util::rt::TokioIo::new(stream);
+    let conn = hyper::server::conn::http1::Builder::new().serve_connection(io, service);
+    let mut conn = std::pin::pin!(conn);
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = shutdown_requested(shutdown) => {
+            conn.as_mut().graceful_shutdown();
+            conn.as_mut().await
+        }
+    };
+    if let Err(err) = result {
+        tracing::error!("Error serving connection: {:?}", err);
+    }
+}
+
+/// Resolve once the server has been asked to shut down.
+async fn shutdown_requested(mut shutdown: tokio::sync::watch::Receiver<bool>) {
+    // an error means that every sender was dropped, in which
+    // case there is nothing left that could keep the server running
+    let _ = shutdown.wait_for(|requested| *requested).await;
+}
@@ -10,6 +10,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure().bytes(["buffer"]).compile_protos(
         &[
             "src/proto/defs/database.proto",
+            "src/proto/defs/health.proto",
             "src/proto/defs/repository.proto",
             "src/proto/defs/payload.proto",
             "src/proto/defs/tag.proto",
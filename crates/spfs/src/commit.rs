@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
+use std::collections::HashSet;
 use std::future::ready;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
@@ -11,6 +12,7 @@ use futures::{FutureExt, StreamExt, TryStreamExt};
 use once_cell::sync::OnceCell;
 use progress_bar_derive_macro::ProgressBar;
 use spfs_encoding::prelude::*;
+use tokio_util::sync::CancellationToken;
 
 use super::status::remount_runtime;
 use crate::prelude::*;
@@ -73,6 +75,7 @@ pub struct Committer<
     builder: ManifestBuilder<H, F, Arc<Reporter>>,
     max_concurrent_blobs: usize,
     allow_empty: bool,
+    committed_blobs: HashSet<encoding::Digest>,
 }
 
 impl<'repo> Committer<'repo, InMemoryBlobHasher, (), SilentCommitReporter> {
@@ -88,6 +91,7 @@ impl<'repo> Committer<'repo, InMemoryBlobHasher, (), SilentCommitReporter> {
             builder,
             max_concurrent_blobs: tracking::DEFAULT_MAX_CONCURRENT_BLOBS,
             allow_empty: false,
+            committed_blobs: HashSet::new(),
         }
     }
 }
@@ -115,6 +119,19 @@ where
         self
     }
 
+    /// Treat these blobs as already committed, without checking for
+    /// them in the repository.
+    ///
+    /// This is used to resume a commit that was cancelled, using the
+    /// digests from the resulting [`Error::Cancelled`].
+    pub fn with_committed_blobs(
+        mut self,
+        committed: impl IntoIterator<Item = encoding::Digest>,
+    ) -> Self {
+        self.committed_blobs.extend(committed);
+        self
+    }
+
     /// Set how many branches should be processed at once (during manifest building).
     ///
     /// Each tree/folder that is processed can have any number of subtrees. This number
@@ -145,6 +162,7 @@ where
             reporter: self.reporter,
             max_concurrent_blobs: self.max_concurrent_blobs,
             allow_empty: self.allow_empty,
+            committed_blobs: self.committed_blobs,
         }
     }
 
@@ -160,6 +178,7 @@ where
             reporter,
             max_concurrent_blobs: self.max_concurrent_blobs,
             allow_empty: self.allow_empty,
+            committed_blobs: self.committed_blobs,
         }
     }
 
@@ -180,6 +199,7 @@ where
             reporter: self.reporter,
             max_concurrent_blobs: self.max_concurrent_blobs,
             allow_empty: self.allow_empty,
+            committed_blobs: self.committed_blobs,
         }
     }
 
@@ -253,10 +273,40 @@ where
     /// This collects all files to store as blobs and maintains a
     /// render of the manifest for use immediately.
     pub async fn commit_dir<P>(&self, path: P) -> Result<tracking::Manifest>
+    where
+        P: AsRef<Path>,
+    {
+        self.commit_dir_cancellable(path, &CancellationToken::new())
+            .await
+    }
+
+    /// Commit a local file system directory to this storage, as with
+    /// [`Self::commit_dir`], stopping early if the token is cancelled.
+    ///
+    /// The token is checked between blobs. Once cancelled, this returns
+    /// [`Error::Cancelled`] with the digests of all the blobs that are
+    /// known to be in the repository so that they can be skipped by a
+    /// later commit, see [`Self::with_committed_blobs`], along with
+    /// those that were still being committed.
+    pub async fn commit_dir_cancellable<P>(
+        &self,
+        path: P,
+        cancel: &CancellationToken,
+    ) -> Result<tracking::Manifest>
     where
         P: AsRef<Path>,
     {
         let (path, manifest) = self.manifest_for_path(&path).await?;
+        let mut committed = self.committed_blobs.clone();
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled {
+                committed,
+                incomplete: HashSet::new(),
+            });
+        }
+        // blobs that were started but have not yet been seen to finish
+        let in_flight = std::sync::Mutex::new(HashSet::new());
+        let in_flight = &in_flight;
 
         let mut stream = futures::stream::iter(manifest.walk_abs("."))
             .filter_map(|node| {
@@ -267,8 +317,15 @@ where
                 self.reporter.visit_blob(&node);
                 let local_path = path.join(relative_path);
                 let node = node.into_owned();
+                in_flight
+                    .lock()
+                    .unwrap()
+                    .insert(node.entry.object);
                 let fut = async move {
                     let entry = &node.entry;
+                    if self.committed_blobs.contains(&entry.object) {
+                        return Ok(CommitBlobResult::AlreadyExists(node));
+                    }
                     let (has_object, has_payload) = tokio::join!(
                         self.repo.has_object(entry.object),
                         self.repo.has_payload(entry.object),
@@ -323,6 +380,17 @@ where
             .boxed();
         while let Some(result) = stream.try_next().await? {
             self.reporter.committed_blob(&result);
+            let digest = result.node().entry.object;
+            committed.insert(digest);
+            if cancel.is_cancelled() {
+                let mut incomplete = std::mem::take(&mut *in_flight.lock().unwrap());
+                // the same blob may appear more than once in a manifest
+                incomplete.retain(|digest| !committed.contains(digest));
+                return Err(Error::Cancelled {
+                    committed,
+                    incomplete,
+                });
+            }
         }
         drop(stream);
 
@@ -7,6 +7,7 @@ use rstest::rstest;
 use super::Committer;
 use crate::Error;
 use crate::fixtures::*;
+use crate::prelude::*;
 
 #[rstest]
 #[tokio::test]
@@ -36,3 +37,101 @@ async fn test_commit_empty(tmpdir: tempfile::TempDir) {
         res => panic!("expected nothing to commit, got {res:?}"),
     }
 }
+
+/// Cancels the commit as soon as the first blob has been committed.
+struct CancelAfterFirstBlob(tokio_util::sync::CancellationToken);
+
+impl crate::tracking::ComputeManifestReporter for CancelAfterFirstBlob {}
+
+impl super::CommitReporter for CancelAfterFirstBlob {
+    fn committed_blob(&self, _result: &super::CommitBlobResult) {
+        self.0.cancel();
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_commit_dir_cancel_and_resume(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = crate::storage::RepositoryHandle::from(
+        crate::storage::fs::FsRepository::create(tmpdir.path().join("repo"))
+            .await
+            .unwrap(),
+    );
+    let dir = tmpdir.path().join("dir");
+    ensure(dir.join("a.txt"), "a");
+    ensure(dir.join("b.txt"), "b");
+    ensure(dir.join("nested/c.txt"), "c");
+
+    let cancel = tokio_util::sync::CancellationToken::new();
+    let res = Committer::new(&repo)
+        .with_max_concurrent_blobs(1)
+        .with_reporter(CancelAfterFirstBlob(cancel.clone()))
+        .commit_dir_cancellable(&dir, &cancel)
+        .await;
+    let committed = match res {
+        Err(Error::Cancelled {
+            committed,
+            incomplete,
+        }) => {
+            assert!(incomplete.is_empty(), "only one blob should be started");
+            committed
+        }
+        res => panic!("expected the commit to be cancelled, got {res:?}"),
+    };
+    assert_eq!(
+        committed.len(),
+        1,
+        "the commit should stop after the first blob"
+    );
+
+    let manifest = Committer::new(&repo)
+        .with_committed_blobs(committed)
+        .commit_dir_cancellable(&dir, &tokio_util::sync::CancellationToken::new())
+        .await
+        .expect("a resumed commit should complete");
+    for node in manifest.walk_abs(".") {
+        if node.entry.kind.is_blob() {
+            assert!(
+                repo.has_payload(node.entry.object).await,
+                "every blob should be committed after resuming"
+            );
+        }
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_commit_dir_cancel_reports_in_flight_blobs(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = crate::storage::RepositoryHandle::from(
+        crate::storage::fs::FsRepository::create(tmpdir.path().join("repo"))
+            .await
+            .unwrap(),
+    );
+    let dir = tmpdir.path().join("dir");
+    ensure(dir.join("a.txt"), "a");
+    ensure(dir.join("b.txt"), "b");
+    ensure(dir.join("nested/c.txt"), "c");
+
+    let cancel = tokio_util::sync::CancellationToken::new();
+    let res = Committer::new(&repo)
+        .with_max_concurrent_blobs(3)
+        .with_reporter(CancelAfterFirstBlob(cancel.clone()))
+        .commit_dir_cancellable(&dir, &cancel)
+        .await;
+    let (committed, incomplete) = match res {
+        Err(Error::Cancelled {
+            committed,
+            incomplete,
+        }) => (committed, incomplete),
+        res => panic!("expected the commit to be cancelled, got {res:?}"),
+    };
+    assert_eq!(committed.len(), 1);
+    assert_eq!(
+        incomplete.len(),
+        2,
+        "blobs that were still being committed should be reported"
+    );
+    assert!(committed.is_disjoint(&incomplete));
+}
@@ -110,6 +110,22 @@ pub enum Error {
 
     #[error("Nothing to commit, resulting filesystem would be empty")]
     NothingToCommit,
+    /// A commit was cancelled before all of its blobs were committed.
+    ///
+    /// The blobs that are already in the repository can be given to
+    /// [`crate::Committer::with_committed_blobs`] to skip them when
+    /// the commit is resumed. Those that were still being committed
+    /// when it was cancelled are incomplete, and may or may not have
+    /// been written.
+    #[error(
+        "Commit was cancelled after committing {} blobs, with {} incomplete",
+        committed.len(),
+        incomplete.len()
+    )]
+    Cancelled {
+        committed: std::collections::HashSet<encoding::Digest>,
+        incomplete: std::collections::HashSet<encoding::Digest>,
+    },
     #[error("No active runtime")]
     NoActiveRuntime,
     #[error("Runtime has not been initialized: {0}")]
@@ -227,6 +243,19 @@ impl Error {
         }
     }
 
+    /// True if this error may succeed when tried again, such as when
+    /// a server was briefly unavailable or was too slow to respond.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Tonic(status) => matches!(
+                status.code(),
+                tonic::Code::Unavailable | tonic::Code::DeadlineExceeded
+            ),
+            Self::Wrapped { source, .. } => source.is_transient(),
+            _ => false,
+        }
+    }
+
     /// Create an [`Error::FailedToOpenRepository`] instance for
     /// a repository using its address and root cause.
     pub fn failed_to_open_repository<R: storage::Repository>(
@@ -135,6 +135,15 @@ impl Stream for DatabaseIterator<'_> {
 pub enum DigestSearchCriteria {
     All,
     StartsWith(encoding::PartialDigest),
+    /// Digests that fall between the two prefixes, inclusive.
+    ///
+    /// A digest is within the range if it sorts at or after `start`
+    /// and its leading bytes sort at or before `end`, so every digest
+    /// that starts with `end` is included.
+    Range {
+        start: encoding::PartialDigest,
+        end: encoding::PartialDigest,
+    },
 }
 
 /// A read-only object database.
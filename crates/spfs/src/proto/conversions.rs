@@ -99,6 +99,33 @@ impl From<&tracking::Tag> for super::Tag {
     }
 }
 
+impl From<&storage::TagEvent> for super::TagEvent {
+    fn from(source: &storage::TagEvent) -> Self {
+        use super::tag_event::Kind;
+        let kind = match source {
+            storage::TagEvent::Created(tag) => Kind::Created(tag.into()),
+            storage::TagEvent::Removed(tag) => Kind::Removed(tag.into()),
+            storage::TagEvent::StreamRemoved(spec) => Kind::StreamRemoved(spec.to_string()),
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<super::TagEvent> for storage::TagEvent {
+    type Error = Error;
+    fn try_from(source: super::TagEvent) -> Result<Self> {
+        use super::tag_event::Kind;
+        match source.kind {
+            Some(Kind::Created(tag)) => Ok(Self::Created(tag.try_into()?)),
+            Some(Kind::Removed(tag)) => Ok(Self::Removed(tag.try_into()?)),
+            Some(Kind::StreamRemoved(spec)) => Ok(Self::StreamRemoved(spec.parse()?)),
+            None => Err(Error::String(
+                "Expected non-empty tag event in rpc message".into(),
+            )),
+        }
+    }
+}
+
 impl From<Error> for super::Error {
     fn from(err: Error) -> Self {
         let kind = Some(match err {
@@ -476,6 +503,14 @@ impl From<graph::DigestSearchCriteria> for super::DigestSearchCriteria {
                         },
                     )
                 }
+                graph::DigestSearchCriteria::Range { start, end } => {
+                    super::digest_search_criteria::Criteria::Range(
+                        super::digest_search_criteria::Range {
+                            start: start.into(),
+                            end: end.into(),
+                        },
+                    )
+                }
             }),
         }
     }
@@ -492,6 +527,12 @@ impl TryFrom<super::DigestSearchCriteria> for graph::DigestSearchCriteria {
                 super::digest_search_criteria::Criteria::StartsWith(bytes) => {
                     Ok(graph::DigestSearchCriteria::StartsWith(bytes.bytes.into()))
                 }
+                super::digest_search_criteria::Criteria::Range(range) => {
+                    Ok(graph::DigestSearchCriteria::Range {
+                        start: range.start.into(),
+                        end: range.end.into(),
+                    })
+                }
             },
             None => Err("Unknown criteria kind".into()),
         }
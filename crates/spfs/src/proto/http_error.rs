@@ -0,0 +1,116 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+#[path = "./http_error_test.rs"]
+mod http_error_test;
+
+/// The stable set of error codes returned by the http payload server.
+///
+/// Each code is sent as a snake_case string alongside a matching http
+/// status, so that clients can decide how to handle an error without
+/// parsing its message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpErrorCode {
+    /// The request was malformed, eg: it named an invalid digest
+    InvalidRequest,
+    /// The request did not include a valid bearer token
+    Unauthorized,
+    /// The server does not allow the request, eg: an upload to a
+    /// read-only server
+    Forbidden,
+    /// The requested payload does not exist
+    NotFound,
+    /// The http method is not supported
+    MethodNotAllowed,
+    /// The content type of the request body is not supported
+    UnsupportedMediaType,
+    /// The requested range is outside of the payload
+    RangeNotSatisfiable,
+    /// The server failed to handle an otherwise valid request
+    Internal,
+    /// A code that is not known to this version of spfs
+    #[serde(other)]
+    Unknown,
+}
+
+impl HttpErrorCode {
+    /// The http status that is sent with this code.
+    pub fn status(&self) -> hyper::http::StatusCode {
+        use hyper::http::StatusCode;
+        match self {
+            Self::InvalidRequest => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::Internal | Self::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Identify the code for an error that occurred while handling a request.
+    pub fn for_error(err: &crate::Error) -> Self {
+        match err {
+            crate::Error::UnknownObject(_) | crate::Error::UnknownReference(_) => Self::NotFound,
+            crate::Error::Encoding(_) | crate::Error::InvalidReference(_) => Self::InvalidRequest,
+            crate::Error::RepositoryIsPinned => Self::Forbidden,
+            _ => Self::Internal,
+        }
+    }
+}
+
+impl std::fmt::Display for HttpErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // these match the serialized names
+        f.write_str(match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::NotFound => "not_found",
+            Self::MethodNotAllowed => "method_not_allowed",
+            Self::UnsupportedMediaType => "unsupported_media_type",
+            Self::RangeNotSatisfiable => "range_not_satisfiable",
+            Self::Internal => "internal",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// The json body of an error response from the http payload server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpError {
+    pub code: HttpErrorCode,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new<S: Into<String>>(code: HttpErrorCode, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Parse an error from the body of a response, if it has one.
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        serde_json::from_slice(body).ok()
+    }
+
+    /// Encode this error as a response body.
+    pub fn to_body(&self) -> Vec<u8> {
+        // serializing a plain struct of strings cannot fail
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
@@ -0,0 +1,29 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use rstest::rstest;
+
+use super::{HttpError, HttpErrorCode};
+
+#[rstest]
+fn test_http_error_round_trip() {
+    let error = HttpError::new(HttpErrorCode::NotFound, "Unknown Object: ABC");
+    let body = error.to_body();
+    assert_eq!(
+        std::str::from_utf8(&body).unwrap(),
+        r#"{"code":"not_found","message":"Unknown Object: ABC"}"#
+    );
+    assert_eq!(HttpError::from_body(&body), Some(error));
+}
+
+#[rstest]
+fn test_http_error_unknown_code() {
+    let error = HttpError::from_body(br#"{"code":"from_the_future","message":"hi"}"#)
+        .expect("unknown codes should still parse");
+    assert_eq!(error.code, HttpErrorCode::Unknown);
+    assert!(
+        HttpError::from_body(b"not json").is_none(),
+        "bodies that are not errors should not parse"
+    );
+}
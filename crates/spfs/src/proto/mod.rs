@@ -5,14 +5,21 @@
 //! Protocol Buffer message formats and conversions.
 
 mod conversions;
+mod http_error;
 mod result;
 mod generated {
     #![allow(clippy::derive_partial_eq_without_eq)]
     tonic::include_proto!("spfs");
 }
+/// The standard grpc health checking protocol
+pub mod health {
+    #![allow(clippy::derive_partial_eq_without_eq)]
+    tonic::include_proto!("grpc.health.v1");
+}
 
 pub(crate) use conversions::{convert_digest, convert_from_datetime};
 pub use generated::*;
+pub use http_error::{HttpError, HttpErrorCode};
 pub(crate) use result::RpcResult;
 #[cfg(feature = "server")]
 pub(crate) use {conversions::convert_to_datetime, result::handle_error};
@@ -93,6 +93,11 @@ rpc_result!(
     g::remove_tag_stream_response::Result
 );
 rpc_result!(g::RemoveTagResponse, g::remove_tag_response::Result);
+rpc_result!(
+    g::WatchTagsResponse,
+    g::watch_tags_response::Result,
+    g::TagEvent
+);
 
 rpc_result!(
     g::ReadObjectResponse,
@@ -0,0 +1,86 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use std::sync::Arc;
+
+use tonic::{Request, Status};
+
+#[cfg(test)]
+#[path = "./auth_test.rs"]
+mod auth_test;
+
+/// Requires a matching bearer token on incoming requests
+///
+/// The default value has no token and allows all requests. This
+/// can be used as a gRPC interceptor, and is also checked by the
+/// http side of the [`super::PayloadService`].
+#[derive(Clone, Default)]
+pub struct BearerAuth {
+    token: Option<Arc<[u8]>>,
+}
+
+impl std::fmt::Debug for BearerAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the token itself must never end up in logs
+        f.debug_struct("BearerAuth")
+            .field("enabled", &self.token.is_some())
+            .finish()
+    }
+}
+
+impl BearerAuth {
+    /// Allow all requests, without a token.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Require all requests to present the given token.
+    pub fn with_token<T: AsRef<[u8]>>(token: T) -> Self {
+        Self {
+            token: Some(token.as_ref().into()),
+        }
+    }
+
+    /// True if a token is required.
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Check the value of an authorization header from a request.
+    pub fn is_authorized(&self, header: Option<&[u8]>) -> bool {
+        let Some(expected) = &self.token else {
+            return true;
+        };
+        match header.and_then(|h| h.strip_prefix(b"Bearer ")) {
+            Some(token) => constant_time_eq(token, expected),
+            None => false,
+        }
+    }
+}
+
+impl tonic::service::Interceptor for BearerAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let header = request
+            .metadata()
+            .get(hyper::http::header::AUTHORIZATION.as_str())
+            .map(|value| value.as_bytes());
+        if self.is_authorized(header) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("a valid bearer token is required"))
+        }
+    }
+}
+
+/// Compare two values in a time that does not depend on their contents.
+///
+/// The length of the token is not considered secret, so differing
+/// lengths return early.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
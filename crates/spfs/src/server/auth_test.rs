@@ -0,0 +1,50 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use rstest::rstest;
+use tonic::service::Interceptor;
+
+use super::BearerAuth;
+
+#[rstest]
+#[case(None, false)]
+#[case(Some(&b"secret"[..]), false)]
+#[case(Some(&b"Bearer wrong!"[..]), false)]
+#[case(Some(&b"Bearer secre"[..]), false)]
+#[case(Some(&b"Basic secret"[..]), false)]
+#[case(Some(&b"Bearer secret"[..]), true)]
+fn test_bearer_auth_header(#[case] header: Option<&[u8]>, #[case] expected: bool) {
+    let auth = BearerAuth::with_token("secret");
+    assert_eq!(auth.is_authorized(header), expected);
+}
+
+#[rstest]
+fn test_bearer_auth_disabled_allows_all() {
+    let auth = BearerAuth::disabled();
+    assert!(!auth.is_enabled());
+    assert!(auth.is_authorized(None));
+    assert!(auth.is_authorized(Some(b"Bearer anything")));
+}
+
+#[rstest]
+fn test_bearer_auth_interceptor() {
+    let mut auth = BearerAuth::with_token("secret");
+    let status = auth
+        .call(tonic::Request::new(()))
+        .expect_err("a request without a token should be rejected");
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+    let mut request = tonic::Request::new(());
+    request
+        .metadata_mut()
+        .insert("authorization", "Bearer secret".parse().unwrap());
+    auth.call(request)
+        .expect("a request with the token should be allowed");
+}
+
+#[rstest]
+fn test_bearer_auth_debug_hides_token() {
+    let auth = BearerAuth::with_token("secret");
+    assert!(!format!("{auth:?}").contains("secret"));
+}
@@ -18,6 +18,7 @@ use crate::storage;
 #[derive(Debug, Clone)]
 pub struct DatabaseService {
     repo: Arc<storage::RepositoryHandle>,
+    read_only: bool,
 }
 
 #[tonic::async_trait]
@@ -93,6 +94,7 @@ impl proto::database_service_server::DatabaseService for DatabaseService {
         &self,
         request: Request<proto::WriteObjectRequest>,
     ) -> Result<Response<proto::WriteObjectResponse>, Status> {
+        self.ensure_writable()?;
         let request = request.into_inner();
         let object = proto::handle_error!(request.object.try_into());
         {
@@ -106,6 +108,7 @@ impl proto::database_service_server::DatabaseService for DatabaseService {
         &self,
         request: Request<proto::RemoveObjectRequest>,
     ) -> Result<Response<proto::RemoveObjectResponse>, Status> {
+        self.ensure_writable()?;
         let request = request.into_inner();
         let digest: crate::encoding::Digest = proto::handle_error!(convert_digest(request.digest));
         proto::handle_error!(self.repo.remove_object(digest).await);
@@ -117,6 +120,7 @@ impl proto::database_service_server::DatabaseService for DatabaseService {
         &self,
         request: Request<proto::RemoveObjectIfOlderThanRequest>,
     ) -> Result<Response<proto::RemoveObjectIfOlderThanResponse>, Status> {
+        self.ensure_writable()?;
         let request = request.into_inner();
         let older_than: DateTime<Utc> =
             proto::handle_error!(convert_to_datetime(request.older_than));
@@ -133,10 +137,26 @@ impl proto::database_service_server::DatabaseService for DatabaseService {
 
 impl DatabaseService {
     pub fn new(repo: Arc<storage::RepositoryHandle>) -> Self {
-        Self { repo }
+        Self {
+            repo,
+            read_only: false,
+        }
     }
 
     pub fn new_srv(repo: Arc<storage::RepositoryHandle>) -> DatabaseServiceServer<Self> {
         DatabaseServiceServer::new(Self::new(repo))
     }
+
+    /// Reject all object writes and removals with a permission denied status.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    fn ensure_writable(&self) -> Result<(), Status> {
+        if self.read_only {
+            return Err(Status::permission_denied("this server is read-only"));
+        }
+        Ok(())
+    }
 }
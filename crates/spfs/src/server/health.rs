@@ -0,0 +1,52 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use tonic::{Request, Response, Status};
+
+use crate::proto::health::health_check_response::ServingStatus;
+use crate::proto::health::health_server::HealthServer;
+use crate::proto::health::{self, HealthCheckRequest, HealthCheckResponse};
+
+#[cfg(test)]
+#[path = "./health_test.rs"]
+mod health_test;
+
+/// The http path that reports the liveness of the server
+pub const HEALTH_CHECK_PATH: &str = "/healthz";
+
+/// Answers standard grpc health checks for the server
+///
+/// The server only starts once its repository has been opened,
+/// so any check that can be answered is reported as serving. The
+/// repository itself is never touched, making this cheap to poll.
+#[derive(Debug, Default, Clone)]
+pub struct HealthService {}
+
+#[tonic::async_trait]
+impl health::health_server::Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let request = request.into_inner();
+        let status = if request.service.is_empty() || request.service.starts_with("spfs.") {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::ServiceUnknown
+        };
+        Ok(Response::new(HealthCheckResponse {
+            status: status.into(),
+        }))
+    }
+}
+
+impl HealthService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_srv() -> HealthServer<Self> {
+        HealthServer::new(Self::new())
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use http_body_util::BodyExt;
+use rstest::rstest;
+
+use super::HealthService;
+use crate::fixtures::*;
+use crate::proto::health::HealthCheckRequest;
+use crate::proto::health::health_check_response::ServingStatus;
+use crate::proto::health::health_server::Health as _;
+
+#[rstest]
+#[case("", ServingStatus::Serving)]
+#[case("spfs.TagService", ServingStatus::Serving)]
+#[case("other.Service", ServingStatus::ServiceUnknown)]
+#[tokio::test]
+async fn test_health_check(#[case] service: &str, #[case] expected: ServingStatus) {
+    let response = HealthService::new()
+        .check(tonic::Request::new(HealthCheckRequest {
+            service: service.to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.status(), expected);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_http_health_check(#[future] tmprepo: TempRepo) {
+    let tmprepo = tmprepo.await;
+    let service =
+        crate::server::PayloadService::new(tmprepo.repo(), "http://localhost".parse().unwrap());
+    let request = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(super::HEALTH_CHECK_PATH)
+        .body(http_body_util::Empty::<bytes::Bytes>::new())
+        .unwrap();
+    let response = hyper::service::Service::call(&service, request)
+        .await
+        .expect("health check should not fail");
+    assert_eq!(response.status(), hyper::http::StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), b"ok");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_http_health_check_skips_auth(#[future] tmprepo: TempRepo) {
+    let tmprepo = tmprepo.await;
+    let service =
+        crate::server::PayloadService::new(tmprepo.repo(), "http://localhost".parse().unwrap())
+            .with_auth(crate::server::BearerAuth::with_token("secret"));
+    let request = |path: &str| {
+        hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(path)
+            .body(http_body_util::Empty::<bytes::Bytes>::new())
+            .unwrap()
+    };
+    let response = hyper::service::Service::call(&service, request(super::HEALTH_CHECK_PATH))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::http::StatusCode::OK);
+
+    let response = hyper::service::Service::call(&service, request("/some-payload"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        hyper::http::StatusCode::UNAUTHORIZED,
+        "payload requests without a token should be rejected"
+    );
+}
@@ -3,12 +3,16 @@
 // https://github.com/spkenv/spk
 
 //! Remote rpc server implementation of the spfs repository
+mod auth;
 mod database;
+mod health;
 mod payload;
 mod repository;
 mod tag;
 
+pub use auth::BearerAuth;
 pub use database::DatabaseService;
+pub use health::{HEALTH_CHECK_PATH, HealthService};
 pub use payload::PayloadService;
 pub use repository::Repository;
 pub use tag::TagService;
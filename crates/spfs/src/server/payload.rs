@@ -7,13 +7,18 @@ use std::sync::Arc;
 
 use futures::{Stream, StreamExt, TryStreamExt};
 use prost::Message;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
 use tonic::{Request, Response, Status};
 
 use crate::prelude::*;
 use crate::proto::payload_service_server::PayloadServiceServer;
-use crate::proto::{self, RpcResult, convert_digest};
+use crate::proto::{self, HttpError, HttpErrorCode, RpcResult, convert_digest};
 use crate::storage;
 
+#[cfg(test)]
+#[path = "./payload_test.rs"]
+mod payload_test;
+
 /// The payload service is both a gRPC service AND an http server
 ///
 /// The grpc portion handles payload-related requests as expected,
@@ -26,6 +31,8 @@ use crate::storage;
 pub struct PayloadService {
     repo: Arc<storage::RepositoryHandle>,
     external_root: url::Url,
+    auth: super::BearerAuth,
+    read_only: bool,
 }
 
 #[tonic::async_trait]
@@ -51,6 +58,7 @@ impl proto::payload_service_server::PayloadService for PayloadService {
         &self,
         _request: Request<proto::WritePayloadRequest>,
     ) -> Result<Response<proto::WritePayloadResponse>, Status> {
+        self.ensure_writable()?;
         let data = proto::write_payload_response::UploadOption {
             url: self.external_root.to_string(),
         };
@@ -93,6 +101,7 @@ impl proto::payload_service_server::PayloadService for PayloadService {
         &self,
         request: Request<proto::RemovePayloadRequest>,
     ) -> Result<Response<proto::RemovePayloadResponse>, Status> {
+        self.ensure_writable()?;
         let request = request.into_inner();
         let digest: crate::encoding::Digest = proto::handle_error!(convert_digest(request.digest));
         proto::handle_error!(self.repo.remove_payload(digest).await);
@@ -113,15 +122,42 @@ where
         std::pin::Pin<Box<dyn futures::Future<Output = crate::Result<Self::Response>> + Send>>;
 
     fn call(&self, req: hyper::http::Request<B>) -> Self::Future {
+        if req.method() == hyper::Method::GET && req.uri().path() == super::HEALTH_CHECK_PATH {
+            return Box::pin(futures::future::ready(handle_health_check()));
+        }
+        let authorization = req
+            .headers()
+            .get(hyper::http::header::AUTHORIZATION)
+            .map(|value| value.as_bytes());
+        if !self.auth.is_authorized(authorization) {
+            return Box::pin(futures::future::ready(
+                error_response(
+                    HttpErrorCode::Unauthorized,
+                    "a valid bearer token is required",
+                )
+                .map(|mut resp| {
+                    resp.headers_mut().insert(
+                        hyper::http::header::WWW_AUTHENTICATE,
+                        hyper::http::HeaderValue::from_static("Bearer"),
+                    );
+                    resp
+                }),
+            ));
+        }
         match *req.method() {
-            hyper::Method::POST => Box::pin(handle_upload(self.repo.clone(), req)),
-            hyper::Method::GET => Box::pin(handle_download(self.repo.clone(), req)),
-            _ => Box::pin(futures::future::ready(
-                hyper::Response::builder()
-                    .status(hyper::http::StatusCode::METHOD_NOT_ALLOWED)
-                    .body(http_body_util::StreamBody::new(FramedReader::default()))
-                    .map_err(|e| crate::Error::String(e.to_string())),
+            hyper::Method::POST if self.read_only => Box::pin(futures::future::ready(
+                error_response(HttpErrorCode::Forbidden, "this server is read-only"),
             )),
+            hyper::Method::POST => {
+                Box::pin(respond_with_errors(handle_upload(self.repo.clone(), req)))
+            }
+            hyper::Method::GET => {
+                Box::pin(respond_with_errors(handle_download(self.repo.clone(), req)))
+            }
+            _ => Box::pin(futures::future::ready(error_response(
+                HttpErrorCode::MethodNotAllowed,
+                "only GET and POST requests are supported",
+            ))),
         }
     }
 }
@@ -131,7 +167,32 @@ impl PayloadService {
         Self {
             repo,
             external_root,
+            auth: super::BearerAuth::disabled(),
+            read_only: false,
+        }
+    }
+
+    /// Require a bearer token on http payload requests.
+    ///
+    /// This does not apply to the gRPC side of the service, which
+    /// should be wrapped with the same [`super::BearerAuth`] as an
+    /// interceptor instead.
+    pub fn with_auth(mut self, auth: super::BearerAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Reject payload uploads and removals, while still serving downloads.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    fn ensure_writable(&self) -> Result<(), Status> {
+        if self.read_only {
+            return Err(Status::permission_denied("this server is read-only"));
         }
+        Ok(())
     }
 
     pub fn new_srv(
@@ -146,6 +207,47 @@ impl PayloadService {
     }
 }
 
+/// Build a response with a json [`HttpError`] body and the matching status.
+fn error_response<S: Into<String>>(
+    code: HttpErrorCode,
+    message: S,
+) -> crate::Result<hyper::http::Response<ResponseBody>> {
+    let body = HttpError::new(code, message).to_body();
+    hyper::Response::builder()
+        .status(code.status())
+        .header(hyper::http::header::CONTENT_TYPE, "application/json")
+        .body(http_body_util::StreamBody::new(FramedReader::from(body)))
+        .map_err(|e| crate::Error::String(e.to_string()))
+}
+
+/// Send any error from handling a request to the client as an error
+/// response, rather than dropping the connection.
+async fn respond_with_errors<F>(handler: F) -> crate::Result<hyper::http::Response<ResponseBody>>
+where
+    F: futures::Future<Output = crate::Result<hyper::http::Response<ResponseBody>>>,
+{
+    match handler.await {
+        Ok(resp) => Ok(resp),
+        Err(err) => {
+            let code = HttpErrorCode::for_error(&err);
+            if code == HttpErrorCode::Internal {
+                tracing::error!("Failed to handle payload request: {err:?}");
+            }
+            error_response(code, err.to_string())
+        }
+    }
+}
+
+/// Report that the server is up without touching the repository,
+/// so that it can be polled cheaply
+fn handle_health_check() -> crate::Result<hyper::http::Response<ResponseBody>> {
+    hyper::Response::builder()
+        .status(hyper::http::StatusCode::OK)
+        .header(hyper::http::header::CONTENT_TYPE, "text/plain")
+        .body(http_body_util::StreamBody::new(FramedReader::from("ok")))
+        .map_err(|e| crate::Error::String(e.to_string()))
+}
+
 async fn handle_upload<B>(
     repo: Arc<storage::RepositoryHandle>,
     mut req: hyper::http::Request<B>,
@@ -167,12 +269,10 @@ where
             let reader = Box::pin(tokio::io::BufReader::new(reader));
             handle_uncompressed_upload(repo, reader).await
         }
-        _ => hyper::http::Response::builder()
-            .status(hyper::http::StatusCode::UNSUPPORTED_MEDIA_TYPE)
-            .body(http_body_util::StreamBody::new(FramedReader::from(
-                "Invalid or unsupported Content-Type",
-            )))
-            .map_err(|e| crate::Error::String(e.to_string())),
+        _ => error_response(
+            HttpErrorCode::UnsupportedMediaType,
+            "Invalid or unsupported Content-Type",
+        ),
     }
 }
 
@@ -225,7 +325,7 @@ where
 
 async fn handle_download<B>(
     repo: Arc<storage::RepositoryHandle>,
-    mut req: hyper::http::Request<B>,
+    req: hyper::http::Request<B>,
 ) -> crate::Result<hyper::http::Response<ResponseBody>>
 where
     B: hyper::body::Body + Send + Sync + 'static,
@@ -234,42 +334,358 @@ where
 {
     let relative_path = req.uri().path().trim_start_matches('/');
     let digest = crate::encoding::Digest::parse(relative_path)?;
-    let (uncompressed_reader, _) = repo.open_payload(digest).await?;
-    let accepted = req
-        .headers_mut()
+    let (mut uncompressed_reader, payload_path) = repo.open_payload(digest).await?;
+    // the size is unknown for payloads without a blob
+    let size = match repo.read_blob(digest).await {
+        Ok(blob) => Some(blob.size()),
+        Err(err) => {
+            tracing::debug!("Serving payload without a blob: {err}");
+            None
+        }
+    };
+    let range = req
+        .headers()
+        .get(hyper::http::header::RANGE)
+        .and_then(|value| ByteRange::parse(value.as_bytes()));
+    // the size of the payload is needed to describe the range, so
+    // the request is served in full when it is unknown
+    let range = match (range, size) {
+        (Some(range), Some(size)) => Some((range, size)),
+        (Some(_), None) => {
+            tracing::debug!("Ignoring range request for payload without a blob");
+            None
+        }
+        (None, _) => None,
+    };
+    // the first supported content type that is accepted is used,
+    // where octet-stream is the default, uncompressed
+    let wants_bzip2 = req
+        .headers()
         .get_all(hyper::http::header::ACCEPT)
-        .into_iter();
-    let get_body_and_content_type = move || -> (FramedReader, hyper::http::HeaderValue) {
-        for accepted in accepted {
-            match accepted.to_str() {
-                Ok("application/octet-stream") => {
-                    // this is the default, uncompressed
-                    break;
-                }
-                Ok("application/x-bzip2") => {
-                    return (
-                        FramedReader::from(async_compression::tokio::bufread::BzEncoder::new(
-                            uncompressed_reader,
-                        )),
-                        accepted.to_owned(),
-                    );
-                }
-                _ => continue,
+        .into_iter()
+        .find_map(|accepted| match accepted.to_str() {
+            Ok("application/octet-stream") => Some(false),
+            Ok("application/x-bzip2") => Some(true),
+            _ => None,
+        })
+        .unwrap_or_default();
+    let payload_encoding = if range.is_some() {
+        // ranges are always served from the payload data as it is
+        PayloadEncoding::Identity
+    } else if wants_bzip2 {
+        PayloadEncoding::Bzip2
+    } else {
+        match negotiate_content_encoding(req.headers()) {
+            Some(encoding) if is_worth_compressing(size, &mut uncompressed_reader).await => {
+                PayloadEncoding::Content(encoding)
             }
+            _ => PayloadEncoding::Identity,
         }
-        (
+    };
+
+    // payloads are content-addressed, so the digest and encoding are
+    // all that a client needs to know that it already has the same data
+    let etag = payload_etag(&digest, payload_encoding);
+    let not_modified = req
+        .headers()
+        .get_all(hyper::http::header::IF_NONE_MATCH)
+        .into_iter()
+        .any(|value| etag_matches(value.as_bytes(), &etag));
+    if not_modified {
+        return hyper::Response::builder()
+            .status(hyper::http::StatusCode::NOT_MODIFIED)
+            .header(hyper::http::header::ETAG, etag)
+            .header(hyper::http::header::VARY, "Accept, Accept-Encoding")
+            .body(http_body_util::StreamBody::new(FramedReader::default()))
+            .map_err(|e| crate::Error::String(e.to_string()));
+    }
+    if let Some((range, size)) = range {
+        return handle_range_download(uncompressed_reader, &payload_path, range, size)
+            .await
+            .map(|resp| with_etag(resp, &etag));
+    }
+
+    let (stream, content_type, content_encoding) = match payload_encoding {
+        PayloadEncoding::Bzip2 => (
+            FramedReader::from(async_compression::tokio::bufread::BzEncoder::new(
+                uncompressed_reader,
+            )),
+            "application/x-bzip2",
+            // the data is already compressed
+            None,
+        ),
+        PayloadEncoding::Content(ContentEncoding::Gzip) => (
+            FramedReader::from_reader(async_compression::tokio::bufread::GzipEncoder::new(
+                uncompressed_reader,
+            )),
+            "application/octet-stream",
+            Some(ContentEncoding::Gzip),
+        ),
+        PayloadEncoding::Content(ContentEncoding::Zstd) => (
+            FramedReader::from_reader(async_compression::tokio::bufread::ZstdEncoder::new(
+                uncompressed_reader,
+            )),
+            "application/octet-stream",
+            Some(ContentEncoding::Zstd),
+        ),
+        PayloadEncoding::Identity => (
             FramedReader::from(uncompressed_reader),
-            hyper::http::HeaderValue::from_static("application/octet-stream"),
-        )
+            "application/octet-stream",
+            None,
+        ),
     };
-    let (stream, content_type) = get_body_and_content_type();
-    hyper::Response::builder()
+    let mut builder = hyper::Response::builder()
         .status(hyper::http::StatusCode::OK)
         .header(hyper::http::header::CONTENT_TYPE, content_type)
+        .header(hyper::http::header::ACCEPT_RANGES, "bytes")
+        .header(hyper::http::header::ETAG, etag)
+        .header(hyper::http::header::VARY, "Accept, Accept-Encoding");
+    if let Some(encoding) = content_encoding {
+        builder = builder.header(hyper::http::header::CONTENT_ENCODING, encoding.as_str());
+    }
+    builder
         .body(http_body_util::StreamBody::new(stream))
         .map_err(|e| crate::Error::String(e.to_string()))
 }
 
+/// Payloads smaller than this are always sent without a content encoding,
+/// as compressing them saves too little to be worth it.
+const MIN_COMPRESSIBLE_PAYLOAD_SIZE: u64 = 1024;
+
+/// The leading bytes of common file formats that are already compressed.
+const COMPRESSED_MAGIC_NUMBERS: &[&[u8]] = &[
+    b"\x1f\x8b",         // gzip
+    b"\x28\xb5\x2f\xfd", // zstd
+    b"BZh",              // bzip2
+    b"\xfd7zXZ\x00",     // xz
+    b"PK\x03\x04",       // zip
+    b"\x89PNG",          // png
+    b"\xff\xd8\xff",     // jpeg
+];
+
+/// A content encoding that payloads can be compressed with for transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ContentEncoding {
+    Gzip,
+    /// Preferred over gzip when the client accepts both equally
+    Zstd,
+}
+
+impl ContentEncoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+/// How the body of a payload download is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PayloadEncoding {
+    /// The payload data as it is
+    Identity,
+    /// Compressed and sent as the `application/x-bzip2` content type
+    Bzip2,
+    /// Compressed with a content encoding that the client accepts
+    Content(ContentEncoding),
+}
+
+/// Choose the content encoding to use from the `Accept-Encoding` headers
+/// of a request, if any of the supported ones are accepted.
+fn negotiate_content_encoding(headers: &hyper::http::HeaderMap) -> Option<ContentEncoding> {
+    let mut best: Option<(f32, ContentEncoding)> = None;
+    for value in headers.get_all(hyper::http::header::ACCEPT_ENCODING) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for item in value.split(',') {
+            let mut params = item.split(';');
+            let encoding = match params.next().unwrap_or_default().trim() {
+                name if name.eq_ignore_ascii_case("zstd") => ContentEncoding::Zstd,
+                name if name.eq_ignore_ascii_case("gzip") => ContentEncoding::Gzip,
+                _ => continue,
+            };
+            let quality = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.trim().parse().unwrap_or(0.0),
+                None => 1.0,
+            };
+            if quality <= 0.0 {
+                continue;
+            }
+            if best.is_none_or(|current| (quality, encoding) > current) {
+                best = Some((quality, encoding));
+            }
+        }
+    }
+    best.map(|(_, encoding)| encoding)
+}
+
+/// Decide whether a payload is likely to be made smaller by compressing it.
+///
+/// Small payloads and those that start like an already compressed file
+/// are sent as they are, to avoid spending time compressing them for
+/// little or no benefit.
+async fn is_worth_compressing(size: Option<u64>, reader: &mut Pin<Box<dyn BlobRead>>) -> bool {
+    if size.is_some_and(|size| size < MIN_COMPRESSIBLE_PAYLOAD_SIZE) {
+        return false;
+    }
+    match reader.fill_buf().await {
+        Ok(prefix) => !has_compressed_magic_number(prefix),
+        Err(_) => false,
+    }
+}
+
+/// True if the given data starts like an already compressed file.
+fn has_compressed_magic_number(prefix: &[u8]) -> bool {
+    COMPRESSED_MAGIC_NUMBERS
+        .iter()
+        .any(|magic| prefix.starts_with(magic))
+}
+
+/// The entity tag of a payload, which is its quoted digest.
+///
+/// Compressed bodies are different representations of the payload, so
+/// their tags are suffixed with the encoding that was used, keeping
+/// caches from serving one encoding to a client that asked for another.
+fn payload_etag(digest: &crate::encoding::Digest, encoding: PayloadEncoding) -> String {
+    match encoding {
+        PayloadEncoding::Identity => format!("\"{digest}\""),
+        PayloadEncoding::Bzip2 => format!("\"{digest}-bzip2\""),
+        PayloadEncoding::Content(encoding) => format!("\"{digest}-{}\"", encoding.as_str()),
+    }
+}
+
+/// Add a payload's entity tag to a response.
+fn with_etag(
+    mut resp: hyper::http::Response<ResponseBody>,
+    etag: &str,
+) -> hyper::http::Response<ResponseBody> {
+    if let Ok(value) = hyper::http::HeaderValue::from_str(etag) {
+        resp.headers_mut().insert(hyper::http::header::ETAG, value);
+    }
+    resp
+}
+
+/// True if an `If-None-Match` header value includes the given entity tag.
+///
+/// Weak comparison is used, as allowed for this header, so a weak
+/// `W/` prefix on any of the listed tags is ignored.
+fn etag_matches(value: &[u8], etag: &str) -> bool {
+    let Ok(value) = std::str::from_utf8(value) else {
+        return false;
+    };
+    value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// A single range of bytes from a `Range` request header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteRange {
+    start: u64,
+    /// The last byte of the range, inclusive, or none to read to the end
+    end: Option<u64>,
+}
+
+impl ByteRange {
+    /// Parse a `bytes=start-` or `bytes=start-end` header value.
+    ///
+    /// Suffix ranges and requests for multiple ranges are not supported,
+    /// and return none so that the request is served in full instead.
+    fn parse(value: &[u8]) -> Option<Self> {
+        let value = std::str::from_utf8(value).ok()?;
+        let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let start = start.trim().parse().ok()?;
+        let end = match end.trim() {
+            "" => None,
+            end => Some(end.parse().ok()?),
+        };
+        match end {
+            Some(end) if end < start => None,
+            _ => Some(Self { start, end }),
+        }
+    }
+}
+
+/// Serve part of a payload, as requested by a `Range` header.
+///
+/// Ranges always refer to the uncompressed payload data, so ranged
+/// responses are never compressed.
+async fn handle_range_download(
+    reader: Pin<Box<dyn BlobRead>>,
+    payload_path: &std::path::Path,
+    range: ByteRange,
+    size: u64,
+) -> crate::Result<hyper::http::Response<ResponseBody>> {
+    if range.start >= size {
+        return error_response(
+            HttpErrorCode::RangeNotSatisfiable,
+            format!("range starts beyond the end of the {size} byte payload"),
+        )
+        .map(|mut resp| {
+            if let Ok(value) = hyper::http::HeaderValue::try_from(format!("bytes */{size}")) {
+                resp.headers_mut()
+                    .insert(hyper::http::header::CONTENT_RANGE, value);
+            }
+            resp
+        });
+    }
+    let end = range.end.map_or(size - 1, |end| end.min(size - 1));
+    let reader = skip_to_offset(reader, payload_path, range.start)
+        .await
+        .map_err(|err| {
+            crate::Error::String(format!(
+                "Failed to skip to the start of the requested range: {err}"
+            ))
+        })?;
+    let len = end - range.start + 1;
+    hyper::Response::builder()
+        .status(hyper::http::StatusCode::PARTIAL_CONTENT)
+        .header(
+            hyper::http::header::CONTENT_TYPE,
+            "application/octet-stream",
+        )
+        .header(hyper::http::header::ACCEPT_RANGES, "bytes")
+        .header(
+            hyper::http::header::CONTENT_RANGE,
+            format!("bytes {}-{end}/{size}", range.start),
+        )
+        .header(hyper::http::header::CONTENT_LENGTH, len)
+        .body(http_body_util::StreamBody::new(FramedReader::from(
+            reader.take(len),
+        )))
+        .map_err(|e| crate::Error::String(e.to_string()))
+}
+
+/// Return a reader for a payload that starts at the given offset.
+///
+/// Payloads stored in a local file are reopened and read from the
+/// offset directly. Otherwise, the leading data is read from the
+/// given reader and discarded.
+async fn skip_to_offset(
+    mut reader: Pin<Box<dyn BlobRead>>,
+    payload_path: &std::path::Path,
+    offset: u64,
+) -> std::io::Result<Pin<Box<dyn BlobRead>>> {
+    use tokio::io::AsyncSeekExt;
+
+    // remote repositories return a url rather than a file path
+    if payload_path.is_absolute() {
+        match tokio::fs::File::open(payload_path).await {
+            Ok(mut file) => {
+                file.seek(std::io::SeekFrom::Start(offset)).await?;
+                return Ok(Box::pin(tokio::io::BufReader::new(file)));
+            }
+            Err(err) => {
+                tracing::debug!(?payload_path, "Reading payload up to range start: {err}")
+            }
+        }
+    }
+    tokio::io::copy(&mut (&mut reader).take(offset), &mut tokio::io::sink()).await?;
+    Ok(reader)
+}
+
 /// The body of the response to a payload upload or download request
 type ResponseBody = http_body_util::StreamBody<FramedReader>;
 
@@ -277,6 +693,17 @@ pub struct FramedReader {
     inner: tokio_util::io::ReaderStream<Pin<Box<dyn tokio::io::AsyncRead + Send + Sync + 'static>>>,
 }
 
+impl FramedReader {
+    fn from_reader<R>(reader: R) -> Self
+    where
+        R: tokio::io::AsyncRead + Send + Sync + 'static,
+    {
+        Self {
+            inner: tokio_util::io::ReaderStream::new(Box::pin(reader)),
+        }
+    }
+}
+
 impl Default for FramedReader {
     fn default() -> Self {
         Self::from("")
@@ -307,6 +734,14 @@ impl From<Pin<Box<dyn BlobRead>>> for FramedReader {
     }
 }
 
+impl From<tokio::io::Take<Pin<Box<dyn BlobRead>>>> for FramedReader {
+    fn from(value: tokio::io::Take<Pin<Box<dyn BlobRead>>>) -> Self {
+        Self {
+            inner: tokio_util::io::ReaderStream::new(Box::pin(value)),
+        }
+    }
+}
+
 impl<T> From<async_compression::tokio::bufread::BzEncoder<T>> for FramedReader
 where
     async_compression::tokio::bufread::BzEncoder<T>: tokio::io::AsyncRead + Send + Sync + 'static,
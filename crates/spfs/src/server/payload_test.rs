@@ -0,0 +1,277 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use http_body_util::BodyExt;
+use rstest::rstest;
+
+use super::{
+    ByteRange,
+    ContentEncoding,
+    PayloadService,
+    etag_matches,
+    has_compressed_magic_number,
+    negotiate_content_encoding,
+    skip_to_offset,
+};
+use crate::fixtures::*;
+use crate::proto::{HttpError, HttpErrorCode};
+
+#[rstest]
+#[case(format!("/{}", random_digest()), HttpErrorCode::NotFound)]
+#[case("/not-a-digest".to_string(), HttpErrorCode::InvalidRequest)]
+#[tokio::test]
+async fn test_http_download_errors(
+    #[future] tmprepo: TempRepo,
+    #[case] path: String,
+    #[case] expected: HttpErrorCode,
+) {
+    let tmprepo = tmprepo.await;
+    let service = PayloadService::new(tmprepo.repo(), "http://localhost".parse().unwrap());
+    let request = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(path)
+        .body(http_body_util::Empty::<bytes::Bytes>::new())
+        .unwrap();
+    let response = hyper::service::Service::call(&service, request)
+        .await
+        .expect("errors should be sent as a response");
+    assert_eq!(response.status(), expected.status());
+    assert_eq!(
+        response.headers()[hyper::http::header::CONTENT_TYPE],
+        "application/json"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let error = HttpError::from_body(&body).expect("body should be a json error");
+    assert_eq!(error.code, expected);
+}
+
+#[rstest]
+#[case(b"bytes=0-".as_slice(), Some(ByteRange { start: 0, end: None }))]
+#[case(b"bytes=5-9".as_slice(), Some(ByteRange { start: 5, end: Some(9) }))]
+#[case(b"bytes=-5".as_slice(), None)]
+#[case(b"bytes=9-5".as_slice(), None)]
+#[case(b"bytes=0-1,5-9".as_slice(), None)]
+#[case(b"items=0-".as_slice(), None)]
+fn test_parse_byte_range(#[case] value: &[u8], #[case] expected: Option<ByteRange>) {
+    assert_eq!(ByteRange::parse(value), expected);
+}
+
+#[rstest]
+#[case("bytes=6-", "bytes 6-10/11", "world")]
+#[case("bytes=0-4", "bytes 0-4/11", "hello")]
+#[case("bytes=6-100", "bytes 6-10/11", "world")]
+#[tokio::test]
+async fn test_http_range_download(
+    #[future] tmprepo: TempRepo,
+    #[case] range: &str,
+    #[case] expected_range: &str,
+    #[case] expected_body: &str,
+) {
+    let tmprepo = tmprepo.await;
+    let digest = tmprepo
+        .commit_blob(Box::pin(b"hello world".as_slice()))
+        .await
+        .unwrap();
+    let service = PayloadService::new(tmprepo.repo(), "http://localhost".parse().unwrap());
+    let response = hyper::service::Service::call(&service, range_request(digest, range))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response.headers()[hyper::http::header::ACCEPT_RANGES],
+        "bytes"
+    );
+    assert_eq!(
+        response.headers()[hyper::http::header::CONTENT_RANGE],
+        expected_range
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), expected_body.as_bytes());
+
+    let response = hyper::service::Service::call(&service, range_request(digest, "bytes=11-"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.status(),
+        hyper::http::StatusCode::RANGE_NOT_SATISFIABLE
+    );
+    assert_eq!(
+        response.headers()[hyper::http::header::CONTENT_RANGE],
+        "bytes */11"
+    );
+}
+
+fn range_request(
+    digest: crate::encoding::Digest,
+    range: &str,
+) -> hyper::Request<http_body_util::Empty<bytes::Bytes>> {
+    hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("/{digest}"))
+        .header(hyper::http::header::RANGE, range)
+        .body(http_body_util::Empty::<bytes::Bytes>::new())
+        .unwrap()
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_skip_to_offset(tmpdir: tempfile::TempDir) {
+    let path = tmpdir.path().join("payload");
+    std::fs::write(&path, b"hello world").unwrap();
+    let read = |mut reader: std::pin::Pin<Box<dyn crate::tracking::BlobRead>>| async move {
+        let mut data = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut reader, &mut data)
+            .await
+            .unwrap();
+        data
+    };
+
+    // the file is read from the offset, rather than the given reader
+    let reader = skip_to_offset(Box::pin(b"".as_slice()), &path, 6)
+        .await
+        .unwrap();
+    assert_eq!(read(reader).await, "world");
+
+    // readers that are not backed by a file are read up to the offset
+    let reader = skip_to_offset(
+        Box::pin(b"hello world".as_slice()),
+        std::path::Path::new("http://localhost/payload"),
+        6,
+    )
+    .await
+    .unwrap();
+    assert_eq!(read(reader).await, "world");
+}
+
+#[rstest]
+#[case(r#""ABC""#, true)]
+#[case(r#"W/"ABC""#, true)]
+#[case(r#""XYZ", "ABC""#, true)]
+#[case("*", true)]
+#[case(r#""XYZ""#, false)]
+#[case("ABC", false)]
+fn test_etag_matches(#[case] value: &str, #[case] expected: bool) {
+    assert_eq!(etag_matches(value.as_bytes(), r#""ABC""#), expected);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_http_download_not_modified(#[future] tmprepo: TempRepo) {
+    let tmprepo = tmprepo.await;
+    let digest = tmprepo
+        .commit_blob(Box::pin(b"hello world".as_slice()))
+        .await
+        .unwrap();
+    let service = PayloadService::new(tmprepo.repo(), "http://localhost".parse().unwrap());
+    let request = |if_none_match: Option<&str>| {
+        let mut builder = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(format!("/{digest}"));
+        if let Some(value) = if_none_match {
+            builder = builder.header(hyper::http::header::IF_NONE_MATCH, value);
+        }
+        builder
+            .body(http_body_util::Empty::<bytes::Bytes>::new())
+            .unwrap()
+    };
+
+    let response = hyper::service::Service::call(&service, request(None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::http::StatusCode::OK);
+    let etag = response.headers()[hyper::http::header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(etag, format!("\"{digest}\""));
+
+    let response = hyper::service::Service::call(&service, request(Some(&etag)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::http::StatusCode::NOT_MODIFIED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(
+        body.is_empty(),
+        "a 304 response should not include the payload"
+    );
+}
+
+#[rstest]
+#[case("gzip", Some(ContentEncoding::Gzip))]
+#[case("gzip, zstd", Some(ContentEncoding::Zstd))]
+#[case("zstd;q=0.5, gzip", Some(ContentEncoding::Gzip))]
+#[case("zstd;q=0, br", None)]
+#[case("identity", None)]
+fn test_negotiate_content_encoding(#[case] value: &str, #[case] expected: Option<ContentEncoding>) {
+    let mut headers = hyper::http::HeaderMap::new();
+    headers.insert(
+        hyper::http::header::ACCEPT_ENCODING,
+        hyper::http::HeaderValue::from_str(value).unwrap(),
+    );
+    assert_eq!(negotiate_content_encoding(&headers), expected);
+}
+
+#[rstest]
+fn test_compressed_magic_numbers() {
+    assert!(has_compressed_magic_number(b"\x1f\x8b\x08\x00"));
+    assert!(has_compressed_magic_number(b"\x89PNG\r\n"));
+    assert!(!has_compressed_magic_number(b"key: value\n"));
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_http_download_content_encoding(#[future] tmprepo: TempRepo) {
+    let tmprepo = tmprepo.await;
+    let data = "some: text\n".repeat(1024);
+    let digest = tmprepo
+        .commit_blob(Box::pin(std::io::Cursor::new(data.clone().into_bytes())))
+        .await
+        .unwrap();
+    let service = PayloadService::new(tmprepo.repo(), "http://localhost".parse().unwrap());
+    let request = |if_none_match: &str| {
+        hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(format!("/{digest}"))
+            .header(hyper::http::header::ACCEPT_ENCODING, "gzip")
+            .header(hyper::http::header::IF_NONE_MATCH, if_none_match)
+            .body(http_body_util::Empty::<bytes::Bytes>::new())
+            .unwrap()
+    };
+
+    // the uncompressed payload is a different representation
+    let uncompressed_etag = format!("\"{digest}\"");
+    let response = hyper::service::Service::call(&service, request(&uncompressed_etag))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::http::StatusCode::OK);
+    assert_eq!(
+        response.headers()[hyper::http::header::CONTENT_ENCODING],
+        "gzip"
+    );
+    assert_eq!(
+        response.headers()[hyper::http::header::ETAG],
+        format!("\"{digest}-gzip\"").as_str(),
+        "each encoding should have its own entity tag"
+    );
+    assert_eq!(
+        response.headers()[hyper::http::header::VARY],
+        "Accept, Accept-Encoding"
+    );
+    let not_modified =
+        hyper::service::Service::call(&service, request(&format!("\"{digest}-gzip\"")))
+            .await
+            .unwrap();
+    assert_eq!(not_modified.status(), hyper::http::StatusCode::NOT_MODIFIED);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.len() < data.len(), "text should be compressed");
+    let mut decoded = String::new();
+    tokio::io::AsyncReadExt::read_to_string(
+        &mut async_compression::tokio::bufread::GzipDecoder::new(body.as_ref()),
+        &mut decoded,
+    )
+    .await
+    .unwrap();
+    assert_eq!(decoded, data);
+}
@@ -3,17 +3,24 @@
 // https://github.com/spkenv/spk
 
 use std::convert::TryInto;
+use std::pin::Pin;
 use std::sync::Arc;
 
-use futures::TryStreamExt;
-use relative_path::RelativePath;
+use futures::{Stream, TryStreamExt};
+use relative_path::{RelativePath, RelativePathBuf};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError, broadcast};
 use tokio_stream::StreamExt;
 use tonic::{Request, Response, Status};
 
 use crate::prelude::*;
 use crate::proto::tag_service_server::TagServiceServer;
 use crate::proto::{self, RpcResult, convert_digest};
-use crate::storage::{self, TagNamespace};
+use crate::storage::{self, TagEvent, TagNamespace, TagNamespaceBuf};
+
+#[cfg(test)]
+#[path = "./tag_test.rs"]
+mod tag_test;
 
 fn string_to_namespace(namespace: &String) -> Option<&TagNamespace> {
     if namespace.is_empty() {
@@ -23,13 +30,28 @@ fn string_to_namespace(namespace: &String) -> Option<&TagNamespace> {
     }
 }
 
+/// The number of tag events that are buffered for each watcher
+/// before it starts missing events.
+const TAG_EVENT_CAPACITY: usize = 1024;
+
+fn same_namespace(a: Option<&TagNamespace>, b: Option<&TagNamespace>) -> bool {
+    a.map(TagNamespace::as_rel_path) == b.map(TagNamespace::as_rel_path)
+}
+
 #[derive(Debug, Clone)]
 pub struct TagService {
     repo: Arc<storage::RepositoryHandle>,
+    write_semaphore: Option<Arc<Semaphore>>,
+    reject_excess_writes: bool,
+    read_only: bool,
+    events: broadcast::Sender<(Option<TagNamespaceBuf>, TagEvent)>,
 }
 
 #[tonic::async_trait]
 impl proto::tag_service_server::TagService for TagService {
+    type WatchTagsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::WatchTagsResponse, Status>> + Send>>;
+
     async fn ls_tags(
         &self,
         request: Request<proto::LsTagsRequest>,
@@ -124,6 +146,8 @@ impl proto::tag_service_server::TagService for TagService {
         &self,
         request: tonic::Request<proto::InsertTagRequest>,
     ) -> Result<tonic::Response<proto::InsertTagResponse>, tonic::Status> {
+        self.ensure_writable()?;
+        let _permit = self.acquire_write_permit().await?;
         let request = request.into_inner();
         let tag = proto::handle_error!(request.tag.try_into());
         proto::handle_error!(
@@ -131,6 +155,10 @@ impl proto::tag_service_server::TagService for TagService {
                 .insert_tag_in_namespace(string_to_namespace(&request.namespace), &tag)
                 .await
         );
+        self.notify(
+            string_to_namespace(&request.namespace),
+            TagEvent::Created(tag),
+        );
         let data = proto::InsertTagResponse::ok(proto::Ok {});
         Ok(Response::new(data))
     }
@@ -139,6 +167,8 @@ impl proto::tag_service_server::TagService for TagService {
         &self,
         request: tonic::Request<proto::RemoveTagStreamRequest>,
     ) -> Result<tonic::Response<proto::RemoveTagStreamResponse>, tonic::Status> {
+        self.ensure_writable()?;
+        let _permit = self.acquire_write_permit().await?;
         let request = request.into_inner();
         let tag_spec = proto::handle_error!(request.tag_spec.parse());
         proto::handle_error!(
@@ -146,6 +176,10 @@ impl proto::tag_service_server::TagService for TagService {
                 .remove_tag_stream_in_namespace(string_to_namespace(&request.namespace), &tag_spec)
                 .await
         );
+        self.notify(
+            string_to_namespace(&request.namespace),
+            TagEvent::StreamRemoved(tag_spec),
+        );
 
         let data = proto::RemoveTagStreamResponse::ok(proto::Ok {});
         Ok(Response::new(data))
@@ -155,6 +189,8 @@ impl proto::tag_service_server::TagService for TagService {
         &self,
         request: tonic::Request<proto::RemoveTagRequest>,
     ) -> Result<tonic::Response<proto::RemoveTagResponse>, tonic::Status> {
+        self.ensure_writable()?;
+        let _permit = self.acquire_write_permit().await?;
         let request = request.into_inner();
         let tag = proto::handle_error!(request.tag.try_into());
         proto::handle_error!(
@@ -162,18 +198,137 @@ impl proto::tag_service_server::TagService for TagService {
                 .remove_tag_in_namespace(string_to_namespace(&request.namespace), &tag)
                 .await
         );
+        self.notify(
+            string_to_namespace(&request.namespace),
+            TagEvent::Removed(tag),
+        );
 
         let data = proto::RemoveTagResponse::ok(proto::Ok {});
         Ok(Response::new(data))
     }
+
+    async fn watch_tags(
+        &self,
+        request: tonic::Request<proto::WatchTagsRequest>,
+    ) -> Result<tonic::Response<Self::WatchTagsStream>, tonic::Status> {
+        let request = request.into_inner();
+        let namespace = string_to_namespace(&request.namespace).map(ToOwned::to_owned);
+        let prefix = RelativePathBuf::from(request.prefix);
+        // subscribe before responding so that no events are missed
+        // by a client that writes tags once the watch is established
+        let mut events = self.events.subscribe();
+        let stream = async_stream::stream! {
+            loop {
+                match events.recv().await {
+                    Ok((event_namespace, event)) => {
+                        if !same_namespace(event_namespace.as_deref(), namespace.as_deref())
+                            || !event.matches_prefix(&prefix)
+                        {
+                            continue;
+                        }
+                        yield Ok(proto::WatchTagsResponse::ok((&event).into()));
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!(missed, "tag watcher fell behind");
+                        yield Ok(proto::WatchTagsResponse::error(crate::Error::String(format!(
+                            "Tag watcher fell behind and missed {missed} events"
+                        ))));
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        };
+        let stream: Self::WatchTagsStream = Box::pin(stream);
+        Ok(Response::new(stream))
+    }
 }
 
 impl TagService {
     pub fn new(repo: Arc<storage::RepositoryHandle>) -> Self {
-        Self { repo }
+        Self {
+            repo,
+            write_semaphore: None,
+            reject_excess_writes: false,
+            read_only: false,
+            events: broadcast::channel(TAG_EVENT_CAPACITY).0,
+        }
     }
 
     pub fn new_srv(repo: Arc<storage::RepositoryHandle>) -> TagServiceServer<Self> {
-        TagServiceServer::new(Self::new(repo))
+        Self::new(repo).into_srv()
+    }
+
+    pub fn into_srv(self) -> TagServiceServer<Self> {
+        TagServiceServer::new(self)
+    }
+
+    /// Set how many tag write operations can be processed at once.
+    ///
+    /// Additional writes wait for a running one to complete unless
+    /// [`Self::with_reject_excess_writes`] is enabled. Reads are never
+    /// limited.
+    pub fn with_max_concurrent_writes(mut self, concurrency: usize) -> Self {
+        self.write_semaphore = Some(Arc::new(Semaphore::new(concurrency)));
+        self
+    }
+
+    /// When a write limit is set, reject writes over the limit with
+    /// an unavailable status instead of queueing them.
+    pub fn with_reject_excess_writes(mut self, reject: bool) -> Self {
+        self.reject_excess_writes = reject;
+        self
+    }
+
+    /// Reject all tag writes with a permission denied status.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    fn ensure_writable(&self) -> Result<(), Status> {
+        if self.read_only {
+            return Err(Status::permission_denied("this server is read-only"));
+        }
+        Ok(())
+    }
+
+    /// The number of additional write operations that could start
+    /// right now, or `None` if writes are not limited.
+    pub fn available_write_permits(&self) -> Option<usize> {
+        self.write_semaphore
+            .as_ref()
+            .map(|semaphore| semaphore.available_permits())
+    }
+
+    /// Send a tag event to everyone that is watching tags.
+    fn notify(&self, namespace: Option<&TagNamespace>, event: TagEvent) {
+        // sending only fails when there are no watchers
+        let _ = self.events.send((namespace.map(ToOwned::to_owned), event));
+    }
+
+    async fn acquire_write_permit(&self) -> Result<Option<OwnedSemaphorePermit>, Status> {
+        let Some(semaphore) = &self.write_semaphore else {
+            return Ok(None);
+        };
+        let semaphore = Arc::clone(semaphore);
+        match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => return Ok(Some(permit)),
+            Err(TryAcquireError::Closed) => {
+                return Err(Status::unavailable("tag service is shutting down"));
+            }
+            Err(TryAcquireError::NoPermits) if self.reject_excess_writes => {
+                tracing::debug!("rejecting tag write, too many concurrent writes");
+                return Err(Status::unavailable(
+                    "too many concurrent tag writes, try again later",
+                ));
+            }
+            Err(TryAcquireError::NoPermits) => {}
+        }
+        tracing::debug!("queueing tag write behind other concurrent writes");
+        semaphore
+            .acquire_owned()
+            .await
+            .map(Some)
+            .map_err(|_| Status::unavailable("tag service is shutting down"))
     }
 }
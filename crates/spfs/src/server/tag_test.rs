@@ -0,0 +1,181 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use relative_path::RelativePath;
+use rstest::rstest;
+
+use super::TagService;
+use crate::fixtures::*;
+use crate::prelude::*;
+use crate::proto::tag_service_server::TagService as _;
+use crate::{encoding, proto, storage, tracking};
+
+fn insert_tag_request(tag: &tracking::Tag) -> tonic::Request<proto::InsertTagRequest> {
+    tonic::Request::new(proto::InsertTagRequest {
+        tag: Some(tag.into()),
+        namespace: String::new(),
+    })
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_write_limit_queues_excess_writes(#[future] tmprepo: TempRepo) {
+    let tmprepo = tmprepo.await;
+    let service = TagService::new(tmprepo.repo()).with_max_concurrent_writes(1);
+
+    // hold the only permit to simulate a write that is still running
+    let first = service.acquire_write_permit().await.unwrap();
+    assert_eq!(service.available_write_permits(), Some(0));
+
+    let tag =
+        tracking::Tag::new(Some("test".into()), "queued", encoding::EMPTY_DIGEST.into()).unwrap();
+    let second = tokio::spawn({
+        let service = service.clone();
+        let request = insert_tag_request(&tag);
+        async move { service.insert_tag(request).await }
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(
+        !second.is_finished(),
+        "the second write should be queued behind the first"
+    );
+    tmprepo
+        .resolve_tag(&tag.to_spec(0))
+        .await
+        .expect_err("the queued write should not have happened yet");
+
+    drop(first);
+    second.await.unwrap().expect("queued write should succeed");
+    tmprepo
+        .resolve_tag(&tag.to_spec(0))
+        .await
+        .expect("the queued write should complete once the first finishes");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_write_limit_rejects_excess_writes(#[future] tmprepo: TempRepo) {
+    let tmprepo = tmprepo.await;
+    let service = TagService::new(tmprepo.repo())
+        .with_max_concurrent_writes(1)
+        .with_reject_excess_writes(true);
+
+    let _first = service.acquire_write_permit().await.unwrap();
+    let tag = tracking::Tag::new(
+        Some("test".into()),
+        "rejected",
+        encoding::EMPTY_DIGEST.into(),
+    )
+    .unwrap();
+    let status = service
+        .insert_tag(insert_tag_request(&tag))
+        .await
+        .expect_err("writes over the limit should be rejected");
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_read_only_rejects_writes(#[future] tmprepo: TempRepo) {
+    let tmprepo = tmprepo.await;
+    let tag = tracking::Tag::new(
+        Some("test".into()),
+        "existing",
+        encoding::EMPTY_DIGEST.into(),
+    )
+    .unwrap();
+    tmprepo.insert_tag(&tag).await.unwrap();
+    let service = TagService::new(tmprepo.repo()).with_read_only(true);
+
+    let new_tag =
+        tracking::Tag::new(Some("test".into()), "new", encoding::EMPTY_DIGEST.into()).unwrap();
+    let status = service
+        .insert_tag(insert_tag_request(&new_tag))
+        .await
+        .expect_err("tag pushes should be rejected");
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    let status = service
+        .remove_tag_stream(tonic::Request::new(proto::RemoveTagStreamRequest {
+            tag_spec: tag.to_spec(0).to_string(),
+            namespace: String::new(),
+        }))
+        .await
+        .expect_err("tag removals should be rejected");
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    let response = service
+        .resolve_tag(tonic::Request::new(proto::ResolveTagRequest {
+            tag_spec: tag.to_spec(0).to_string(),
+            namespace: String::new(),
+        }))
+        .await
+        .expect("reads should still be allowed")
+        .into_inner();
+    assert!(
+        matches!(
+            response.result,
+            Some(proto::resolve_tag_response::Result::Ok(_))
+        ),
+        "the existing tag should still resolve"
+    );
+    tmprepo
+        .resolve_tag(&tag.to_spec(0))
+        .await
+        .expect("the existing tag should not have been removed");
+}
+
+#[rstest]
+#[case::rpc(tmprepo("rpc"))]
+#[tokio::test]
+async fn test_watch_tags_streams_matching_events(
+    #[case]
+    #[future]
+    tmprepo: TempRepo,
+) {
+    let tmprepo = tmprepo.await;
+    let storage::RepositoryHandle::Rpc(repo) = &*tmprepo else {
+        panic!("expected an rpc repository");
+    };
+    let mut events = repo
+        .watch_tags(RelativePath::new("spk/spec/maya"))
+        .await
+        .unwrap();
+
+    let unrelated = tracking::Tag::new(
+        Some("spk/spec/houdini".into()),
+        "1.0.0",
+        encoding::EMPTY_DIGEST.into(),
+    )
+    .unwrap();
+    repo.insert_tag(&unrelated).await.unwrap();
+    let tag = tracking::Tag::new(
+        Some("spk/spec/maya".into()),
+        "2024.0",
+        encoding::EMPTY_DIGEST.into(),
+    )
+    .unwrap();
+    repo.insert_tag(&tag).await.unwrap();
+    repo.remove_tag_stream(&tag.to_spec(0)).await.unwrap();
+
+    let created = tokio::time::timeout(Duration::from_secs(5), events.next())
+        .await
+        .expect("the created event should be received")
+        .expect("the stream should not end")
+        .unwrap();
+    assert_eq!(
+        created,
+        storage::TagEvent::Created(tag.clone()),
+        "only tags under the prefix should be reported"
+    );
+    let removed = tokio::time::timeout(Duration::from_secs(5), events.next())
+        .await
+        .expect("the removed event should be received")
+        .expect("the stream should not end")
+        .unwrap();
+    assert_eq!(removed, storage::TagEvent::StreamRemoved(tag.to_spec(0)));
+}
@@ -11,7 +11,6 @@ use std::pin::Pin;
 use async_stream::try_stream;
 use close_err::Closable;
 use futures::{Stream, TryStreamExt};
-use tokio::fs::DirEntry;
 use tokio::io::AsyncWriteExt;
 
 use crate::runtime::makedirs_with_perms;
@@ -26,6 +25,13 @@ mod hash_store_test;
 pub(crate) const PROXY_DIRNAME: &str = "proxy";
 const WORK_DIRNAME: &str = "work";
 
+/// See [`FsHashStore::shard_prefix_len`].
+const DEFAULT_SHARD_PREFIX_LEN: usize = 2;
+
+/// The characters used to encode digests, in the order of the
+/// values that they represent.
+const BASE32_ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
 pub(crate) enum PersistableObject {
     #[cfg(test)]
     EmptyFile,
@@ -42,6 +48,26 @@ pub struct FsHashStore {
     pub directory_permissions: u32,
     /// permissions used when creating new files
     pub file_permissions: u32,
+    shard_prefix_len: usize,
+    dirs: DirReader,
+}
+
+/// Reads the directories of an [`FsHashStore`].
+///
+/// In tests, every directory that is read is also recorded so that
+/// searches can be checked for how much of the store they touch.
+#[derive(Clone, Default)]
+struct DirReader {
+    #[cfg(test)]
+    read: std::sync::Arc<std::sync::Mutex<Vec<PathBuf>>>,
+}
+
+impl DirReader {
+    async fn read_dir(&self, path: &Path) -> std::io::Result<tokio::fs::ReadDir> {
+        #[cfg(test)]
+        self.read.lock().unwrap().push(path.to_owned());
+        tokio::fs::read_dir(path).await
+    }
 }
 
 impl FsHashStore {
@@ -60,9 +86,21 @@ impl FsHashStore {
             root: root.as_ref().to_path_buf(),
             directory_permissions: 0o777, // this is a shared store for all users
             file_permissions: 0o666,      // read+write is required to make hard links
+            shard_prefix_len: DEFAULT_SHARD_PREFIX_LEN,
+            dirs: DirReader::default(),
         }
     }
 
+    /// Use the given number of leading digest characters to name shard
+    /// directories, see [`Self::shard_prefix_len`].
+    ///
+    /// This must match the layout of any data already in the store, and
+    /// be shorter than an encoded digest.
+    pub fn with_shard_prefix_len(mut self, len: usize) -> Self {
+        self.shard_prefix_len = len;
+        self
+    }
+
     /// The folder where payloads are copied to have the expected ownership
     /// and permissions suitable for hard-linking into a render.
     pub fn proxydir(&self) -> PathBuf {
@@ -79,12 +117,59 @@ impl FsHashStore {
         self.root.join(WORK_DIRNAME)
     }
 
+    /// The number of leading characters of an encoded digest that are
+    /// used to name the shard directory that it is stored in.
+    ///
+    /// Defaults to 2.
+    pub fn shard_prefix_len(&self) -> usize {
+        self.shard_prefix_len
+    }
+
+    /// Return the only shard directory that can contain matches for the
+    /// given search, if the search is narrow enough to identify one.
+    fn shard_for_criteria(
+        &self,
+        search_criteria: &crate::graph::DigestSearchCriteria,
+    ) -> Option<String> {
+        match search_criteria {
+            crate::graph::DigestSearchCriteria::StartsWith(partial) => {
+                let encoded = partial.to_string();
+                // we can't trust that the encoded partial digest
+                // references a single shard unless it encodes
+                // to more characters than the shard name, because base 32
+                // may encode partial data to the final character
+                let len = self.shard_prefix_len;
+                (encoded.len() > len).then(|| encoded[..len].to_owned())
+            }
+            // ranges are read shard by shard, in digest order
+            crate::graph::DigestSearchCriteria::Range { .. } => None,
+            crate::graph::DigestSearchCriteria::All => None,
+        }
+    }
+
+    /// Return a key that sorts shard directory names in the same order
+    /// as the digests stored within them.
+    ///
+    /// The encoded characters do not sort in the same order as the values
+    /// that they represent, so names are compared by their position in the
+    /// encoding alphabet instead.
+    fn shard_sort_key(name: &str) -> Vec<usize> {
+        name.chars()
+            .map(|c| BASE32_ALPHABET.find(c).unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// True if the given digest bytes fall within the range.
+    fn in_range(bytes: &[u8], start: &[u8], end: &[u8]) -> bool {
+        bytes >= start && &bytes[..end.len().min(bytes.len())] <= end
+    }
+
     async fn find_in_entry(
+        dirs: &DirReader,
         search_criteria: crate::graph::DigestSearchCriteria,
-        entry: DirEntry,
+        entry_path: PathBuf,
+        entry_filename: String,
     ) -> Pin<Box<dyn Stream<Item = Result<encoding::Digest>> + Send + Sync + 'static>> {
-        let entry_filename = entry.file_name();
-        let entry_filename = entry_filename.to_string_lossy().into_owned();
         if entry_filename == WORK_DIRNAME || entry_filename == PROXY_DIRNAME {
             return Box::pin(futures::stream::empty());
         }
@@ -111,20 +196,38 @@ impl FsHashStore {
                 if entry_bytes.len() >= bytes.len() && !entry_bytes.starts_with(bytes) => {
                     return Box::pin(futures::stream::empty());
                 }
+            crate::graph::DigestSearchCriteria::Range { start, end } => {
+                // The directory name only decodes to the complete bytes
+                // that it encodes, and the partial byte in its final character
+                // is dropped, so it is only compared over the bytes that
+                // it shares with each end of the range. Entries at the
+                // boundaries are checked individually below.
+                let start_len = entry_bytes.len().min(start.len());
+                let end_len = entry_bytes.len().min(end.len());
+                if entry_bytes[..start_len] < start[..start_len]
+                    || entry_bytes[..end_len] > end[..end_len]
+                {
+                    return Box::pin(futures::stream::empty());
+                }
+            }
             _ => {}
         };
 
-        let mut subdir = match tokio::fs::read_dir(entry.path()).await {
+        let mut subdir = match dirs.read_dir(&entry_path).await {
             Err(err) => match err.os_error() {
                 Some(libc::ENOTDIR) => {
                     tracing::debug!(?entry_filename, "found non-directory in hash storage");
                     return Box::pin(futures::stream::empty());
                 }
+                // a shard that was looked up directly may not exist
+                _ if err.kind() == ErrorKind::NotFound => {
+                    return Box::pin(futures::stream::empty());
+                }
                 _ => {
                     return Box::pin(futures::stream::once(async move {
                         Err(Error::StorageReadError(
                             "read_dir on hash store entry",
-                            entry.path(),
+                            entry_path,
                             err,
                         ))
                     }));
@@ -134,7 +237,7 @@ impl FsHashStore {
         };
 
         Box::pin(try_stream! {
-            while let Some(name) = subdir.next_entry().await.map_err(|err| Error::StorageReadError("next_entry on hash store directory", entry.path(), err))? {
+            while let Some(name) = subdir.next_entry().await.map_err(|err| Error::StorageReadError("next_entry on hash store directory", entry_path.clone(), err))? {
                 let digest_str = format!("{entry_filename}{}", name.file_name().to_string_lossy());
                 if digest_str.ends_with(".completed") {
                     // We're operating on a renders store. These files used to be created
@@ -150,6 +253,12 @@ impl FsHashStore {
                             yield digest
                         }
                         crate::graph::DigestSearchCriteria::StartsWith(_) => continue,
+                        crate::graph::DigestSearchCriteria::Range { start, end }
+                            if Self::in_range(digest.as_bytes(), start, end) =>
+                        {
+                            yield digest
+                        }
+                        crate::graph::DigestSearchCriteria::Range { .. } => continue,
                         crate::graph::DigestSearchCriteria::All => yield digest,
                     },
                     Err(err) => {
@@ -163,34 +272,59 @@ impl FsHashStore {
         })
     }
 
+    /// Find the digests in this storage that match the given criteria.
+    ///
+    /// When the criteria identify a single shard directory, only that
+    /// directory is read rather than scanning every shard in the storage.
+    ///
+    /// Digests within a [`crate::graph::DigestSearchCriteria::Range`] are
+    /// yielded in digest order, reading one shard at a time and stopping
+    /// once the shards are beyond the end of the range, so that large
+    /// stores can be paged through. Otherwise, no order is guaranteed.
     pub fn find(
         &self,
         search_criteria: crate::graph::DigestSearchCriteria,
     ) -> impl Stream<Item = Result<encoding::Digest>> + use<> {
         // Don't capture self inside try_stream.
         let root = self.root.clone();
+        let dirs = self.dirs.clone();
+        let shard = self.shard_for_criteria(&search_criteria);
 
         try_stream! {
-            let mut root_entries = tokio::fs::read_dir(&root).await.map_err(|err| Error::StorageReadError("read_dir on hash store root", root.clone(), err))?;
-            while let Some(entry) = root_entries.next_entry().await.map_err(|err| Error::StorageReadError("next_entry on hash store root entry", root.clone(), err))? {
-                let entry_filename = entry.file_name();
-                let entry_filename = entry_filename.to_string_lossy();
-
-                let mut entry_stream = Self::find_in_entry(search_criteria.clone(), entry).await;
+            if let Some(shard) = shard {
+                let mut entry_stream = Self::find_in_entry(&dirs, search_criteria, root.join(&shard), shard).await;
                 while let Some(digest) = entry_stream.try_next().await? {
                     yield digest
                 }
-                drop(entry_stream);
-
-                if let crate::graph::DigestSearchCriteria::StartsWith(partial) = &search_criteria {
-                    let encoded = partial.to_string();
-                    // we can't trust that the encoded partial digest
-                    // references a single subdirectory unless it encodes
-                    // to more characters than the filename, because base 32
-                    // may encode partial data to the final character
-                    let must_be_in_this_folder = encoded.len() > entry_filename.len();
-                    if must_be_in_this_folder && encoded.starts_with(entry_filename.as_ref()) {
-                        break;
+            } else if let crate::graph::DigestSearchCriteria::Range { end, .. } = &search_criteria {
+                let mut shards = Vec::new();
+                let mut root_entries = dirs.read_dir(&root).await.map_err(|err| Error::StorageReadError("read_dir on hash store root", root.clone(), err))?;
+                while let Some(entry) = root_entries.next_entry().await.map_err(|err| Error::StorageReadError("next_entry on hash store root entry", root.clone(), err))? {
+                    let entry_filename = entry.file_name().to_string_lossy().into_owned();
+                    shards.push((Self::shard_sort_key(&entry_filename), entry_filename, entry.path()));
+                }
+                shards.sort();
+                for (_, entry_filename, entry_path) in shards {
+                    if let Ok(partial) = encoding::PartialDigest::parse(&entry_filename) {
+                        let len = partial.len().min(end.len());
+                        if partial[..len] > end[..len] {
+                            // all of the remaining shards are beyond the range
+                            break;
+                        }
+                    }
+                    let mut digests: Vec<_> = Self::find_in_entry(&dirs, search_criteria.clone(), entry_path, entry_filename).await.try_collect().await?;
+                    digests.sort();
+                    for digest in digests {
+                        yield digest
+                    }
+                }
+            } else {
+                let mut root_entries = dirs.read_dir(&root).await.map_err(|err| Error::StorageReadError("read_dir on hash store root", root.clone(), err))?;
+                while let Some(entry) = root_entries.next_entry().await.map_err(|err| Error::StorageReadError("next_entry on hash store root entry", root.clone(), err))? {
+                    let entry_filename = entry.file_name().to_string_lossy().into_owned();
+                    let mut entry_stream = Self::find_in_entry(&dirs, search_criteria.clone(), entry.path(), entry_filename).await;
+                    while let Some(digest) = entry_stream.try_next().await? {
+                        yield digest
                     }
                 }
             }
@@ -201,6 +335,84 @@ impl FsHashStore {
         self.find(crate::graph::DigestSearchCriteria::All)
     }
 
+    /// Count the entries in this storage.
+    ///
+    /// This is much faster than counting the digests from [`Self::iter`],
+    /// because the entries are tallied without being parsed. Invalid
+    /// entries are included in the count, and so it should be treated
+    /// as an estimate.
+    pub async fn count(&self) -> Result<usize> {
+        self.count_by_prefix("").await
+    }
+
+    /// Count the entries in this storage whose encoded digest starts
+    /// with the given prefix, as with [`Self::count`].
+    ///
+    /// When the prefix names a shard directory, only that directory is read.
+    pub async fn count_by_prefix(&self, prefix: &str) -> Result<usize> {
+        let shards = match prefix.get(..self.shard_prefix_len) {
+            Some(shard) => vec![shard.to_owned()],
+            None => {
+                let mut root_entries = match tokio::fs::read_dir(&self.root).await {
+                    Ok(entries) => entries,
+                    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+                    Err(err) => {
+                        return Err(Error::StorageReadError(
+                            "read_dir on hash store root",
+                            self.root.clone(),
+                            err,
+                        ));
+                    }
+                };
+                let mut shards = Vec::new();
+                while let Some(entry) = root_entries.next_entry().await.map_err(|err| {
+                    Error::StorageReadError("next_entry on hash store root", self.root.clone(), err)
+                })? {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if name == WORK_DIRNAME || name == PROXY_DIRNAME || !name.starts_with(prefix) {
+                        continue;
+                    }
+                    shards.push(name);
+                }
+                shards
+            }
+        };
+        let file_prefix = prefix.get(self.shard_prefix_len..).unwrap_or_default();
+
+        let mut count = 0;
+        for shard in shards {
+            let shard_path = self.root.join(shard);
+            let mut entries = match tokio::fs::read_dir(&shard_path).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) if err.os_error() == Some(libc::ENOTDIR) => continue,
+                Err(err) => {
+                    return Err(Error::StorageReadError(
+                        "read_dir on hash store entry",
+                        shard_path,
+                        err,
+                    ));
+                }
+            };
+            while let Some(entry) = entries.next_entry().await.map_err(|err| {
+                Error::StorageReadError(
+                    "next_entry on hash store directory",
+                    shard_path.clone(),
+                    err,
+                )
+            })? {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                // see find_in_entry
+                if name.ends_with(".completed") || !name.starts_with(file_prefix) {
+                    continue;
+                }
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
     /// Return true if the given digest is stored in this storage
     ///
     /// Upon error, false is returned
@@ -377,9 +589,43 @@ impl FsHashStore {
         Ok((digest, copied))
     }
 
+    /// Sync the directories of this storage to disk.
+    ///
+    /// New entries are moved into their shard directories by renaming,
+    /// so it is the directories themselves that must be synced for those
+    /// entries to be durable. Storage that has not been created yet is
+    /// skipped.
+    pub async fn sync_dirs(&self) -> Result<()> {
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => {
+                return Err(Error::StorageReadError(
+                    "read_dir on hash store root",
+                    self.root.clone(),
+                    err,
+                ));
+            }
+        };
+        while let Some(entry) = entries.next_entry().await.map_err(|err| {
+            Error::StorageReadError("next_entry on hash store root", self.root.clone(), err)
+        })? {
+            let path = entry.path();
+            let file_type = entry.file_type().await.map_err(|err| {
+                Error::StorageReadError("file_type on hash store entry", path.clone(), err)
+            })?;
+            if file_type.is_dir() {
+                sync_dir(&path).await?;
+            }
+        }
+        sync_dir(&self.root).await
+    }
+
     pub fn build_digest_path(&self, digest: &encoding::Digest) -> PathBuf {
         let digest_str = digest.to_string();
-        self.root.join(&digest_str[..2]).join(&digest_str[2..])
+        self.root
+            .join(&digest_str[..self.shard_prefix_len])
+            .join(&digest_str[self.shard_prefix_len..])
     }
 
     pub fn ensure_base_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
@@ -426,7 +672,10 @@ impl FsHashStore {
         partial: &encoding::PartialDigest,
     ) -> Result<PathBuf> {
         let short_digest = partial.to_string();
-        let (dirname, file_prefix) = (&short_digest[..2], &short_digest[2..]);
+        let (dirname, file_prefix) = (
+            &short_digest[..self.shard_prefix_len],
+            &short_digest[self.shard_prefix_len..],
+        );
         let dirpath = self.root.join(dirname);
         if short_digest.len() == encoding::DIGEST_SIZE {
             return Ok(dirpath.join(file_prefix));
@@ -531,3 +780,21 @@ impl FsHashStore {
         self.get_digest_from_path(path).await
     }
 }
+
+/// Sync the entries of a directory to disk.
+#[cfg(unix)]
+async fn sync_dir(path: &Path) -> Result<()> {
+    let dir = tokio::fs::File::open(path).await.map_err(|err| {
+        Error::StorageReadError("open hash store directory", path.to_owned(), err)
+    })?;
+    dir.sync_all().await.map_err(|err| {
+        Error::StorageWriteError("sync_all on hash store directory", path.to_owned(), err)
+    })
+}
+
+/// Directories cannot be opened to be synced on windows, so this
+/// is a no-op there.
+#[cfg(windows)]
+async fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
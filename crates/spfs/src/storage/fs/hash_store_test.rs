@@ -83,3 +83,133 @@ async fn test_hash_store_find_digest(tmpdir: tempfile::TempDir) {
         )
     }
 }
+
+#[rstest]
+// four characters decode to two complete bytes, so these bounds are exact
+#[case("ABCA", "BCDA", &["ABC", "ABD", "BBB", "BCD"])]
+// two characters only decode to a single complete byte, so the partial
+// byte in the final character is ignored at both ends of the range
+#[case("AB", "BB", &["AAA", "ABC", "ABD", "BBB", "BCD"])]
+#[case("CCCA", "CCCA", &["CCC"])]
+#[case("DDDA", "DDDA", &[])]
+#[tokio::test]
+async fn test_hash_store_find_range(
+    tmpdir: tempfile::TempDir,
+    #[case] start: &str,
+    #[case] end: &str,
+    #[case] expected: &[&str],
+) {
+    init_logging();
+    let store = super::FsHashStore::open(tmpdir.path()).unwrap();
+    let content = ["EEE", "BCD", "AAA", "CCC", "ABD", "BBB", "ABC"];
+    for s in content {
+        store
+            .persist_object_with_digest(PersistableObject::EmptyFile, digest!(s))
+            .await
+            .expect("persist digest file");
+    }
+
+    let matches: Vec<_> = store
+        .find(DigestSearchCriteria::Range {
+            start: crate::encoding::PartialDigest::parse(start).unwrap(),
+            end: crate::encoding::PartialDigest::parse(end).unwrap(),
+        })
+        .try_collect()
+        .await
+        .expect("should not fail to search");
+    let expected: Vec<_> = expected.iter().map(|s| digest!(s)).collect();
+    assert_eq!(
+        matches, expected,
+        "Using Range({start}, {end}), digests should be found in order"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_hash_store_find_reads_only_matching_shard(tmpdir: tempfile::TempDir) {
+    init_logging();
+    for shard_prefix_len in [2, 3] {
+        let root = tmpdir.path().join(shard_prefix_len.to_string());
+        let store = super::FsHashStore::open(&root)
+            .unwrap()
+            .with_shard_prefix_len(shard_prefix_len);
+        for s in ["AAAA", "ABCD", "BBBB"] {
+            store
+                .persist_object_with_digest(PersistableObject::EmptyFile, digest!(s))
+                .await
+                .expect("persist digest file");
+        }
+
+        let _: Vec<_> = store.iter().try_collect().await.unwrap();
+        assert!(
+            store.dirs.read.lock().unwrap().len() > 2,
+            "a full scan should read every shard"
+        );
+
+        store.dirs.read.lock().unwrap().clear();
+        let partial = crate::encoding::PartialDigest::parse("ABCD").expect("valid partial digest");
+        let matches: Vec<_> = store
+            .find(DigestSearchCriteria::StartsWith(partial))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(matches, vec![digest!("ABCD")]);
+        assert_eq!(
+            *store.dirs.read.lock().unwrap(),
+            vec![root.join(&"ABCD"[..shard_prefix_len])],
+            "a narrow search should only read the matching shard"
+        );
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_hash_store_count(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let store = super::FsHashStore::open(tmpdir.path()).unwrap();
+    assert_eq!(store.count().await.unwrap(), 0);
+
+    let content = ["AAA", "ABC", "ABD", "BBB", "BCD", "CCC", "EEE"];
+    for s in content {
+        store
+            .persist_object_with_digest(PersistableObject::EmptyFile, digest!(s))
+            .await
+            .expect("persist digest file");
+    }
+    // the working directory should not be counted
+    std::fs::create_dir_all(store.workdir()).unwrap();
+    std::fs::write(store.workdir().join("in-progress"), b"").unwrap();
+
+    assert_eq!(store.count().await.unwrap(), content.len());
+    for prefix in ["", "A", "AB", "ABC", "BB", "Z"] {
+        assert_eq!(
+            store.count_by_prefix(prefix).await.unwrap(),
+            content.iter().filter(|s| s.starts_with(prefix)).count(),
+            "Using prefix {prefix:?}"
+        );
+    }
+
+    let missing = super::FsHashStore::open_unchecked(tmpdir.path().join("missing"));
+    assert_eq!(missing.count().await.unwrap(), 0);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_hash_store_sync_dirs(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let store = super::FsHashStore::open(tmpdir.path()).unwrap();
+    store
+        .persist_object_with_digest(PersistableObject::EmptyFile, digest!("AAA"))
+        .await
+        .unwrap();
+    store
+        .sync_dirs()
+        .await
+        .expect("should sync a populated store");
+
+    let missing = super::FsHashStore::open_unchecked(tmpdir.path().join("missing"));
+    missing
+        .sync_dirs()
+        .await
+        .expect("should skip a store that was never created");
+}
@@ -221,6 +221,13 @@ impl FsRepository {
         }
     }
 
+    /// Sync the object and payload storage of this repository to disk.
+    ///
+    /// See [`OpenFsRepository::sync_to_disk`].
+    pub async fn sync_to_disk(&self) -> Result<()> {
+        self.opened().await?.sync_to_disk().await
+    }
+
     pub fn get_tag_namespace(&self) -> Option<Cow<'_, TagNamespace>> {
         match &**self.0.load() {
             InnerFsRepository::Open(repo) => repo
@@ -348,6 +355,15 @@ impl OpenFsRepository {
         self.root.clone()
     }
 
+    /// Sync the object and payload storage of this repository to disk.
+    ///
+    /// Once this completes, everything that has been written to the
+    /// repository so far will survive a crash or power loss.
+    pub async fn sync_to_disk(&self) -> Result<()> {
+        tokio::try_join!(self.objects.sync_dirs(), self.payloads.sync_dirs())?;
+        Ok(())
+    }
+
     /// Establish a new filesystem repository
     pub async fn create<P: AsRef<Path>>(root: P) -> OpenRepositoryResult<Self> {
         let root = root.as_ref();
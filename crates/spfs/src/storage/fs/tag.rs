@@ -153,7 +153,64 @@ impl FsRepository {
     }
 }
 
+impl FsRepository {
+    /// List every tag namespace that exists in this repository.
+    ///
+    /// See [`OpenFsRepository::list_tag_namespaces`].
+    pub async fn list_tag_namespaces(&self) -> Result<Vec<TagNamespaceBuf>> {
+        self.opened().await?.list_tag_namespaces().await
+    }
+}
+
 impl OpenFsRepository {
+    /// List every tag namespace that exists in this repository.
+    ///
+    /// Namespaces are found by scanning the root of the tag storage,
+    /// regardless of the namespace that this repository is configured
+    /// to use. Nested namespaces are listed by their full path, and
+    /// the results are sorted.
+    pub async fn list_tag_namespaces(&self) -> Result<Vec<TagNamespaceBuf>> {
+        let mut namespaces = Vec::new();
+        let mut to_scan = vec![(
+            relative_path::RelativePathBuf::new(),
+            self.tags_root_in_namespace(None),
+        )];
+        while let Some((namespace, dirpath)) = to_scan.pop() {
+            let mut entries = match tokio::fs::read_dir(&dirpath).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(Error::StorageReadError(
+                        "read_dir on tags path",
+                        dirpath,
+                        err,
+                    ));
+                }
+            };
+            while let Some(entry) = entries.next_entry().await.map_err(|err| {
+                Error::StorageReadError("entry of tags path", dirpath.clone(), err)
+            })? {
+                let is_dir = entry
+                    .file_type()
+                    .await
+                    .map(|ft| ft.is_dir())
+                    .unwrap_or(false);
+                if !is_dir {
+                    continue;
+                }
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                if let Some(name) = file_name.strip_suffix(TAG_NAMESPACE_MARKER) {
+                    let nested = namespace.join(name);
+                    namespaces.push(TagNamespaceBuf::new(&nested));
+                    to_scan.push((nested, entry.path()));
+                }
+            }
+        }
+        namespaces.sort_by(|a, b| a.as_rel_path().cmp(b.as_rel_path()));
+        Ok(namespaces)
+    }
+
     fn tags_root_in_namespace(&self, namespace: Option<&TagNamespace>) -> PathBuf {
         let mut tags_root = self.root().join("tags");
         if let Some(tag_namespace) = namespace {
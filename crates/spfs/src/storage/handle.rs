@@ -68,6 +68,17 @@ impl RepositoryHandle {
         }
     }
 
+    /// Remove any pin from this repository.
+    ///
+    /// The unpinned repository may be shared with other pinned
+    /// views of it, and so is returned behind an [`Arc`].
+    pub fn into_unpinned(self) -> Arc<Self> {
+        match self {
+            RepositoryHandle::Pinned(pinned) => pinned.into_inner(),
+            _ => Arc::new(self),
+        }
+    }
+
     pub fn try_as_tag_mut(&mut self) -> Result<&mut dyn TagStorageMut> {
         match self {
             RepositoryHandle::FS(repo) => Ok(repo),
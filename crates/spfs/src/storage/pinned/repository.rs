@@ -39,6 +39,11 @@ impl<T> PinnedRepository<T> {
     pub fn inner(&self) -> &Arc<T> {
         &self.inner
     }
+
+    /// Take the unpinned/unlimited repository that backs this one
+    pub fn into_inner(self) -> Arc<T> {
+        self.inner
+    }
 }
 
 impl<T> Clone for PinnedRepository<T> {
@@ -9,4 +9,5 @@ mod payload;
 mod repository;
 mod tag;
 
+pub use payload::{UNIX_SOCKET_SCHEME, unix_socket_url};
 pub use repository::{Config, Params, RpcRepository};
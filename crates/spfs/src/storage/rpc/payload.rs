@@ -3,6 +3,7 @@
 // https://github.com/spkenv/spk
 
 use std::convert::TryInto;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 
 use futures::{Stream, TryStreamExt};
@@ -12,6 +13,39 @@ use crate::proto::{self, RpcResult};
 use crate::tracking::BlobRead;
 use crate::{Error, Result, encoding, storage};
 
+#[cfg(test)]
+#[path = "./payload_test.rs"]
+mod payload_test;
+
+/// The url scheme for payload locations that are served over a unix socket.
+///
+/// The host of these urls is the percent-encoded path to the socket,
+/// eg: `unix://%2Frun%2Fspfs%2Fhttp.sock/`
+pub const UNIX_SOCKET_SCHEME: &str = "unix";
+
+/// Create the url of a payload server that listens on the given unix socket.
+pub fn unix_socket_url(socket: &Path) -> Result<url::Url> {
+    let socket = socket.to_str().ok_or_else(|| {
+        Error::String(format!(
+            "Unix socket path must be valid unicode: {socket:?}"
+        ))
+    })?;
+    let host = percent_encoding::utf8_percent_encode(socket, percent_encoding::NON_ALPHANUMERIC);
+    url::Url::parse(&format!("{UNIX_SOCKET_SCHEME}://{host}/"))
+        .map_err(|err| Error::String(format!("Invalid unix socket url: {err}")))
+}
+
+/// Get the path to the socket from a url created by [`unix_socket_url`].
+fn unix_socket_path(uri: &hyper::Uri) -> Result<PathBuf> {
+    let host = uri
+        .host()
+        .ok_or_else(|| Error::new(format!("missing socket path in request uri, got {uri}")))?;
+    let path = percent_encoding::percent_decode_str(host)
+        .decode_utf8()
+        .map_err(|err| Error::new(format!("invalid socket path in request uri {uri}: {err}")))?;
+    Ok(PathBuf::from(path.as_ref()))
+}
+
 #[async_trait::async_trait]
 impl storage::PayloadStorage for super::RpcRepository {
     async fn has_payload(&self, digest: encoding::Digest) -> bool {
@@ -64,12 +98,7 @@ impl storage::PayloadStorage for super::RpcRepository {
             })?;
         let resp = self.send_http_request(request).await?;
         if !resp.status().is_success() {
-            // the server is expected to return all errors via the gRPC message
-            // payload in the body. Any other status code is unexpected
-            return Err(crate::Error::String(format!(
-                "Unexpected status code from payload server: {}",
-                resp.status()
-            )));
+            return Err(error_from_response(resp, None).await);
         }
         let stream = http_body_util::BodyDataStream::new(resp.into_body());
         let bytes = stream
@@ -114,12 +143,7 @@ impl storage::PayloadStorage for super::RpcRepository {
             })?;
         let resp = self.send_http_request(req).await?;
         if !resp.status().is_success() {
-            // the server is expected to return all errors via the gRPC message
-            // payload in the body. Any other status code is unexpected
-            return Err(crate::Error::String(format!(
-                "Unexpected status code from payload server: {}",
-                resp.status()
-            )));
+            return Err(error_from_response(resp, Some(digest)).await);
         }
         let stream = open_download_stream(resp)?;
         Ok((stream, url_str.into()))
@@ -149,6 +173,9 @@ impl super::RpcRepository {
         B::Data: Send + Sync,
         B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
     {
+        if request.uri().scheme_str() == Some(UNIX_SOCKET_SCHEME) {
+            return self.send_http_request_over_unix_socket(request).await;
+        }
         let host = request.uri().host().ok_or_else(|| {
             Error::new(format!(
                 "missing valid host in request uri, got {}",
@@ -161,6 +188,58 @@ impl super::RpcRepository {
         let stream = tokio::net::TcpStream::connect(address)
             .await
             .map_err(|err| Error::new(format!("failed to connect to remote repository: {err}")))?;
+        self.send_http_request_over(stream, request).await
+    }
+
+    #[cfg(unix)]
+    async fn send_http_request_over_unix_socket<B>(
+        &self,
+        mut request: hyper::Request<B>,
+    ) -> Result<hyper::Response<hyper::body::Incoming>>
+    where
+        B: hyper::body::Body + Send + Sync + 'static,
+        B::Data: Send + Sync,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let socket = unix_socket_path(request.uri())?;
+        tracing::trace!("Connecting to remote repository at {socket:?}");
+        let stream = tokio::net::UnixStream::connect(&socket)
+            .await
+            .map_err(|err| Error::new(format!("failed to connect to remote repository: {err}")))?;
+        // the socket path means nothing to the server, so
+        // only the path of the request is sent along
+        let path = match request.uri().path_and_query() {
+            Some(path) => path.as_str().to_owned(),
+            None => "/".to_owned(),
+        };
+        *request.uri_mut() = path
+            .parse()
+            .map_err(|err| Error::new(format!("invalid request path {path}: {err}")))?;
+        self.send_http_request_over(stream, request).await
+    }
+
+    #[cfg(not(unix))]
+    async fn send_http_request_over_unix_socket<B>(
+        &self,
+        request: hyper::Request<B>,
+    ) -> Result<hyper::Response<hyper::body::Incoming>> {
+        Err(Error::new(format!(
+            "unix sockets are not supported on this platform, got {}",
+            request.uri()
+        )))
+    }
+
+    async fn send_http_request_over<S, B>(
+        &self,
+        stream: S,
+        request: hyper::Request<B>,
+    ) -> Result<hyper::Response<hyper::body::Incoming>>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+        B: hyper::body::Body + Send + Sync + 'static,
+        B::Data: Send + Sync,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
         let io = hyper_util::rt::TokioIo::new(stream);
         let (mut sender, conn) = self.http_client.handshake(io).await.map_err(|err| {
             Error::new(format!(
@@ -175,6 +254,31 @@ impl super::RpcRepository {
     }
 }
 
+/// Convert an unsuccessful response from the payload server into an error.
+///
+/// The server describes errors with a json [`proto::HttpError`] body. A
+/// missing payload is reported as [`Error::UnknownObject`] when the digest
+/// that was requested is given.
+async fn error_from_response(
+    resp: hyper::http::Response<hyper::body::Incoming>,
+    digest: Option<encoding::Digest>,
+) -> Error {
+    let status = resp.status();
+    let body = http_body_util::BodyExt::collect(resp.into_body())
+        .await
+        .map(|body| body.to_bytes())
+        .unwrap_or_default();
+    match (proto::HttpError::from_body(&body), digest) {
+        (Some(err), Some(digest)) if err.code == proto::HttpErrorCode::NotFound => {
+            Error::UnknownObject(digest)
+        }
+        (Some(err), _) => Error::String(format!("Payload server returned an error: {err}")),
+        (None, _) => Error::String(format!(
+            "Unexpected status code from payload server: {status}"
+        )),
+    }
+}
+
 fn open_download_stream(
     mut resp: hyper::http::Response<hyper::body::Incoming>,
 ) -> Result<Pin<Box<dyn BlobRead>>> {
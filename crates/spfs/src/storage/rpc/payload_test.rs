@@ -0,0 +1,22 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use std::path::Path;
+
+use rstest::rstest;
+
+use super::{unix_socket_path, unix_socket_url};
+
+#[rstest]
+fn test_unix_socket_url_round_trip() {
+    let socket = Path::new("/run/spfs/http-server.sock");
+    let mut url = unix_socket_url(socket).unwrap();
+    assert_eq!(url.scheme(), super::UNIX_SOCKET_SCHEME);
+
+    // payload locations are made by pushing the digest onto the url
+    url.path_segments_mut().unwrap().push("DIGEST");
+    let uri: hyper::Uri = url.as_str().parse().unwrap();
+    assert_eq!(unix_socket_path(&uri).unwrap(), socket);
+    assert_eq!(uri.path(), "/DIGEST");
+}
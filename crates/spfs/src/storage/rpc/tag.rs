@@ -211,3 +211,33 @@ async fn read_tag(
         .collect();
     Ok(Box::pin(futures::stream::iter(items?.into_iter().map(Ok))))
 }
+
+impl super::RpcRepository {
+    /// Watch for changes to the tags at or under the given path prefix,
+    /// within the tag namespace of this repository.
+    ///
+    /// The watch is established before this returns, so any change
+    /// made afterwards is reported on the returned stream.
+    pub async fn watch_tags(
+        &self,
+        prefix: &RelativePath,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<storage::TagEvent>> + Send>>> {
+        let request = proto::WatchTagsRequest {
+            prefix: prefix.to_string(),
+            namespace: self
+                .tag_namespace()
+                .map(|p| p.to_string())
+                .unwrap_or_default(),
+        };
+        let stream = self
+            .tag_client
+            .clone()
+            .watch_tags(request)
+            .await?
+            .into_inner()
+            .map_err(crate::Error::from)
+            .and_then(|r| async { r.to_result() })
+            .and_then(|event| async { event.try_into() });
+        Ok(Box::pin(stream))
+    }
+}
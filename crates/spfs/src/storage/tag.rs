@@ -29,6 +29,32 @@ pub enum EntryType {
     Tag(String),
 }
 
+/// A change made to the tags of a repository.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TagEvent {
+    /// A tag was inserted into its stream
+    Created(tracking::Tag),
+    /// A single tag was removed from its stream
+    Removed(tracking::Tag),
+    /// An entire tag stream was removed
+    StreamRemoved(tracking::TagSpec),
+}
+
+impl TagEvent {
+    /// The path of the tag stream that was changed.
+    pub fn path(&self) -> relative_path::RelativePathBuf {
+        match self {
+            Self::Created(tag) | Self::Removed(tag) => tag.path().into(),
+            Self::StreamRemoved(spec) => spec.path(),
+        }
+    }
+
+    /// True if the changed tag is at or under the given path prefix.
+    pub fn matches_prefix(&self, prefix: &RelativePath) -> bool {
+        self.path().starts_with(prefix)
+    }
+}
+
 impl AsRef<str> for EntryType {
     fn as_ref(&self) -> &str {
         match self {
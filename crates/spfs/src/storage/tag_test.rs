@@ -365,6 +365,44 @@ async fn test_tag_in_namespace(
     assert_eq!(tags, vec![EntryType::Namespace(namespace_name.to_string())]);
 }
 
+#[rstest]
+#[case::fs(tmprepo("fs"))]
+#[tokio::test]
+async fn test_list_tag_namespaces(
+    #[case]
+    #[future]
+    tmprepo: TempRepo,
+) {
+    init_logging();
+    let tmprepo = tmprepo.await;
+
+    let spec = tracking::TagSpec::parse("a-tag").unwrap();
+    tmprepo
+        .push_tag(&spec, &encoding::EMPTY_DIGEST.into())
+        .await
+        .unwrap();
+    for namespace in ["foo", "foo/bar", "other"] {
+        tmprepo
+            .with_tag_namespace(namespace)
+            .await
+            .push_tag(&spec, &encoding::EMPTY_DIGEST.into())
+            .await
+            .unwrap();
+    }
+
+    let crate::storage::RepositoryHandle::FS(repo) = &*tmprepo else {
+        panic!("expected an fs repository");
+    };
+    let namespaces = repo.list_tag_namespaces().await.unwrap();
+    assert_eq!(
+        namespaces
+            .iter()
+            .map(|ns| ns.to_string())
+            .collect::<Vec<_>>(),
+        vec!["foo", "foo/bar", "other"]
+    );
+}
+
 #[rstest]
 #[case::fs(tmprepo("fs"))]
 #[tokio::test]
@@ -6,4 +6,4 @@
 //! into a tar archive on disk
 
 mod repository;
-pub use repository::{Config, TarRepository};
+pub use repository::{ArchiveCompression, Config, TarRepository, decompress_archive};
@@ -3,7 +3,7 @@
 // https://github.com/spkenv/spk
 
 use std::borrow::Cow;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::Path;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -59,6 +59,59 @@ impl storage::FromUrl for Config {
     }
 }
 
+/// The compression applied to a tar repository archive.
+///
+/// The compression of an existing archive is detected when it is
+/// opened, so archives can be read without knowing how they were written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArchiveCompression {
+    /// A plain, uncompressed tar archive
+    #[default]
+    None,
+    /// A gzip compressed tar archive
+    Gzip,
+    /// A zstd compressed tar archive, with the given compression level
+    Zstd { level: i32 },
+}
+
+impl ArchiveCompression {
+    const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &'static [u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    /// Identify the compression of an archive from its first bytes.
+    ///
+    /// When detecting a zstd archive, the level cannot be known and
+    /// the default zstd level is assumed.
+    pub fn detect(header: &[u8]) -> Self {
+        if header.starts_with(Self::GZIP_MAGIC) {
+            Self::Gzip
+        } else if header.starts_with(Self::ZSTD_MAGIC) {
+            Self::Zstd {
+                level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            }
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Wrap the given archive reader so that it yields the uncompressed tar
+/// stream, detecting the compression that was used from its first bytes.
+pub fn decompress_archive<'a, R>(
+    mut reader: R,
+) -> std::io::Result<(ArchiveCompression, Box<dyn Read + Send + 'a>)>
+where
+    R: BufRead + Send + 'a,
+{
+    let compression = ArchiveCompression::detect(reader.fill_buf()?);
+    let reader: Box<dyn Read + Send + 'a> = match compression {
+        ArchiveCompression::None => Box::new(reader),
+        ArchiveCompression::Gzip => Box::new(flate2::bufread::GzDecoder::new(reader)),
+        ArchiveCompression::Zstd { .. } => Box::new(zstd::Decoder::with_buffer(reader)?),
+    };
+    Ok((compression, reader))
+}
+
 /// An spfs repository in a tarball.
 ///
 /// Tarball repos are unpacked to a temporary directory on creation
@@ -67,6 +120,7 @@ impl storage::FromUrl for Config {
 pub struct TarRepository {
     up_to_date: AtomicBool,
     archive: std::path::PathBuf,
+    compression: ArchiveCompression,
     repo_dir: tempfile::TempDir,
     repo: crate::storage::fs::FsRepository,
 }
@@ -112,6 +166,22 @@ impl TarRepository {
         Self::open(path).await
     }
 
+    /// Set the compression used when this repository is written back
+    /// to its archive.
+    ///
+    /// An opened archive keeps the compression that it was written
+    /// with unless this is changed.
+    pub fn with_compression(mut self, compression: ArchiveCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// The compression used when this repository is written back
+    /// to its archive.
+    pub fn compression(&self) -> ArchiveCompression {
+        self.compression
+    }
+
     /// Remove the top-level durable directory, assuming it is empty.
     /// This is used when exporting packages from another repo via a
     /// tar repo, and you do not want to include any durable runtime
@@ -133,13 +203,19 @@ impl TarRepository {
                 source,
             }
         })?;
-        let mut file = BufReader::new(std::fs::File::open(&path).map_err(|source| {
+        let file = BufReader::new(std::fs::File::open(&path).map_err(|source| {
             OpenRepositoryError::FailedToOpenArchive {
                 path: path.clone(),
                 source,
             }
         })?);
-        let mut archive = Archive::new(&mut file);
+        let (compression, reader) = decompress_archive(file).map_err(|source| {
+            OpenRepositoryError::FailedToOpenArchive {
+                path: path.clone(),
+                source,
+            }
+        })?;
+        let mut archive = Archive::new(reader);
         let tmpdir = tempfile::Builder::new()
             .prefix("spfs-tar-repo")
             .tempdir()
@@ -157,13 +233,14 @@ impl TarRepository {
         Ok(Self {
             up_to_date: AtomicBool::new(false),
             archive: path,
+            compression,
             repo_dir: tmpdir,
             repo: crate::storage::fs::FsRepository::create(&repo_path).await?,
         })
     }
 
     pub fn flush(&self) -> Result<()> {
-        let mut file = std::fs::OpenOptions::new()
+        let file = std::fs::OpenOptions::new()
             .write(true)
             .truncate(true)
             .open(&self.archive)
@@ -174,29 +251,103 @@ impl TarRepository {
                     err,
                 )
             })?;
-        let mut builder = Builder::new(&mut file);
-        builder
-            .append_dir_all(".", self.repo_dir.path())
-            .map_err(|err| {
-                Error::StorageWriteError(
-                    "append_all_dir on tar repository builder",
-                    self.archive.clone(),
-                    err,
-                )
-            })?;
-        builder.finish().map_err(|err| {
+        match self.compression {
+            ArchiveCompression::None => {
+                self.write_archive(file)?;
+            }
+            ArchiveCompression::Gzip => {
+                let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+                self.write_archive(encoder)?.finish().map_err(|err| {
+                    Error::StorageWriteError(
+                        "finish gzip compression of tar repository",
+                        self.archive.clone(),
+                        err,
+                    )
+                })?;
+            }
+            ArchiveCompression::Zstd { level } => {
+                let encoder = zstd::Encoder::new(file, level).map_err(|err| {
+                    Error::StorageWriteError(
+                        "create zstd encoder for tar repository",
+                        self.archive.clone(),
+                        err,
+                    )
+                })?;
+                self.write_archive(encoder)?.finish().map_err(|err| {
+                    Error::StorageWriteError(
+                        "finish zstd compression of tar repository",
+                        self.archive.clone(),
+                        err,
+                    )
+                })?;
+            }
+        }
+        self.up_to_date
+            .store(true, std::sync::atomic::Ordering::Release);
+        Ok(())
+    }
+
+    /// Write the contents of this repository as a tar stream to the
+    /// given writer, returning the writer once the archive is complete.
+    fn write_archive<W: Write>(&self, writer: W) -> Result<W> {
+        let mut builder = Builder::new(writer);
+        append_dir_sorted(&mut builder, self.repo_dir.path(), Path::new(".")).map_err(|err| {
             Error::StorageWriteError(
-                "finish on tar repository builder in flush",
+                "append sorted dir on tar repository builder",
                 self.archive.clone(),
                 err,
             )
         })?;
-        self.up_to_date
-            .store(true, std::sync::atomic::Ordering::Release);
-        Ok(())
+        builder.into_inner().map_err(|err| {
+            Error::StorageWriteError(
+                "finish on tar repository builder in flush",
+                self.archive.clone(),
+                err,
+            )
+        })
     }
 }
 
+/// Append the contents of a directory to an archive.
+///
+/// Unlike [`Builder::append_dir_all`], entries are written in sorted order
+/// and without timestamps or ownership so that the same repository contents
+/// always produce a byte-identical archive.
+fn append_dir_sorted<W: std::io::Write>(
+    builder: &mut Builder<W>,
+    root: &Path,
+    path: &Path,
+) -> std::io::Result<()> {
+    let full_path = root.join(path);
+    let metadata = std::fs::metadata(&full_path)?;
+    let mut header = deterministic_header(&metadata);
+    if !metadata.is_dir() {
+        let file = std::fs::File::open(&full_path)?;
+        return builder.append_data(&mut header, path, file);
+    }
+    builder.append_data(&mut header, path, std::io::empty())?;
+
+    let mut entries = std::fs::read_dir(&full_path)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        append_dir_sorted(builder, root, &path.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+fn deterministic_header(metadata: &std::fs::Metadata) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(metadata, tar::HeaderMode::Deterministic);
+    // the deterministic mode normalizes permissions, but the stored
+    // permissions of payloads are meaningful and must be kept
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        header.set_mode(metadata.permissions().mode() & 0o7777);
+    }
+    header
+}
+
 impl Drop for TarRepository {
     fn drop(&mut self) {
         if self.up_to_date.load(Ordering::Acquire) {
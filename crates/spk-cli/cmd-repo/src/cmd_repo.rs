@@ -44,18 +44,30 @@ pub enum RepoCommand {
         /// The repository to upgrade (name or path or url)
         #[clap(name = "REPO")]
         repo: String,
+
+        /// Report the changes that the upgrade would make, without making them
+        #[clap(long)]
+        dry_run: bool,
     },
 }
 
 impl RepoCommand {
     pub async fn run(&mut self) -> Result<i32> {
-        let repo = match &self {
-            Self::Upgrade { repo } => repo,
+        let (repo, dry_run) = match &self {
+            Self::Upgrade { repo, dry_run } => (repo, *dry_run),
         };
         let repo = match repo.as_str() {
             "local" => storage::local_repository().await?,
             _ => storage::remote_repository(repo).await?,
         };
+        if dry_run {
+            let plan = repo
+                .upgrade_plan()
+                .await
+                .wrap_err("Failed to plan upgrade")?;
+            tracing::info!("{}", plan);
+            return Ok(0);
+        }
         let status = repo.upgrade().await.wrap_err("Upgrade failed")?;
         tracing::info!("{}", status);
         Ok(1)
@@ -16,7 +16,15 @@ use spk_schema::foundation::option_map::{HOST_OPTIONS, OptionMap};
 use spk_schema::prelude::*;
 use spk_schema::{Recipe, Request, TestStage};
 
-use crate::test::{PackageBuildTester, PackageInstallTester, PackageSourceTester, Tester};
+use crate::test::{
+    PackageBuildTester,
+    PackageInstallTester,
+    PackageSourceTester,
+    TestOutcome,
+    Tester,
+    TesterOptions,
+    summarize_reports,
+};
 
 #[cfg(test)]
 #[path = "./cmd_test_test.rs"]
@@ -48,6 +56,21 @@ pub struct CmdTest {
     #[clap(long)]
     here: bool,
 
+    /// Stop any test script that runs for longer than this many seconds
+    #[clap(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// Only run the tests at these positions within each stage
+    ///
+    /// Tests are numbered from zero, in the order that they are
+    /// selected for each stage and variant.
+    #[clap(long = "test-index", value_name = "INDEX")]
+    test_indices: Vec<usize>,
+
+    /// Keep running the remaining tests after a test fails
+    #[clap(long)]
+    no_fail_fast: bool,
+
     /// The package(s) to test
     ///
     /// This can be a file name or `<name>/<version>` of an existing package
@@ -78,6 +101,7 @@ impl Run for CmdTest {
         let mut workspace = self.workspace.load_or_default()?;
 
         let source = if self.here { Some(".".into()) } else { None };
+        let timeout = self.timeout.map(std::time::Duration::from_secs);
 
         let opt_host_options =
             (!self.options.no_host).then(|| HOST_OPTIONS.get().unwrap_or_default());
@@ -91,16 +115,16 @@ impl Run for CmdTest {
             .map(Request::Var)
             .collect();
 
+        let mut reports = Vec::new();
         for package in &self.packages {
-            let (name, stages) = match package.split_once('@') {
+            // tests of the stages that were not asked for are
+            // reported as skipped rather than left out entirely
+            let (name, stage_filter) = match package.split_once('@') {
                 Some((name, stage)) => {
                     let stage = TestStage::from_str(stage)?;
                     (name.to_string(), vec![stage])
                 }
-                None => {
-                    let stages = vec![TestStage::Sources, TestStage::Build, TestStage::Install];
-                    (package.to_string(), stages)
-                }
+                None => (package.to_string(), Vec::new()),
             };
 
             let (spec_data, filename) = flags::find_package_recipe_from_workspace_or_repo(
@@ -117,7 +141,7 @@ impl Run for CmdTest {
                 )
             })?;
 
-            for stage in stages {
+            for stage in [TestStage::Sources, TestStage::Build, TestStage::Install] {
                 tracing::info!("Testing {}@{stage}...", filename.display());
 
                 let default_variants = recipe.default_variants(&options);
@@ -167,6 +191,16 @@ impl Run for CmdTest {
                         let install_formatter =
                             builder.with_header("Install Env Resolver ").build();
 
+                        let mut tester_options = TesterOptions::new();
+                        tester_options
+                            .with_timeout(timeout)
+                            .with_workdir(test.workdir())
+                            .with_env(test.environment())
+                            .with_index(index)
+                            .with_stage_filter(stage_filter.clone())
+                            .with_test_indices(self.test_indices.clone())
+                            .with_fail_fast(!self.no_fail_fast);
+
                         let mut tester: Box<dyn Tester> = match stage {
                             TestStage::Sources => {
                                 let mut tester =
@@ -174,6 +208,7 @@ impl Run for CmdTest {
 
                                 tester
                                     .with_options(variant.options().into_owned())
+                                    .with_tester_options(tester_options)
                                     .with_repositories(repos.iter().cloned())
                                     .with_requirements(test.additional_requirements())
                                     .with_source(source.clone())
@@ -188,6 +223,7 @@ impl Run for CmdTest {
 
                                 tester
                                     .with_options(variant.options().into_owned())
+                                    .with_tester_options(tester_options)
                                     .with_repositories(repos.iter().cloned())
                                     .with_requirements(
                                         variant
@@ -223,6 +259,7 @@ impl Run for CmdTest {
 
                                 tester
                                     .with_options(variant.options().into_owned())
+                                    .with_tester_options(tester_options)
                                     .with_repositories(repos.iter().cloned())
                                     .with_requirements(test.additional_requirements())
                                     .with_requirements(options_reqs.clone())
@@ -233,16 +270,32 @@ impl Run for CmdTest {
                             }
                         };
 
-                        tracing::info!(
-                            variant=%variant.options().format_option_map(),
-                            "Running selected test #{index}",
-                        );
+                        if tester.is_selected() {
+                            tracing::info!(
+                                variant=%variant.options().format_option_map(),
+                                "Running selected test #{index}",
+                            );
+                        }
 
-                        tester.test().await?
+                        reports.extend(tester.test().await?);
                     }
                 }
             }
         }
+        tracing::info!("Test results: {}", summarize_reports(&reports));
+        for report in reports.iter() {
+            if let TestOutcome::Failed { reason } = &report.outcome {
+                tracing::error!(
+                    "{}@{} test #{} failed: {reason}",
+                    report.package,
+                    report.stage,
+                    report.index
+                );
+            }
+        }
+        if reports.iter().any(|r| r.is_failed()) {
+            return Ok(1);
+        }
         Ok(0)
     }
 }
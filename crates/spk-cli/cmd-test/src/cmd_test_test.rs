@@ -497,3 +497,321 @@ tests:
         .await
         .expect_err("the test run should fail, otherwise the selectors aren't working properly");
 }
+
+#[rstest]
+#[tokio::test]
+async fn test_hanging_test_script_times_out(tmpdir: tempfile::TempDir) {
+    let _rt = spfs_runtime().await;
+
+    let filename_str = build_package!(
+        tmpdir,
+        "simple.spk.yaml",
+        br#"
+pkg: simple/1.0.0
+build:
+  script:
+    - "true"
+
+tests:
+  - stage: install
+    script:
+      - echo "waiting for a license"
+      - sleep 600
+"#
+    );
+
+    let mut opt = TestOpt::try_parse_from([
+        "test",
+        // Don't exec a new process to move into a new runtime, this confuses
+        // coverage testing.
+        "--no-runtime",
+        "--disable-repo=origin",
+        "--timeout=1",
+        filename_str,
+    ])
+    .unwrap();
+    let err = opt
+        .test
+        .run()
+        .await
+        .expect_err("a hanging test script should time out");
+    match err.downcast_ref::<spk_cli_common::Error>() {
+        Some(spk_cli_common::Error::TestTimeout {
+            stage,
+            elapsed,
+            stdout,
+            ..
+        }) => {
+            assert_eq!(*stage, spk_schema::TestStage::Install);
+            assert!(*elapsed >= std::time::Duration::from_secs(1));
+            assert!(
+                stdout.contains("waiting for a license"),
+                "partial output should be captured, got: {stdout:?}"
+            );
+        }
+        _ => panic!("expected a test timeout error, got: {err:?}"),
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_test_script_uses_workdir_and_env(tmpdir: tempfile::TempDir) {
+    let _rt = spfs_runtime().await;
+
+    // Install tests run from the current directory, which is
+    // the root of this crate when running under cargo.
+    let filename_str = build_package!(
+        tmpdir,
+        "simple.spk.yaml",
+        br#"
+pkg: simple/1.0.0
+build:
+  script:
+    - "true"
+
+tests:
+  - stage: install
+    workdir: src
+    env:
+      MY_TEST_VAR: my-value
+    script:
+      - test -f cmd_test.rs
+      - test "$MY_TEST_VAR" = my-value
+"#
+    );
+
+    let mut opt = TestOpt::try_parse_from([
+        "test",
+        // Don't exec a new process to move into a new runtime, this confuses
+        // coverage testing.
+        "--no-runtime",
+        "--disable-repo=origin",
+        filename_str,
+    ])
+    .unwrap();
+    opt.test
+        .run()
+        .await
+        .expect("test script should run in the workdir with the extra env");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_only_selected_test_indices_run(tmpdir: tempfile::TempDir) {
+    let _rt = spfs_runtime().await;
+
+    let filename_str = build_package!(
+        tmpdir,
+        "simple.spk.yaml",
+        br#"
+pkg: simple/1.0.0
+build:
+  script:
+    - "true"
+
+tests:
+  - stage: install
+    script:
+      - "false"
+  - stage: install
+    script:
+      - "true"
+"#
+    );
+
+    let mut opt = TestOpt::try_parse_from([
+        "test",
+        // Don't exec a new process to move into a new runtime, this confuses
+        // coverage testing.
+        "--no-runtime",
+        "--disable-repo=origin",
+        "--test-index=1",
+        filename_str,
+    ])
+    .unwrap();
+    opt.test
+        .run()
+        .await
+        .expect("the failing test should not have been run");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_no_fail_fast_runs_all_tests(tmpdir: tempfile::TempDir) {
+    let _rt = spfs_runtime().await;
+
+    let marker = tmpdir.path().join("second-test-ran");
+    let spec = format!(
+        r#"
+pkg: simple/1.0.0
+build:
+  script:
+    - "true"
+
+tests:
+  - stage: install
+    script:
+      - "false"
+  - stage: install
+    script:
+      - touch {}
+"#,
+        marker.display()
+    );
+    let filename_str = build_package!(tmpdir, "simple.spk.yaml", spec);
+
+    let mut opt = TestOpt::try_parse_from([
+        "test",
+        // Don't exec a new process to move into a new runtime, this confuses
+        // coverage testing.
+        "--no-runtime",
+        "--disable-repo=origin",
+        "--no-fail-fast",
+        filename_str,
+    ])
+    .unwrap();
+    let code = opt
+        .test
+        .run()
+        .await
+        .expect("a failing test should be reported, not returned");
+    assert_eq!(
+        code, 1,
+        "a failing test should produce a non-zero exit code"
+    );
+    assert!(marker.exists(), "the second test should still have run");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_only_requested_stage_runs(tmpdir: tempfile::TempDir) {
+    let _rt = spfs_runtime().await;
+
+    let filename_str = build_package!(
+        tmpdir,
+        "simple.spk.yaml",
+        br#"
+pkg: simple/1.0.0
+build:
+  script:
+    - "true"
+
+tests:
+  - stage: sources
+    script:
+      - "false"
+  - stage: install
+    script:
+      - "true"
+"#
+    );
+
+    let package = format!("{filename_str}@install");
+    let mut opt = TestOpt::try_parse_from([
+        "test",
+        // Don't exec a new process to move into a new runtime, this confuses
+        // coverage testing.
+        "--no-runtime",
+        "--disable-repo=origin",
+        package.as_str(),
+    ])
+    .unwrap();
+    let code = opt
+        .test
+        .run()
+        .await
+        .expect("the failing sources test should not have been run");
+    assert_eq!(code, 0);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_selectors_match_default_option_values(tmpdir: tempfile::TempDir) {
+    let _rt = spfs_runtime().await;
+
+    let filename_str = build_package!(
+        tmpdir,
+        "simple.spk.yaml",
+        br#"
+pkg: simple/1.0.0
+build:
+  options:
+    - var: debug/off
+  script:
+    - "true"
+
+tests:
+  - stage: build
+    selectors:
+      - { debug: "on" }
+    script:
+      - "true"
+  - stage: build
+    selectors:
+      - { debug: "off" }
+    script:
+      # The variant never sets debug, so this selector only matches
+      # the option's default value. If the whole test run fails we
+      # know that this test was run.
+      - "false"
+"#
+    );
+
+    let mut opt = TestOpt::try_parse_from([
+        "test",
+        // Don't exec a new process to move into a new runtime, this confuses
+        // coverage testing.
+        "--no-runtime",
+        "--disable-repo=origin",
+        filename_str,
+    ])
+    .unwrap();
+    opt.test
+        .run()
+        .await
+        .expect_err("the test run should fail, otherwise the default option value was not matched");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_install_test_has_test_component_payloads(tmpdir: tempfile::TempDir) {
+    let _rt = spfs_runtime().await;
+
+    let filename_str = build_package!(
+        tmpdir,
+        "simple.spk.yaml",
+        br#"
+pkg: simple/1.0.0
+build:
+  script:
+    - touch "$PREFIX"/runfile
+    - touch "$PREFIX"/testfile
+
+install:
+  components:
+    - name: run
+      files:
+        - runfile
+    - name: test
+      files:
+        - testfile
+
+tests:
+  - stage: install
+    script:
+      - test -f "$PREFIX"/runfile
+      - test -f "$PREFIX"/testfile
+"#
+    );
+
+    let mut opt = TestOpt::try_parse_from([
+        "test",
+        // Don't exec a new process to move into a new runtime, this confuses
+        // coverage testing.
+        "--no-runtime",
+        "--disable-repo=origin",
+        filename_str,
+    ])
+    .unwrap();
+    opt.test.run().await.unwrap();
+}
@@ -4,4 +4,4 @@
 
 pub mod cmd_test;
 
-mod test;
+pub mod test;
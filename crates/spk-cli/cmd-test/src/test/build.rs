@@ -13,12 +13,12 @@ use spk_schema::foundation::ident_build::Build;
 use spk_schema::foundation::ident_component::Component;
 use spk_schema::foundation::option_map::OptionMap;
 use spk_schema::ident::{PkgRequest, PreReleasePolicy, RangeIdent, Request, RequestedBy};
-use spk_schema::{AnyIdent, Recipe, SpecRecipe};
+use spk_schema::{AnyIdent, Recipe, SpecRecipe, TestStage, VersionIdent};
 use spk_solve::solution::Solution;
 use spk_solve::{BoxedResolverCallback, DefaultResolver, ResolverCallback, Solver};
 use spk_storage as storage;
 
-use super::Tester;
+use super::{TestReport, Tester, TesterOptions};
 
 pub struct PackageBuildTester<'a> {
     prefix: PathBuf,
@@ -30,6 +30,7 @@ pub struct PackageBuildTester<'a> {
     source: BuildSource,
     source_resolver: BoxedResolverCallback<'a>,
     build_resolver: BoxedResolverCallback<'a>,
+    tester_options: TesterOptions,
 }
 
 impl<'a> PackageBuildTester<'a> {
@@ -46,14 +47,22 @@ impl<'a> PackageBuildTester<'a> {
             source,
             source_resolver: Box::new(DefaultResolver {}),
             build_resolver: Box::new(DefaultResolver {}),
+            tester_options: TesterOptions::default(),
         }
     }
 
+    /// Pin the options used to solve the test environment, see [`Tester::options`].
     pub fn with_options(&mut self, mut options: OptionMap) -> &mut Self {
         self.options.append(&mut options);
         self
     }
 
+    /// Use the given settings for running the test script.
+    pub fn with_tester_options(&mut self, options: TesterOptions) -> &mut Self {
+        self.tester_options = options;
+        self
+    }
+
     pub fn with_repositories(
         &mut self,
         repos: impl IntoIterator<Item = Arc<storage::RepositoryHandle>>,
@@ -102,7 +111,7 @@ impl<'a> PackageBuildTester<'a> {
         self
     }
 
-    pub async fn test(&mut self) -> Result<()> {
+    pub async fn run(&mut self) -> Result<TestReport> {
         let mut rt = spfs::active_runtime().await?;
         rt.reset_all()?;
         rt.status.editable = true;
@@ -150,7 +159,7 @@ impl<'a> PackageBuildTester<'a> {
             BuildSource::LocalPath(path) => path.clone(),
         };
 
-        self.execute_test_script(&source_dir, env, &rt)
+        self.execute_test_script(&source_dir, env, &rt).await
     }
 
     async fn resolve_source_package(&mut self, package: &AnyIdent) -> Result<Solution> {
@@ -182,8 +191,11 @@ impl<'a> PackageBuildTester<'a> {
 
 #[async_trait::async_trait]
 impl Tester for PackageBuildTester<'_> {
-    async fn test(&mut self) -> Result<()> {
-        self.test().await
+    async fn run(&mut self) -> Result<TestReport> {
+        PackageBuildTester::run(self).await
+    }
+    fn package(&self) -> &VersionIdent {
+        self.recipe.ident()
     }
     fn prefix(&self) -> &Path {
         &self.prefix
@@ -191,4 +203,13 @@ impl Tester for PackageBuildTester<'_> {
     fn script(&self) -> &String {
         &self.script
     }
+    fn stage(&self) -> TestStage {
+        TestStage::Build
+    }
+    fn options(&self) -> &OptionMap {
+        &self.options
+    }
+    fn tester_options(&self) -> &TesterOptions {
+        &self.tester_options
+    }
 }
@@ -11,11 +11,11 @@ use spk_schema::foundation::ident_component::Component;
 use spk_schema::foundation::option_map::OptionMap;
 use spk_schema::ident::{PkgRequest, PreReleasePolicy, RangeIdent, Request, RequestedBy};
 use spk_schema::ident_build::Build;
-use spk_schema::{Recipe, SpecRecipe, Variant, VariantExt};
+use spk_schema::{Recipe, SpecRecipe, TestStage, Variant, VariantExt, VersionIdent};
 use spk_solve::{BoxedResolverCallback, DefaultResolver, ResolverCallback, Solver};
 use spk_storage as storage;
 
-use super::Tester;
+use super::{TestReport, Tester, TesterOptions};
 
 pub struct PackageInstallTester<'a, V> {
     prefix: PathBuf,
@@ -27,6 +27,7 @@ pub struct PackageInstallTester<'a, V> {
     source: Option<PathBuf>,
     env_resolver: BoxedResolverCallback<'a>,
     variant: V,
+    tester_options: TesterOptions,
 }
 
 impl<'a, V> PackageInstallTester<'a, V>
@@ -44,14 +45,22 @@ where
             source: None,
             env_resolver: Box::new(DefaultResolver {}),
             variant,
+            tester_options: TesterOptions::default(),
         }
     }
 
+    /// Pin the options used to solve the test environment, see [`Tester::options`].
     pub fn with_options(&mut self, mut options: OptionMap) -> &mut Self {
         self.options.append(&mut options);
         self
     }
 
+    /// Use the given settings for running the test script.
+    pub fn with_tester_options(&mut self, options: TesterOptions) -> &mut Self {
+        self.tester_options = options;
+        self
+    }
+
     pub fn with_repositories(
         &mut self,
         repos: impl IntoIterator<Item = Arc<storage::RepositoryHandle>>,
@@ -86,7 +95,7 @@ where
         self
     }
 
-    pub async fn test(&mut self) -> Result<()> {
+    pub async fn run(&mut self) -> Result<TestReport> {
         let mut rt = spfs::active_runtime().await?;
         rt.reset_all()?;
         rt.status.editable = true;
@@ -112,7 +121,16 @@ where
             .to_any_ident(None)
             .with_build(Some(Build::BuildId(build_digest_for_variant)));
 
-        let pkg = RangeIdent::double_equals(&build_to_test, [Component::All]);
+        // all components are requested so that the whole package is
+        // available to the test script, and the test component is also
+        // named whenever the recipe declares one so that its payloads,
+        // which are never part of a normal run environment, must be
+        // present in the build being tested
+        let mut components = vec![Component::All];
+        if declares_test_component(&self.recipe) {
+            components.push(Component::Test);
+        }
+        let pkg = RangeIdent::double_equals(&build_to_test, components);
         let request = PkgRequest::new(pkg, RequestedBy::InstallTest(self.recipe.ident().clone()))
             .with_prerelease(Some(PreReleasePolicy::IncludeAll))
             .with_pin(None)
@@ -137,7 +155,15 @@ where
             None => PathBuf::from("."),
         };
 
-        self.execute_test_script(&source_dir, env, &rt)
+        self.execute_test_script(&source_dir, env, &rt).await
+    }
+}
+
+/// True if the recipe defines a [`Component::Test`] component.
+fn declares_test_component(recipe: &SpecRecipe) -> bool {
+    match recipe {
+        SpecRecipe::V0Package(spec) => spec.install.components.get(Component::Test).is_some(),
+        SpecRecipe::V0Platform(_) | SpecRecipe::V1Platform(_) => false,
     }
 }
 
@@ -146,8 +172,11 @@ impl<V> Tester for PackageInstallTester<'_, V>
 where
     V: Clone + Variant + Send,
 {
-    async fn test(&mut self) -> Result<()> {
-        PackageInstallTester::test(self).await
+    async fn run(&mut self) -> Result<TestReport> {
+        PackageInstallTester::run(self).await
+    }
+    fn package(&self) -> &VersionIdent {
+        self.recipe.ident()
     }
     fn prefix(&self) -> &Path {
         &self.prefix
@@ -155,4 +184,13 @@ where
     fn script(&self) -> &String {
         &self.script
     }
+    fn stage(&self) -> TestStage {
+        TestStage::Install
+    }
+    fn options(&self) -> &OptionMap {
+        &self.options
+    }
+    fn tester_options(&self) -> &TesterOptions {
+        &self.tester_options
+    }
 }
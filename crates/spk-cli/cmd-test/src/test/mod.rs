@@ -4,10 +4,12 @@
 
 mod build;
 mod install;
+mod report;
 mod sources;
 mod tester;
 
 pub use build::PackageBuildTester;
 pub use install::PackageInstallTester;
+pub use report::{TestOutcome, TestReport, summarize_reports};
 pub use sources::PackageSourceTester;
-pub use tester::Tester;
+pub use tester::{Tester, TesterOptions};
@@ -0,0 +1,57 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use std::time::Duration;
+
+use spk_schema::{TestStage, VersionIdent};
+
+/// The result of running a single test.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TestOutcome {
+    Passed,
+    Failed { reason: String },
+    Skipped { reason: String },
+}
+
+/// Describes a single test that was run (or skipped) by a [`super::Tester`].
+#[derive(Clone, Debug)]
+pub struct TestReport {
+    /// The package that was tested
+    pub package: VersionIdent,
+    pub stage: TestStage,
+    /// The position of the test amongst the selected tests for its stage
+    pub index: usize,
+    pub outcome: TestOutcome,
+    /// How long the test script ran for
+    pub duration: Duration,
+    /// The combined output of the test script, as far as it was captured
+    pub output: String,
+}
+
+impl TestReport {
+    pub fn is_passed(&self) -> bool {
+        matches!(self.outcome, TestOutcome::Passed)
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self.outcome, TestOutcome::Failed { .. })
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        matches!(self.outcome, TestOutcome::Skipped { .. })
+    }
+}
+
+/// Summarize a set of reports, eg: "12 passed, 2 failed, 1 skipped".
+pub fn summarize_reports<'a>(reports: impl IntoIterator<Item = &'a TestReport>) -> String {
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for report in reports {
+        match report.outcome {
+            TestOutcome::Passed => passed += 1,
+            TestOutcome::Failed { .. } => failed += 1,
+            TestOutcome::Skipped { .. } => skipped += 1,
+        }
+    }
+    format!("{passed} passed, {failed} failed, {skipped} skipped")
+}
@@ -12,11 +12,11 @@ use spk_schema::foundation::ident_build::Build;
 use spk_schema::foundation::ident_component::Component;
 use spk_schema::foundation::option_map::OptionMap;
 use spk_schema::ident::{PkgRequest, PreReleasePolicy, RangeIdent, Request, RequestedBy};
-use spk_schema::{Recipe, SpecRecipe};
+use spk_schema::{Recipe, SpecRecipe, TestStage, VersionIdent};
 use spk_solve::{BoxedResolverCallback, DefaultResolver, ResolverCallback, Solver};
 use spk_storage as storage;
 
-use super::Tester;
+use super::{TestReport, Tester, TesterOptions};
 
 pub struct PackageSourceTester<'a> {
     prefix: PathBuf,
@@ -27,6 +27,7 @@ pub struct PackageSourceTester<'a> {
     additional_requirements: Vec<Request>,
     source: Option<PathBuf>,
     env_resolver: BoxedResolverCallback<'a>,
+    tester_options: TesterOptions,
 }
 
 impl<'a> PackageSourceTester<'a> {
@@ -40,14 +41,22 @@ impl<'a> PackageSourceTester<'a> {
             additional_requirements: Vec::new(),
             source: None,
             env_resolver: Box::new(DefaultResolver {}),
+            tester_options: TesterOptions::default(),
         }
     }
 
+    /// Pin the options used to solve the test environment, see [`Tester::options`].
     pub fn with_options(&mut self, mut options: OptionMap) -> &mut Self {
         self.options.append(&mut options);
         self
     }
 
+    /// Use the given settings for running the test script.
+    pub fn with_tester_options(&mut self, options: TesterOptions) -> &mut Self {
+        self.tester_options = options;
+        self
+    }
+
     pub fn with_repositories(
         &mut self,
         repos: impl IntoIterator<Item = Arc<storage::RepositoryHandle>>,
@@ -84,7 +93,7 @@ impl<'a> PackageSourceTester<'a> {
     }
 
     /// Execute the source package test as configured.
-    pub async fn test(&mut self) -> Result<()> {
+    pub async fn run(&mut self) -> Result<TestReport> {
         let mut rt = spfs::active_runtime().await?;
         rt.reset_all()?;
         rt.status.editable = true;
@@ -132,14 +141,17 @@ impl<'a> PackageSourceTester<'a> {
                 .to_path(&self.prefix),
         };
 
-        self.execute_test_script(&source_dir, env, &rt)
+        self.execute_test_script(&source_dir, env, &rt).await
     }
 }
 
 #[async_trait::async_trait]
 impl Tester for PackageSourceTester<'_> {
-    async fn test(&mut self) -> Result<()> {
-        self.test().await
+    async fn run(&mut self) -> Result<TestReport> {
+        PackageSourceTester::run(self).await
+    }
+    fn package(&self) -> &VersionIdent {
+        self.recipe.ident()
     }
     fn prefix(&self) -> &Path {
         &self.prefix
@@ -147,4 +159,13 @@ impl Tester for PackageSourceTester<'_> {
     fn script(&self) -> &String {
         &self.script
     }
+    fn stage(&self) -> TestStage {
+        TestStage::Sources
+    }
+    fn options(&self) -> &OptionMap {
+        &self.options
+    }
+    fn tester_options(&self) -> &TesterOptions {
+        &self.tester_options
+    }
 }
@@ -2,28 +2,83 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::io::Write;
 use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use relative_path::{RelativePath, RelativePathBuf};
 use spfs::runtime::Runtime;
 use spk_cli_common::{Error, Result, TestError};
+use spk_schema::foundation::option_map::OptionMap;
+use spk_schema::{TestStage, VersionIdent};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+use super::{TestOutcome, TestReport};
 
 /// Common code and logic for all test flavors.
 #[async_trait::async_trait]
 pub trait Tester: Send {
     /// Create the runtime environment for the defined test and then execute
-    /// the test.
-    async fn test(&mut self) -> Result<()>;
+    /// the test, unless it was filtered out.
+    ///
+    /// A failing test is returned as an error when the tester fails
+    /// fast, and is otherwise included in the reports.
+    async fn test(&mut self) -> Result<Vec<TestReport>> {
+        if !self.is_selected() {
+            tracing::info!(
+                "Skipping {} test #{}, it was not selected",
+                self.stage(),
+                self.tester_options().index()
+            );
+            let outcome = TestOutcome::Skipped {
+                reason: "it was not selected".to_string(),
+            };
+            return Ok(vec![self.report(outcome, Duration::ZERO, String::new())]);
+        }
+
+        let start = Instant::now();
+        let report = match self.run().await {
+            Ok(report) => report,
+            Err(err) if self.tester_options().fail_fast() => return Err(err),
+            Err(err) => {
+                let output = match &err {
+                    Error::TestTimeout { stdout, stderr, .. } => format!("{stdout}{stderr}"),
+                    _ => String::new(),
+                };
+                let outcome = TestOutcome::Failed {
+                    reason: err.to_string(),
+                };
+                self.report(outcome, start.elapsed(), output)
+            }
+        };
+        match &report.outcome {
+            TestOutcome::Failed { reason } if self.tester_options().fail_fast() => {
+                Err(TestError::new_error(reason.clone()))
+            }
+            _ => Ok(vec![report]),
+        }
+    }
+
+    /// Create the runtime environment for the defined test and then execute
+    /// the test script.
+    async fn run(&mut self) -> Result<TestReport>;
 
     /// Generate and invoke the test script defined in the recipe.
-    fn execute_test_script(
-        &self,
+    ///
+    /// If the tester has a timeout, the script is stopped once it has
+    /// run for that long and [`Error::TestTimeout`] is returned.
+    async fn execute_test_script(
+        &mut self,
         source_dir: &Path,
         mut env: HashMap<String, String>,
         rt: &Runtime,
-    ) -> Result<()> {
+    ) -> Result<TestReport> {
+        env.extend(self.tester_options().environment().clone());
         env.insert(
             "PREFIX".to_string(),
             self.prefix()
@@ -53,32 +108,252 @@ pub trait Tester: Send {
             OsString::from("bash"),
             [OsString::from("-ex"), script_path.into_os_string()],
         )?;
-        let mut cmd = cmd.into_std();
-        let status = cmd
-            .envs(env)
-            .current_dir(source_dir)
+        let workdir = match self.tester_options().workdir() {
+            Some(workdir) => workdir.to_path(source_dir),
+            None => source_dir.to_owned(),
+        };
+        let mut cmd = cmd.into_tokio();
+        cmd.envs(env)
+            .current_dir(&workdir)
             .env("SHELL", "bash")
-            .status()
-            .map_err(|err| {
-                Error::ProcessSpawnError(spfs::Error::process_spawn_error(
-                    "bash",
-                    err,
-                    Some(source_dir.to_owned()),
-                ))
-            })?;
-        if !status.success() {
-            Err(TestError::new_error(format!(
-                "Test script returned non-zero exit status: {}",
-                status.code().unwrap_or(1)
-            )))
+            .kill_on_drop(true);
+        let spawn_error = |err: std::io::Error| {
+            Error::ProcessSpawnError(spfs::Error::process_spawn_error(
+                "bash",
+                err,
+                Some(workdir.clone()),
+            ))
+        };
+
+        // The output is captured as well as shown so that it
+        // can be reported, even if the script never finishes
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let start = Instant::now();
+        let mut child = cmd.spawn().map_err(spawn_error)?;
+        let (stdout_task, stdout) = tee_output(child.stdout.take(), tokio::io::stdout());
+        let (stderr_task, stderr) = tee_output(child.stderr.take(), tokio::io::stderr());
+        let take = |buf: Arc<Mutex<Vec<u8>>>| {
+            String::from_utf8_lossy(&buf.lock().expect("lock poisoned")).into_owned()
+        };
+
+        let status = match self.tester_options().timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, child.wait()).await.ok(),
+            None => Some(child.wait().await),
+        };
+        let Some(status) = status else {
+            if let Err(err) = child.kill().await {
+                tracing::warn!("Failed to stop timed out test script: {err}");
+            }
+            // processes started by the script may still be
+            // holding the pipes open, so don't wait for them
+            stdout_task.abort();
+            stderr_task.abort();
+            return Err(Error::TestTimeout {
+                stage: self.stage(),
+                elapsed: start.elapsed(),
+                stdout: take(stdout),
+                stderr: take(stderr),
+            });
+        };
+        let status = status.map_err(spawn_error)?;
+        let _ = stdout_task.await;
+        let _ = stderr_task.await;
+
+        let outcome = if status.success() {
+            TestOutcome::Passed
         } else {
-            Ok(())
+            TestOutcome::Failed {
+                reason: format!(
+                    "Test script returned non-zero exit status: {}",
+                    status.code().unwrap_or(1)
+                ),
+            }
+        };
+        let output = format!("{}{}", take(stdout), take(stderr));
+        Ok(self.report(outcome, start.elapsed(), output))
+    }
+
+    /// Create a report about this test.
+    fn report(&self, outcome: TestOutcome, duration: Duration, output: String) -> TestReport {
+        TestReport {
+            package: self.package().clone(),
+            stage: self.stage(),
+            index: self.tester_options().index(),
+            outcome,
+            duration,
+            output,
         }
     }
 
+    /// Return the package being tested.
+    fn package(&self) -> &VersionIdent;
+
     /// Return the root path of the overlayfs
     fn prefix(&self) -> &Path;
 
     /// Return the text of the test script.
     fn script(&self) -> &String;
+
+    /// Return the stage of the test being run.
+    fn stage(&self) -> TestStage;
+
+    /// Return the options that the test environment is solved with.
+    ///
+    /// Only the environment is affected by these. The tests to run
+    /// are expected to be chosen with the same options using
+    /// [`spk_schema::Recipe::get_tests`], which resolves the recipe's
+    /// default values and checks each test's selectors.
+    fn options(&self) -> &OptionMap;
+
+    /// Return the settings that are shared by every flavor of tester.
+    fn tester_options(&self) -> &TesterOptions;
+
+    /// Return true if this test passes both the stage and index filters.
+    fn is_selected(&self) -> bool {
+        self.tester_options().is_selected(self.stage())
+    }
+}
+
+/// The settings that are shared by every flavor of [`Tester`].
+#[derive(Clone, Debug)]
+pub struct TesterOptions {
+    timeout: Option<Duration>,
+    workdir: Option<RelativePathBuf>,
+    env: BTreeMap<String, String>,
+    index: usize,
+    stage_filter: Vec<TestStage>,
+    test_indices: Vec<usize>,
+    fail_fast: bool,
+}
+
+impl Default for TesterOptions {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            workdir: None,
+            env: BTreeMap::new(),
+            index: 0,
+            stage_filter: Vec::new(),
+            test_indices: Vec::new(),
+            fail_fast: true,
+        }
+    }
+}
+
+impl TesterOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the test script if it runs for longer than the given duration.
+    pub fn with_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run the test script from the given directory, relative
+    /// to the directory that it would otherwise run in.
+    pub fn with_workdir(&mut self, workdir: Option<RelativePathBuf>) -> &mut Self {
+        self.workdir = workdir;
+        self
+    }
+
+    /// Set additional environment variables for the test script.
+    pub fn with_env(&mut self, env: impl IntoIterator<Item = (String, String)>) -> &mut Self {
+        self.env.extend(env);
+        self
+    }
+
+    /// Set the position of this test amongst the selected tests
+    /// for its stage, as used by [`Self::with_test_indices`].
+    pub fn with_index(&mut self, index: usize) -> &mut Self {
+        self.index = index;
+        self
+    }
+
+    /// Only run this test if its stage is one of the given stages.
+    ///
+    /// An empty filter allows all stages.
+    pub fn with_stage_filter(&mut self, stages: Vec<TestStage>) -> &mut Self {
+        self.stage_filter = stages;
+        self
+    }
+
+    /// Only run this test if its index is one of the given indices.
+    ///
+    /// An empty filter allows all tests.
+    pub fn with_test_indices(&mut self, indices: Vec<usize>) -> &mut Self {
+        self.test_indices = indices;
+        self
+    }
+
+    /// Report a failing test instead of returning it as an error.
+    ///
+    /// Testers fail fast by default.
+    pub fn with_fail_fast(&mut self, fail_fast: bool) -> &mut Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Return the maximum time that the test script is allowed to run.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Return true if a failing test should be returned as an error.
+    pub fn fail_fast(&self) -> bool {
+        self.fail_fast
+    }
+
+    /// Return the directory to run the test script from, relative
+    /// to the directory that it would otherwise run in.
+    pub fn workdir(&self) -> Option<&RelativePath> {
+        self.workdir.as_deref()
+    }
+
+    /// Return the additional environment variables for the test script.
+    pub fn environment(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    /// Return the position of this test amongst the selected tests for its stage.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Return true if a test of the given stage passes both the
+    /// stage and index filters.
+    pub fn is_selected(&self, stage: TestStage) -> bool {
+        (self.stage_filter.is_empty() || self.stage_filter.contains(&stage))
+            && (self.test_indices.is_empty() || self.test_indices.contains(&self.index))
+    }
+}
+
+/// Forward everything read from `reader` to the given output, while
+/// also collecting it into the returned buffer.
+fn tee_output<R, W>(reader: Option<R>, mut output: W) -> (JoinHandle<()>, Arc<Mutex<Vec<u8>>>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let buf = Arc::clone(&captured);
+    let task = tokio::spawn(async move {
+        let Some(mut reader) = reader else {
+            return;
+        };
+        let mut chunk = [0; 4096];
+        loop {
+            match reader.read(&mut chunk).await {
+                Ok(0) | Err(_) => break,
+                Ok(count) => {
+                    let _ = output.write_all(&chunk[..count]).await;
+                    buf.lock()
+                        .expect("lock poisoned")
+                        .extend_from_slice(&chunk[..count]);
+                }
+            }
+        }
+    });
+    (task, captured)
 }
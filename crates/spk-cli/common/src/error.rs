@@ -58,6 +58,15 @@ pub enum Error {
     #[error(transparent)]
     #[diagnostic(forward(0))]
     Test(#[from] TestError),
+    #[error("Test script for the {stage} stage timed out after {elapsed:?}")]
+    TestTimeout {
+        stage: spk_schema::TestStage,
+        elapsed: std::time::Duration,
+        /// Output from the script before it was stopped
+        stdout: String,
+        /// Error output from the script before it was stopped
+        stderr: String,
+    },
 
     /// Not running under an active spk environment
     #[error("No current spfs runtime environment")]
@@ -114,6 +123,14 @@ impl FormatError for Error {
             */
             Error::SpkSolverError(err) => return err.format_error(verbosity).await,
             Error::String(err) => msg.push_str(err),
+            Error::TestTimeout { stdout, stderr, .. } => {
+                msg.push_str(&self.to_string());
+                for (name, output) in [("stdout", stdout), ("stderr", stderr)] {
+                    if !output.is_empty() {
+                        msg.push_str(&format!("\n--- partial {name} ---\n{output}"));
+                    }
+                }
+            }
             err => msg.push_str(&err.to_string()),
         }
         msg.red().to_string()
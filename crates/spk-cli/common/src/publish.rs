@@ -233,7 +233,10 @@ impl Publisher {
                 .with_reporter(spfs::sync::reporter::SyncReporters::console())
                 .sync_env(env_spec)
                 .await?;
-            self.to.publish_package(&spec, &components).await?;
+            let result = self.to.publish_package(&spec, &components).await?;
+            for tag in result.tags_written.iter() {
+                tracing::debug!("        wrote tag: {tag}");
+            }
         }
 
         Ok(builds)
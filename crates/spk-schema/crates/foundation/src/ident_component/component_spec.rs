@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
 use std::fmt::{Display, Write};
 
@@ -23,6 +23,18 @@ pub trait Components {
     /// A set with a single entry is formatted as `":name"`.
     /// A set with multiple entries is formatted as `":{name1,name2}"`.
     fn fmt_component_set(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result;
+
+    /// Render the difference between this set of [`Component`] and `other`.
+    ///
+    /// Components that are only in this set are formatted as `+name`,
+    /// and those only in `other` as `-name`, bracketed in the same way
+    /// as [`Components::fmt_component_set`]. No difference is an
+    /// empty string.
+    fn fmt_component_diff(
+        &self,
+        other: &BTreeSet<Component>,
+        f: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result;
 }
 
 /// Identifies a component by name
@@ -32,11 +44,19 @@ pub enum Component {
     Build,
     Run,
     Source,
+    /// Files that are only needed to test the package, such as
+    /// fixtures or golden files.
+    ///
+    /// This component is never included by default, and must be
+    /// requested explicitly (or through `all`).
+    Test,
     Named(String),
 }
 
 impl Component {
     /// Return the default build component based on migration-to-components feature
+    ///
+    /// This never includes the [`Component::Test`] component on its own.
     #[inline]
     pub fn default_for_build() -> Self {
         // For sites that started using spk after component support was added
@@ -50,6 +70,8 @@ impl Component {
     }
 
     /// Return the default run component based on migration-to-components feature
+    ///
+    /// This never includes the [`Component::Test`] component on its own.
     #[inline]
     pub fn default_for_run() -> Self {
         // For sites that started using spk after component support was added
@@ -72,16 +94,33 @@ impl Component {
             "run" => Self::Run,
             "build" => Self::Build,
             "src" => Self::Source,
+            "test" => Self::Test,
             _ => Self::Named(source.to_string()),
         })
     }
 
+    /// Parse a component name from a string, resolving any of the
+    /// given aliases to the component that they stand for.
+    ///
+    /// Names that are not an alias are parsed the same as [`Self::parse`].
+    pub fn parse_with_aliases<S: AsRef<str>>(
+        source: S,
+        aliases: &ComponentAliases,
+    ) -> Result<Self> {
+        let source = source.as_ref();
+        match aliases.get(source) {
+            Some(component) => Ok(component.clone()),
+            None => Self::parse(source),
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             Self::All => "all",
             Self::Run => "run",
             Self::Build => "build",
             Self::Source => "src",
+            Self::Test => "test",
             Self::Named(value) => value,
         }
     }
@@ -102,11 +141,45 @@ impl Component {
         matches!(self, Self::Source)
     }
 
+    pub fn is_test(&self) -> bool {
+        matches!(self, Self::Test)
+    }
+
     pub fn is_named(&self) -> bool {
         matches!(self, Self::Named(_))
     }
 }
 
+/// Alternate names for components, see [`Component::parse_with_aliases`].
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct ComponentAliases(BTreeMap<String, Component>);
+
+impl ComponentAliases {
+    /// Create an empty set of aliases.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The aliases for built-in components that are commonly found
+    /// in specs from other tools and older packages.
+    pub fn legacy() -> Self {
+        Self::new()
+            .with_alias("runtime", Component::Run)
+            .with_alias("source", Component::Source)
+    }
+
+    /// Add an alias, replacing any existing one with the same name.
+    pub fn with_alias<S: Into<String>>(mut self, alias: S, component: Component) -> Self {
+        self.0.insert(alias.into(), component);
+        self
+    }
+
+    /// The component that the given name is an alias for, if any.
+    pub fn get(&self, alias: &str) -> Option<&Component> {
+        self.0.get(alias)
+    }
+}
+
 impl Components for BTreeSet<Component> {
     fn fmt_component_set(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self.len() {
@@ -124,6 +197,30 @@ impl Components for BTreeSet<Component> {
         }
         Ok(())
     }
+
+    fn fmt_component_diff(
+        &self,
+        other: &BTreeSet<Component>,
+        f: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result {
+        let added = self.difference(other).map(|c| format!("+{c}"));
+        let removed = other.difference(self).map(|c| format!("-{c}"));
+        let changes: Vec<_> = added.chain(removed).collect();
+        match changes.len() {
+            0 => (),
+            1 => {
+                f.write_char(':')?;
+                changes.join(",").fmt(f)?;
+            }
+            _ => {
+                f.write_char(':')?;
+                f.write_char('{')?;
+                changes.join(",").fmt(f)?;
+                f.write_char('}')?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl std::str::FromStr for Component {
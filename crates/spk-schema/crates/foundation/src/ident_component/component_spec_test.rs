@@ -2,9 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
+use std::collections::BTreeSet;
+
 use rstest::rstest;
 
-use super::Component;
+use super::{Component, ComponentAliases, Components};
 
 #[rstest]
 fn test_component_name_serialize() {
@@ -12,8 +14,72 @@ fn test_component_name_serialize() {
     assert_eq!(Component::Run, serde_yaml::from_str("run").unwrap());
     assert_eq!(Component::Build, serde_yaml::from_str("build").unwrap());
     assert_eq!(Component::Source, serde_yaml::from_str("src").unwrap());
+    assert_eq!(Component::Test, serde_yaml::from_str("test").unwrap());
     assert_eq!(
         Component::Named("other".into()),
         serde_yaml::from_str("other").unwrap()
     );
 }
+
+#[rstest]
+fn test_test_component_round_trip() {
+    let component = Component::parse("test").unwrap();
+    assert!(component.is_test());
+    assert!(!component.is_named());
+    assert_eq!(component.as_str(), "test");
+    assert_ne!(Component::default_for_run(), Component::Test);
+    assert_ne!(Component::default_for_build(), Component::Test);
+}
+
+#[rstest]
+#[case("runtime", Component::Run)]
+#[case("source", Component::Source)]
+#[case("run", Component::Run)]
+#[case("other", Component::Named("other".into()))]
+fn test_parse_with_aliases(#[case] source: &str, #[case] expected: Component) {
+    let aliases = ComponentAliases::legacy();
+    assert_eq!(
+        Component::parse_with_aliases(source, &aliases).unwrap(),
+        expected
+    );
+}
+
+#[rstest]
+fn test_parse_is_strict_about_aliases() {
+    assert_eq!(
+        Component::parse("runtime").unwrap(),
+        Component::Named("runtime".into()),
+        "aliases should only be resolved when asked for"
+    );
+    let aliases = ComponentAliases::new().with_alias("tests", Component::Test);
+    assert_eq!(
+        Component::parse_with_aliases("tests", &aliases).unwrap(),
+        Component::Test
+    );
+    assert!(Component::parse_with_aliases("Not Valid", &aliases).is_err());
+}
+
+struct ComponentDiff(BTreeSet<Component>, BTreeSet<Component>);
+
+impl std::fmt::Display for ComponentDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_component_diff(&self.1, f)
+    }
+}
+
+#[rstest]
+#[case(&["run"], &["run"], "")]
+#[case(&["run", "build"], &["run"], ":+build")]
+#[case(&["run"], &["run", "build"], ":-build")]
+#[case(&["run", "test"], &["build", "run"], ":{+test,-build}")]
+fn test_fmt_component_diff(
+    #[case] resolved: &[&str],
+    #[case] requested: &[&str],
+    #[case] expected: &str,
+) {
+    let parse = |names: &[&str]| -> BTreeSet<Component> {
+        names.iter().map(|n| Component::parse(n).unwrap()).collect()
+    };
+    let diff = ComponentDiff(parse(resolved), parse(requested));
+    assert_eq!(diff.to_string(), expected);
+}
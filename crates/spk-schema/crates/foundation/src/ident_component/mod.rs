@@ -8,5 +8,5 @@ mod error;
 pub mod parsing;
 
 pub use component_set::{ComponentBTreeSet, ComponentBTreeSetBuf, ComponentSet};
-pub use component_spec::{Component, Components};
+pub use component_spec::{Component, ComponentAliases, Components};
 pub use error::{Error, Result};
@@ -38,6 +38,7 @@ where
             "build" => Component::Build,
             "run" => Component::Run,
             "src" => Component::Source,
+            "test" => Component::Test,
             s => Component::Named(s.to_owned()),
         },
     )(input)
@@ -3,7 +3,7 @@
 // https://github.com/spkenv/spk
 
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::io::Read;
 use std::path::Path;
 use std::str::FromStr;
@@ -11,6 +11,7 @@ use std::sync::Arc;
 
 use enum_dispatch::enum_dispatch;
 use format_serde_error::SerdeError;
+use relative_path::RelativePathBuf;
 use serde::{Deserialize, Serialize};
 use spk_schema_foundation::SerdeYamlError;
 use spk_schema_foundation::ident_build::{Build, BuildId};
@@ -599,6 +600,18 @@ impl Test for SpecTest {
             Self::V0(t) => t.additional_requirements(),
         }
     }
+
+    fn workdir(&self) -> Option<RelativePathBuf> {
+        match self {
+            Self::V0(t) => t.workdir(),
+        }
+    }
+
+    fn environment(&self) -> BTreeMap<String, String> {
+        match self {
+            Self::V0(t) => t.environment(),
+        }
+    }
 }
 
 /// Specifies some data object within the spk ecosystem.
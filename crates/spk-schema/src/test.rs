@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
+use relative_path::RelativePathBuf;
 use serde::{Deserialize, Serialize};
 use spk_schema_ident::Request;
 
@@ -20,6 +22,17 @@ pub trait Test {
     fn additional_requirements(&self) -> Vec<Request> {
         Vec::new()
     }
+
+    /// The directory to run the script from, relative to the
+    /// directory that the test would otherwise run in.
+    fn workdir(&self) -> Option<RelativePathBuf> {
+        None
+    }
+
+    /// Additional environment variables to set for the script.
+    fn environment(&self) -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, strum::EnumIter)]
@@ -2,6 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
+use std::collections::BTreeMap;
+
+use relative_path::RelativePathBuf;
 use serde::{Deserialize, Serialize};
 use spk_schema_ident::{RequestedBy, VersionIdent};
 
@@ -22,6 +25,13 @@ pub struct TestSpec {
     pub selectors: Vec<super::VariantSpec>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub requirements: Vec<Request>,
+    /// The directory to run the script from, relative to the
+    /// directory that the test would otherwise run in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<RelativePathBuf>,
+    /// Additional environment variables to set for the script.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
 }
 
 impl TestSpec {
@@ -52,4 +62,12 @@ impl crate::Test for TestSpec {
     fn additional_requirements(&self) -> Vec<Request> {
         self.requirements.clone()
     }
+
+    fn workdir(&self) -> Option<RelativePathBuf> {
+        self.workdir.clone()
+    }
+
+    fn environment(&self) -> BTreeMap<String, String> {
+        self.env.clone()
+    }
 }
@@ -19,3 +19,50 @@ selectors:
     )
     .expect("successfully parse selector with component specified");
 }
+
+#[rstest]
+fn test_workdir_and_env_are_optional() {
+    let test_spec: TestSpec = serde_yaml::from_str(
+        r#"
+stage: install
+script:
+  - true
+    "#,
+    )
+    .expect("parse test spec without workdir or env");
+    assert!(test_spec.workdir.is_none());
+    assert!(test_spec.env.is_empty());
+
+    let serialized = serde_yaml::to_string(&test_spec).unwrap();
+    assert!(
+        !serialized.contains("workdir") && !serialized.contains("env"),
+        "empty workdir and env should not be serialized, got: {serialized}"
+    );
+}
+
+#[rstest]
+fn test_workdir_and_env_round_trip() {
+    let test_spec: TestSpec = serde_yaml::from_str(
+        r#"
+stage: build
+script:
+  - true
+workdir: src/tests
+env:
+  MY_VAR: my-value
+    "#,
+    )
+    .expect("parse test spec with workdir and env");
+    assert_eq!(
+        test_spec.workdir.as_deref().map(|p| p.as_str()),
+        Some("src/tests")
+    );
+    assert_eq!(
+        test_spec.env.get("MY_VAR").map(String::as_str),
+        Some("my-value")
+    );
+
+    let serialized = serde_yaml::to_string(&test_spec).unwrap();
+    let parsed: TestSpec = serde_yaml::from_str(&serialized).unwrap();
+    assert_eq!(parsed, test_spec);
+}
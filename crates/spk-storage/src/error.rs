@@ -22,6 +22,8 @@ pub enum Error {
     FileOpenError(std::path::PathBuf, #[source] std::io::Error),
     #[error("Failed to read file {0}")]
     FileReadError(std::path::PathBuf, #[source] std::io::Error),
+    #[error("Failed to write file {0}")]
+    FileWriteError(std::path::PathBuf, #[source] std::io::Error),
     #[error("Invalid package spec for {0}: {1}")]
     InvalidPackageSpec(
         AnyIdent,
@@ -34,6 +36,15 @@ pub enum Error {
     InvalidRepositoryMetadata(#[source] serde_yaml::Error),
     #[error("Package not found: {0}")]
     PackageNotFound(AnyIdent),
+    #[error(
+        "Repository version {repo_version} is newer than this client supports ({client_version}), an update is required"
+    )]
+    RepositoryTooNew {
+        repo_version: spk_schema::foundation::version::Version,
+        client_version: spk_schema::foundation::version::Version,
+    },
+    #[error("Repository '{0}' is pinned to a point in time and is read-only")]
+    RepositoryPinnedReadOnly(spk_schema::foundation::name::RepositoryNameBuf),
     #[error("Version exists: {0}")]
     VersionExists(VersionIdent),
     #[error(transparent)]
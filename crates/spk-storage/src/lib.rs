@@ -8,17 +8,47 @@ mod storage;
 
 pub use error::{Error, Result};
 pub use storage::{
+    BuildSelectionPolicy,
     CachePolicy,
+    CachePolicyGuard,
+    EmbedStubDecodeError,
+    ExportOptions,
+    ExportVerification,
+    FallbackRepository,
+    LazyComponentMap,
     MemRepository,
     NameAndRepository,
+    NameCollision,
+    PublishPolicy,
+    PublishResult,
+    RecipeMigration,
+    RecipeMigrations,
     Repository,
+    RepositoryDescription,
     RepositoryHandle,
+    RetryPolicy,
     RuntimeRepository,
+    SkippedEntry,
+    SpecRewriter,
     SpfsRepository,
     Storage,
+    TagHistoryEntry,
+    UpgradeAction,
+    UpgradePlan,
+    VerificationIssue,
+    VersionBundle,
     export_package,
+    export_package_with_options,
+    export_packages,
+    export_recipe,
+    export_recipes,
     find_path_providers,
+    import_package,
+    local_and_remote,
     local_repository,
+    normalize_repository_address,
     pretty_print_filepath,
+    purge_unused_address_caches,
     remote_repository,
+    verify_export_stream,
 };
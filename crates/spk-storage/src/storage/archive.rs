@@ -2,58 +2,62 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
+use std::io::Read;
 use std::path::Path;
 
+use futures::TryStreamExt;
 use itertools::{Itertools, Position};
+use spfs::encoding::prelude::*;
+use spk_schema::foundation::ident_component::Component;
+use spk_schema::foundation::name::PkgName;
 use spk_schema::ident::AsVersionIdent;
 use spk_schema::{AnyIdent, BuildIdent, VersionIdent};
 use variantly::Variantly;
 
+use super::repository::internal::RepositoryExt;
+use super::repository::{PublishPolicy, Storage};
 use super::{Repository, SpfsRepository};
 use crate::{Error, NameAndRepository, Result};
 
+#[cfg(test)]
+#[path = "./archive_test.rs"]
+mod archive_test;
+
+/// Options for how an export archive is written.
+#[derive(Clone, Debug, Default)]
+pub struct ExportOptions {
+    /// The compression applied to the archive.
+    ///
+    /// The compression is detected when the archive is opened, so
+    /// imports do not need to be told which was used.
+    pub compression: spfs::storage::tar::ArchiveCompression,
+    /// When set, only these components of each build are exported.
+    ///
+    /// The spec of each build is always exported, regardless of
+    /// this filter, so that the archive remains self-describing.
+    pub components: Option<BTreeSet<Component>>,
+}
+
 pub async fn export_package(
     source_repos: &[&SpfsRepository],
     pkg: impl AsRef<AnyIdent>,
     filename: impl AsRef<Path>,
 ) -> Result<()> {
-    let pkg = pkg.as_ref();
-    // Make filename absolute as spfs::runtime::makedirs_with_perms does not handle
-    // relative paths properly.
-    let filename = std::env::current_dir()
-        .map_err(|err| Error::String(format!("Failed to get current directory: {err}")))?
-        .join(filename);
-
-    if let Err(err) = std::fs::remove_file(&filename) {
-        match err.kind() {
-            std::io::ErrorKind::NotFound => (),
-            _ => tracing::warn!("Error trying to remove old file: {:?}", err),
-        }
-    }
-
-    filename
-        .parent()
-        .map(|dir| {
-            std::fs::create_dir_all(dir)
-                .map_err(|err| Error::DirectoryCreateError(dir.to_owned(), err))
-        })
-        .unwrap_or_else(|| Ok(()))?;
-
-    let tar_repo = spfs::storage::tar::TarRepository::create(&filename)
-        .await
-        .map_err(|source| spfs::Error::FailedToOpenRepository {
-            repository: "<TAR Archive>".into(),
-            source,
-        })?;
-    // Package exports should not include the top-level directory for
-    // durable runtime upperdir edits.
-    tar_repo.remove_durable_dir().await?;
+    export_package_with_options(source_repos, pkg, filename, &ExportOptions::default()).await
+}
 
-    let target_repo = super::SpfsRepository::try_from(NameAndRepository::new(
-        "archive",
-        spfs::storage::RepositoryHandle::from(tar_repo),
-    ))?;
+/// Export a package, as with [`export_package`], writing the archive
+/// according to the given options.
+pub async fn export_package_with_options(
+    source_repos: &[&SpfsRepository],
+    pkg: impl AsRef<AnyIdent>,
+    filename: impl AsRef<Path>,
+    options: &ExportOptions,
+) -> Result<()> {
+    let pkg = pkg.as_ref();
+    let (filename, target_repo) = create_archive_repo(filename, options).await?;
 
     // these are sorted to ensure that the recipe is published
     // before any build - it's only an error in testing, but still best practice
@@ -90,7 +94,14 @@ pub async fn export_package(
         let mut all_errors_are_build_not_found = true;
 
         for (position, repo) in source_repos.iter().with_position() {
-            let err = match copy_any(transfer_pkg.clone(), repo, &target_repo).await {
+            let err = match copy_any(
+                transfer_pkg.clone(),
+                repo,
+                &target_repo,
+                options.components.as_ref(),
+            )
+            .await
+            {
                 Ok(_) => continue 'pkg,
                 Err(Error::PackageNotFound(ident)) => {
                     if ident.build().is_some() {
@@ -140,6 +151,251 @@ pub async fn export_package(
         }
     }
 
+    preserve_source_tags(source_repos, &target_repo).await?;
+    flush_archive_repo(&filename, &target_repo)
+}
+
+/// Export many package builds into a single archive.
+///
+/// Objects that are shared between the builds are only stored once. The
+/// recipe of each build is included when one can be found, but it is not
+/// an error for it to be missing.
+///
+/// Builds that cannot be read from any of the source repositories are
+/// skipped and returned along with the error that was encountered, unless
+/// `strict` is set, in which case the first failure is returned as an
+/// error instead. It is always an error if none of the builds can be read.
+pub async fn export_packages(
+    source_repos: &[&SpfsRepository],
+    pkgs: &[BuildIdent],
+    filename: impl AsRef<Path>,
+    strict: bool,
+) -> Result<Vec<(BuildIdent, Error)>> {
+    let (filename, target_repo) = create_archive_repo(filename, &ExportOptions::default()).await?;
+
+    // sorted to ensure that recipes are published before their builds
+    let builds: BTreeSet<_> = pkgs
+        .iter()
+        // Embedded stubs are recreated when exporting their provider.
+        .filter(|pkg| !pkg.is_embedded())
+        .collect();
+    let recipes: BTreeSet<_> = builds.iter().map(|pkg| pkg.as_version_ident()).collect();
+
+    for recipe in recipes {
+        match copy_recipe_from_any(source_repos, recipe, &target_repo).await {
+            Ok(()) => {}
+            Err(Error::PackageNotFound(_)) => {
+                tracing::debug!(pkg=%recipe, "no recipe found for version, skipping");
+            }
+            Err(err) if strict => return Err(err),
+            Err(err) => tracing::warn!(pkg=%recipe, "failed to export recipe: {err}"),
+        }
+    }
+
+    let mut failed = Vec::new();
+    for pkg in builds.iter() {
+        match copy_package_from_any(source_repos, pkg, &target_repo).await {
+            Ok(()) => {}
+            Err(err) if strict => return Err(err),
+            Err(err) => {
+                tracing::warn!(%pkg, "failed to export package: {err}");
+                failed.push(((*pkg).clone(), err));
+            }
+        }
+    }
+    if !builds.is_empty() && failed.len() == builds.len() {
+        let (_, err) = failed.swap_remove(0);
+        return Err(err);
+    }
+
+    preserve_source_tags(source_repos, &target_repo).await?;
+    flush_archive_repo(&filename, &target_repo)?;
+    Ok(failed)
+}
+
+/// Export the recipe of a single package version, without any of its builds.
+///
+/// The resulting archive contains only the recipe blob and its tag, which is
+/// much smaller than a full package export and suitable for sharing a recipe
+/// with another site to be built there.
+pub async fn export_recipe(
+    source_repos: &[&SpfsRepository],
+    pkg: &VersionIdent,
+    filename: impl AsRef<Path>,
+) -> Result<()> {
+    let (filename, target_repo) = create_archive_repo(filename, &ExportOptions::default()).await?;
+    copy_recipe_from_any(source_repos, pkg, &target_repo).await?;
+    preserve_source_tags(source_repos, &target_repo).await?;
+    flush_archive_repo(&filename, &target_repo)
+}
+
+/// Export the recipes of all versions of a package, without any builds.
+///
+/// Versions which have builds but no recipe in any of the source
+/// repositories are skipped.
+pub async fn export_recipes(
+    source_repos: &[&SpfsRepository],
+    name: &PkgName,
+    filename: impl AsRef<Path>,
+) -> Result<()> {
+    let mut versions = BTreeSet::new();
+    for repo in source_repos {
+        versions.extend(
+            repo.list_package_versions(name)
+                .await?
+                .iter()
+                .map(|v| (**v).clone()),
+        );
+    }
+
+    let (filename, target_repo) = create_archive_repo(filename, &ExportOptions::default()).await?;
+    let mut exported_any = false;
+    for version in versions.into_iter() {
+        let pkg = VersionIdent::new(name.to_owned(), version);
+        match copy_recipe_from_any(source_repos, &pkg, &target_repo).await {
+            Ok(()) => exported_any = true,
+            Err(Error::PackageNotFound(_)) => {
+                tracing::debug!(%pkg, "no recipe found for version, skipping");
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if !exported_any {
+        return Err(Error::PackageNotFound(
+            VersionIdent::new_zero(name).into_any_ident(None),
+        ));
+    }
+    preserve_source_tags(source_repos, &target_repo).await?;
+    flush_archive_repo(&filename, &target_repo)
+}
+
+/// Import all of the recipes and package builds from an export archive
+/// into the destination repository.
+///
+/// The objects for each build are synced from the archive before it is
+/// published. Recipes and builds that already exist in the destination
+/// are overwritten or cause an error according to the given policy.
+///
+/// Returns the builds that were imported.
+pub async fn import_package(
+    archive: &Path,
+    dest: &SpfsRepository,
+    policy: PublishPolicy,
+) -> Result<Vec<BuildIdent>> {
+    let tar_repo = spfs::storage::tar::TarRepository::open(archive)
+        .await
+        .map_err(|source| spfs::Error::FailedToOpenRepository {
+            repository: "<TAR Archive>".into(),
+            source,
+        })?;
+    let src_repo = SpfsRepository::try_from(NameAndRepository::new(
+        "archive",
+        spfs::storage::RepositoryHandle::from(tar_repo),
+    ))?;
+    src_repo.check_compatibility().await?;
+    tracing::info!(?archive, "importing");
+
+    let mut imported = Vec::new();
+    for name in src_repo.list_packages().await? {
+        for version in src_repo.list_package_versions(&name).await?.iter() {
+            let pkg = VersionIdent::new(name.clone(), (**version).clone());
+            match src_repo.read_recipe(&pkg).await {
+                Ok(recipe) => dest.publish_recipe_to_storage(&recipe, policy).await?,
+                Err(Error::PackageNotFound(_)) => {
+                    tracing::debug!(%pkg, "no recipe found in archive, skipping");
+                }
+                Err(err) => return Err(err),
+            }
+
+            // sorted so that the import order is predictable
+            let builds: BTreeSet<_> = Storage::get_concrete_package_builds(&src_repo, &pkg)
+                .await?
+                .into_iter()
+                // Embedded stubs are recreated when importing their provider.
+                .filter(|build| !build.is_embedded())
+                .collect();
+            for build in builds {
+                import_build(&build, &src_repo, dest, policy).await?;
+                imported.push(build);
+            }
+        }
+    }
+    Ok(imported)
+}
+
+async fn import_build(
+    pkg: &BuildIdent,
+    src_repo: &SpfsRepository,
+    dst_repo: &SpfsRepository,
+    policy: PublishPolicy,
+) -> Result<()> {
+    let spec = src_repo.read_package(pkg).await?;
+    let components = src_repo.read_components(pkg).await?;
+    tracing::info!(%pkg, "importing");
+    let syncer = spfs::Syncer::new(src_repo, dst_repo)
+        .with_reporter(spfs::sync::reporter::SyncReporters::console());
+    let desired = components.values().copied().collect();
+    syncer.sync_env(desired).await?;
+    dst_repo
+        .publish_package_to_storage(&spec, &components, policy)
+        .await?;
+    if spec.ident().can_embed() {
+        for (embed, embedded_by) in dst_repo.get_embedded_providers(&spec)? {
+            dst_repo
+                .create_embedded_stub_for_spec(&spec, &embed, embedded_by)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Create a new, empty tar repository for an export at the given path.
+///
+/// Returns the absolute path to the archive along with the opened repository.
+async fn create_archive_repo(
+    filename: impl AsRef<Path>,
+    options: &ExportOptions,
+) -> Result<(std::path::PathBuf, SpfsRepository)> {
+    // Make filename absolute as spfs::runtime::makedirs_with_perms does not handle
+    // relative paths properly.
+    let filename = std::env::current_dir()
+        .map_err(|err| Error::String(format!("Failed to get current directory: {err}")))?
+        .join(filename);
+
+    if let Err(err) = std::fs::remove_file(&filename) {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => (),
+            _ => tracing::warn!("Error trying to remove old file: {:?}", err),
+        }
+    }
+
+    filename
+        .parent()
+        .map(|dir| {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| Error::DirectoryCreateError(dir.to_owned(), err))
+        })
+        .unwrap_or_else(|| Ok(()))?;
+
+    let tar_repo = spfs::storage::tar::TarRepository::create(&filename)
+        .await
+        .map_err(|source| spfs::Error::FailedToOpenRepository {
+            repository: "<TAR Archive>".into(),
+            source,
+        })?
+        .with_compression(options.compression);
+    // Package exports should not include the top-level directory for
+    // durable runtime upperdir edits.
+    tar_repo.remove_durable_dir().await?;
+
+    let target_repo = super::SpfsRepository::try_from(NameAndRepository::new(
+        "archive",
+        spfs::storage::RepositoryHandle::from(tar_repo),
+    ))?;
+    Ok((filename, target_repo))
+}
+
+fn flush_archive_repo(filename: &Path, target_repo: &SpfsRepository) -> Result<()> {
     tracing::info!(path=?filename, "building archive");
     use std::ops::Deref;
     if let spfs::storage::RepositoryHandle::Tar(tar) = target_repo.deref() {
@@ -148,15 +404,91 @@ pub async fn export_package(
     Ok(())
 }
 
+/// Replace the tags written into an export with the matching tags from
+/// the source repositories.
+///
+/// Publishing into the archive creates new tags with the current time
+/// and user, which would make every export of the same package differ.
+async fn preserve_source_tags(
+    source_repos: &[&SpfsRepository],
+    target_repo: &SpfsRepository,
+) -> Result<()> {
+    use spfs::storage::TagStorage;
+
+    let target = target_repo.inner();
+    let exported: Vec<_> = target.iter_tags().try_collect().await?;
+    'tag: for (tag_spec, exported_tag) in exported {
+        for repo in source_repos {
+            let Ok(source_tag) = repo.inner().resolve_tag(&tag_spec).await else {
+                continue;
+            };
+            if source_tag.target != exported_tag.target {
+                continue;
+            }
+            target.remove_tag_stream(&tag_spec).await?;
+            target.insert_tag(&source_tag).await?;
+            continue 'tag;
+        }
+    }
+    Ok(())
+}
+
+/// Copy a recipe from the first of the source repositories that has it.
+async fn copy_recipe_from_any(
+    source_repos: &[&SpfsRepository],
+    pkg: &VersionIdent,
+    dst_repo: &SpfsRepository,
+) -> Result<()> {
+    let mut first_error = None;
+    for repo in source_repos {
+        match copy_recipe(pkg, repo, dst_repo).await {
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                if first_error.is_none() {
+                    first_error = Some(err);
+                }
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| Error::PackageNotFound(pkg.to_any_ident(None))))
+}
+
+/// Copy a package build from the first of the source repositories that has it.
+async fn copy_package_from_any(
+    source_repos: &[&SpfsRepository],
+    pkg: &BuildIdent,
+    dst_repo: &SpfsRepository,
+) -> Result<()> {
+    let mut first_error = None;
+    for repo in source_repos {
+        match copy_package(pkg, repo, dst_repo, None).await {
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                if first_error.is_none() {
+                    first_error = Some(err);
+                }
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| Error::PackageNotFound(pkg.to_any_ident())))
+}
+
 async fn copy_any(
     pkg: AnyIdent,
     src_repo: &SpfsRepository,
     dst_repo: &SpfsRepository,
+    components: Option<&BTreeSet<Component>>,
 ) -> Result<()> {
     match pkg.into_inner() {
         (base, None) => copy_recipe(&base, src_repo, dst_repo).await,
         (base, Some(build)) => {
-            copy_package(&BuildIdent::new(base, build), src_repo, dst_repo).await
+            copy_package(
+                &BuildIdent::new(base, build),
+                src_repo,
+                dst_repo,
+                components,
+            )
+            .await
         }
     }
 }
@@ -172,13 +504,19 @@ async fn copy_recipe(
     Ok(())
 }
 
+/// Copy a package build, limited to the given components if any are
+/// specified.
 async fn copy_package(
     pkg: &BuildIdent,
     src_repo: &SpfsRepository,
     dst_repo: &SpfsRepository,
+    only_components: Option<&BTreeSet<Component>>,
 ) -> Result<()> {
     let spec = src_repo.read_package(pkg).await?;
-    let components = src_repo.read_components(pkg).await?;
+    let mut components = src_repo.read_components(pkg).await?;
+    if let Some(only_components) = only_components {
+        components.retain(|name, _| only_components.contains(name));
+    }
     tracing::info!(%pkg, "exporting");
     let syncer = spfs::Syncer::new(src_repo, dst_repo)
         .with_reporter(spfs::sync::reporter::SyncReporters::console());
@@ -187,3 +525,150 @@ async fn copy_package(
     dst_repo.publish_package(&spec, &components).await?;
     Ok(())
 }
+
+/// The outcome of verifying an export archive with [`verify_export_stream`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExportVerification {
+    /// The number of objects whose content matched their digest.
+    pub objects_verified: usize,
+    /// The number of payloads whose content matched their digest.
+    pub payloads_verified: usize,
+    /// The number of tag streams that were read from the archive.
+    pub tags_verified: usize,
+    /// Digests referenced by a tag or another object that were
+    /// not found in the archive.
+    pub missing: BTreeSet<spfs::encoding::Digest>,
+    /// Digests whose stored content does not hash back to the digest.
+    pub corrupt: BTreeSet<spfs::encoding::Digest>,
+}
+
+impl ExportVerification {
+    /// True if the archive had no missing or corrupt entries.
+    pub fn is_valid(&self) -> bool {
+        self.missing.is_empty() && self.corrupt.is_empty()
+    }
+}
+
+/// Verify an export archive as it is being read, without extracting it.
+///
+/// Each object and payload in the archive is re-hashed and compared
+/// against the digest that it was stored under, and every digest referenced
+/// by a tag or object is checked to be present. Only digests are kept in
+/// memory, so arbitrarily large archives can be verified.
+pub async fn verify_export_stream<R>(reader: R) -> Result<ExportVerification>
+where
+    R: tokio::io::AsyncRead + Send + Unpin + 'static,
+{
+    // The tar crate only works with blocking readers
+    let reader = tokio_util::io::SyncIoBridge::new(reader);
+    tokio::task::spawn_blocking(move || verify_export_reader(reader))
+        .await
+        .map_err(|err| Error::String(format!("Tokio join error: {err}")))?
+}
+
+fn verify_export_reader(reader: impl Read + Send) -> Result<ExportVerification> {
+    let read_error =
+        |err: std::io::Error| Error::String(format!("Failed to read export archive: {err}"));
+    let (_, reader) = spfs::storage::tar::decompress_archive(std::io::BufReader::new(reader))
+        .map_err(read_error)?;
+
+    let mut verification = ExportVerification::default();
+    let mut objects = BTreeSet::new();
+    let mut payloads = BTreeSet::new();
+    let mut referenced_objects = BTreeSet::new();
+    let mut referenced_payloads = BTreeSet::new();
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(read_error)? {
+        let mut entry = entry.map_err(read_error)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(read_error)?.into_owned();
+        let parts = path
+            .components()
+            .filter_map(|c| match c {
+                std::path::Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect_vec();
+        match parts.as_slice() {
+            [store, prefix, rest] if store == "objects" => {
+                let Ok(digest) = spfs::encoding::parse_digest(format!("{prefix}{rest}")) else {
+                    continue;
+                };
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf).map_err(read_error)?;
+                let object = match spfs::graph::Object::new(buf) {
+                    Ok(object) => object,
+                    Err(_) => {
+                        verification.corrupt.insert(digest);
+                        continue;
+                    }
+                };
+                match object.digest() {
+                    Ok(actual) if actual == digest => {}
+                    _ => {
+                        verification.corrupt.insert(digest);
+                        continue;
+                    }
+                }
+                if object.has_payload() {
+                    referenced_payloads.insert(digest);
+                }
+                referenced_objects.extend(object.child_objects());
+                objects.insert(digest);
+                verification.objects_verified += 1;
+            }
+            [store, prefix, rest] if store == "payloads" => {
+                let Ok(digest) = spfs::encoding::parse_digest(format!("{prefix}{rest}")) else {
+                    continue;
+                };
+                let mut hasher = spfs::encoding::Hasher::new_sync();
+                std::io::copy(&mut entry, &mut hasher).map_err(read_error)?;
+                if hasher.digest() != digest {
+                    verification.corrupt.insert(digest);
+                    continue;
+                }
+                payloads.insert(digest);
+                verification.payloads_verified += 1;
+            }
+            [store, ..] if store == "tags" && path.extension().is_some_and(|e| e == "tag") => {
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf).map_err(read_error)?;
+                // Tag files are a sequence of size-prefixed tags, with the
+                // latest version of the tag written last.
+                let mut remaining = buf.as_slice();
+                let mut latest = None;
+                while remaining.len() >= size_of::<i64>() {
+                    let (size, rest) = remaining.split_at(size_of::<i64>());
+                    let size = i64::from_be_bytes(size.try_into().expect("slice of 8 bytes"));
+                    let size = usize::try_from(size).unwrap_or(usize::MAX);
+                    if rest.len() < size {
+                        break;
+                    }
+                    let (mut tag_bytes, rest) = rest.split_at(size);
+                    latest = spfs::tracking::Tag::decode(&mut tag_bytes).ok();
+                    remaining = rest;
+                }
+                let Some(tag) = latest.filter(|_| remaining.is_empty()) else {
+                    return Err(Error::String(format!(
+                        "Invalid tag file in export archive: {}",
+                        path.display()
+                    )));
+                };
+                referenced_objects.insert(tag.target);
+                verification.tags_verified += 1;
+            }
+            _ => {}
+        }
+    }
+
+    verification.missing.extend(
+        referenced_objects
+            .difference(&objects)
+            .chain(referenced_payloads.difference(&payloads))
+            .filter(|digest| !verification.corrupt.contains(*digest)),
+    );
+    Ok(verification)
+}
@@ -0,0 +1,409 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use std::collections::HashMap;
+
+use rstest::{fixture, rstest};
+use spfs::storage::tar::ArchiveCompression;
+use spk_schema::foundation::ident_component::Component;
+use spk_schema::{Package, Recipe, Spec, SpecRecipe, recipe, spec};
+
+use super::{
+    ExportOptions,
+    export_package,
+    export_package_with_options,
+    export_packages,
+    export_recipe,
+    export_recipes,
+    import_package,
+    verify_export_stream,
+};
+use crate::fixtures::*;
+use crate::{
+    Error,
+    NameAndRepository,
+    PublishPolicy,
+    Repository,
+    RepositoryHandle,
+    SpfsRepository,
+};
+
+/// A temporary spfs repository with one published recipe and build.
+struct PublishedRepo {
+    repo: TempRepo,
+    recipe: SpecRecipe,
+    spec: Spec,
+    components: HashMap<Component, spfs::encoding::Digest>,
+}
+
+impl PublishedRepo {
+    fn spfs(&self) -> &SpfsRepository {
+        as_spfs(&self.repo)
+    }
+}
+
+fn as_spfs(repo: &TempRepo) -> &SpfsRepository {
+    let RepositoryHandle::SPFS(spfs_repo) = &*repo.repo else {
+        panic!("expected an spfs repository");
+    };
+    spfs_repo
+}
+
+/// Publishes `my-pkg/1.0.0` and a build of it with only a run component.
+#[fixture]
+async fn published() -> PublishedRepo {
+    let repo = make_repo(RepoKind::Spfs).await;
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+    PublishedRepo {
+        repo,
+        recipe,
+        spec,
+        components,
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_verify_export_stream(#[future] published: PublishedRepo) {
+    init_logging();
+    let published = published.await;
+    let PublishedRepo { repo, spec, .. } = &published;
+
+    let filename = repo.tmpdir.path().join("archive.spk");
+    export_package(&[published.spfs()], spec.ident().to_any_ident(), &filename)
+        .await
+        .unwrap();
+
+    let file = tokio::fs::File::open(&filename).await.unwrap();
+    let verification = verify_export_stream(file).await.unwrap();
+    assert!(
+        verification.is_valid(),
+        "a complete archive should verify: {verification:?}"
+    );
+    assert!(verification.tags_verified > 0);
+
+    // rewrite the archive without the layer that the package tags point to
+    let missing = empty_layer_digest();
+    let missing_name = missing.to_string()[2..].to_string();
+    let truncated = repo.tmpdir.path().join("truncated.spk");
+    {
+        let mut source = tar::Archive::new(std::fs::File::open(&filename).unwrap());
+        let mut builder = tar::Builder::new(std::fs::File::create(&truncated).unwrap());
+        for entry in source.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().into_owned();
+            if path.ends_with(&missing_name) {
+                continue;
+            }
+            let mut header = entry.header().clone();
+            builder.append_data(&mut header, path, &mut entry).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    let file = tokio::fs::File::open(&truncated).await.unwrap();
+    let verification = verify_export_stream(file).await.unwrap();
+    assert!(!verification.is_valid(), "a truncated archive should fail");
+    assert!(
+        verification.missing.contains(&missing),
+        "the removed layer should be reported missing: {verification:?}"
+    );
+}
+
+async fn open_archive(filename: &std::path::Path) -> SpfsRepository {
+    let tar = spfs::storage::tar::TarRepository::open(filename)
+        .await
+        .unwrap();
+    SpfsRepository::try_from(NameAndRepository::new(
+        "archive",
+        spfs::storage::RepositoryHandle::from(tar),
+    ))
+    .unwrap()
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_export_recipe_without_builds(#[future] published: PublishedRepo) {
+    init_logging();
+    let published = published.await;
+    let PublishedRepo { repo, recipe, .. } = &published;
+
+    let filename = repo.tmpdir.path().join("recipe.spk");
+    export_recipe(&[published.spfs()], recipe.ident(), &filename)
+        .await
+        .unwrap();
+
+    let archive = open_archive(&filename).await;
+    archive
+        .read_recipe(recipe.ident())
+        .await
+        .expect("recipe should be in the archive");
+    let builds = archive.list_package_builds(recipe.ident()).await.unwrap();
+    assert!(
+        builds.is_empty(),
+        "no builds should be exported: {builds:?}"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_export_recipes_all_versions() {
+    init_logging();
+    let repo = make_repo(RepoKind::Spfs).await;
+    repo.publish_recipe(&recipe!({"pkg": "my-pkg/1.0.0"}))
+        .await
+        .unwrap();
+    repo.publish_recipe(&recipe!({"pkg": "my-pkg/2.0.0"}))
+        .await
+        .unwrap();
+    let spfs_repo = as_spfs(&repo);
+
+    let filename = repo.tmpdir.path().join("recipes.spk");
+    let name = spk_schema::foundation::name::PkgName::new("my-pkg").unwrap();
+    export_recipes(&[spfs_repo], name, &filename).await.unwrap();
+
+    let archive = open_archive(&filename).await;
+    let versions = archive.list_package_versions(name).await.unwrap();
+    assert_eq!(versions.len(), 2, "all versions should be exported");
+
+    let missing = spk_schema::foundation::name::PkgName::new("other-pkg").unwrap();
+    let res = export_recipes(&[spfs_repo], missing, &filename).await;
+    assert!(
+        matches!(res, Err(crate::Error::PackageNotFound(_))),
+        "exporting a package with no recipes should fail"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_export_is_reproducible(#[future] published: PublishedRepo) {
+    init_logging();
+    let published = published.await;
+    let PublishedRepo { repo, spec, .. } = &published;
+
+    let first = repo.tmpdir.path().join("first.spk");
+    let second = repo.tmpdir.path().join("second.spk");
+    for filename in [&first, &second] {
+        export_package(&[published.spfs()], spec.ident().to_any_ident(), filename)
+            .await
+            .unwrap();
+    }
+
+    let first = std::fs::read(first).unwrap();
+    let second = std::fs::read(second).unwrap();
+    assert!(
+        first == second,
+        "exporting the same package twice should produce identical archives"
+    );
+}
+
+#[rstest]
+#[case::none(ArchiveCompression::None)]
+#[case::gzip(ArchiveCompression::Gzip)]
+#[case::zstd(ArchiveCompression::Zstd { level: 19 })]
+#[tokio::test]
+async fn test_export_package_compression_round_trip(
+    #[case] compression: ArchiveCompression,
+    #[future] published: PublishedRepo,
+) {
+    init_logging();
+    let published = published.await;
+    let PublishedRepo {
+        repo,
+        spec,
+        components,
+        ..
+    } = &published;
+
+    let filename = repo.tmpdir.path().join("archive.spk");
+    export_package_with_options(
+        &[published.spfs()],
+        spec.ident().to_any_ident(),
+        &filename,
+        &ExportOptions {
+            compression,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let header = std::fs::read(&filename).unwrap();
+    assert_eq!(
+        std::mem::discriminant(&ArchiveCompression::detect(&header)),
+        std::mem::discriminant(&compression),
+        "the archive should be written with the requested compression"
+    );
+
+    let tar = spfs::storage::tar::TarRepository::open(&filename)
+        .await
+        .unwrap();
+    assert_eq!(
+        std::mem::discriminant(&tar.compression()),
+        std::mem::discriminant(&compression),
+        "the compression should be detected when the archive is opened"
+    );
+    let archive = open_archive(&filename).await;
+    archive
+        .read_package(spec.ident())
+        .await
+        .expect("package should be in the archive");
+    assert_eq!(
+        &archive.read_components(spec.ident()).await.unwrap(),
+        components
+    );
+
+    let file = tokio::fs::File::open(&filename).await.unwrap();
+    let verification = verify_export_stream(file).await.unwrap();
+    assert!(
+        verification.is_valid(),
+        "a compressed archive should verify: {verification:?}"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_export_package_component_filter(#[future] published: PublishedRepo) {
+    init_logging();
+    let published = published.await;
+    let PublishedRepo { repo, spec, .. } = &published;
+    let components = vec![
+        (Component::Run, empty_layer_digest()),
+        (Component::Build, empty_layer_digest()),
+    ]
+    .into_iter()
+    .collect();
+    repo.publish_package(spec, &components).await.unwrap();
+
+    let filename = repo.tmpdir.path().join("archive.spk");
+    export_package_with_options(
+        &[published.spfs()],
+        spec.ident().to_any_ident(),
+        &filename,
+        &ExportOptions {
+            components: Some([Component::Run].into_iter().collect()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let archive = open_archive(&filename).await;
+    archive
+        .read_package(spec.ident())
+        .await
+        .expect("the spec should always be exported");
+    assert_eq!(
+        archive
+            .read_components(spec.ident())
+            .await
+            .unwrap()
+            .into_keys()
+            .collect::<Vec<_>>(),
+        vec![Component::Run],
+        "only the selected components should be exported"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_export_packages_into_one_archive() {
+    init_logging();
+    let repo = make_repo(RepoKind::Spfs).await;
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    let first = spec!({"pkg": "first-pkg/1.0.0/3I42H3S6"});
+    let second = spec!({"pkg": "second-pkg/2.0.0/3I42H3S6"});
+    let recipe = recipe!({"pkg": "first-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+    for pkg in [&first, &second] {
+        repo.publish_package(pkg, &components).await.unwrap();
+    }
+    let missing = spec!({"pkg": "missing-pkg/1.0.0/3I42H3S6"});
+    let spfs_repo = as_spfs(&repo);
+    let pkgs = vec![
+        first.ident().clone(),
+        second.ident().clone(),
+        missing.ident().clone(),
+    ];
+
+    let filename = repo.tmpdir.path().join("strict.spk");
+    export_packages(&[spfs_repo], &pkgs, &filename, true)
+        .await
+        .expect_err("a missing package should fail a strict export");
+
+    let filename = repo.tmpdir.path().join("packages.spk");
+    let failed = export_packages(&[spfs_repo], &pkgs, &filename, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        failed.iter().map(|(pkg, _)| pkg).collect::<Vec<_>>(),
+        vec![missing.ident()],
+        "only the missing package should be reported"
+    );
+
+    let archive = open_archive(&filename).await;
+    archive
+        .read_recipe(recipe.ident())
+        .await
+        .expect("recipes should be included when they exist");
+    for pkg in [&first, &second] {
+        archive
+            .read_package(pkg.ident())
+            .await
+            .expect("every readable package should be in the archive");
+        assert_eq!(
+            archive.read_components(pkg.ident()).await.unwrap(),
+            components
+        );
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_import_package_round_trip(#[future] published: PublishedRepo) {
+    init_logging();
+    let source = published.await;
+    let PublishedRepo {
+        recipe,
+        spec,
+        components,
+        ..
+    } = &source;
+    let filename = source.repo.tmpdir.path().join("archive.spk");
+    export_package(&[source.spfs()], spec.ident().to_any_ident(), &filename)
+        .await
+        .unwrap();
+
+    let dest = make_repo(RepoKind::Spfs).await;
+    let dest_repo = as_spfs(&dest);
+    let imported = import_package(&filename, dest_repo, PublishPolicy::DoNotOverwriteVersion)
+        .await
+        .unwrap();
+    assert_eq!(imported, vec![spec.ident().clone()]);
+    dest_repo
+        .read_recipe(recipe.ident())
+        .await
+        .expect("the recipe should be imported");
+    assert_eq!(
+        &dest_repo.read_components(spec.ident()).await.unwrap(),
+        components
+    );
+
+    let res = import_package(&filename, dest_repo, PublishPolicy::DoNotOverwriteVersion).await;
+    assert!(
+        matches!(res, Err(Error::VersionExists(_))),
+        "importing over an existing version should fail without overwrite, got {res:?}"
+    );
+    import_package(&filename, dest_repo, PublishPolicy::OverwriteVersion)
+        .await
+        .expect("importing with overwrite should replace the existing version");
+}
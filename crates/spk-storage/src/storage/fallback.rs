@@ -0,0 +1,239 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+
+use spk_schema::foundation::ident_component::Component;
+use spk_schema::foundation::name::{PkgName, PkgNameBuf, RepositoryName};
+use spk_schema::foundation::version::Version;
+use spk_schema::{BuildIdent, Spec, SpecRecipe, VersionIdent};
+
+use super::repository::{PublishPolicy, PublishResult, Storage};
+use super::{CachePolicy, Repository, SpfsRepository};
+use crate::{Error, Result};
+
+#[cfg(test)]
+#[path = "./fallback_test.rs"]
+mod fallback_test;
+
+/// Open the local repository and the named remote repository as a
+/// single repository that reads from local before remote.
+///
+/// All writes are made to the local repository.
+pub async fn local_and_remote<S: AsRef<str>>(remote_name: S) -> Result<FallbackRepository> {
+    let local = super::local_repository().await?;
+    let remote = super::remote_repository(remote_name).await?;
+    Ok(FallbackRepository::new(local, vec![remote]))
+}
+
+/// A repository that reads through a primary repository and then
+/// each of its secondary repositories, in order.
+///
+/// Listings are merged from all of the repositories, and packages are
+/// read from the first repository that has them. All writes are made
+/// to the primary repository only.
+#[derive(Clone, Debug)]
+pub struct FallbackRepository {
+    primary: SpfsRepository,
+    secondary: Vec<SpfsRepository>,
+}
+
+impl FallbackRepository {
+    pub fn new(primary: SpfsRepository, secondary: Vec<SpfsRepository>) -> Self {
+        Self { primary, secondary }
+    }
+
+    /// The repository that all writes are made to.
+    pub fn primary(&self) -> &SpfsRepository {
+        &self.primary
+    }
+
+    /// The repositories that are read from when the primary does not
+    /// have what is requested.
+    pub fn secondary(&self) -> &[SpfsRepository] {
+        &self.secondary
+    }
+
+    /// All of the repositories, in the order that they are read from.
+    fn repos(&self) -> impl Iterator<Item = &SpfsRepository> {
+        std::iter::once(&self.primary).chain(self.secondary.iter())
+    }
+
+    /// Return the first successful read from the repositories, skipping
+    /// any that do not have the requested package.
+    async fn read_first<'a, T, F, Fut>(&'a self, read: F) -> Result<T>
+    where
+        F: Fn(&'a SpfsRepository) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut not_found = None;
+        for repo in self.repos() {
+            match read(repo).await {
+                Ok(value) => return Ok(value),
+                Err(err @ Error::PackageNotFound(_)) => {
+                    not_found.get_or_insert(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(not_found.expect("there is always a primary repository"))
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for FallbackRepository {
+    type Recipe = SpecRecipe;
+    type Package = Spec;
+
+    async fn get_concrete_package_builds(&self, pkg: &VersionIdent) -> Result<HashSet<BuildIdent>> {
+        let mut builds = HashSet::new();
+        for repo in self.repos() {
+            builds.extend(repo.get_concrete_package_builds(pkg).await?);
+        }
+        Ok(builds)
+    }
+
+    async fn get_embedded_package_builds(&self, pkg: &VersionIdent) -> Result<HashSet<BuildIdent>> {
+        let mut builds = HashSet::new();
+        for repo in self.repos() {
+            builds.extend(repo.get_embedded_package_builds(pkg).await?);
+        }
+        Ok(builds)
+    }
+
+    async fn publish_embed_stub_to_storage(&self, spec: &Self::Package) -> Result<()> {
+        self.primary.publish_embed_stub_to_storage(spec).await
+    }
+
+    async fn publish_package_to_storage(
+        &self,
+        package: &<Self::Recipe as spk_schema::Recipe>::Output,
+        components: &HashMap<Component, spfs::encoding::Digest>,
+        publish_policy: PublishPolicy,
+    ) -> Result<PublishResult> {
+        self.primary
+            .publish_package_to_storage(package, components, publish_policy)
+            .await
+    }
+
+    async fn publish_recipe_to_storage(
+        &self,
+        spec: &Self::Recipe,
+        publish_policy: PublishPolicy,
+    ) -> Result<()> {
+        self.primary
+            .publish_recipe_to_storage(spec, publish_policy)
+            .await
+    }
+
+    async fn read_components_from_storage(
+        &self,
+        pkg: &BuildIdent,
+    ) -> Result<HashMap<Component, spfs::encoding::Digest>> {
+        self.read_first(|repo| repo.read_components_from_storage(pkg))
+            .await
+    }
+
+    async fn read_package_from_storage(
+        &self,
+        pkg: &BuildIdent,
+    ) -> Result<Arc<<Self::Recipe as spk_schema::Recipe>::Output>> {
+        self.read_first(|repo| repo.read_package_from_storage(pkg))
+            .await
+    }
+
+    async fn remove_embed_stub_from_storage(&self, pkg: &BuildIdent) -> Result<()> {
+        self.primary.remove_embed_stub_from_storage(pkg).await
+    }
+
+    async fn remove_package_from_storage(&self, pkg: &BuildIdent) -> Result<()> {
+        self.primary.remove_package_from_storage(pkg).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Repository for FallbackRepository {
+    fn address(&self) -> &url::Url {
+        self.primary.address()
+    }
+
+    async fn list_packages(&self) -> Result<Vec<PkgNameBuf>> {
+        let mut packages = BTreeSet::new();
+        for repo in self.repos() {
+            packages.extend(repo.list_packages().await?);
+        }
+        Ok(packages.into_iter().collect())
+    }
+
+    async fn list_package_versions(&self, name: &PkgName) -> Result<Arc<Vec<Arc<Version>>>> {
+        let mut versions = BTreeSet::new();
+        for repo in self.repos() {
+            versions.extend(repo.list_package_versions(name).await?.iter().cloned());
+        }
+        Ok(Arc::new(versions.into_iter().collect()))
+    }
+
+    async fn list_build_components(&self, pkg: &BuildIdent) -> Result<Vec<Component>> {
+        for repo in self.repos() {
+            let components = repo.list_build_components(pkg).await?;
+            if !components.is_empty() {
+                return Ok(components);
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    fn name(&self) -> &RepositoryName {
+        self.primary.name()
+    }
+
+    async fn read_embed_stub(&self, pkg: &BuildIdent) -> Result<Arc<Self::Package>> {
+        self.read_first(|repo| repo.read_embed_stub(pkg)).await
+    }
+
+    async fn read_recipe(&self, pkg: &VersionIdent) -> Result<Arc<Self::Recipe>> {
+        self.read_first(|repo| repo.read_recipe(pkg)).await
+    }
+
+    async fn get_package_publish_time(
+        &self,
+        pkg: &BuildIdent,
+    ) -> Result<chrono::DateTime<chrono::Utc>> {
+        self.read_first(|repo| repo.get_package_publish_time(pkg))
+            .await
+    }
+
+    async fn component_digest(
+        &self,
+        pkg: &BuildIdent,
+        component: &Component,
+    ) -> Result<Option<spfs::encoding::Digest>> {
+        self.read_first(|repo| repo.component_digest(pkg, component))
+            .await
+    }
+
+    /// A version is yanked if it has been yanked in any of the
+    /// repositories.
+    async fn is_yanked(&self, pkg: &VersionIdent) -> Result<bool> {
+        for repo in self.repos() {
+            if repo.is_yanked(pkg).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn remove_recipe(&self, pkg: &VersionIdent) -> Result<()> {
+        self.primary.remove_recipe(pkg).await
+    }
+
+    fn set_cache_policy(&self, cache_policy: CachePolicy) -> CachePolicy {
+        for repo in self.secondary.iter() {
+            repo.set_cache_policy(cache_policy);
+        }
+        self.primary.set_cache_policy(cache_policy)
+    }
+}
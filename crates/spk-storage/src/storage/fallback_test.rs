@@ -0,0 +1,71 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use rstest::rstest;
+use spk_schema::foundation::fixtures::*;
+use spk_schema::foundation::ident_component::Component;
+use spk_schema::{Package, Recipe, recipe, spec};
+
+use super::FallbackRepository;
+use crate::fixtures::{RepoKind, empty_layer_digest, make_repo};
+use crate::{Repository, RepositoryHandle};
+
+#[rstest]
+#[tokio::test]
+async fn test_fallback_reads_remote_and_writes_local() {
+    init_logging();
+    let local = make_repo(RepoKind::Spfs).await;
+    let remote = make_repo(RepoKind::Spfs).await;
+    let (RepositoryHandle::SPFS(local_repo), RepositoryHandle::SPFS(remote_repo)) =
+        (&*local.repo, &*remote.repo)
+    else {
+        panic!("expected spfs repositories");
+    };
+
+    let remote_recipe = recipe!({"pkg": "remote-pkg/1.0.0"});
+    remote_repo.publish_recipe(&remote_recipe).await.unwrap();
+    let remote_spec = spec!({"pkg": "remote-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    remote_repo
+        .publish_package(&remote_spec, &components)
+        .await
+        .unwrap();
+
+    let combined = FallbackRepository::new(local_repo.clone(), vec![remote_repo.clone()]);
+    combined
+        .read_recipe(remote_recipe.ident())
+        .await
+        .expect("a recipe only in the remote should be readable");
+    combined
+        .read_package(remote_spec.ident())
+        .await
+        .expect("a package only in the remote should be readable");
+    assert_eq!(
+        combined.read_components(remote_spec.ident()).await.unwrap(),
+        components
+    );
+
+    let local_recipe = recipe!({"pkg": "local-pkg/1.0.0"});
+    combined.publish_recipe(&local_recipe).await.unwrap();
+    local_repo
+        .read_recipe(local_recipe.ident())
+        .await
+        .expect("writes should land in the local repository");
+    assert!(
+        remote_repo
+            .read_recipe(local_recipe.ident())
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "writes should not be made to the remote repository"
+    );
+
+    let packages = combined.list_packages().await.unwrap();
+    assert_eq!(
+        packages.iter().map(|pkg| pkg.as_str()).collect::<Vec<_>>(),
+        vec!["local-pkg", "remote-pkg"]
+    );
+}
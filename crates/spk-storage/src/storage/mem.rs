@@ -2,10 +2,12 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
+use std::path::Path;
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use spk_schema::foundation::ident_build::Build;
 use spk_schema::foundation::ident_component::Component;
 use spk_schema::foundation::name::{PkgName, PkgNameBuf, RepositoryName, RepositoryNameBuf};
@@ -13,15 +15,21 @@ use spk_schema::foundation::version::Version;
 use spk_schema::{BuildIdent, Spec, SpecRecipe, VersionIdent};
 use tokio::sync::RwLock;
 
-use super::Repository;
-use super::repository::{PublishPolicy, Storage};
+use super::repository::{PublishPolicy, PublishResult, Storage};
+use super::{Repository, TagHistoryEntry};
 use crate::{Error, Result};
 
+#[cfg(test)]
+#[path = "./mem_test.rs"]
+mod mem_test;
+
 type ComponentMap = HashMap<Component, spfs::encoding::Digest>;
 type PackageMap<T> = HashMap<PkgNameBuf, VersionMap<T>>;
 type VersionMap<T> = HashMap<Version, T>;
 type BuildMap<Package> = HashMap<Build, (Arc<Package>, ComponentMap)>;
 type StubMap<Package> = HashMap<Build, Arc<Package>>;
+/// Every version of each build's spec, oldest first, like the spec tag stream in spfs.
+type HistoryMap = HashMap<BuildIdent, Vec<TagHistoryEntry>>;
 
 #[derive(Clone, Debug)]
 pub struct MemRepository<Recipe = SpecRecipe, Package = Spec>
@@ -34,6 +42,9 @@ where
     specs: Arc<RwLock<PackageMap<Arc<Recipe>>>>,
     packages: Arc<RwLock<PackageMap<BuildMap<Recipe::Output>>>>,
     embedded_stubs: Arc<RwLock<PackageMap<StubMap<Package>>>>,
+    build_history: Arc<RwLock<HistoryMap>>,
+    /// The user that is recorded in the history of each publish
+    user: String,
     _marker: std::marker::PhantomData<Package>,
 }
 
@@ -56,9 +67,229 @@ where
             specs,
             packages: Arc::default(),
             embedded_stubs: Arc::default(),
+            build_history: Arc::default(),
+            // an invalid config should not stop an in-memory repo
+            // from being used, the user is only informational
+            user: spfs::get_config()
+                .map(|config| config.user.to_string())
+                .unwrap_or_else(|_| spfs::config::User::default().to_string()),
             _marker: std::marker::PhantomData,
         }
     }
+
+    /// Return every version of the given build that was published,
+    /// with the newest first.
+    ///
+    /// This mirrors [`super::SpfsRepository::package_tag_history`],
+    /// where each target is the digest that the published spec would
+    /// have if it were stored in spfs.
+    pub async fn package_tag_history(&self, pkg: &BuildIdent) -> Result<Vec<TagHistoryEntry>> {
+        match self.build_history.read().await.get(pkg) {
+            Some(history) if !history.is_empty() => Ok(history.iter().rev().cloned().collect()),
+            _ => Err(Error::PackageNotFound(pkg.to_any_ident())),
+        }
+    }
+}
+
+/// The contents of a [`MemRepository`], as written by [`MemRepository::dump_to`].
+///
+/// Specs are stored in the same form that they are published in, and
+/// everything is sorted so that the file can be checked in and edited
+/// by hand.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MemSnapshot {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    recipes: Vec<serde_yaml::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    packages: Vec<MemSnapshotPackage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    embedded_stubs: Vec<serde_yaml::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<MemSnapshotHistory>,
+}
+
+/// A package build in a [`MemSnapshot`], with the digest of each of its components.
+#[derive(Debug, Serialize, Deserialize)]
+struct MemSnapshotPackage {
+    spec: serde_yaml::Value,
+    #[serde(default)]
+    components: BTreeMap<String, String>,
+}
+
+/// The publish history of a package build in a [`MemSnapshot`], oldest first.
+#[derive(Debug, Serialize, Deserialize)]
+struct MemSnapshotHistory {
+    pkg: String,
+    entries: Vec<MemSnapshotHistoryEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MemSnapshotHistoryEntry {
+    target: String,
+    user: String,
+    time: chrono::DateTime<chrono::Utc>,
+}
+
+impl MemRepository {
+    /// Write all of the recipes, packages and component digests in
+    /// this repository to a single yaml file.
+    ///
+    /// The file can be loaded into a new repository with
+    /// [`MemRepository::load_from`].
+    pub async fn dump_to(&self, path: &Path) -> Result<()> {
+        let (specs, packages, embedded_stubs, build_history) = tokio::join!(
+            self.specs.read(),
+            self.packages.read(),
+            self.embedded_stubs.read(),
+            self.build_history.read()
+        );
+
+        let mut snapshot = MemSnapshot::default();
+        for recipe in sorted_entries(&specs) {
+            snapshot.recipes.push(encode_spec(&**recipe)?);
+        }
+        for (spec, components) in sorted_entries(&packages).flat_map(sorted_values) {
+            snapshot.packages.push(MemSnapshotPackage {
+                spec: encode_spec(&**spec)?,
+                components: components
+                    .iter()
+                    .map(|(component, digest)| (component.to_string(), digest.to_string()))
+                    .collect(),
+            });
+        }
+        for stub in sorted_entries(&embedded_stubs).flat_map(sorted_values) {
+            snapshot.embedded_stubs.push(encode_spec(&**stub)?);
+        }
+        let mut history = build_history.iter().collect::<Vec<_>>();
+        history.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (pkg, entries) in history {
+            snapshot.history.push(MemSnapshotHistory {
+                pkg: pkg.to_string(),
+                entries: entries
+                    .iter()
+                    .map(|entry| MemSnapshotHistoryEntry {
+                        target: entry.target.to_string(),
+                        user: entry.user.clone(),
+                        time: entry.time,
+                    })
+                    .collect(),
+            });
+        }
+
+        let yaml = serde_yaml::to_string(&snapshot)
+            .map_err(|err| Error::SpkSpecError(spk_schema::Error::SpecEncodingError(err)))?;
+        std::fs::write(path, yaml).map_err(|err| Error::FileWriteError(path.to_owned(), err))
+    }
+
+    /// Create a new repository from the contents of a yaml file that
+    /// was written by [`MemRepository::dump_to`].
+    pub fn load_from(path: &Path) -> Result<Self> {
+        use spk_schema::{FromYaml, Package, Recipe};
+
+        let yaml = std::fs::read_to_string(path)
+            .map_err(|err| Error::FileReadError(path.to_owned(), err))?;
+        let snapshot: MemSnapshot = serde_yaml::from_str(&yaml).map_err(|err| {
+            Error::String(format!(
+                "Invalid repository snapshot {}: {err}",
+                path.display()
+            ))
+        })?;
+        let decode = |spec: &serde_yaml::Value| {
+            serde_yaml::to_string(spec)
+                .map_err(|err| Error::SpkSpecError(spk_schema::Error::SpecEncodingError(err)))
+        };
+
+        let mut specs = PackageMap::<Arc<SpecRecipe>>::new();
+        for recipe in snapshot.recipes.iter() {
+            let recipe = SpecRecipe::from_yaml(decode(recipe)?)
+                .map_err(|err| Error::String(format!("Invalid recipe in snapshot: {err}")))?;
+            let ident = recipe.ident().clone();
+            specs
+                .entry(ident.name().to_owned())
+                .or_default()
+                .insert(ident.version().clone(), Arc::new(recipe));
+        }
+
+        let mut packages = PackageMap::<BuildMap<Spec>>::new();
+        for package in snapshot.packages.iter() {
+            let spec = Spec::from_yaml(decode(&package.spec)?)
+                .map_err(|err| Error::String(format!("Invalid package in snapshot: {err}")))?;
+            let mut components = ComponentMap::with_capacity(package.components.len());
+            for (component, digest) in package.components.iter() {
+                components.insert(
+                    Component::parse(component)?,
+                    spfs::encoding::parse_digest(digest)?,
+                );
+            }
+            let ident = spec.ident().clone();
+            packages
+                .entry(ident.name().to_owned())
+                .or_default()
+                .entry(ident.version().clone())
+                .or_default()
+                .insert(ident.build().clone(), (Arc::new(spec), components));
+        }
+
+        let mut embedded_stubs = PackageMap::<StubMap<Spec>>::new();
+        for stub in snapshot.embedded_stubs.iter() {
+            let stub = Spec::from_yaml(decode(stub)?)
+                .map_err(|err| Error::String(format!("Invalid embed stub in snapshot: {err}")))?;
+            let ident = stub.ident().clone();
+            embedded_stubs
+                .entry(ident.name().to_owned())
+                .or_default()
+                .entry(ident.version().clone())
+                .or_default()
+                .insert(ident.build().clone(), Arc::new(stub));
+        }
+
+        let mut build_history = HistoryMap::new();
+        for history in snapshot.history.iter() {
+            let pkg = spk_schema::ident::parse_build_ident(&history.pkg)?;
+            let mut entries = Vec::with_capacity(history.entries.len());
+            for entry in history.entries.iter() {
+                entries.push(TagHistoryEntry {
+                    target: spfs::encoding::parse_digest(&entry.target)?,
+                    user: entry.user.clone(),
+                    time: entry.time,
+                });
+            }
+            build_history.insert(pkg, entries);
+        }
+
+        let mut repo = Self::new();
+        repo.specs = Arc::new(RwLock::new(specs));
+        repo.packages = Arc::new(RwLock::new(packages));
+        repo.embedded_stubs = Arc::new(RwLock::new(embedded_stubs));
+        repo.build_history = Arc::new(RwLock::new(build_history));
+        Ok(repo)
+    }
+}
+
+/// Iterate the values of a package map, sorted by name and version.
+fn sorted_entries<T>(map: &PackageMap<T>) -> impl Iterator<Item = &T> {
+    let mut entries = map
+        .iter()
+        .flat_map(|(name, versions)| {
+            versions
+                .iter()
+                .map(move |(version, value)| ((name, version), value))
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries.into_iter().map(|(_, value)| value)
+}
+
+/// Iterate the values of a map, sorted by key.
+fn sorted_values<K: Ord, V>(map: &HashMap<K, V>) -> impl Iterator<Item = &V> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries.into_iter().map(|(_, value)| value)
+}
+
+fn encode_spec<T: Serialize>(spec: &T) -> Result<serde_yaml::Value> {
+    serde_yaml::to_value(spec)
+        .map_err(|err| Error::SpkSpecError(spk_schema::Error::SpecEncodingError(err)))
 }
 
 impl<Recipe, Package> Default for MemRepository<Recipe>
@@ -109,7 +340,7 @@ impl<Recipe> Eq for MemRepository<Recipe> where Recipe: spk_schema::Recipe + Sen
 impl<Recipe, Package> Storage for MemRepository<Recipe, Package>
 where
     Recipe: spk_schema::Recipe<Output = Package> + Send + Sync,
-    Package: spk_schema::Package<Package = Package> + Send + Sync,
+    Package: spk_schema::Package<Package = Package> + Serialize + Send + Sync,
 {
     type Recipe = Recipe;
     type Package = Package;
@@ -158,16 +389,51 @@ where
         &self,
         package: &<Self::Recipe as spk_schema::Recipe>::Output,
         components: &ComponentMap,
-    ) -> Result<()> {
+        publish_policy: PublishPolicy,
+    ) -> Result<PublishResult> {
         // Caller has already proven that build is `Some`.
         let build = package.ident().build().clone();
 
         let mut packages = self.packages.write().await;
         let versions = packages.entry(package.name().to_owned()).or_default();
         let builds = versions.entry(package.version().clone()).or_default();
+        if matches!(publish_policy, PublishPolicy::DoNotOverwriteVersion)
+            && builds.contains_key(&build)
+        {
+            return Err(Error::VersionExists(
+                package.ident().clone().to_version_ident(),
+            ));
+        }
 
         builds.insert(build, (Arc::new(package.clone()), components.clone()));
-        Ok(())
+        drop(packages);
+
+        // Nothing in memory is stored as a tag, but the history is
+        // kept the same way that the spec tag stream would keep it,
+        // pointing at the digest of the spec that spfs would store.
+        let payload = serde_yaml::to_string(package)
+            .map_err(|err| Error::SpkSpecError(spk_schema::Error::SpecEncodingError(err)))?;
+        let mut hasher = spfs::encoding::Hasher::new_sync();
+        hasher.update(payload.as_bytes());
+        let entry = TagHistoryEntry {
+            target: hasher.digest(),
+            user: self.user.clone(),
+            time: chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 6),
+        };
+        self.build_history
+            .write()
+            .await
+            .entry(package.ident().clone())
+            .or_default()
+            .push(entry);
+
+        Ok(PublishResult {
+            ident: package.ident().clone(),
+            tags_written: Vec::new(),
+            spec_digest: None,
+            components: components.clone(),
+            legacy_tag: None,
+        })
     }
 
     async fn publish_recipe_to_storage(
@@ -267,6 +533,7 @@ where
         if builds.remove(pkg.build()).is_none() {
             Err(Error::PackageNotFound(pkg.to_any_ident()))
         } else {
+            self.build_history.write().await.remove(pkg);
             Ok(())
         }
     }
@@ -277,7 +544,7 @@ impl<Recipe, Package> Repository for MemRepository<Recipe, Package>
 where
     Recipe: spk_schema::Recipe<Output = Package> + Clone + Send + Sync,
     Recipe::Output: spk_schema::Package + Clone + Send + Sync,
-    Package: spk_schema::Package<Package = Package> + Send + Sync,
+    Package: spk_schema::Package<Package = Package> + Serialize + Send + Sync,
 {
     fn address(&self) -> &url::Url {
         &self.address
@@ -356,6 +623,20 @@ where
             .ok_or_else(|| Error::PackageNotFound(pkg.to_any_ident(None)))
     }
 
+    async fn get_package_publish_time(
+        &self,
+        pkg: &BuildIdent,
+    ) -> Result<chrono::DateTime<chrono::Utc>> {
+        // the first publish, like the earliest tag in spfs
+        self.build_history
+            .read()
+            .await
+            .get(pkg)
+            .and_then(|history| history.first())
+            .map(|entry| entry.time)
+            .ok_or_else(|| Error::PackageNotFound(pkg.to_any_ident()))
+    }
+
     async fn remove_recipe(&self, pkg: &VersionIdent) -> Result<()> {
         let mut specs = self.specs.write().await;
         match specs.get_mut(pkg.name()) {
@@ -0,0 +1,131 @@
+// Copyright (c) Contributors to the SPK project.
+// SPDX-License-Identifier: Apache-2.0
+// https://github.com/spkenv/spk
+
+use std::str::FromStr;
+
+use rstest::rstest;
+use spk_schema::foundation::fixtures::*;
+use spk_schema::foundation::ident_component::Component;
+use spk_schema::{Package, Recipe, VersionIdent, recipe, spec};
+
+use super::MemRepository;
+use crate::fixtures::empty_layer_digest;
+use crate::{Repository, Storage};
+
+#[rstest]
+#[tokio::test]
+async fn test_dump_and_load_snapshot(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo: MemRepository = MemRepository::new();
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+    let spec = spec!({
+        "pkg": "my-pkg/1.0.0/3I42H3S6",
+        "install": {
+            "embedded": [
+                {"pkg": "my-embedded-pkg/1.0.0/embedded"}
+            ]
+        }
+    });
+    let components = vec![
+        (Component::Run, empty_layer_digest()),
+        (Component::Build, empty_layer_digest()),
+    ]
+    .into_iter()
+    .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    let filename = tmpdir.path().join("repo.yaml");
+    repo.dump_to(&filename).await.unwrap();
+    let loaded = MemRepository::load_from(&filename).unwrap();
+
+    assert_eq!(
+        *loaded.read_recipe(recipe.ident()).await.unwrap(),
+        recipe,
+        "recipes should be restored"
+    );
+    assert_eq!(*loaded.read_package(spec.ident()).await.unwrap(), spec);
+    assert_eq!(
+        loaded.read_components(spec.ident()).await.unwrap(),
+        components
+    );
+    let embedded = VersionIdent::from_str("my-embedded-pkg/1.0.0").unwrap();
+    assert_eq!(
+        loaded.list_package_builds(&embedded).await.unwrap(),
+        repo.list_package_builds(&embedded).await.unwrap(),
+        "embed stubs should be restored"
+    );
+    assert_eq!(
+        loaded.package_tag_history(spec.ident()).await.unwrap(),
+        repo.package_tag_history(spec.ident()).await.unwrap(),
+        "the publish history should be restored"
+    );
+
+    let redumped = tmpdir.path().join("redumped.yaml");
+    loaded.dump_to(&redumped).await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&filename).unwrap(),
+        std::fs::read_to_string(&redumped).unwrap(),
+        "the snapshot format should be stable"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_package_tag_history() {
+    init_logging();
+    let repo: MemRepository = MemRepository::new();
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let first = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &first).await.unwrap();
+
+    let updated = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6", "deprecated": true});
+    let second = vec![
+        (Component::Run, empty_layer_digest()),
+        (Component::Build, empty_layer_digest()),
+    ]
+    .into_iter()
+    .collect();
+    repo.publish_package(&updated, &second).await.unwrap();
+
+    let history = repo.package_tag_history(spec.ident()).await.unwrap();
+    assert_eq!(history.len(), 2, "each publish should be kept");
+    assert!(history[0].time >= history[1].time, "newest should be first");
+    let mut hasher = spfs::encoding::Hasher::new_sync();
+    hasher.update(serde_yaml::to_string(&updated).unwrap().as_bytes());
+    assert_eq!(
+        history[0].target,
+        hasher.digest(),
+        "the target should be the digest of the published spec"
+    );
+    assert_ne!(history[0].target, history[1].target);
+    assert_eq!(
+        *repo.read_package(spec.ident()).await.unwrap(),
+        updated,
+        "reads should see the newest publish"
+    );
+    assert_eq!(repo.read_components(spec.ident()).await.unwrap(), second);
+
+    let err = repo
+        .publish_package_to_storage(&spec, &first, crate::PublishPolicy::DoNotOverwriteVersion)
+        .await
+        .expect_err("an existing build should not be overwritten");
+    assert!(matches!(err, crate::Error::VersionExists(_)));
+    assert_eq!(
+        repo.package_tag_history(spec.ident()).await.unwrap().len(),
+        2,
+        "a rejected publish should not be recorded"
+    );
+
+    repo.remove_package(spec.ident()).await.unwrap();
+    assert!(
+        repo.package_tag_history(spec.ident())
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "removing a build should remove its history"
+    );
+}
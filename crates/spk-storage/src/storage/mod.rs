@@ -3,16 +3,57 @@
 // https://github.com/spkenv/spk
 
 mod archive;
+mod fallback;
 mod handle;
 mod mem;
 mod repository;
 mod runtime;
 mod spfs;
 
-pub use archive::export_package;
+pub use archive::{
+    ExportOptions,
+    ExportVerification,
+    export_package,
+    export_package_with_options,
+    export_packages,
+    export_recipe,
+    export_recipes,
+    import_package,
+    verify_export_stream,
+};
+pub use fallback::{FallbackRepository, local_and_remote};
 pub use handle::RepositoryHandle;
 pub use mem::MemRepository;
-pub use repository::{CachePolicy, Repository, Storage};
+pub use repository::{
+    BuildSelectionPolicy,
+    CachePolicy,
+    PublishPolicy,
+    PublishResult,
+    Repository,
+    Storage,
+};
 pub use runtime::{RuntimeRepository, find_path_providers, pretty_print_filepath};
 
-pub use self::spfs::{NameAndRepository, SpfsRepository, local_repository, remote_repository};
+pub use self::spfs::{
+    CachePolicyGuard,
+    EmbedStubDecodeError,
+    LazyComponentMap,
+    NameAndRepository,
+    NameCollision,
+    RecipeMigration,
+    RecipeMigrations,
+    RepositoryDescription,
+    RetryPolicy,
+    SkippedEntry,
+    SpecRewriter,
+    SpfsRepository,
+    TagHistoryEntry,
+    UpgradeAction,
+    UpgradePlan,
+    VerificationIssue,
+    VersionBundle,
+    local_repository,
+    normalize_repository_address,
+    purge_unused_address_caches,
+    remote_repository,
+};
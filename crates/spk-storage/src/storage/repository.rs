@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
 
 use relative_path::RelativePathBuf;
@@ -25,11 +25,22 @@ mod repository_test;
 pub enum CachePolicy {
     CacheOk,
     BypassCache,
+    /// Serve cached errors, such as packages that were not found, but
+    /// always re-fetch anything that was previously read successfully.
+    CacheErrorsOnly,
 }
 
 impl CachePolicy {
     /// Return true if the policy allows for a cached result.
+    ///
+    /// Under [`CachePolicy::CacheErrorsOnly`] this is only true for
+    /// cached errors, see [`CachePolicy::cached_success_permitted`].
     pub fn cached_result_permitted(&self) -> bool {
+        matches!(self, CachePolicy::CacheOk | CachePolicy::CacheErrorsOnly)
+    }
+
+    /// Return true if the policy allows for a cached successful result.
+    pub fn cached_success_permitted(&self) -> bool {
         matches!(self, CachePolicy::CacheOk)
     }
 }
@@ -41,6 +52,34 @@ pub enum PublishPolicy {
     DoNotOverwriteVersion,
 }
 
+/// How [`Repository::latest_build`] chooses between the builds of a version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildSelectionPolicy {
+    /// The build that was first published most recently.
+    MostRecentlyPublished,
+    /// The build with the lexicographically greatest build digest.
+    GreatestDigest,
+    /// The greatest build digest amongst the builds that match the
+    /// host options, or amongst all of the builds if none match.
+    PreferHostOptions,
+}
+
+/// A description of everything that was written when publishing a package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublishResult {
+    /// The package that was published.
+    pub ident: BuildIdent,
+    /// Every tag that was written, in the order that they were written.
+    pub tags_written: Vec<spfs::tracking::TagSpec>,
+    /// The digest of the stored package spec, if the repository
+    /// stores specs as spfs payloads.
+    pub spec_digest: Option<spfs::encoding::Digest>,
+    /// The layer digest published for each component.
+    pub components: HashMap<Component, spfs::encoding::Digest>,
+    /// The legacy (componentless) tag, if one was written.
+    pub legacy_tag: Option<spfs::tracking::TagSpec>,
+}
+
 /// Low level storage operations.
 ///
 /// These methods are expected to have different implementations for different
@@ -102,11 +141,16 @@ pub trait Storage: Sync {
     ///
     /// The provided component digests are expected to each identify an spfs
     /// layer which contains properly constructed binary package files and metadata.
+    ///
+    /// # Errors:
+    /// - VersionExists: if the package build is already present and
+    ///   `publish_policy` does not allow overwrite.
     async fn publish_package_to_storage(
         &self,
         package: &<Self::Recipe as spk_schema::Recipe>::Output,
         components: &HashMap<Component, spfs::encoding::Digest>,
-    ) -> Result<()>;
+        publish_policy: PublishPolicy,
+    ) -> Result<PublishResult>;
 
     /// Publish a package spec to this repository.
     ///
@@ -317,6 +361,36 @@ pub trait Repository: Storage + Sync {
         Ok(concrete.into_iter().collect())
     }
 
+    /// Return the source builds for the given package name and version.
+    ///
+    /// Unlike [`Self::list_package_builds`], embedded builds are never
+    /// included, and their embed stubs are not read.
+    async fn get_source_builds(&self, pkg: &VersionIdent) -> Result<Vec<BuildIdent>> {
+        let mut builds = self
+            .get_concrete_package_builds(pkg)
+            .await?
+            .into_iter()
+            .filter(|build| build.is_source())
+            .collect::<Vec<_>>();
+        builds.sort();
+        Ok(builds)
+    }
+
+    /// Return the binary builds for the given package name and version.
+    ///
+    /// Embedded builds are not included, and can be listed with
+    /// [`Storage::get_embedded_package_builds`] when they are needed.
+    async fn get_binary_builds(&self, pkg: &VersionIdent) -> Result<Vec<BuildIdent>> {
+        let mut builds = self
+            .get_concrete_package_builds(pkg)
+            .await?
+            .into_iter()
+            .filter(|build| !build.is_source() && !build.is_embedded())
+            .collect::<Vec<_>>();
+        builds.sort();
+        Ok(builds)
+    }
+
     /// Returns the set of components published for a package build
     async fn list_build_components(&self, pkg: &BuildIdent) -> Result<Vec<Component>>;
 
@@ -379,15 +453,91 @@ pub trait Repository: Storage + Sync {
         }
     }
 
+    /// Read package information for many builds at once.
+    ///
+    /// The results are returned in the same order as the given builds.
+    /// Implementations may read the packages concurrently.
+    async fn read_packages(
+        &self,
+        builds: &[BuildIdent],
+    ) -> Vec<Result<Arc<<Self::Recipe as spk_schema::Recipe>::Output>>> {
+        let mut results = Vec::with_capacity(builds.len());
+        for build in builds {
+            results.push(self.read_package(build).await);
+        }
+        results
+    }
+
+    /// Check whether each of the given builds exists in this repository.
+    ///
+    /// Implementations may check the builds concurrently.
+    async fn packages_exist(&self, builds: &[BuildIdent]) -> Result<HashMap<BuildIdent, bool>> {
+        let mut exists = HashMap::with_capacity(builds.len());
+        for build in builds {
+            let found = match self.read_package(build).await {
+                Ok(_) => true,
+                Err(Error::PackageNotFound(_)) => false,
+                Err(err) => return Err(err),
+            };
+            exists.insert(build.clone(), found);
+        }
+        Ok(exists)
+    }
+
+    /// Choose one binary build of the given version, using the given policy.
+    ///
+    /// Source builds and embedded packages are never chosen. Returns
+    /// `None` when the version has no binary builds in this repository.
+    async fn latest_build(
+        &self,
+        version: &VersionIdent,
+        policy: BuildSelectionPolicy,
+    ) -> Result<Option<BuildIdent>> {
+        let mut builds = self.list_package_builds(version).await?;
+        builds.retain(|build| !build.is_source() && !build.is_embedded());
+        builds.sort_by_key(|build| build.build().digest());
+
+        match policy {
+            BuildSelectionPolicy::GreatestDigest => Ok(builds.pop()),
+            BuildSelectionPolicy::PreferHostOptions => {
+                let filter_by = get_host_options_filters();
+                for build in builds.iter().rev() {
+                    if self
+                        .read_package(build)
+                        .await?
+                        .matches_all_filters(&filter_by)
+                    {
+                        return Ok(Some(build.clone()));
+                    }
+                }
+                Ok(builds.pop())
+            }
+            BuildSelectionPolicy::MostRecentlyPublished => {
+                let mut latest = None;
+                for build in builds {
+                    let published = self.get_package_publish_time(&build).await?;
+                    // builds are sorted, so ties go to the greater digest
+                    if latest.as_ref().is_none_or(|(time, _)| published >= *time) {
+                        latest = Some((published, build));
+                    }
+                }
+                Ok(latest.map(|(_, build)| build))
+            }
+        }
+    }
+
     /// Publish a package to this repository.
     ///
     /// The provided component digests are expected to each identify an spfs
     /// layer which contains properly constructed binary package files and metadata.
+    ///
+    /// The returned [`PublishResult`] describes the tags written for the
+    /// package itself; stubs for any embedded packages are not included.
     async fn publish_package(
         &self,
         package: &<<Self as Storage>::Recipe as spk_schema::Recipe>::Output,
         components: &HashMap<Component, spfs::encoding::Digest>,
-    ) -> Result<()>
+    ) -> Result<PublishResult>
     where
         Self::Package: PackageMut,
     {
@@ -397,7 +547,9 @@ pub trait Repository: Storage + Sync {
             )));
         }
 
-        self.publish_package_to_storage(package, components).await?;
+        let result = self
+            .publish_package_to_storage(package, components, PublishPolicy::OverwriteVersion)
+            .await?;
 
         // After successfully publishing a package, also publish stubs for any
         // embedded packages in this package.
@@ -410,6 +562,71 @@ pub trait Repository: Storage + Sync {
             }
         }
 
+        Ok(result)
+    }
+
+    /// Publish a package along with stubs for the given embedded
+    /// packages, as a single operation.
+    ///
+    /// Each of the `embeds` is paired with the components of `package`
+    /// that provide it. If the package or any of its stubs fails to be
+    /// published, everything that was written is removed again before
+    /// the error is returned, so that a package is never left without
+    /// its stubs, or stubs without their package. For the same reason,
+    /// an existing build is never replaced, since it could not be
+    /// restored afterwards, and [`Error::VersionExists`] is returned
+    /// instead.
+    async fn publish_package_with_embeds(
+        &self,
+        package: &<<Self as Storage>::Recipe as spk_schema::Recipe>::Output,
+        components: &HashMap<Component, spfs::encoding::Digest>,
+        embeds: &HashMap<Self::Package, BTreeSet<Component>>,
+    ) -> Result<()>
+    where
+        Self::Package: PackageMut,
+    {
+        if package.ident().build().is_embedded() {
+            return Err(Error::SpkIdentBuildError(InvalidBuildError::new_error(
+                "Cannot publish embedded package".to_string(),
+            )));
+        }
+
+        self.publish_package_to_storage(package, components, PublishPolicy::DoNotOverwriteVersion)
+            .await?;
+
+        let mut published = Vec::with_capacity(embeds.len());
+        for (embed, providers) in embeds.iter() {
+            let err = match self
+                .create_embedded_stub_for_spec(package, embed, providers.clone())
+                .await
+            {
+                Ok(()) => {
+                    published.push((embed, providers));
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            for (embed, providers) in published {
+                if let Err(err) = self
+                    .remove_embedded_stub_for_spec(package, embed, providers.clone())
+                    .await
+                {
+                    tracing::warn!(
+                        "Failed to remove embed stub for {} after failed publish: {err}",
+                        embed.ident()
+                    );
+                }
+            }
+            if let Err(err) = self.remove_package_from_storage(package.ident()).await {
+                tracing::warn!(
+                    "Failed to remove {} after failed publish: {err}",
+                    package.ident()
+                );
+            }
+            return Err(err);
+        }
+
         Ok(())
     }
 
@@ -440,7 +657,7 @@ pub trait Repository: Storage + Sync {
         };
 
         let components = self.read_components(package.ident()).await?;
-        self.publish_package_to_storage(package, &components)
+        self.publish_package_to_storage(package, &components, PublishPolicy::OverwriteVersion)
             .await?;
 
         // Changes that affect embedded stubs:
@@ -561,6 +778,42 @@ pub trait Repository: Storage + Sync {
         self.read_components_from_storage(pkg).await
     }
 
+    /// Identify the payload of a single component of the identified package.
+    ///
+    /// Returns `None` if the package exists but does not have the
+    /// given component.
+    async fn component_digest(
+        &self,
+        pkg: &BuildIdent,
+        component: &Component,
+    ) -> Result<Option<spfs::encoding::Digest>> {
+        let mut components = self.read_components(pkg).await?;
+        Ok(components.remove(component))
+    }
+
+    /// Return true if the identified version has been yanked.
+    ///
+    /// Yanked versions are still published and can be read, but should
+    /// not be chosen for new environments. Not all storage types support
+    /// yanking, and the default implementation reports that nothing is
+    /// yanked.
+    async fn is_yanked(&self, _pkg: &VersionIdent) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Return the time that the identified package build was published.
+    ///
+    /// Not all storage types record when packages are published, and
+    /// the default implementation returns an error.
+    async fn get_package_publish_time(
+        &self,
+        pkg: &BuildIdent,
+    ) -> Result<chrono::DateTime<chrono::Utc>> {
+        Err(Error::String(format!(
+            "Cannot get the publish time of {pkg}, this repository does not record it"
+        )))
+    }
+
     /// Perform any upgrades that are pending on this repository.
     ///
     /// This will bring the repository up-to-date for the current
@@ -21,8 +21,8 @@ use spk_schema::{
     spec,
 };
 
-use crate::Error;
 use crate::fixtures::*;
+use crate::{BuildSelectionPolicy, Error, PublishPolicy, Storage};
 
 #[rstest]
 #[case::mem(RepoKind::Mem)]
@@ -176,6 +176,120 @@ async fn test_repo_publish_package(#[case] repo: RepoKind) {
     );
 }
 
+#[rstest]
+#[case::mem(RepoKind::Mem)]
+#[case::spfs(RepoKind::Spfs)]
+#[tokio::test]
+async fn test_repo_publish_package_without_overwrite(#[case] repo: RepoKind) {
+    let repo = make_repo(repo).await;
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package_to_storage(&spec, &components, PublishPolicy::DoNotOverwriteVersion)
+        .await
+        .expect("a new build should be published");
+
+    match repo
+        .publish_package_to_storage(&spec, &components, PublishPolicy::DoNotOverwriteVersion)
+        .await
+    {
+        Err(Error::VersionExists(_)) => (),
+        res => panic!("expected version exists error, got {res:?}"),
+    }
+    repo.publish_package_to_storage(&spec, &components, PublishPolicy::OverwriteVersion)
+        .await
+        .expect("overwriting should ignore the existing build");
+
+    let other = spec!({"pkg": "my-pkg/1.0.0/7CI5R7Y4"});
+    repo.publish_package_to_storage(&other, &components, PublishPolicy::DoNotOverwriteVersion)
+        .await
+        .expect("other builds of the same version should not be affected");
+}
+
+#[rstest]
+#[case::mem(RepoKind::Mem)]
+#[case::spfs(RepoKind::Spfs)]
+#[tokio::test]
+async fn test_repo_latest_build(#[case] repo: RepoKind) {
+    let repo = make_repo(repo).await;
+    let version = parse_version_ident("my-pkg/1.0.0").unwrap();
+    assert_eq!(
+        repo.latest_build(&version, BuildSelectionPolicy::GreatestDigest)
+            .await
+            .unwrap(),
+        None,
+        "a version without builds should have no latest build"
+    );
+
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    let greater = spec!({"pkg": "my-pkg/1.0.0/7CI5R7Y4"});
+    repo.publish_package(&greater, &components).await.unwrap();
+    // publish times are only as precise as microseconds
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let newer = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    repo.publish_package(&newer, &components).await.unwrap();
+
+    assert_eq!(
+        repo.latest_build(&version, BuildSelectionPolicy::GreatestDigest)
+            .await
+            .unwrap()
+            .as_ref(),
+        Some(greater.ident())
+    );
+    assert_eq!(
+        repo.latest_build(&version, BuildSelectionPolicy::MostRecentlyPublished)
+            .await
+            .unwrap()
+            .as_ref(),
+        Some(newer.ident())
+    );
+    assert!(
+        repo.latest_build(&version, BuildSelectionPolicy::PreferHostOptions)
+            .await
+            .unwrap()
+            .is_some(),
+        "a build should be chosen even if none match the host options"
+    );
+}
+
+#[rstest]
+#[case::mem(RepoKind::Mem)]
+#[case::spfs(RepoKind::Spfs)]
+#[tokio::test]
+async fn test_repo_component_digest(#[case] repo: RepoKind) {
+    let repo = make_repo(repo).await;
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    assert_eq!(
+        repo.component_digest(spec.ident(), &Component::Run)
+            .await
+            .unwrap(),
+        Some(empty_layer_digest())
+    );
+    assert_eq!(
+        repo.component_digest(spec.ident(), &Component::Build)
+            .await
+            .unwrap(),
+        None,
+        "a missing component should not be an error"
+    );
+    let missing = parse_build_ident("my-pkg/1.0.0/7CI5R7Y4").unwrap();
+    assert!(
+        repo.component_digest(&missing, &Component::Run)
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "a missing package should still be an error"
+    );
+}
+
 async fn create_repo_for_embed_stubs_test(repo: &TempRepo) -> (SpecRecipe, Spec) {
     let recipe = recipe!({
         "pkg": "my-pkg/1.0.0",
@@ -408,3 +522,86 @@ async fn test_repo_remove_package_removes_embed_stubs(#[case] repo: RepoKind) {
             .any(|pkg| pkg == "my-embedded-pkg")
     );
 }
+
+#[rstest]
+#[case::mem(RepoKind::Mem)]
+#[case::spfs(RepoKind::Spfs)]
+#[tokio::test]
+async fn test_repo_source_and_binary_builds(#[case] repo: RepoKind) {
+    let repo = make_repo(repo).await;
+    let (recipe, binary) = create_repo_for_embed_stubs_test(&repo).await;
+    let source = spec!({"pkg": "my-pkg/1.0.0/src"});
+    repo.publish_package(
+        &source,
+        &vec![(Component::Source, empty_layer_digest())]
+            .into_iter()
+            .collect(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        repo.get_source_builds(recipe.ident()).await.unwrap(),
+        vec![source.ident().clone()]
+    );
+    assert_eq!(
+        repo.get_binary_builds(recipe.ident()).await.unwrap(),
+        vec![binary.ident().clone()]
+    );
+    let embedded = parse_version_ident("my-embedded-pkg/1.0.0").unwrap();
+    assert!(
+        repo.get_binary_builds(&embedded).await.unwrap().is_empty(),
+        "embedded builds should not be listed"
+    );
+    assert!(
+        !repo
+            .list_package_builds(&embedded)
+            .await
+            .unwrap()
+            .is_empty(),
+        "embedded builds should still be listed with all builds"
+    );
+}
+
+#[rstest]
+#[case::mem(RepoKind::Mem)]
+#[case::spfs(RepoKind::Spfs)]
+#[tokio::test]
+async fn test_repo_read_packages(#[case] repo: RepoKind) {
+    let repo = make_repo(repo).await;
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+    let first = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let second = spec!({"pkg": "my-pkg/1.0.0/7CI5R7Y4"});
+    for spec in [&first, &second] {
+        repo.publish_package(
+            spec,
+            &vec![(Component::Run, empty_layer_digest())]
+                .into_iter()
+                .collect(),
+        )
+        .await
+        .unwrap();
+    }
+    let missing = parse_build_ident("my-pkg/1.0.0/QYB6QLCN").unwrap();
+
+    let builds = [
+        second.ident().clone(),
+        first.ident().clone(),
+        missing,
+        second.ident().clone(),
+    ];
+    let results = repo.read_packages(&builds).await;
+    assert_eq!(
+        results.len(),
+        builds.len(),
+        "should return one result per build"
+    );
+    assert_eq!(**results[0].as_ref().unwrap(), second);
+    assert_eq!(**results[1].as_ref().unwrap(), first);
+    assert!(
+        matches!(results[2], Err(Error::PackageNotFound(_))),
+        "missing builds should fail individually"
+    );
+    assert_eq!(**results[3].as_ref().unwrap(), second);
+}
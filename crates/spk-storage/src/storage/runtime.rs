@@ -20,7 +20,7 @@ use spk_schema::ident_build::{Build, EmbeddedSource};
 use spk_schema::{BuildIdent, FromYaml, Package, Spec, SpecRecipe, VersionIdent};
 
 use super::Repository;
-use super::repository::{PublishPolicy, Storage};
+use super::repository::{PublishPolicy, PublishResult, Storage};
 use crate::{Error, Result};
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -288,7 +288,8 @@ impl Storage for RuntimeRepository {
         &self,
         _package: &<Self::Recipe as spk_schema::Recipe>::Output,
         _components: &HashMap<Component, spfs::encoding::Digest>,
-    ) -> Result<()> {
+        _publish_policy: PublishPolicy,
+    ) -> Result<PublishResult> {
         Err(Error::String(
             "Cannot publish to a runtime repository".into(),
         ))
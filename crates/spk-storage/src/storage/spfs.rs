@@ -2,14 +2,15 @@
 // SPDX-License-Identifier: Apache-2.0
 // https://github.com/spkenv/spk
 
-use std::collections::{HashMap, HashSet, hash_map};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, hash_map};
 use std::convert::{TryFrom, TryInto};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use dashmap::DashMap;
-use futures::{Future, StreamExt};
+use futures::{Future, StreamExt, TryStreamExt};
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use paste::paste;
@@ -19,21 +20,22 @@ use spfs::prelude::{RepositoryExt as SpfsRepositoryExt, *};
 use spfs::storage::EntryType;
 use spfs::tracking::{self, Tag, TagSpec};
 use spk_schema::foundation::ident_build::{Build, parse_build};
-use spk_schema::foundation::ident_component::Component;
+use spk_schema::foundation::ident_component::{Component, ComponentAliases};
 use spk_schema::foundation::name::{PkgName, PkgNameBuf, RepositoryName, RepositoryNameBuf};
 use spk_schema::foundation::version::{Version, parse_version};
+use spk_schema::foundation::version_range::{Ranged, VersionRange};
 use spk_schema::ident::{AsVersionIdent, ToAnyIdentWithoutBuild, VersionIdent};
 use spk_schema::ident_build::parsing::embedded_source_package;
 use spk_schema::ident_build::{EmbeddedSource, EmbeddedSourcePackage};
 use spk_schema::ident_ops::TagPath;
 use spk_schema::spec_ops::{HasVersion, WithVersion};
 use spk_schema::version::VersionParts;
-use spk_schema::{AnyIdent, BuildIdent, FromYaml, Package, Recipe, Spec, SpecRecipe};
+use spk_schema::{AnyIdent, BuildIdent, FromYaml, OptionMap, Package, Recipe, Spec, SpecRecipe};
 use tokio::io::AsyncReadExt;
 use tokio::task::JoinSet;
 
 use super::CachePolicy;
-use super::repository::{PublishPolicy, Storage};
+use super::repository::{PublishPolicy, PublishResult, Storage};
 use crate::storage::repository::internal::RepositoryExt;
 use crate::{Error, Result, with_cache_policy};
 
@@ -44,6 +46,31 @@ mod spfs_test;
 const REPO_METADATA_TAG: &str = "spk/repo";
 const REPO_VERSION: &str = "1.0.0";
 
+/// The address query parameter that identifies the tag namespace
+/// of a repository, matching the one used by spfs fs repositories.
+const TAG_NAMESPACE_QUERY_KEY: &str = "tag_namespace";
+
+/// The number of packages read at once by [`SpfsRepository::read_packages`],
+/// and checked at once by [`SpfsRepository::packages_exist`].
+const READ_PACKAGES_CONCURRENCY: usize = 16;
+
+/// The number of versions listed at once by
+/// [`SpfsRepository::list_package_versions_with_build_counts`].
+const LIST_BUILD_COUNTS_CONCURRENCY: usize = 16;
+
+/// The default number of version parts to probe for when looking up
+/// package tags that may have been published with trailing zeros.
+const DEFAULT_VERSION_PART_PROBE_DEPTH: usize = 5;
+
+/// The default number of component tags pushed at once when publishing
+/// a package, see [`SpfsRepository::with_component_push_concurrency`].
+const DEFAULT_COMPONENT_PUSH_CONCURRENCY: usize = 8;
+
+/// The default number of packages upgraded at once by
+/// [`crate::Repository::upgrade`], see
+/// [`SpfsRepository::with_upgrade_concurrency`].
+const DEFAULT_UPGRADE_CONCURRENCY: usize = 8;
+
 macro_rules! verbatim_build_spec_tag_if_enabled {
     ($self:expr, $output:ty, $ident:expr) => {{ verbatim_tag_if_enabled!($self, spec, $output, $ident) }};
     ($self:expr, $ident:expr) => {{ verbatim_build_spec_tag_if_enabled!($self, _, $ident) }};
@@ -74,6 +101,136 @@ pub struct SpfsRepository {
     cache_policy: Arc<ArcSwap<CachePolicy>>,
     caches: CachesForAddress,
     legacy_spk_version_tags: bool,
+    version_part_probe_depth: usize,
+    component_push_concurrency: usize,
+    upgrade_concurrency: usize,
+    cache_ttl: Option<Duration>,
+    spec_rewriter: Option<Arc<dyn SpecRewriter>>,
+    recipe_migrations: RecipeMigrations,
+    /// Skip, rather than fail on, component tags that cannot be resolved
+    lenient_components: bool,
+    /// Leave yanked versions out of the listed versions of packages
+    hide_yanked: bool,
+    /// The component that the legacy package tag points to, rather than
+    /// the run or source component
+    legacy_component: Option<Component>,
+    /// How reads are retried after transient errors from an rpc repository
+    retry_policy: Option<RetryPolicy>,
+    /// Also publish packages under the single tag that is read by spk
+    /// versions that predate package components
+    write_legacy_tags: bool,
+    /// Set when this repository is pinned to a point in time, which
+    /// makes it read-only
+    pinned: bool,
+}
+
+/// Rewrites the raw yaml of specs as they are read from a repository.
+///
+/// This allows older specs that use deprecated fields to be migrated
+/// in-memory when they are read, rather than failing to load or needing
+/// to be republished. See [`SpfsRepository::with_spec_rewriter`].
+pub trait SpecRewriter: std::fmt::Debug + Send + Sync {
+    /// Rewrite the yaml of a recipe before it is parsed.
+    fn rewrite_recipe(&self, _pkg: &VersionIdent, yaml: String) -> Result<String> {
+        Ok(yaml)
+    }
+
+    /// Rewrite the yaml of a package build before it is parsed.
+    fn rewrite_package(&self, _pkg: &BuildIdent, yaml: String) -> Result<String> {
+        Ok(yaml)
+    }
+}
+
+/// The api version of recipes that do not specify one.
+const DEFAULT_RECIPE_API_VERSION: &str = "v0/package";
+
+/// A single step that brings a recipe from one api version to another.
+///
+/// See [`RecipeMigrations`].
+pub trait RecipeMigration: std::fmt::Debug + Send + Sync {
+    /// The api version of the recipes produced by this migration.
+    fn target_version(&self) -> &str;
+
+    /// Migrate the yaml of a recipe to the target version.
+    ///
+    /// The `api` field of the result is always set to the target
+    /// version, so it does not need to be updated here.
+    fn migrate(&self, pkg: &VersionIdent, recipe: serde_yaml::Value) -> Result<serde_yaml::Value>;
+}
+
+/// A chain of recipe migrations, keyed by the api version that each
+/// one migrates from.
+///
+/// Migrations are applied one after another until the recipe reaches
+/// an api version that has nothing registered for it. See
+/// [`SpfsRepository::read_recipe_migrated`].
+#[derive(Clone, Debug, Default)]
+pub struct RecipeMigrations {
+    by_source: Arc<HashMap<String, Arc<dyn RecipeMigration>>>,
+}
+
+impl RecipeMigrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a migration for recipes of the given api version,
+    /// replacing any that was already registered for it.
+    pub fn with_migration<S: Into<String>>(
+        mut self,
+        source_version: S,
+        migration: Arc<dyn RecipeMigration>,
+    ) -> Self {
+        Arc::make_mut(&mut self.by_source).insert(source_version.into(), migration);
+        self
+    }
+
+    /// True if there are no migrations registered.
+    pub fn is_empty(&self) -> bool {
+        self.by_source.is_empty()
+    }
+
+    /// Apply the chain of migrations to the yaml of a recipe.
+    ///
+    /// The yaml is returned unchanged when no migration applies to it.
+    fn migrate(&self, pkg: &VersionIdent, yaml: String) -> Result<String> {
+        if self.is_empty() {
+            return Ok(yaml);
+        }
+        let invalid = |err: String| Error::InvalidPackageSpec(pkg.to_any_ident(None), err);
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str(&yaml).map_err(|err| invalid(err.to_string()))?;
+        let mut applied = 0;
+        loop {
+            let version = match value.get("api") {
+                None => DEFAULT_RECIPE_API_VERSION,
+                Some(api) => api
+                    .as_str()
+                    .ok_or_else(|| invalid("the api version must be a string".to_string()))?,
+            };
+            let Some(migration) = self.by_source.get(version) else {
+                break;
+            };
+            if applied == self.by_source.len() {
+                return Err(invalid(format!(
+                    "recipe migrations form a cycle at api version {version}"
+                )));
+            }
+            let target = migration.target_version().to_string();
+            value = migration.migrate(pkg, value)?;
+            match value.as_mapping_mut() {
+                Some(mapping) => {
+                    mapping.insert("api".into(), target.into());
+                }
+                None => return Err(invalid("a migrated recipe must be a mapping".to_string())),
+            }
+            applied += 1;
+        }
+        if applied == 0 {
+            return Ok(yaml);
+        }
+        serde_yaml::to_string(&value).map_err(|err| invalid(err.to_string()))
+    }
 }
 
 impl std::hash::Hash for SpfsRepository {
@@ -110,6 +267,313 @@ impl std::ops::Deref for SpfsRepository {
     }
 }
 
+/// A snapshot of the effective settings of an [`SpfsRepository`].
+///
+/// See [`SpfsRepository::describe`].
+#[derive(Clone, Debug)]
+pub struct RepositoryDescription {
+    /// The address of the repository, including any pin query
+    pub address: url::Url,
+    /// The name of the repository, as in "local" or "origin"
+    pub name: RepositoryNameBuf,
+    /// The cache policy currently in effect
+    pub cache_policy: CachePolicy,
+    /// How long cached results are reused, if limited
+    pub cache_ttl: Option<Duration>,
+    /// The tag namespace that the repository reads and writes in, if any
+    pub tag_namespace: Option<spfs::storage::TagNamespaceBuf>,
+    /// The time that this repository is pinned at, if any
+    pub pinned_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The kind of spfs repository backing this one, eg "fs" or "rpc"
+    pub kind: &'static str,
+    /// Whether legacy spk version tags are searched
+    pub legacy_spk_version_tags: bool,
+}
+
+/// Restores the previous cache policy of a repository when dropped.
+///
+/// See [`SpfsRepository::cache_policy_scope`].
+#[must_use = "the previous cache policy is restored as soon as the guard is dropped"]
+pub struct CachePolicyGuard<'a> {
+    repo: &'a SpfsRepository,
+    previous: CachePolicy,
+}
+
+impl Drop for CachePolicyGuard<'_> {
+    fn drop(&mut self) {
+        crate::Repository::set_cache_policy(self.repo, self.previous);
+    }
+}
+
+/// The recipe of a package version, along with all of its builds.
+///
+/// See [`SpfsRepository::read_version_bundle`].
+#[derive(Clone, Debug)]
+pub struct VersionBundle {
+    pub recipe: Arc<SpecRecipe>,
+    /// Every concrete build of the version, which includes its source
+    /// build but not the stubs of any embedded packages
+    pub builds: HashMap<BuildIdent, Arc<Spec>>,
+}
+
+/// One version of a tag in a package's tag history.
+///
+/// See [`SpfsRepository::package_tag_history`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagHistoryEntry {
+    /// The digest that the tag pointed to
+    pub target: spfs::encoding::Digest,
+    /// The user that wrote this version of the tag
+    pub user: String,
+    /// When this version of the tag was written
+    pub time: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<Tag> for TagHistoryEntry {
+    fn from(tag: Tag) -> Self {
+        Self {
+            target: tag.target,
+            user: tag.user,
+            time: tag.time,
+        }
+    }
+}
+
+/// An embed stub tag whose name could not be decoded.
+///
+/// See [`SpfsRepository::get_embedded_package_builds_verbose`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmbedStubDecodeError {
+    /// The path of the offending tag
+    pub tag: RelativePathBuf,
+    /// Why the tag could not be decoded
+    pub message: String,
+}
+
+impl std::fmt::Display for EmbedStubDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "embed stub {} could not be decoded: {}",
+            self.tag, self.message
+        )
+    }
+}
+
+/// A tag that was skipped while listing a repository because its
+/// version or build could not be parsed.
+///
+/// See [`SpfsRepository::list_package_versions_with_diagnostics`] and
+/// [`SpfsRepository::get_concrete_package_builds_with_diagnostics`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedEntry {
+    /// The path of the offending tag or tag folder
+    pub tag: RelativePathBuf,
+    /// Why the entry was skipped
+    pub message: String,
+}
+
+impl std::fmt::Display for SkippedEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "skipped {}: {}", self.tag, self.message)
+    }
+}
+
+/// A problem with how a package build is stored in a repository.
+///
+/// See [`SpfsRepository::verify_package`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerificationIssue {
+    /// The build has a spec but no package tags, so it cannot be installed
+    SpecWithoutPackage { spec_tag: TagSpec },
+    /// The build has package tags but no spec
+    MissingSpec { expected: TagSpec },
+    /// The spec of the build exists but could not be read
+    InvalidSpec { spec_tag: TagSpec, message: String },
+    /// The build is missing the legacy package tag that older
+    /// versions of spk use to find it
+    MissingLegacyTag { expected: TagSpec },
+    /// A component described by the spec of the build has no tag
+    MissingComponentTag {
+        component: Component,
+        expected: TagSpec,
+    },
+    /// The name of an embed stub tag could not be decoded into the
+    /// package that embeds it
+    UndecodableEmbedStub { tag: TagSpec },
+}
+
+impl std::fmt::Display for VerificationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SpecWithoutPackage { spec_tag } => write!(
+                f,
+                "spec tag {spec_tag} has no package tags, remove it or republish the build"
+            ),
+            Self::MissingSpec { expected } => write!(
+                f,
+                "package has no spec, expected one at {expected}, republish the build"
+            ),
+            Self::InvalidSpec { spec_tag, message } => {
+                write!(f, "spec at {spec_tag} could not be read: {message}")
+            }
+            Self::MissingLegacyTag { expected } => write!(
+                f,
+                "legacy package tag {expected} is missing, older versions of spk will not find this build"
+            ),
+            Self::MissingComponentTag {
+                component,
+                expected,
+            } => write!(
+                f,
+                "component {component} is in the spec but has no tag at {expected}"
+            ),
+            Self::UndecodableEmbedStub { tag } => write!(
+                f,
+                "embed stub tag {tag} could not be decoded, remove it and republish the package that embeds it"
+            ),
+        }
+    }
+}
+
+/// A change that is made to a package when upgrading a repository.
+///
+/// See [`SpfsRepository::upgrade_plan`].
+#[derive(Clone, Debug)]
+pub enum UpgradeAction {
+    /// Insert per-component copies of the old style tags of a build
+    ReplicateTags { build: BuildIdent, tags: Vec<Tag> },
+    /// Create, or recreate, the stub of a package embedded by a build
+    CreateEmbeddedStub {
+        parent: Arc<Spec>,
+        embedded: Spec,
+        components: BTreeSet<Component>,
+    },
+}
+
+impl std::fmt::Display for UpgradeAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReplicateTags { build, tags } => {
+                write!(f, "replicate {} old tag(s) of {build}", tags.len())
+            }
+            Self::CreateEmbeddedStub {
+                parent, embedded, ..
+            } => write!(
+                f,
+                "create embedded stub for {} in {}",
+                embedded.ident(),
+                parent.ident()
+            ),
+        }
+    }
+}
+
+/// The changes that upgrading a repository would make, without
+/// making them.
+///
+/// See [`SpfsRepository::upgrade_plan`] and
+/// [`SpfsRepository::apply_upgrade_plan`].
+#[derive(Clone, Debug, Default)]
+pub struct UpgradePlan {
+    /// The version that the repository metadata is updated to, or
+    /// `None` if the repository is already newer than this client
+    pub target_version: Option<Version>,
+    /// The changes to make to each package, for only the packages
+    /// that need any
+    pub packages: BTreeMap<PkgNameBuf, Vec<UpgradeAction>>,
+}
+
+impl UpgradePlan {
+    /// The number of builds whose old style tags will be replicated.
+    pub fn replicated_builds(&self) -> usize {
+        self.actions()
+            .filter(|action| matches!(action, UpgradeAction::ReplicateTags { .. }))
+            .count()
+    }
+
+    /// The number of embedded stubs that will be [re]created.
+    pub fn embedded_stubs(&self) -> usize {
+        self.actions()
+            .filter(|action| matches!(action, UpgradeAction::CreateEmbeddedStub { .. }))
+            .count()
+    }
+
+    fn actions(&self) -> impl Iterator<Item = &UpgradeAction> {
+        self.packages.values().flatten()
+    }
+}
+
+impl std::fmt::Display for UpgradePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(target_version) = &self.target_version else {
+            return write!(f, "Nothing to do.");
+        };
+        for (name, actions) in self.packages.iter() {
+            for action in actions.iter() {
+                writeln!(f, "{name}: {action}")?;
+            }
+        }
+        write!(
+            f,
+            "{} build(s) to replicate, {} embedded stub(s) to create, then set repo version to {target_version}",
+            self.replicated_builds(),
+            self.embedded_stubs()
+        )
+    }
+}
+
+/// How reads from an spfs server are retried after transient errors.
+///
+/// See [`SpfsRepository::with_retry_policy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The total number of times that a read is attempted
+    pub max_attempts: u32,
+    /// The delay before the first retry, which doubles for each
+    /// retry after it
+    pub base_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delays before each retry.
+    fn delays(&self) -> impl Iterator<Item = Duration> {
+        let base_backoff = self.base_backoff;
+        (0..self.max_attempts.saturating_sub(1))
+            .map(move |retry| base_backoff.saturating_mul(2u32.saturating_pow(retry)))
+    }
+}
+
+/// A set of package folders whose names differ only by case.
+///
+/// Such folders clash on case-insensitive filesystems. The names are
+/// the raw tag folder names, which are not necessarily valid package
+/// names. See [`SpfsRepository::check_name_collisions`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameCollision {
+    /// The colliding folder names, sorted
+    pub names: Vec<String>,
+}
+
+impl std::fmt::Display for NameCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "package names differ only by case: {}",
+            self.names.join(", ")
+        )
+    }
+}
+
 pub struct NameAndRepository<S, T>
 where
     S: AsRef<str>,
@@ -117,6 +581,7 @@ where
 {
     name: S,
     repo: T,
+    share_caches: bool,
 }
 
 impl<S, T> NameAndRepository<S, T>
@@ -125,10 +590,30 @@ where
     T: Into<spfs::storage::RepositoryHandle>,
 {
     pub fn new(name: S, repo: T) -> Self {
-        Self { name, repo }
+        Self {
+            name,
+            repo,
+            share_caches: true,
+        }
+    }
+
+    /// Give the resulting repository its own caches, rather than
+    /// sharing them with every other repository at the same address.
+    ///
+    /// See [`SpfsRepository::new_unshared`].
+    pub fn unshared(mut self) -> Self {
+        self.share_caches = false;
+        self
     }
 }
 
+/// Wrap an already opened spfs repository.
+///
+/// Unlike [`SpfsRepository::new`], this does not run
+/// [`SpfsRepository::check_compatibility`], which needs to read the
+/// repository and so cannot be done in a synchronous conversion. Callers
+/// that open repositories which may have been written by a newer version
+/// of spk should check it themselves.
 impl<S, T> TryFrom<NameAndRepository<S, T>> for SpfsRepository
 where
     S: AsRef<str>,
@@ -138,57 +623,1763 @@ where
 
     fn try_from(name_and_repo: NameAndRepository<S, T>) -> Result<Self> {
         let inner = name_and_repo.repo.into();
-        let address = inner.address().into_owned();
-        Ok(Self {
-            caches: CachesForAddress::new(&address),
-            address,
-            name: name_and_repo.name.as_ref().try_into()?,
+        let address = normalize_repository_address(&inner.address());
+        let caches = if name_and_repo.share_caches {
+            CachesForAddress::new(&address)
+        } else {
+            CachesForAddress::new_unshared()
+        };
+        Ok(Self::from_parts(
+            name_and_repo.name.as_ref().try_into()?,
+            inner,
+            caches,
+        ))
+    }
+}
+
+impl SpfsRepository {
+    pub async fn new(name: &str, address: &str) -> Result<Self> {
+        let inner = spfs::open_repository(address).await?;
+        let address = normalize_repository_address(&inner.address());
+        let caches = CachesForAddress::new(&address);
+        let repo = Self::from_parts(name.try_into()?, inner, caches);
+        repo.check_compatibility().await?;
+        Ok(repo)
+    }
+
+    /// Open a repository with its own caches.
+    ///
+    /// By default, all repositories at the same address share their
+    /// caches so that a publish through one is seen by the others. The
+    /// caches of a repository created this way are not shared with any
+    /// other instance, which keeps independent users of the same
+    /// address, such as tests, isolated from each other.
+    pub async fn new_unshared(name: &str, address: &str) -> Result<Self> {
+        let inner = spfs::open_repository(address).await?;
+        let repo = Self::from_parts(name.try_into()?, inner, CachesForAddress::new_unshared());
+        repo.check_compatibility().await?;
+        Ok(repo)
+    }
+
+    /// Assemble a repository with the default settings, without
+    /// checking its compatibility.
+    fn from_parts(
+        name: RepositoryNameBuf,
+        inner: spfs::storage::RepositoryHandle,
+        caches: CachesForAddress,
+    ) -> Self {
+        Self {
+            address: normalize_repository_address(&inner.address()),
+            name,
+            pinned: matches!(inner, spfs::storage::RepositoryHandle::Pinned(_)),
             inner: Arc::new(inner),
+            caches,
             cache_policy: Arc::new(ArcSwap::new(Arc::new(CachePolicy::CacheOk))),
             legacy_spk_version_tags: cfg!(feature = "legacy-spk-version-tags"),
+            version_part_probe_depth: DEFAULT_VERSION_PART_PROBE_DEPTH,
+            component_push_concurrency: DEFAULT_COMPONENT_PUSH_CONCURRENCY,
+            upgrade_concurrency: DEFAULT_UPGRADE_CONCURRENCY,
+            cache_ttl: None,
+            spec_rewriter: None,
+            recipe_migrations: RecipeMigrations::default(),
+            lenient_components: false,
+            hide_yanked: false,
+            legacy_component: None,
+            retry_policy: None,
+            write_legacy_tags: true,
+        }
+    }
+
+    /// Access to the underlying [`spfs::storage::RepositoryHandle`].
+    pub fn inner(&self) -> &spfs::storage::RepositoryHandle {
+        &self.inner
+    }
+
+    /// Pin this repository to a specific point in time, limiting
+    /// all queries and making it read-only
+    ///
+    /// Relative time specs are resolved once, when this is called.
+    pub fn pin_at_time(&mut self, ts: &spfs::tracking::TimeSpec) {
+        self.pin_at_datetime(ts.to_datetime_from_now());
+    }
+
+    /// Pin this repository to an absolute point in time, limiting
+    /// all queries and making it read-only
+    pub fn pin_at_datetime(&mut self, dt: chrono::DateTime<chrono::Utc>) {
+        self.inner = Arc::new(self.inner.to_pinned(dt));
+        self.address
+            .query_pairs_mut()
+            .append_pair("when", &dt.to_rfc3339());
+        self.address = normalize_repository_address(&self.address);
+        self.pinned = true;
+    }
+
+    /// True if this repository is pinned to a point in time.
+    ///
+    /// Pinned repositories are read-only, and any attempt to publish
+    /// or remove packages will fail with [`Error::RepositoryPinnedReadOnly`].
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Return an error if this repository is pinned and so cannot be modified.
+    fn ensure_writable(&self) -> Result<()> {
+        if self.pinned {
+            return Err(Error::RepositoryPinnedReadOnly(self.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Remove any pin from this repository, restoring a live view
+    /// of the repository.
+    ///
+    /// This does nothing if the repository is not pinned.
+    pub fn unpin(&mut self) {
+        let spfs::storage::RepositoryHandle::Pinned(pinned) = &*self.inner else {
+            return;
+        };
+        self.inner = Arc::clone(pinned.inner());
+
+        let pairs = self
+            .address
+            .query_pairs()
+            .filter(|(key, _)| key != "when")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+        if pairs.is_empty() {
+            self.address.set_query(None);
+        } else {
+            self.address.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        self.pinned = false;
+    }
+
+    /// Enable or disable the use of legacy spk version tags
+    pub fn set_legacy_spk_version_tags(&mut self, enabled: bool) {
+        self.legacy_spk_version_tags = enabled;
+    }
+
+    /// Set the maximum number of version parts to probe for when
+    /// looking for legacy version tags with trailing zeros.
+    ///
+    /// For example, with a depth of 6 the builds of `pkg/1.2` are
+    /// also searched for under `pkg/1.2.0.0.0.0`. Defaults to 5.
+    pub fn with_version_part_probe_depth(mut self, depth: usize) -> Self {
+        self.version_part_probe_depth = depth;
+        self
+    }
+
+    /// Set the maximum number of component tags that are pushed at once
+    /// when publishing a package.
+    ///
+    /// Higher values can speed up publishing packages with many
+    /// components to high-latency repositories. Defaults to 8.
+    pub fn with_component_push_concurrency(mut self, concurrency: usize) -> Self {
+        self.component_push_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set the maximum number of packages that are processed at once
+    /// when upgrading this repository.
+    ///
+    /// Each package's tags are replicated and its embedded stubs are
+    /// recreated independently of the others. Defaults to 8.
+    pub fn with_upgrade_concurrency(mut self, concurrency: usize) -> Self {
+        self.upgrade_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Limit how long cached results are reused before being fetched again.
+    ///
+    /// Without a ttl, which is the default, cached results are reused
+    /// until the caches are invalidated. This is useful for long-running
+    /// processes that need to see changes made to the repository by
+    /// other processes.
+    pub fn with_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Skip over component tags that cannot be resolved when reading
+    /// the components of a package, rather than failing.
+    ///
+    /// A warning is logged for each component that is skipped, and the
+    /// components that did resolve are returned. This allows the healthy
+    /// components of a package to still be used when an unrelated
+    /// component's tag is damaged. It is still an error if none of the
+    /// package's components can be resolved.
+    pub fn with_lenient_components(mut self, enabled: bool) -> Self {
+        self.lenient_components = enabled;
+        self
+    }
+
+    /// Leave yanked versions out when listing the versions of a package.
+    ///
+    /// Yanked versions can still be read directly, so that existing
+    /// environments that use them can be reproduced. See
+    /// [`Self::yank`].
+    pub fn with_hide_yanked(mut self, enabled: bool) -> Self {
+        self.hide_yanked = enabled;
+        self
+    }
+
+    /// Choose the component that the legacy package tag points to when
+    /// publishing packages.
+    ///
+    /// Older versions of spk only read this single tag, which points to
+    /// the run component of binary packages and the source component of
+    /// source packages by default. It is an error to publish a package
+    /// that does not have the chosen component.
+    pub fn with_legacy_component(mut self, component: Option<Component>) -> Self {
+        self.legacy_component = component;
+        self
+    }
+
+    /// Retry reads that fail with a transient error, when this
+    /// repository is backed by an spfs server.
+    ///
+    /// Only errors that the server may recover from, such as it being
+    /// unavailable while restarting, are retried. Missing or invalid
+    /// data is never retried. Reads are not retried by default.
+    pub fn with_retry_policy(mut self, policy: Option<RetryPolicy>) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Enable or disable publishing packages under their legacy package
+    /// tag, in addition to the tag for each of their components.
+    ///
+    /// Only versions of spk that predate package components read the
+    /// legacy tag, and they will not be able to find any package that is
+    /// published while this is disabled, making the repository unreadable
+    /// to them. Enabled by default.
+    pub fn with_write_legacy_tags(mut self, enabled: bool) -> Self {
+        self.write_legacy_tags = enabled;
+        self
+    }
+
+    /// Rewrite the yaml of specs as they are read from this repository.
+    ///
+    /// Specs are cached after being rewritten, so an instance with a
+    /// rewriter gets its own caches rather than sharing them with the
+    /// other instances at the same address, which may not rewrite specs.
+    pub fn with_spec_rewriter(mut self, rewriter: Option<Arc<dyn SpecRewriter>>) -> Self {
+        if rewriter.is_some() {
+            self.caches = CachesForAddress::new_unshared();
+        }
+        self.spec_rewriter = rewriter;
+        self
+    }
+
+    /// Read and write packages within the given tag namespace of the
+    /// underlying spfs repository, or within the root namespace if `None`.
+    ///
+    /// The namespace is added to the address of this repository, so that
+    /// its caches are not shared with any other namespace of the same
+    /// spfs repository. This fails if other instances are still sharing
+    /// the underlying repository with this one.
+    pub fn with_tag_namespace(
+        mut self,
+        namespace: Option<spfs::storage::TagNamespaceBuf>,
+    ) -> Result<Self> {
+        let Some(inner) = Arc::get_mut(&mut self.inner) else {
+            return Err(Error::String(format!(
+                "Cannot change the tag namespace of repository {}, it is shared with other instances",
+                self.name
+            )));
+        };
+        spfs::storage::TagStorageMut::try_set_tag_namespace(inner, namespace.clone())?;
+
+        let pairs = self
+            .address
+            .query_pairs()
+            .filter(|(key, _)| key != TAG_NAMESPACE_QUERY_KEY)
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect_vec();
+        self.address.set_query(None);
+        if !pairs.is_empty() || namespace.is_some() {
+            let mut query = self.address.query_pairs_mut();
+            query.extend_pairs(pairs);
+            if let Some(namespace) = &namespace {
+                query.append_pair(TAG_NAMESPACE_QUERY_KEY, &namespace.to_string());
+            }
+        }
+        self.address = normalize_repository_address(&self.address);
+        self.caches = if self.caches.registration.is_some() {
+            CachesForAddress::new(&self.address)
+        } else {
+            CachesForAddress::new_unshared()
+        };
+        Ok(self)
+    }
+
+    /// Use the given chain of migrations in [`Self::read_recipe_migrated`].
+    pub fn with_recipe_migrations(mut self, migrations: RecipeMigrations) -> Self {
+        self.recipe_migrations = migrations;
+        self
+    }
+
+    /// The maximum number of version parts probed for legacy version tags.
+    pub fn version_part_probe_depth(&self) -> usize {
+        self.version_part_probe_depth
+    }
+
+    /// The maximum number of component tags pushed at once when publishing.
+    pub fn component_push_concurrency(&self) -> usize {
+        self.component_push_concurrency
+    }
+
+    /// The maximum number of packages processed at once when upgrading.
+    pub fn upgrade_concurrency(&self) -> usize {
+        self.upgrade_concurrency
+    }
+
+    /// Describe the current settings of this repository, for diagnostics.
+    pub fn describe(&self) -> RepositoryDescription {
+        let (kind, pinned_at) = match &*self.inner {
+            spfs::storage::RepositoryHandle::Pinned(pinned) => {
+                (Self::handle_kind(pinned.inner()), Some(pinned.pin))
+            }
+            handle => (Self::handle_kind(handle), None),
+        };
+        RepositoryDescription {
+            address: self.address.clone(),
+            name: self.name.clone(),
+            cache_policy: **self.cache_policy.load(),
+            cache_ttl: self.cache_ttl,
+            tag_namespace: self.inner.get_tag_namespace().map(|ns| ns.into_owned()),
+            pinned_at,
+            kind,
+            legacy_spk_version_tags: self.legacy_spk_version_tags,
+        }
+    }
+
+    /// Read a recipe, migrating it to the current schema if needed.
+    ///
+    /// The api version of the stored recipe is used to apply the chain
+    /// of migrations from [`Self::with_recipe_migrations`] before it is
+    /// parsed. The migration only happens in-memory, and the recipe in
+    /// storage is left untouched.
+    pub async fn read_recipe_migrated(&self, pkg: &VersionIdent) -> Result<Arc<SpecRecipe>> {
+        if self.recipe_migrations.is_empty() {
+            return crate::Repository::read_recipe(self, pkg).await;
+        }
+        self.with_build_spec_tag_for_pkg(pkg, |pkg, _, tag| async move {
+            let (mut reader, _) = self.open_payload_with_retries(tag.target).await?;
+            let mut yaml = String::new();
+            reader
+                .read_to_string(&mut yaml)
+                .await
+                .map_err(|err| Error::FileReadError(tag.target.to_string().into(), err))?;
+            if let Some(rewriter) = &self.spec_rewriter {
+                yaml = rewriter.rewrite_recipe(&pkg, yaml)?;
+            }
+            let yaml = self.recipe_migrations.migrate(&pkg, yaml)?;
+            SpecRecipe::from_yaml(yaml)
+                .map_err(|err| Error::InvalidPackageSpec(pkg.to_any_ident(None), err.to_string()))
+                .map(Arc::new)
         })
+        .await
+    }
+
+    /// Read the resolved build options of a package build.
+    ///
+    /// Only the build options are parsed from the stored spec, which is
+    /// much faster than reading the whole package when scanning many
+    /// builds for their options.
+    pub async fn read_resolved_options(&self, pkg: &BuildIdent) -> Result<OptionMap> {
+        if let Some(v) = self.cached(&self.caches.resolved_options, pkg) {
+            return v.into();
+        }
+
+        let r: Result<OptionMap> = self
+            .with_build_spec_tag_for_pkg(pkg, |pkg, _, tag| async move {
+                let (mut reader, filename) = self.open_payload_with_retries(tag.target).await?;
+                let mut yaml = String::new();
+                reader
+                    .read_to_string(&mut yaml)
+                    .await
+                    .map_err(|err| Error::FileReadError(filename, err))?;
+                if let Some(rewriter) = &self.spec_rewriter {
+                    yaml = rewriter.rewrite_package(&pkg, yaml)?;
+                }
+                let spec: PartialPackageSpec = serde_yaml::from_str(&yaml).map_err(|err| {
+                    Error::InvalidPackageSpec(pkg.to_any_ident(), err.to_string())
+                })?;
+                let mut opts = OptionMap::default();
+                for opt in spec.build.options.iter() {
+                    // stored builds have all of their options resolved
+                    opts.insert(opt.full_name().to_owned(), opt.get_value(None));
+                }
+                Ok(opts)
+            })
+            .await;
+
+        self.caches
+            .resolved_options
+            .insert(pkg.clone(), CacheEntry::new(r.as_ref().cloned().into()));
+        r
+    }
+
+    /// Read the recipe of a package version and all of its concrete builds.
+    ///
+    /// The builds are read concurrently, and everything is read through
+    /// the recipe and package caches so that later reads of the same
+    /// recipe or builds are served from them. Embedded packages are
+    /// skipped, as they are when upgrading the repository.
+    pub async fn read_version_bundle(&self, pkg: &VersionIdent) -> Result<VersionBundle> {
+        let (recipe, builds) = tokio::try_join!(
+            crate::Repository::read_recipe(self, pkg),
+            Storage::get_concrete_package_builds(self, pkg),
+        )?;
+        let builds = builds
+            .into_iter()
+            .filter(|build| !build.is_embedded())
+            .collect_vec();
+        let specs = crate::Repository::read_packages(self, &builds).await;
+        let builds = builds
+            .into_iter()
+            .zip(specs)
+            .map(|(build, spec)| Ok((build, spec?)))
+            .collect::<Result<_>>()?;
+        Ok(VersionBundle { recipe, builds })
+    }
+
+    /// List the builds of a package, separating those whose spec can be
+    /// read from those that fail to read or parse.
+    ///
+    /// Specs are read concurrently and through the package cache, see
+    /// [`crate::Repository::read_packages`].
+    pub async fn list_healthy_builds(
+        &self,
+        pkg: &VersionIdent,
+    ) -> Result<(Vec<BuildIdent>, Vec<(BuildIdent, Error)>)> {
+        let mut builds = crate::Repository::list_package_builds(self, pkg).await?;
+        builds.sort();
+        let results = crate::Repository::read_packages(self, &builds).await;
+        let mut healthy = Vec::with_capacity(builds.len());
+        let mut broken = Vec::new();
+        for (build, result) in builds.into_iter().zip(results) {
+            match result {
+                Ok(_) => healthy.push(build),
+                Err(err) => broken.push((build, err)),
+            }
+        }
+        Ok((healthy, broken))
+    }
+
+    /// List the packages in this repository whose name starts with
+    /// the given prefix.
+    ///
+    /// A prefix that includes a version, as in `my-pkg/1.`, is answered
+    /// by listing only that package's tags. Otherwise, the prefix could
+    /// match any package folder, so the full listing is filtered
+    /// instead. Either way, the listings come from the `ls_tags` cache
+    /// when possible.
+    pub async fn list_packages_matching(&self, prefix: &str) -> Result<Vec<PkgNameBuf>> {
+        let Some((name, version_prefix)) = prefix.split_once('/') else {
+            let mut packages = crate::Repository::list_packages(self).await?;
+            packages.retain(|pkg| pkg.as_str().starts_with(prefix));
+            packages.sort();
+            return Ok(packages);
+        };
+        let Ok(name) = PkgName::new(name) else {
+            return Ok(Vec::new());
+        };
+        let mut path = RelativePathBuf::from("spk/spec");
+        path.push(name.as_str());
+        let has_match = self
+            .ls_tags(&path)
+            .await
+            .into_iter()
+            .any(|entry| match entry {
+                Ok(EntryType::Folder(version)) | Ok(EntryType::Tag(version)) => {
+                    version.starts_with(version_prefix)
+                }
+                Ok(EntryType::Namespace { .. }) => false,
+                Err(_) => false,
+            });
+        Ok(if has_match {
+            vec![name.to_owned()]
+        } else {
+            Vec::new()
+        })
+    }
+
+    /// Check if any concrete build exists for a version of the named
+    /// package within the given range.
+    ///
+    /// Versions are checked newest first, and this returns as soon as
+    /// one with a build is found, without listing the builds of any of
+    /// the remaining versions.
+    pub async fn any_build_exists(&self, name: &PkgName, range: &VersionRange) -> Result<bool> {
+        let versions = crate::Repository::list_package_versions(self, name).await?;
+        for version in versions.iter().rev() {
+            if !range.is_applicable(version).is_ok() {
+                continue;
+            }
+            let pkg = VersionIdent::new(name.to_owned(), (**version).clone());
+            if !self.get_concrete_package_builds(&pkg).await?.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// List the versions of the named package along with the number of
+    /// builds that each version has.
+    ///
+    /// The builds of each version are listed concurrently. Versions
+    /// without any builds are included with a count of zero.
+    pub async fn list_package_versions_with_build_counts(
+        &self,
+        name: &PkgName,
+    ) -> Result<Vec<(Arc<Version>, usize)>> {
+        let versions = crate::Repository::list_package_versions(self, name).await?;
+        futures::stream::iter(versions.iter().cloned())
+            .map(|version| async move {
+                let pkg = VersionIdent::new(name.to_owned(), (*version).clone());
+                let builds = crate::Repository::list_package_builds(self, &pkg).await?;
+                Ok((version, builds.len()))
+            })
+            .buffered(LIST_BUILD_COUNTS_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    /// Populate the caches used to list the versions and builds of the
+    /// named packages, such as before a solve that is known to need them.
+    ///
+    /// The packages are read concurrently. A package that cannot be read
+    /// is logged as a warning instead of failing the prewarm, so that one
+    /// missing package does not stop the others from being cached. Nothing
+    /// is read if the cache policy does not permit cached results to be
+    /// reused.
+    pub async fn prewarm(&self, names: &[PkgNameBuf]) -> Result<()> {
+        if !self.cached_success_permitted() {
+            return Ok(());
+        }
+        let mut reads = futures::stream::iter(names.iter())
+            .map(|name| async move {
+                if let Err(err) = self.prewarm_package(name).await {
+                    tracing::warn!("Failed to prewarm the caches for {name}: {err}");
+                }
+            })
+            .buffer_unordered(READ_PACKAGES_CONCURRENCY);
+        while reads.next().await.is_some() {}
+        Ok(())
+    }
+
+    async fn prewarm_package(&self, name: &PkgName) -> Result<()> {
+        let versions = self.list_all_package_versions(name).await?;
+        for version in versions.iter() {
+            let pkg = VersionIdent::new(name.to_owned(), (**version).clone());
+            Storage::get_concrete_package_builds(self, &pkg).await?;
+        }
+        Ok(())
+    }
+
+    /// List the packages that have no concrete builds in any version.
+    ///
+    /// These are packages where every version only has a recipe
+    /// published. The versions of each package are checked newest
+    /// first, moving on to the next package as soon as one build is
+    /// found.
+    pub async fn packages_without_builds(&self) -> Result<Vec<PkgNameBuf>> {
+        let mut buildless = Vec::new();
+        'packages: for name in crate::Repository::list_packages(self).await? {
+            let versions = crate::Repository::list_package_versions(self, &name).await?;
+            for version in versions.iter().rev() {
+                let pkg = VersionIdent::new(name.clone(), (**version).clone());
+                if !self.get_concrete_package_builds(&pkg).await?.is_empty() {
+                    continue 'packages;
+                }
+            }
+            buildless.push(name);
+        }
+        Ok(buildless)
+    }
+
+    /// List the embedded builds of a package, also returning the
+    /// embed stubs that could not be decoded.
+    ///
+    /// [`Storage::get_embedded_package_builds`] skips these stubs, so
+    /// this is useful for tools that need to report on them.
+    pub async fn get_embedded_package_builds_verbose(
+        &self,
+        pkg: &VersionIdent,
+    ) -> Result<(HashSet<BuildIdent>, Vec<EmbedStubDecodeError>)> {
+        let (builds, errors) = self.list_embedded_package_builds(pkg).await?;
+        Ok((builds.into_keys().collect(), errors))
+    }
+
+    /// List the versions of a package, also returning the tags that
+    /// could not be parsed as a version.
+    ///
+    /// [`crate::Repository::list_package_versions`] logs a warning for
+    /// each of these tags instead, while this does not log anything so
+    /// that callers can report them as they choose.
+    pub async fn list_package_versions_with_diagnostics(
+        &self,
+        name: &PkgName,
+    ) -> Result<(Arc<Vec<Arc<Version>>>, Vec<SkippedEntry>)> {
+        let (versions, skipped) = self.scan_package_versions(name).await;
+        let versions = self
+            .hide_yanked_versions(name, Ok(Arc::new(versions)))
+            .await?;
+        Ok((versions, skipped))
+    }
+
+    /// List the concrete builds of a package, also returning the tags
+    /// that could not be parsed as a build.
+    ///
+    /// [`Storage::get_concrete_package_builds`] logs a warning for each
+    /// of these tags instead, while this does not log anything.
+    pub async fn get_concrete_package_builds_with_diagnostics(
+        &self,
+        pkg: &VersionIdent,
+    ) -> Result<(HashSet<BuildIdent>, Vec<SkippedEntry>)> {
+        let (builds, skipped) = self.scan_concrete_package_builds(pkg).await?;
+        Ok((builds.into_keys().collect(), skipped))
+    }
+
+    /// Read every version of the spec tag of a package build, newest first.
+    ///
+    /// Tags are never overwritten in spfs, so this includes each time
+    /// that the build was published, along with who published it.
+    pub async fn package_tag_history(&self, pkg: &BuildIdent) -> Result<Vec<TagHistoryEntry>> {
+        let tag_spec = self
+            .with_build_spec_tag_for_pkg(pkg, |_, tag_spec, _| async move { Ok(tag_spec) })
+            .await?;
+        self.inner
+            .read_tag(&tag_spec)
+            .await?
+            .map_ok(TagHistoryEntry::from)
+            .map_err(Error::from)
+            .try_collect()
+            .await
+    }
+
+    /// Find every spfs object that the components of a build depend on.
+    ///
+    /// The layer or platform of each component is walked through the
+    /// object graph, collecting its digest along with those of every
+    /// manifest and blob that it references. Syncing all of these
+    /// objects to another repository is enough to make the build
+    /// available there.
+    pub async fn package_object_closure(
+        &self,
+        pkg: &BuildIdent,
+    ) -> Result<HashSet<spfs::encoding::Digest>> {
+        let components = crate::Repository::read_components(self, pkg).await?;
+        let mut closure = HashSet::new();
+        let mut next: Vec<_> = components.into_values().collect();
+        // each level of the graph is read concurrently, and objects that
+        // are shared between components or subtrees are only read once
+        while !next.is_empty() {
+            let level: Vec<_> = next.drain(..).filter(|d| closure.insert(*d)).collect();
+            let mut reads = futures::stream::iter(
+                level
+                    .into_iter()
+                    .map(|digest| async move { self.inner.read_object(digest).await }),
+            )
+            .buffer_unordered(READ_PACKAGES_CONCURRENCY);
+            while let Some(object) = reads.try_next().await? {
+                next.extend(
+                    object
+                        .child_objects()
+                        .into_iter()
+                        .filter(|d| !closure.contains(d)),
+                );
+            }
+        }
+        Ok(closure)
+    }
+
+    /// Stream every build in this repository.
+    ///
+    /// Packages, versions and builds are listed lazily as the stream is
+    /// polled, rather than being collected up front, and the listings
+    /// come from the `ls_tags` cache when possible. Package folders
+    /// with invalid names are skipped with a warning.
+    pub fn iter_all_builds(&self) -> impl futures::Stream<Item = Result<BuildIdent>> + '_ {
+        self.ls_tags_stream(relative_path::RelativePath::new("spk/spec"))
+            .filter_map(|entry| async move {
+                match entry {
+                    Ok(EntryType::Folder(name)) => match PkgNameBuf::from_str(&name) {
+                        Ok(name) => Some(name),
+                        Err(_) => {
+                            tracing::warn!("Invalid package name found in spfs tags: {name}");
+                            None
+                        }
+                    },
+                    Ok(EntryType::Tag(_)) => None,
+                    Ok(EntryType::Namespace { .. }) => None,
+                    Err(_) => None,
+                }
+            })
+            .then(move |name| async move {
+                let versions = crate::Repository::list_package_versions(self, &name).await;
+                listing_stream(versions.map(|versions| {
+                    versions
+                        .iter()
+                        .map(|version| VersionIdent::new(name.clone(), (**version).clone()))
+                        .collect()
+                }))
+            })
+            .flatten()
+            .then(move |pkg| async move {
+                match pkg {
+                    Ok(pkg) => {
+                        listing_stream(crate::Repository::list_package_builds(self, &pkg).await)
+                    }
+                    Err(err) => listing_stream(Err(err)),
+                }
+            })
+            .flatten()
+    }
+
+    /// Find the package folders in this repository whose names differ
+    /// only by case.
+    ///
+    /// Package names are always lowercase, but folders with other
+    /// casings can still be created by older tools or by writing tags
+    /// directly. On a case-insensitive filesystem these folders would
+    /// be merged unpredictably.
+    pub async fn check_name_collisions(&self) -> Result<Vec<NameCollision>> {
+        let mut by_folded_name: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for root in ["spk/spec", "spk/pkg"] {
+            for entry in self.ls_tags(relative_path::RelativePath::new(root)).await {
+                if let EntryType::Folder(name) = entry? {
+                    by_folded_name
+                        .entry(name.to_lowercase())
+                        .or_default()
+                        .insert(name);
+                }
+            }
+        }
+        Ok(by_folded_name
+            .into_values()
+            .filter(|names| names.len() > 1)
+            .map(|names| NameCollision {
+                names: names.into_iter().collect(),
+            })
+            .collect())
+    }
+
+    /// Log a warning for any existing package folders whose names
+    /// differ from the given one only by case.
+    async fn warn_about_name_collisions(&self, name: &PkgName) {
+        for root in ["spk/spec", "spk/pkg"] {
+            for entry in self.ls_tags(relative_path::RelativePath::new(root)).await {
+                let Ok(EntryType::Folder(existing)) = entry else {
+                    continue;
+                };
+                if existing != name.as_str() && existing.eq_ignore_ascii_case(name.as_str()) {
+                    tracing::warn!(
+                        "Package {name} collides with existing package folder {existing} on case-insensitive filesystems"
+                    );
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Find the component tags of the named package's builds that are
+    /// not declared by the build's spec.
+    ///
+    /// Partial publishes and failed removals can leave these behind.
+    /// Builds without a readable spec, or that are stored in the legacy
+    /// format without component tags, are skipped.
+    /// See [`Self::remove_stray_component_tags`] to clean them up.
+    pub async fn find_stray_component_tags(
+        &self,
+        name: &PkgName,
+    ) -> Result<Vec<(BuildIdent, Component)>> {
+        Ok(self
+            .stray_component_tags(name)
+            .await?
+            .into_iter()
+            .map(|(build, component, _)| (build, component))
+            .collect())
+    }
+
+    /// Remove the component tags found by [`Self::find_stray_component_tags`],
+    /// returning the components that were removed.
+    pub async fn remove_stray_component_tags(
+        &self,
+        name: &PkgName,
+    ) -> Result<Vec<(BuildIdent, Component)>> {
+        self.ensure_writable()?;
+        let stray = self.stray_component_tags(name).await?;
+        let mut removed = Vec::with_capacity(stray.len());
+        for (build, component, tag_spec) in stray {
+            match self.inner.remove_tag_stream(&tag_spec).await {
+                Ok(_) | Err(spfs::Error::UnknownReference(_)) => {}
+                Err(err) => {
+                    self.invalidate_caches_for(name);
+                    return Err(err.into());
+                }
+            }
+            tracing::info!(%build, %component, "removed stray component tag");
+            removed.push((build, component));
+        }
+        self.invalidate_caches_for(name);
+        Ok(removed)
+    }
+
+    /// Find the stray component tags of the named package's builds,
+    /// along with the tag of each one.
+    async fn stray_component_tags(
+        &self,
+        name: &PkgName,
+    ) -> Result<Vec<(BuildIdent, Component, TagSpec)>> {
+        let mut stray = Vec::new();
+        let versions = crate::Repository::list_package_versions(self, name).await?;
+        for version in versions.iter() {
+            let pkg = VersionIdent::new(name.to_owned(), (**version).clone());
+            let mut builds = self
+                .get_concrete_package_builds(&pkg)
+                .await?
+                .into_iter()
+                .collect_vec();
+            builds.sort();
+            for build in builds {
+                let stored = match self.lookup_package(&build).await {
+                    Ok(StoredPackage::WithComponents(stored)) => stored,
+                    Ok(StoredPackage::WithoutComponents(_)) => continue,
+                    Err(Error::PackageNotFound(_)) => continue,
+                    Err(err) => return Err(err),
+                };
+                let spec = match self.read_package_from_storage(&build).await {
+                    Ok(spec) => spec,
+                    Err(Error::PackageNotFound(_)) => continue,
+                    Err(err) => {
+                        tracing::warn!(%build, "skipping build with unreadable spec: {err}");
+                        continue;
+                    }
+                };
+                let declared = spec.components().names();
+                let mut extras = stored
+                    .into_iter()
+                    .filter(|(component, _)| {
+                        // the source component of a source build is not
+                        // declared in its spec
+                        let is_source = build.is_source() && *component == Component::Source;
+                        !is_source && !declared.contains(component)
+                    })
+                    .collect_vec();
+                extras.sort_by(|(a, _), (b, _)| a.cmp(b));
+                stray.extend(
+                    extras
+                        .into_iter()
+                        .map(|(component, tag_spec)| (build.clone(), component, tag_spec)),
+                );
+            }
+        }
+        Ok(stray)
+    }
+
+    /// Find the builds that have a spec tag but no package tags.
+    ///
+    /// Builds are published under both the `spk/spec` and `spk/pkg` tag
+    /// trees, and partial publishes or failed removals can leave one
+    /// without the other. These builds are still listed by the
+    /// repository, but their components cannot be read. Embed stubs,
+    /// which only ever have a spec tag, are not included.
+    /// See [`Self::find_orphaned_packages`] for the inverse.
+    pub async fn find_orphaned_specs(&self) -> Result<Vec<BuildIdent>> {
+        Ok(self
+            .orphaned_builds("spk/spec", "spk/pkg")
+            .await?
+            .into_iter()
+            .map(|(build, _)| build)
+            .collect())
+    }
+
+    /// Find the builds that have package tags but no spec tag.
+    ///
+    /// The components of these builds can be found, but the builds
+    /// cannot be read or resolved without their spec.
+    /// See [`Self::find_orphaned_specs`] for the inverse.
+    pub async fn find_orphaned_packages(&self) -> Result<Vec<BuildIdent>> {
+        Ok(self
+            .orphaned_builds("spk/pkg", "spk/spec")
+            .await?
+            .into_iter()
+            .map(|(build, _)| build)
+            .collect())
+    }
+
+    /// Remove the spec tags found by [`Self::find_orphaned_specs`],
+    /// returning the builds that were removed.
+    pub async fn remove_orphaned_specs(&self) -> Result<Vec<BuildIdent>> {
+        self.ensure_writable()?;
+        let orphans = self.orphaned_builds("spk/spec", "spk/pkg").await?;
+        self.remove_orphaned_builds(orphans).await
+    }
+
+    /// Remove the package tags found by [`Self::find_orphaned_packages`],
+    /// returning the builds that were removed.
+    pub async fn remove_orphaned_packages(&self) -> Result<Vec<BuildIdent>> {
+        self.ensure_writable()?;
+        let orphans = self.orphaned_builds("spk/pkg", "spk/spec").await?;
+        self.remove_orphaned_builds(orphans).await
+    }
+
+    /// Remove all of the given tags of each build.
+    async fn remove_orphaned_builds(
+        &self,
+        orphans: Vec<(BuildIdent, Vec<TagSpec>)>,
+    ) -> Result<Vec<BuildIdent>> {
+        let mut removed = Vec::with_capacity(orphans.len());
+        for (build, tags) in orphans {
+            for tag_spec in tags {
+                match self.inner.remove_tag_stream(&tag_spec).await {
+                    Ok(_) | Err(spfs::Error::UnknownReference(_)) => {}
+                    Err(err) => {
+                        self.invalidate_caches();
+                        return Err(err.into());
+                    }
+                }
+            }
+            tracing::info!(%build, "removed orphaned build tags");
+            removed.push(build);
+        }
+        self.invalidate_caches();
+        Ok(removed)
+    }
+
+    /// Find the builds in the `root` tag tree that have no tags in the
+    /// `other` tag tree, along with their tags in `root`.
+    async fn orphaned_builds(
+        &self,
+        root: &str,
+        other: &str,
+    ) -> Result<Vec<(BuildIdent, Vec<TagSpec>)>> {
+        let builds = self.build_tags_in_tree(root).await?;
+        let others = self.build_tags_in_tree(other).await?;
+        Ok(builds
+            .into_iter()
+            .filter(|(path, _)| !others.contains_key(path))
+            .map(|(_, orphan)| orphan)
+            .collect())
+    }
+
+    /// Walk the `spk/spec` or `spk/pkg` tag tree, collecting the tags of
+    /// every build keyed by their path within the tree.
+    ///
+    /// Paths are compared exactly, so a build stored under a legacy
+    /// version tag in one tree is not matched to the normalized version
+    /// in the other.
+    async fn build_tags_in_tree(
+        &self,
+        root: &str,
+    ) -> Result<BTreeMap<RelativePathBuf, (BuildIdent, Vec<TagSpec>)>> {
+        let root = relative_path::RelativePath::new(root);
+        let mut builds: BTreeMap<RelativePathBuf, (BuildIdent, Vec<TagSpec>)> = BTreeMap::new();
+        for entry in self.ls_tags(root).await {
+            let EntryType::Folder(name) = entry? else {
+                continue;
+            };
+            let Ok(pkg_name) = PkgNameBuf::from_str(&name) else {
+                tracing::warn!("Invalid package name found in spfs tags: {name}");
+                continue;
+            };
+            for entry in self.ls_tags(&root.join(&name)).await {
+                let EntryType::Folder(version) = entry? else {
+                    continue;
+                };
+                // undo our encoding of the invalid '+' character in spfs tags
+                let Ok(parsed_version) = parse_version(version.replace("..", "+")) else {
+                    tracing::warn!("Invalid version found in spfs tags: {version}");
+                    continue;
+                };
+                let pkg = VersionIdent::new(pkg_name.clone(), parsed_version);
+                let version_path = root.join(&name).join(&version);
+                for entry in self.ls_tags(&version_path).await {
+                    let (build, is_folder) = match entry? {
+                        EntryType::Tag(build) => (build, false),
+                        EntryType::Folder(build) => (build, true),
+                        EntryType::Namespace { .. } => continue,
+                    };
+                    if build.starts_with(EmbeddedSourcePackage::EMBEDDED_BY_PREFIX) {
+                        continue;
+                    }
+                    let parsed_build = match parse_build(&build) {
+                        Ok(parsed_build) if parsed_build.is_embedded() => continue,
+                        Ok(parsed_build) => parsed_build,
+                        Err(_) => {
+                            tracing::warn!("Invalid build found in spfs tags: {build}");
+                            continue;
+                        }
+                    };
+                    let build_path = version_path.join(&build);
+                    let mut tags = Vec::new();
+                    if is_folder {
+                        // the component tags of a build are stored in its folder
+                        for entry in self.ls_tags(&build_path).await {
+                            if let EntryType::Tag(component) = entry? {
+                                tags.push(TagSpec::parse(build_path.join(component))?);
+                            }
+                        }
+                    } else {
+                        tags.push(TagSpec::parse(&build_path)?);
+                    }
+                    builds
+                        .entry(
+                            RelativePathBuf::from(name.as_str())
+                                .join(&version)
+                                .join(&build),
+                        )
+                        .or_insert_with(|| (pkg.to_build_ident(parsed_build), Vec::new()))
+                        .1
+                        .extend(tags);
+                }
+            }
+        }
+        Ok(builds)
+    }
+
+    /// Move the tags and objects of this filesystem repository into a new
+    /// storage root, and switch this repository over to it.
+    ///
+    /// Every tag, including its history, is copied into a repository at
+    /// `new_root` along with the objects and payloads that it references.
+    /// Each copied object and payload is then checked against its digest
+    /// before this repository is updated to point at the new root. The
+    /// original root is never modified, and data that already exists in
+    /// the new root is not copied again, so an interrupted migration can
+    /// be resumed by calling this again.
+    pub async fn migrate_storage_root(
+        &mut self,
+        new_root: &std::path::Path,
+        progress: spfs::sync::reporter::SyncReporters,
+    ) -> Result<()> {
+        self.ensure_writable()?;
+        if !matches!(&*self.inner, spfs::storage::RepositoryHandle::FS(_)) {
+            return Err(Error::String(format!(
+                "Only filesystem repositories can be migrated to a new storage root, {} is a {} repository",
+                self.address,
+                Self::handle_kind(&self.inner)
+            )));
+        }
+        let target: spfs::storage::RepositoryHandle =
+            spfs::storage::fs::FsRepository::create(new_root)
+                .await?
+                .into();
+
+        {
+            let syncer = spfs::Syncer::new(&self.inner, &target).with_reporter(progress);
+            let mut tag_streams = self.inner.iter_tag_streams();
+            while let Some((_, stream)) = tag_streams.try_next().await? {
+                let mut history: Vec<Tag> = stream.try_collect().await?;
+                // tag streams are read newest first, but the history
+                // is rebuilt in the order that it was originally written
+                history.reverse();
+                for tag in history {
+                    syncer.sync_digest(tag.target).await?;
+                    target.insert_tag(&tag).await?;
+                }
+            }
+        }
+
+        let mut objects = target.find_digests(spfs::graph::DigestSearchCriteria::All);
+        while let Some(digest) = objects.try_next().await? {
+            let actual = target.read_object(digest).await?.digest()?;
+            if actual != digest {
+                return Err(Error::String(format!(
+                    "Object {digest} was corrupted while migrating to {}, got {actual}",
+                    new_root.display()
+                )));
+            }
+        }
+        let mut payloads = target.iter_payload_digests();
+        while let Some(digest) = payloads.try_next().await? {
+            let (mut reader, filename) = target.open_payload(digest).await?;
+            let mut hasher = spfs::encoding::Hasher::new_async();
+            tokio::io::copy(&mut reader, &mut hasher)
+                .await
+                .map_err(|err| Error::FileReadError(filename, err))?;
+            let actual = hasher.digest();
+            if actual != digest {
+                return Err(Error::String(format!(
+                    "Payload {digest} was corrupted while migrating to {}, got {actual}",
+                    new_root.display()
+                )));
+            }
+        }
+
+        self.address = normalize_repository_address(&target.address());
+        self.caches = if self.caches.registration.is_some() {
+            CachesForAddress::new(&self.address)
+        } else {
+            CachesForAddress::new_unshared()
+        };
+        self.inner = Arc::new(target);
+        Ok(())
+    }
+
+    /// Check that the spec and package tags of a build are consistent.
+    ///
+    /// The issues found are returned, and an empty list means that the
+    /// build is stored correctly. Embed stubs stored alongside the build
+    /// are also checked. Returns [`Error::PackageNotFound`] if the build
+    /// has neither a spec nor any package tags.
+    pub async fn verify_package(&self, pkg: &BuildIdent) -> Result<Vec<VerificationIssue>> {
+        let mut issues = Vec::new();
+        let spec_tag = TagSpec::parse(Self::build_spec_tag(pkg))?;
+
+        let (has_spec, spec) = match self.read_package_from_storage(pkg).await {
+            Ok(spec) => (true, Some(spec)),
+            Err(Error::PackageNotFound(_)) => (false, None),
+            Err(err) => {
+                issues.push(VerificationIssue::InvalidSpec {
+                    spec_tag: spec_tag.clone(),
+                    message: err.to_string(),
+                });
+                (true, None)
+            }
+        };
+        let stored = match self.lookup_package(pkg).await {
+            Ok(stored) => Some(stored),
+            Err(Error::PackageNotFound(_)) => None,
+            Err(err) => return Err(err),
+        };
+
+        match (has_spec, &stored) {
+            (false, None) => return Err(Error::PackageNotFound(pkg.to_any_ident())),
+            (true, None) => issues.push(VerificationIssue::SpecWithoutPackage {
+                spec_tag: spec_tag.clone(),
+            }),
+            (false, Some(_)) => issues.push(VerificationIssue::MissingSpec {
+                expected: spec_tag.clone(),
+            }),
+            (true, Some(_)) => {}
+        }
+
+        if let Some(StoredPackage::WithComponents(tags)) = &stored {
+            // the legacy tag sits at the folder that holds the component tags
+            let legacy_tag = match tags.values().next().and_then(|tag| tag.org()) {
+                Some(path) => TagSpec::parse(path)?,
+                None => TagSpec::parse(Self::build_package_tag(pkg))?,
+            };
+            if self.write_legacy_tags && !self.inner.has_tag(&legacy_tag).await {
+                issues.push(VerificationIssue::MissingLegacyTag {
+                    expected: legacy_tag.clone(),
+                });
+            }
+            let expected_components = if pkg.is_source() {
+                vec![Component::Source]
+            } else {
+                spec.iter()
+                    .flat_map(|spec| spec.components().iter())
+                    .map(|c| c.name.clone())
+                    .collect()
+            };
+            for component in expected_components {
+                if !tags.contains_key(&component) {
+                    let expected = TagSpec::parse(legacy_tag.path().join(component.as_str()))?;
+                    issues.push(VerificationIssue::MissingComponentTag {
+                        component,
+                        expected,
+                    });
+                }
+            }
+        }
+
+        let mut version_folder = Self::build_spec_tag(pkg);
+        version_folder.pop();
+        for entry in self.ls_tags(&version_folder).await {
+            let Ok(EntryType::Tag(name)) = entry else {
+                continue;
+            };
+            if name.starts_with(EmbeddedSourcePackage::EMBEDDED_BY_PREFIX)
+                && Self::decode_embed_stub_tag(&name).is_none()
+            {
+                issues.push(VerificationIssue::UndecodableEmbedStub {
+                    tag: TagSpec::parse(version_folder.join(name))?,
+                });
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// Copy a package build from this repository into another.
+    ///
+    /// The objects of each component are synced directly into the
+    /// destination before the package is published there, which replaces
+    /// any existing tags for the build just like a normal publish. Stubs
+    /// for any packages embedded in the build are also recreated.
+    pub async fn copy_package(&self, pkg: &BuildIdent, dest: &SpfsRepository) -> Result<()> {
+        let spec = crate::Repository::read_package(self, pkg).await?;
+        let components = crate::Repository::read_components(self, pkg).await?;
+        let syncer = spfs::Syncer::new(&self.inner, &dest.inner);
+        let desired = components.values().copied().collect();
+        syncer.sync_env(desired).await?;
+        crate::Repository::publish_package(dest, &spec, &components).await?;
+        Ok(())
+    }
+
+    /// Move all of a package's spec and build tags from one tag
+    /// namespace into another, where `None` is the root namespace.
+    ///
+    /// The complete history of each tag is copied, preserving the
+    /// original tag times, before the tags are removed from the source
+    /// namespace. Nothing is changed if any of the package's tags
+    /// already exist in the target namespace, and the tags that were
+    /// already copied are removed again if any tag fails to copy.
+    pub async fn promote_namespace(
+        &self,
+        name: &PkgName,
+        from: &spfs::storage::TagNamespace,
+        to: Option<&spfs::storage::TagNamespace>,
+    ) -> Result<()> {
+        self.ensure_writable()?;
+        let pkg = VersionIdent::new_zero(name).into_any_ident(None);
+        let mut tag_specs = Vec::new();
+        for root in [Self::build_spec_tag(&pkg), Self::build_package_tag(&pkg)] {
+            tag_specs.extend(self.find_tags_under(Some(from), &root).await?);
+        }
+        if tag_specs.is_empty() {
+            return Err(Error::PackageNotFound(pkg));
+        }
+
+        for tag_spec in tag_specs.iter() {
+            if self.inner.has_tag_in_namespace(to, tag_spec).await {
+                return Err(Error::String(format!(
+                    "Cannot promote {name}, {tag_spec} already exists in the target namespace"
+                )));
+            }
+        }
+
+        let mut copied = Vec::with_capacity(tag_specs.len());
+        for tag_spec in tag_specs.iter() {
+            let res = self
+                .copy_tag_stream_to_namespace(tag_spec, Some(from), to)
+                .await;
+            copied.push(tag_spec);
+            if let Err(err) = res {
+                tracing::warn!("Failed to promote {name}, removing the copied tags");
+                for tag_spec in copied {
+                    // the stream may not have been copied at all
+                    let _ = self
+                        .inner
+                        .remove_tag_stream_in_namespace(to, tag_spec)
+                        .await;
+                }
+                self.invalidate_caches_for(name);
+                return Err(err);
+            }
+        }
+        for tag_spec in tag_specs.iter() {
+            self.inner
+                .remove_tag_stream_in_namespace(Some(from), tag_spec)
+                .await?;
+        }
+        self.invalidate_caches_for(name);
+        Ok(())
+    }
+
+    /// Move all of the spec and package tags in the root namespace
+    /// into the given tag namespace.
+    ///
+    /// The complete history of each tag is copied, preserving the original
+    /// targets, times and users, and then the original tag is removed. Each
+    /// tag is moved on its own, so this is safe to run again if it is
+    /// interrupted. Tags that already exist in the namespace are not copied
+    /// again, and the original is only removed if it points to the same
+    /// target as the one in the namespace.
+    pub async fn move_all_to_namespace(
+        &self,
+        namespace: &spfs::storage::TagNamespace,
+    ) -> Result<()> {
+        self.ensure_writable()?;
+        let mut tag_specs = Vec::new();
+        for root in ["spk/spec", "spk/pkg"] {
+            tag_specs.extend(
+                self.find_tags_under(None, relative_path::RelativePath::new(root))
+                    .await?,
+            );
+        }
+
+        let result = self.move_tags_to_namespace(&tag_specs, namespace).await;
+        // the moves may have been interrupted partway, so the caches
+        // are invalidated regardless of the result
+        self.invalidate_caches();
+        result
+    }
+
+    async fn move_tags_to_namespace(
+        &self,
+        tag_specs: &[TagSpec],
+        namespace: &spfs::storage::TagNamespace,
+    ) -> Result<()> {
+        for tag_spec in tag_specs.iter() {
+            let source = self.inner.resolve_tag_in_namespace(None, tag_spec).await?;
+            match self
+                .inner
+                .resolve_tag_in_namespace(Some(namespace), tag_spec)
+                .await
+            {
+                Ok(existing) if existing.target == source.target => {
+                    tracing::debug!(%tag_spec, "tag already in namespace, skipping copy");
+                }
+                Ok(_) => {
+                    tracing::warn!(
+                        %tag_spec,
+                        "a different tag already exists in namespace {namespace}, leaving original in place"
+                    );
+                    continue;
+                }
+                Err(spfs::Error::UnknownReference(_)) => {
+                    self.copy_tag_stream_to_namespace(tag_spec, None, Some(namespace))
+                        .await?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+            self.inner
+                .remove_tag_stream_in_namespace(None, tag_spec)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Copy the complete history of a tag from one tag namespace into
+    /// another, oldest first, so that the original times are preserved.
+    async fn copy_tag_stream_to_namespace(
+        &self,
+        tag_spec: &TagSpec,
+        from: Option<&spfs::storage::TagNamespace>,
+        to: Option<&spfs::storage::TagNamespace>,
+    ) -> Result<()> {
+        let mut history: Vec<_> = self
+            .inner
+            .read_tag_in_namespace(from, tag_spec)
+            .await?
+            .try_collect()
+            .await?;
+        // tag streams are read newest first
+        history.reverse();
+        for tag in history.iter() {
+            self.inner.insert_tag_in_namespace(to, tag).await?;
+        }
+        Ok(())
+    }
+
+    /// Rename every version and build of a package.
+    ///
+    /// All of the spec, package and component tags of `from` are recreated
+    /// under `to`, preserving the complete history of each tag along with
+    /// its original times and users. Spec blobs are rewritten with the new
+    /// name, while package and component tags keep their original targets.
+    /// Embedded stubs are recreated for the renamed builds. The original
+    /// tags are only removed once everything has been recreated, and it is
+    /// an error if `to` already has any tags.
+    pub async fn rename_package(&self, from: &PkgName, to: &PkgName) -> Result<()> {
+        self.ensure_writable()?;
+        let from_pkg = VersionIdent::new_zero(from).into_any_ident(None);
+        let to_pkg = VersionIdent::new_zero(to).into_any_ident(None);
+        let roots = [
+            (
+                Self::build_spec_tag(&from_pkg),
+                Self::build_spec_tag(&to_pkg),
+                true,
+            ),
+            (
+                Self::build_package_tag(&from_pkg),
+                Self::build_package_tag(&to_pkg),
+                false,
+            ),
+        ];
+
+        let mut renames = Vec::new();
+        for (from_root, to_root, is_spec) in roots.iter() {
+            if !self.find_tags_under(None, to_root).await?.is_empty() {
+                return Err(Error::String(format!(
+                    "Cannot rename {from} to {to}, {to} already exists"
+                )));
+            }
+            for tag_spec in self.find_tags_under(None, from_root).await? {
+                let path = tag_spec.path();
+                let Ok(rest) = path.strip_prefix(from_root) else {
+                    continue;
+                };
+                let new_tag_spec = TagSpec::parse(to_root.join(rest).as_str())?;
+                renames.push((tag_spec, new_tag_spec, *is_spec));
+            }
+        }
+        if renames.is_empty() {
+            return Err(Error::PackageNotFound(from_pkg));
+        }
+
+        // Find the builds with embedded stubs before anything is changed,
+        // since the stubs point back to the original package.
+        let mut embedding_specs = Vec::new();
+        for version in crate::Repository::list_package_versions(self, from)
+            .await?
+            .iter()
+        {
+            let pkg = VersionIdent::new(from.to_owned(), (**version).clone());
+            for build in Storage::get_concrete_package_builds(self, &pkg).await? {
+                if !build.can_embed() {
+                    continue;
+                }
+                let spec = crate::Repository::read_package(self, &build).await?;
+                if !self.get_embedded_providers(&spec)?.is_empty() {
+                    embedding_specs.push(spec);
+                }
+            }
+        }
+
+        let mut created = Vec::with_capacity(renames.len());
+        for (tag_spec, new_tag_spec, is_spec) in renames.iter() {
+            let res = self
+                .copy_renamed_tag_stream(tag_spec, new_tag_spec, *is_spec, from, to)
+                .await;
+            created.push(new_tag_spec);
+            if let Err(err) = res {
+                tracing::warn!("Failed to rename {from} to {to}, removing the new tags");
+                for tag_spec in created {
+                    // the stream may not have been created at all
+                    let _ = self.inner.remove_tag_stream(tag_spec).await;
+                }
+                self.invalidate_caches_for(to);
+                return Err(err);
+            }
+        }
+        self.invalidate_caches_for(to);
+
+        for old_spec in embedding_specs.iter() {
+            let old_ident = old_spec.ident();
+            let new_ident = BuildIdent::new(
+                VersionIdent::new(to.to_owned(), old_ident.version().clone()),
+                old_ident.build().clone(),
+            );
+            let new_spec = crate::Repository::read_package(self, &new_ident).await?;
+            for (embedded, components) in self.get_embedded_providers(old_spec)? {
+                self.create_embedded_stub_for_spec(&new_spec, &embedded, components.clone())
+                    .await?;
+                self.remove_embedded_stub_for_spec(old_spec, &embedded, components)
+                    .await?;
+            }
+        }
+
+        for (tag_spec, _, _) in renames.iter() {
+            self.inner.remove_tag_stream(tag_spec).await?;
+        }
+        self.invalidate_caches_for(from);
+        Ok(())
+    }
+
+    /// Publish the builds of one version of a package again as another
+    /// version, such as to promote a validated pre-release to a release
+    /// without rebuilding it.
+    ///
+    /// The recipe of `from`, if there is one, and each of its builds are
+    /// published under `to` with only the version in their specs changed.
+    /// The cloned builds keep their build digests, and their components
+    /// point to the same payloads as the originals. Embedded stubs are
+    /// created for the cloned builds as they are published.
+    ///
+    /// Build digests are computed from the resolved build options, so a
+    /// build with an option whose value is the version of `from` would
+    /// need a new digest to agree with its cloned spec. Such builds must
+    /// be rebuilt for `to` instead, and it is an error to clone them.
+    /// Every build is checked before anything is published, so nothing
+    /// is cloned in this case. It is also an error if `to` already exists.
+    pub async fn clone_version(&self, from: &VersionIdent, to: &VersionIdent) -> Result<()> {
+        self.ensure_writable()?;
+        if from.name() != to.name() {
+            return Err(Error::String(format!(
+                "Cannot clone {from} to {to}, the package names differ"
+            )));
+        }
+        let to_has_recipe = match crate::Repository::read_recipe(self, to).await {
+            Ok(_) => true,
+            Err(Error::PackageNotFound(_)) => false,
+            Err(err) => return Err(err),
+        };
+        if to_has_recipe
+            || !Storage::get_concrete_package_builds(self, to)
+                .await?
+                .is_empty()
+        {
+            return Err(Error::VersionExists(to.clone()));
+        }
+
+        let recipe = match crate::Repository::read_recipe(self, from).await {
+            Ok(recipe) => {
+                let yaml = replace_spec_pkg_yaml(&*recipe, &to.to_string())?;
+                let recipe = SpecRecipe::from_yaml(yaml).map_err(|err| {
+                    Error::InvalidPackageSpec(to.to_any_ident(None), err.to_string())
+                })?;
+                Some(recipe)
+            }
+            Err(Error::PackageNotFound(_)) => None,
+            Err(err) => return Err(err),
+        };
+
+        let from_version = from.version().to_string();
+        let mut builds = Vec::new();
+        for build in Storage::get_concrete_package_builds(self, from)
+            .await?
+            .into_iter()
+            .sorted()
+        {
+            let spec = crate::Repository::read_package(self, &build).await?;
+            let option_values = spec.option_values();
+            if let Some((name, _)) = option_values
+                .iter()
+                .find(|(_, value)| **value == from_version)
+            {
+                return Err(Error::String(format!(
+                    "Cannot clone {build} to {to}, its build digest includes the version through option '{name}', it must be rebuilt instead"
+                )));
+            }
+            let components = crate::Repository::read_components(self, &build).await?;
+            let ident = BuildIdent::new(to.clone(), build.build().clone());
+            let yaml = replace_spec_pkg_yaml(&*spec, &ident.to_string())?;
+            let spec = Spec::from_yaml(yaml)
+                .map_err(|err| Error::InvalidPackageSpec(ident.to_any_ident(), err.to_string()))?;
+            builds.push((spec, components));
+        }
+        if recipe.is_none() && builds.is_empty() {
+            return Err(Error::PackageNotFound(from.to_any_ident(None)));
+        }
+
+        if let Some(recipe) = recipe {
+            crate::Repository::publish_recipe(self, &recipe).await?;
+        }
+        for (spec, components) in builds.iter() {
+            crate::Repository::publish_package(self, spec, components).await?;
+        }
+        Ok(())
+    }
+
+    /// Mark a published version of a package as one that should not be used.
+    ///
+    /// The version is not removed, and can still be read and resolved
+    /// so that existing environments can be reproduced, but it is
+    /// reported by [`crate::Repository::is_yanked`] and is left out of
+    /// listings by repositories that hide yanked versions. The marker is
+    /// a `spk/yanked/<name>/<version>` tag.
+    pub async fn yank(&self, pkg: &VersionIdent) -> Result<()> {
+        self.ensure_writable()?;
+        let versions = self.list_all_package_versions(pkg.name()).await?;
+        if !versions.iter().any(|version| **version == *pkg.version()) {
+            return Err(Error::PackageNotFound(pkg.to_any_ident(None)));
+        }
+        let tag_spec = TagSpec::parse(Self::build_yanked_tag(pkg).as_str())?;
+        let digest = self
+            .inner
+            .commit_blob(Box::pin(std::io::Cursor::new(Vec::new())))
+            .await?;
+        self.inner.push_tag(&tag_spec, &digest).await?;
+        self.invalidate_caches_for(pkg.name());
+        Ok(())
+    }
+
+    /// Remove the marker made by [`Self::yank`], if there is one.
+    pub async fn unyank(&self, pkg: &VersionIdent) -> Result<()> {
+        self.ensure_writable()?;
+        let tag_spec = TagSpec::parse(Self::build_yanked_tag(pkg).as_str())?;
+        match self.inner.remove_tag_stream(&tag_spec).await {
+            Ok(()) | Err(spfs::Error::UnknownReference(_)) => {}
+            Err(err) => return Err(err.into()),
+        }
+        self.invalidate_caches_for(pkg.name());
+        Ok(())
+    }
+
+    /// Copy the complete history of a tag to a new location, rewriting
+    /// the package name in the spec blobs that it points to if `is_spec`.
+    async fn copy_renamed_tag_stream(
+        &self,
+        tag_spec: &TagSpec,
+        new_tag_spec: &TagSpec,
+        is_spec: bool,
+        from: &PkgName,
+        to: &PkgName,
+    ) -> Result<()> {
+        let mut history: Vec<_> = self.inner.read_tag(tag_spec).await?.try_collect().await?;
+        // tag streams are read newest first
+        history.reverse();
+        for tag in history.into_iter() {
+            let target = if is_spec {
+                let (mut reader, filename) = self.open_payload_with_retries(tag.target).await?;
+                let mut yaml = String::new();
+                reader
+                    .read_to_string(&mut yaml)
+                    .await
+                    .map_err(|err| Error::FileReadError(filename, err))?;
+                let yaml = rename_spec_yaml(&yaml, from, to)?;
+                self.inner
+                    .commit_blob(Box::pin(std::io::Cursor::new(yaml.into_bytes())))
+                    .await?
+            } else {
+                tag.target
+            };
+            let mut new_tag =
+                spfs::tracking::Tag::new(new_tag_spec.org(), new_tag_spec.name(), target)?;
+            new_tag.parent = tag.parent;
+            new_tag.time = tag.time;
+            new_tag.user = tag.user;
+            self.inner.insert_tag(&new_tag).await?;
+        }
+        Ok(())
+    }
+
+    /// Compute a fingerprint of the entire tag state of this repository.
+    ///
+    /// The fingerprint covers the target of every tag in the repository,
+    /// and so changes whenever any package, recipe or other tag is
+    /// changed. The tag tree is walked in sorted order and hashed as it
+    /// goes, so the result is stable for an unchanged repository.
+    pub async fn content_fingerprint(&self) -> Result<String> {
+        let mut hasher = spfs::encoding::Hasher::new_sync();
+        let mut to_visit = vec![RelativePathBuf::from("")];
+        while let Some(path) = to_visit.pop() {
+            let mut entries: Vec<EntryType> = self.inner.ls_tags(&path).try_collect().await?;
+            entries.sort();
+            let mut folders = Vec::new();
+            for entry in entries {
+                match entry {
+                    EntryType::Folder(name) => folders.push(path.join(name)),
+                    EntryType::Tag(name) => {
+                        let tag_spec = TagSpec::parse(path.join(name).as_str())?;
+                        let tag = self.inner.resolve_tag(&tag_spec).await?;
+                        hasher.update(format!("{tag_spec}={}\n", tag.target).as_bytes());
+                    }
+                    EntryType::Namespace { .. } => {}
+                }
+            }
+            // pushed in reverse so that folders are visited in sorted order
+            to_visit.extend(folders.into_iter().rev());
+        }
+        Ok(hasher.digest().to_string())
+    }
+
+    /// Recursively find all of the tags under the given path.
+    async fn find_tags_under(
+        &self,
+        namespace: Option<&spfs::storage::TagNamespace>,
+        root: &relative_path::RelativePath,
+    ) -> Result<Vec<TagSpec>> {
+        let mut tag_specs = Vec::new();
+        let mut to_visit = vec![root.to_owned()];
+        while let Some(path) = to_visit.pop() {
+            let mut entries = self.inner.ls_tags_in_namespace(namespace, &path);
+            while let Some(entry) = entries.next().await {
+                match entry? {
+                    EntryType::Folder(name) => to_visit.push(path.join(name)),
+                    EntryType::Tag(name) => {
+                        tag_specs.push(TagSpec::parse(path.join(name).as_str())?);
+                    }
+                    EntryType::Namespace { .. } => {}
+                }
+            }
+        }
+        Ok(tag_specs)
+    }
+
+    fn handle_kind(handle: &spfs::storage::RepositoryHandle) -> &'static str {
+        use spfs::storage::RepositoryHandle;
+        match handle {
+            RepositoryHandle::FS(_) => "fs",
+            RepositoryHandle::Tar(_) => "tar",
+            RepositoryHandle::Rpc(_) => "rpc",
+            RepositoryHandle::FallbackProxy(_) => "fallback",
+            RepositoryHandle::Proxy(_) => "proxy",
+            RepositoryHandle::Pinned(pinned) => Self::handle_kind(pinned.inner()),
+        }
+    }
+
+    /// Identify the payloads for the identified package's components,
+    /// deferring the resolution of each component's tag until it is
+    /// first accessed.
+    ///
+    /// See [`Storage::read_components_from_storage`] for a version
+    /// that eagerly resolves all components.
+    pub async fn read_components_lazy(&self, pkg: &BuildIdent) -> Result<LazyComponentMap> {
+        let tags = if pkg.build().is_embedded() {
+            HashMap::new()
+        } else {
+            self.lookup_package(pkg).await?.into_components()
+        };
+        Ok(LazyComponentMap {
+            repo: self.clone(),
+            pkg: pkg.clone(),
+            tags,
+            resolved: DashMap::new(),
+        })
+    }
+}
+
+/// The components of a package, mapped to their payloads.
+///
+/// The digest for each component is resolved from its tag on first
+/// access and remembered for subsequent accesses.
+#[derive(Debug)]
+pub struct LazyComponentMap {
+    repo: SpfsRepository,
+    pkg: BuildIdent,
+    tags: HashMap<Component, TagSpec>,
+    resolved: DashMap<Component, spfs::encoding::Digest>,
+}
+
+impl LazyComponentMap {
+    /// The package that these components belong to.
+    pub fn package(&self) -> &BuildIdent {
+        &self.pkg
+    }
+
+    /// Iterate over the components in this map, without resolving them.
+    pub fn components(&self) -> impl Iterator<Item = &Component> {
+        self.tags.keys()
+    }
+
+    /// True if the package has the given component.
+    pub fn contains(&self, component: &Component) -> bool {
+        self.tags.contains_key(component)
     }
-}
 
-impl SpfsRepository {
-    pub async fn new(name: &str, address: &str) -> Result<Self> {
-        let inner = spfs::open_repository(address).await?;
-        let address = inner.address().into_owned();
-        Ok(Self {
-            caches: CachesForAddress::new(&address),
-            address,
-            name: name.try_into()?,
-            inner: Arc::new(inner),
-            cache_policy: Arc::new(ArcSwap::new(Arc::new(CachePolicy::CacheOk))),
-            legacy_spk_version_tags: cfg!(feature = "legacy-spk-version-tags"),
-        })
+    /// The number of components in this map.
+    pub fn len(&self) -> usize {
+        self.tags.len()
     }
 
-    /// Access to the underlying [`spfs::storage::RepositoryHandle`].
-    pub fn inner(&self) -> &spfs::storage::RepositoryHandle {
-        &self.inner
+    /// True if the package has no components.
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
     }
 
-    /// Pin this repository to a specific point in time, limiting
-    /// all queries and making it read-only
-    pub fn pin_at_time(&mut self, ts: &spfs::tracking::TimeSpec) {
-        // Safety: we are going to mutate and replace the value that
-        // is being read here, and know that self.inner is both
-        // initialized and valid for reads
-        let tmp = unsafe { std::ptr::read(&*self.inner) };
-        let new = tmp.into_pinned(ts.to_datetime_from_now());
-        // Safety: we are replacing the old value with a moved copy
-        // of itself, and so explicitly do not want the old value
-        // dropped or accessed in any way
-        unsafe { std::ptr::write(Arc::as_ptr(&self.inner) as *mut _, new) };
-        self.address
-            .query_pairs_mut()
-            .append_pair("when", &ts.to_string());
+    /// Resolve the payload for a single component, if it exists.
+    pub async fn get(&self, component: &Component) -> Result<Option<spfs::encoding::Digest>> {
+        if let Some(digest) = self.resolved.get(component) {
+            return Ok(Some(*digest.value()));
+        }
+        let Some(tag_spec) = self.tags.get(component) else {
+            return Ok(None);
+        };
+        let tag = self
+            .repo
+            .resolve_tag(|| self.pkg.to_any_ident(), tag_spec)
+            .await?;
+        self.resolved.insert(component.clone(), tag.target);
+        Ok(Some(tag.target))
     }
 
-    /// Enable or disable the use of legacy spk version tags
-    pub fn set_legacy_spk_version_tags(&mut self, enabled: bool) {
-        self.legacy_spk_version_tags = enabled;
+    /// Resolve the payloads of all components.
+    pub async fn resolve_all(&self) -> Result<HashMap<Component, spfs::encoding::Digest>> {
+        let mut components = HashMap::with_capacity(self.tags.len());
+        for name in self.tags.keys() {
+            if let Some(digest) = self.get(name).await? {
+                components.insert(name.clone(), digest);
+            }
+        }
+        Ok(components)
     }
 }
 
@@ -213,6 +2404,22 @@ impl<T> From<CacheValue<T>> for Result<T> {
     }
 }
 
+/// A value that can be stored in one of the repository caches.
+trait Cacheable {
+    /// Return true if this value records a failed read.
+    fn is_cached_error(&self) -> bool {
+        false
+    }
+}
+
+impl<T> Cacheable for CacheValue<T> {
+    fn is_cached_error(&self) -> bool {
+        !matches!(self, CacheValue::Success(_))
+    }
+}
+
+impl Cacheable for Vec<EntryType> {}
+
 impl<T> From<std::result::Result<T, &crate::Error>> for CacheValue<T> {
     fn from(r: std::result::Result<T, &crate::Error>) -> Self {
         match r {
@@ -229,43 +2436,166 @@ impl<T> From<std::result::Result<T, &crate::Error>> for CacheValue<T> {
     }
 }
 
+/// The subset of a stored package spec needed to read its build options.
+#[derive(Deserialize)]
+struct PartialPackageSpec {
+    #[serde(default)]
+    build: PartialBuildSpec,
+}
+
+#[derive(Default, Deserialize)]
+struct PartialBuildSpec {
+    #[serde(default)]
+    options: Vec<spk_schema::Opt>,
+}
+
+/// A cached value along with the time that it was cached.
+#[derive(Clone)]
+struct CacheEntry<T> {
+    value: T,
+    inserted_at: Instant,
+}
+
+impl<T> CacheEntry<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value,
+            inserted_at: Instant::now(),
+        }
+    }
+
+    /// True if this entry was cached no longer than `ttl` ago.
+    fn is_fresh(&self, ttl: Option<Duration>) -> bool {
+        ttl.is_none_or(|ttl| self.inserted_at.elapsed() < ttl)
+    }
+}
+
+/// Turn the result of a listing into a stream of its items, or of
+/// the single error if the listing failed.
+fn listing_stream<T>(
+    listing: Result<Vec<T>>,
+) -> futures::stream::Iter<std::vec::IntoIter<Result<T>>> {
+    futures::stream::iter(match listing {
+        Ok(items) => items.into_iter().map(Ok).collect_vec(),
+        Err(err) => vec![Err(err)],
+    })
+}
+
 // To keep clippy happy
 type ArcVecArcVersion = Arc<Vec<Arc<Version>>>;
 /// The set of caches for a specific repository.
 #[derive(Clone)]
 struct CachesForAddress {
     /// Components list cache for list_build_components()
-    list_build_components: Arc<DashMap<BuildIdent, CacheValue<Vec<Component>>>>,
+    list_build_components: Arc<DashMap<BuildIdent, CacheEntry<CacheValue<Vec<Component>>>>>,
     /// EntryTypes list cache for ls_tags() caches
-    ls_tags: Arc<DashMap<relative_path::RelativePathBuf, Vec<EntryType>>>,
+    ls_tags: Arc<DashMap<relative_path::RelativePathBuf, CacheEntry<Vec<EntryType>>>>,
+    /// Repository metadata cache for read_metadata()
+    metadata: Arc<ArcSwapOption<CacheEntry<RepositoryMetadata>>>,
     /// Package specs cache for read_component_from_storage() and read_embed_stub()
-    package: Arc<DashMap<BuildIdent, CacheValue<Arc<Spec>>>>,
+    package: Arc<DashMap<BuildIdent, CacheEntry<CacheValue<Arc<Spec>>>>>,
     /// Versions list cache for list_packages_versions()
-    package_versions: Arc<DashMap<PkgNameBuf, CacheValue<ArcVecArcVersion>>>,
+    package_versions: Arc<DashMap<PkgNameBuf, CacheEntry<CacheValue<ArcVecArcVersion>>>>,
     /// Recipe specs cache for read_recipe()
-    recipe: Arc<DashMap<VersionIdent, CacheValue<Arc<spk_schema::SpecRecipe>>>>,
+    recipe: Arc<DashMap<VersionIdent, CacheEntry<CacheValue<Arc<spk_schema::SpecRecipe>>>>>,
+    /// Build options cache for read_resolved_options()
+    resolved_options: Arc<DashMap<BuildIdent, CacheEntry<CacheValue<OptionMap>>>>,
     /// Recipe specs cache for read_recipe()
-    tag_spec: Arc<DashMap<tracking::TagSpec, CacheValue<tracking::Tag>>>,
+    tag_spec: Arc<DashMap<tracking::TagSpec, CacheEntry<CacheValue<tracking::Tag>>>>,
+    /// Yanked versions cache for is_yanked()
+    yanked: Arc<DashMap<PkgNameBuf, CacheEntry<CacheValue<Arc<BTreeSet<Version>>>>>>,
+    /// Keeps this address registered in [`CACHES_FOR_ADDRESS`], if shared
+    registration: Option<Arc<CacheRegistration>>,
+}
+
+/// The shared caches for an address, along with a weak reference to
+/// the registration held by every repository that is using them.
+struct RegisteredCaches {
+    caches: CachesForAddress,
+    registration: std::sync::Weak<CacheRegistration>,
 }
 
-static CACHES_FOR_ADDRESS: Lazy<std::sync::Mutex<HashMap<String, CachesForAddress>>> =
+static CACHES_FOR_ADDRESS: Lazy<std::sync::Mutex<HashMap<String, RegisteredCaches>>> =
     Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
 
+/// Removes an address from [`CACHES_FOR_ADDRESS`] once the last
+/// repository using its caches is dropped.
+struct CacheRegistration {
+    address: String,
+}
+
+impl Drop for CacheRegistration {
+    fn drop(&mut self) {
+        let Ok(mut caches) = CACHES_FOR_ADDRESS.lock() else {
+            return;
+        };
+        // the address may have been registered again by a new
+        // repository after this registration was released
+        match caches.entry(std::mem::take(&mut self.address)) {
+            hash_map::Entry::Occupied(entry) if entry.get().registration.strong_count() == 0 => {
+                entry.remove();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Remove the shared caches of any address that is no longer used by
+/// an open repository, returning the number of addresses removed.
+///
+/// Caches are removed automatically when the last repository for an
+/// address is dropped, so this is only needed for explicit cleanup.
+pub fn purge_unused_address_caches() -> usize {
+    let mut caches = CACHES_FOR_ADDRESS.lock().unwrap();
+    let before = caches.len();
+    caches.retain(|_, registered| registered.registration.strong_count() > 0);
+    before - caches.len()
+}
+
 impl CachesForAddress {
     fn new(address: &url::Url) -> Self {
         let mut caches = CACHES_FOR_ADDRESS.lock().unwrap();
-        match caches.entry(address.as_str().to_owned()) {
-            hash_map::Entry::Occupied(entry) => entry.get().clone(),
-            hash_map::Entry::Vacant(entry) => entry
-                .insert(Self {
-                    list_build_components: Arc::new(DashMap::new()),
-                    ls_tags: Arc::new(DashMap::new()),
-                    package: Arc::new(DashMap::new()),
-                    package_versions: Arc::new(DashMap::new()),
-                    recipe: Arc::new(DashMap::new()),
-                    tag_spec: Arc::new(DashMap::new()),
-                })
-                .clone(),
+        let address = address.as_str().to_owned();
+        let existing = caches.get(&address).and_then(|registered| {
+            let registration = registered.registration.upgrade()?;
+            Some(Self {
+                registration: Some(registration),
+                ..registered.caches.clone()
+            })
+        });
+        if let Some(existing) = existing {
+            return existing;
+        }
+        let registration = Arc::new(CacheRegistration {
+            address: address.clone(),
+        });
+        let new = Self::new_unshared();
+        caches.insert(
+            address,
+            RegisteredCaches {
+                caches: new.clone(),
+                registration: Arc::downgrade(&registration),
+            },
+        );
+        Self {
+            registration: Some(registration),
+            ..new
+        }
+    }
+
+    /// Create a new set of caches that is not registered for any address.
+    fn new_unshared() -> Self {
+        Self {
+            list_build_components: Arc::new(DashMap::new()),
+            ls_tags: Arc::new(DashMap::new()),
+            metadata: Arc::new(ArcSwapOption::empty()),
+            package: Arc::new(DashMap::new()),
+            package_versions: Arc::new(DashMap::new()),
+            recipe: Arc::new(DashMap::new()),
+            resolved_options: Arc::new(DashMap::new()),
+            tag_spec: Arc::new(DashMap::new()),
+            yanked: Arc::new(DashMap::new()),
+            registration: None,
         }
     }
 }
@@ -297,63 +2627,10 @@ impl Storage for SpfsRepository {
     where
         Self: Clone,
     {
-        // It is possible for a `spk/spec/pkgname/1.0.0/BUILDKEY` tag to
-        // exist without a corresponding `spk/spk/pkgname/1.0.0/BUILDKEY`
-        // tag. In this scenario, "pkgname" will appear in the results of
-        // `list_packages` and `list_package_versions`, because those look at
-        // the `spk/spec/...` spfs tag tree, i.e., this package will appear
-        // in the output of `spk ls`. In order to make it possible to locate
-        // the build spec, e.g., for `spk rm pkgname/1.0.0` to work, this
-        // method needs to return a union of all the build tags of both the
-        // `spk/spec/` and `spk/pkg/` tag trees.
-
-        let mut set = JoinSet::new();
-        for pkg in Self::iter_possible_parts(pkg, self.legacy_spk_version_tags) {
-            let repo = self.clone();
-            set.spawn(async move {
-                let spec_base = verbatim_build_spec_tag_if_enabled!(repo, &pkg);
-                let package_base = verbatim_build_package_tag_if_enabled!(repo, &pkg);
-
-                let spec_tags = repo.ls_tags(&spec_base);
-                let package_tags = repo.ls_tags(&package_base);
-
-                let (spec_tags, package_tags) = tokio::join!(spec_tags, package_tags);
-
-                spec_tags
-                    .into_iter()
-                    .map(|tag| (&spec_base, tag))
-                    .chain(package_tags.into_iter().map(|tag| (&package_base, tag)))
-                    .filter_map(|(base, entry)| match entry {
-                        Ok(EntryType::Tag(name))
-                            if !name.starts_with(EmbeddedSourcePackage::EMBEDDED_BY_PREFIX) =>
-                        {
-                            Some((base, name))
-                        }
-                        Ok(EntryType::Tag(_)) => None,
-                        Ok(EntryType::Folder(name)) => Some((base, name)),
-                        Ok(EntryType::Namespace { .. }) => None,
-                        Err(_) => None,
-                    })
-                    .filter_map(|(base, b)| match parse_build(&b) {
-                        Ok(v) => Some((base.join(b), v)),
-                        Err(_) => {
-                            tracing::warn!("Invalid build found in spfs tags: {}", b);
-                            None
-                        }
-                    })
-                    .map(|(tag_spec, b)| (pkg.to_build_ident(b), Some(tag_spec)))
-                    // Because of the `chain` order above, this is intended to
-                    // keep the tag spec of the package instead of the spec, in
-                    // the case where both may exist.
-                    .collect::<HashMap<_, _>>()
-            });
-        }
-
-        let mut builds = HashMap::new();
-        while let Some(b) = set.join_next().await {
-            builds.extend(b.map_err(|err| Error::String(format!("Tokio join error: {err}")))?);
+        let (builds, skipped) = self.scan_concrete_package_builds(pkg).await?;
+        for entry in skipped {
+            tracing::warn!("Invalid build found in spfs tags: {}", entry.tag);
         }
-
         Ok(builds)
     }
 
@@ -367,64 +2644,13 @@ impl Storage for SpfsRepository {
         &self,
         pkg: &VersionIdent,
     ) -> Result<HashMap<BuildIdent, Option<RelativePathBuf>>> {
-        let mut builds = HashMap::new();
-
-        let pkg = pkg.to_any_ident(Some(Build::Source));
-        for pkg in Self::iter_possible_parts(&pkg, self.legacy_spk_version_tags) {
-            let mut base = verbatim_build_spec_tag_if_enabled!(self, &pkg);
-            // the package tag contains the name and build, but we need to
-            // remove the trailing build in order to list the containing 'folder'
-            // eg: pkg/1.0.0/src => pkg/1.0.0
-            base.pop();
-
-            builds.extend(
-                self.ls_tags(&base)
-                    .await
-                    .into_iter()
-                    .filter_map(|entry| match entry {
-                        Ok(EntryType::Tag(name)) => Some(name),
-                        Ok(EntryType::Folder(_)) => None,
-                        Ok(EntryType::Namespace { .. }) => None,
-                        Err(_) => None,
-                    })
-                    .filter_map(|b| {
-                        b.strip_prefix(EmbeddedSourcePackage::EMBEDDED_BY_PREFIX)
-                            .and_then(|encoded_ident| {
-                                data_encoding::BASE32_NOPAD
-                                    .decode(encoded_ident.as_bytes())
-                                    .ok()
-                            })
-                            .and_then(|bytes| String::from_utf8(bytes).ok())
-                            .and_then(|ident_str| {
-                                // The decoded BASE32 value will look something like this:
-                                //
-                                //     "embedded[embed-projection:run/1.0/3I42H3S6]"
-                                //
-                                // The `embedded_source_package` parser knows how to
-                                // parse the "[...]" part and return the type we want,
-                                // but we need to strip the "embedded" prefix.
-                                ident_str
-                                    .strip_prefix("embedded")
-                                    .and_then(|ident_str| {
-                                        use nom::combinator::all_consuming;
-
-                                        all_consuming(
-                                            embedded_source_package::<(_, nom::error::ErrorKind)>,
-                                        )(ident_str)
-                                        .map(|(_, ident_with_components)| ident_with_components)
-                                        .ok()
-                                    })
-                                    .map(|src| (base.join(b), Build::Embedded(src)))
-                            })
-                    })
-                    .map(|(tag_spec, b)| (pkg.to_build_ident(b), Some(tag_spec))),
-            );
-        }
-
-        Ok(builds)
+        self.list_embedded_package_builds(pkg)
+            .await
+            .map(|(builds, _)| builds)
     }
 
     async fn publish_embed_stub_to_storage(&self, spec: &Self::Package) -> Result<()> {
+        self.ensure_writable()?;
         let ident = spec.ident();
         let tag_path = Self::build_spec_tag(ident);
         let tag_spec = spfs::tracking::TagSpec::parse(tag_path.as_str())?;
@@ -436,7 +2662,7 @@ impl Storage for SpfsRepository {
             .commit_blob(Box::pin(std::io::Cursor::new(payload.into_bytes())))
             .await?;
         self.inner.push_tag(&tag_spec, &digest).await?;
-        self.invalidate_caches();
+        self.invalidate_caches_for(spec.ident().name());
         Ok(())
     }
 
@@ -444,34 +2670,73 @@ impl Storage for SpfsRepository {
         &self,
         package: &<Self::Recipe as spk_schema::Recipe>::Output,
         components: &HashMap<Component, spfs::encoding::Digest>,
-    ) -> Result<()> {
+        publish_policy: PublishPolicy,
+    ) -> Result<PublishResult> {
+        self.ensure_writable()?;
+        self.warn_about_name_collisions(package.ident().name())
+            .await;
         let tag_path = Self::build_package_tag(package.ident());
 
+        if matches!(publish_policy, PublishPolicy::DoNotOverwriteVersion) {
+            for name in components.keys() {
+                let tag_spec = spfs::tracking::TagSpec::parse(tag_path.join(name.as_str()))?;
+                // this has the same race condition as the check when
+                // publishing recipes, see publish_recipe_to_storage
+                if self.inner.has_tag(&tag_spec).await {
+                    return Err(Error::VersionExists(
+                        package.ident().clone().to_version_ident(),
+                    ));
+                }
+            }
+        }
+
         // We will also publish the 'run' component in the old style
         // for compatibility with older versions of the spk command.
-        // It's not perfect but at least the package will be visible
-        let legacy_tag = spfs::tracking::TagSpec::parse(&tag_path)?;
-        let legacy_component = if package.ident().is_source() {
-            *components.get(&Component::Source).ok_or_else(|| {
-                Error::String("Package must have a source component to be published".to_string())
-            })?
+        // It's not perfect but at least the package will be visible,
+        // unless this repository has been told not to write these tags
+        let mut tags_written = Vec::new();
+        let legacy_tag = if self.write_legacy_tags {
+            let legacy_tag = spfs::tracking::TagSpec::parse(&tag_path)?;
+            let legacy_component = match &self.legacy_component {
+                Some(component) => *components.get(component).ok_or_else(|| {
+                    Error::String(format!(
+                        "Package must have a {component} component to be published, it is the configured legacy component"
+                    ))
+                })?,
+                None if package.ident().is_source() => {
+                    *components.get(&Component::Source).ok_or_else(|| {
+                        Error::String(
+                            "Package must have a source component to be published".to_string(),
+                        )
+                    })?
+                }
+                None => *components.get(&Component::Run).ok_or_else(|| {
+                    Error::String("Package must have a run component to be published".to_string())
+                })?,
+            };
+            self.inner.push_tag(&legacy_tag, &legacy_component).await?;
+            tags_written.push(legacy_tag.clone());
+            Some(legacy_tag)
         } else {
-            *components.get(&Component::Run).ok_or_else(|| {
-                Error::String("Package must have a run component to be published".to_string())
-            })?
+            None
         };
 
-        self.inner.push_tag(&legacy_tag, &legacy_component).await?;
-
-        let components: std::result::Result<Vec<_>, _> = components
+        let component_tags: std::result::Result<Vec<_>, _> = components
             .iter()
             .map(|(name, digest)| {
                 spfs::tracking::TagSpec::parse(tag_path.join(name.as_str()))
                     .map(|spec| (spec, digest))
             })
             .collect();
-        for (tag_spec, digest) in components?.into_iter() {
-            self.inner.push_tag(&tag_spec, digest).await?;
+        let mut component_pushes = futures::stream::iter(component_tags?.into_iter().map(
+            |(tag_spec, digest)| async move {
+                self.inner.push_tag(&tag_spec, digest).await?;
+                Ok::<_, Error>(tag_spec)
+            },
+        ))
+        .buffer_unordered(self.component_push_concurrency);
+        while let Some(tag_spec) = component_pushes.try_next().await? {
+            tags_written.push(tag_spec);
         }
 
         // TODO: dedupe this part with force_publish_recipe
@@ -484,8 +2749,15 @@ impl Storage for SpfsRepository {
             .commit_blob(Box::pin(std::io::Cursor::new(payload.into_bytes())))
             .await?;
         self.inner.push_tag(&tag_spec, &digest).await?;
-        self.invalidate_caches();
-        Ok(())
+        tags_written.push(tag_spec);
+        self.invalidate_caches_for(package.ident().name());
+        Ok(PublishResult {
+            ident: package.ident().clone(),
+            tags_written,
+            spec_digest: Some(digest),
+            components: components.clone(),
+            legacy_tag,
+        })
     }
 
     async fn publish_recipe_to_storage(
@@ -493,7 +2765,9 @@ impl Storage for SpfsRepository {
         spec: &Self::Recipe,
         publish_policy: PublishPolicy,
     ) -> Result<()> {
+        self.ensure_writable()?;
         let ident = spec.ident();
+        self.warn_about_name_collisions(ident.name()).await;
         let tag_path = Self::build_spec_tag(ident);
         let tag_spec = spfs::tracking::TagSpec::parse(tag_path.as_str())?;
         if matches!(publish_policy, PublishPolicy::DoNotOverwriteVersion)
@@ -511,7 +2785,7 @@ impl Storage for SpfsRepository {
             .commit_blob(Box::pin(std::io::Cursor::new(payload.into_bytes())))
             .await?;
         self.inner.push_tag(&tag_spec, &digest).await?;
-        self.invalidate_caches();
+        self.invalidate_caches_for(ident.name());
         Ok(())
     }
 
@@ -525,11 +2799,23 @@ impl Storage for SpfsRepository {
         let package = self.lookup_package(pkg).await?;
         let component_tags = package.into_components();
         let mut components = HashMap::with_capacity(component_tags.len());
+        let mut first_err = None;
         for (name, tag_spec) in component_tags.into_iter() {
-            let tag = self.resolve_tag(|| pkg.to_any_ident(), &tag_spec).await?;
-            components.insert(name, tag.target);
+            match self.resolve_tag(|| pkg.to_any_ident(), &tag_spec).await {
+                Ok(tag) => {
+                    components.insert(name, tag.target);
+                }
+                Err(err) if self.lenient_components => {
+                    tracing::warn!(%pkg, component=%name, "skipping unreadable component: {err}");
+                    first_err.get_or_insert(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        match first_err {
+            Some(err) if components.is_empty() => Err(err),
+            _ => Ok(components),
         }
-        Ok(components)
     }
 
     async fn read_package_from_storage(
@@ -537,20 +2823,21 @@ impl Storage for SpfsRepository {
         pkg: &BuildIdent,
     ) -> Result<Arc<<Self::Recipe as spk_schema::Recipe>::Output>> {
         // TODO: reduce duplicate code with read_recipe
-        if self.cached_result_permitted() {
-            if let Some(v) = self.caches.package.get(pkg) {
-                return v.value().clone().into();
-            }
+        if let Some(v) = self.cached(&self.caches.package, pkg) {
+            return v.into();
         }
 
         let r: Result<Arc<Spec>> = self
             .with_build_spec_tag_for_pkg(pkg, |pkg, _, tag| async move {
-                let (mut reader, filename) = self.inner.open_payload(tag.target).await?;
+                let (mut reader, filename) = self.open_payload_with_retries(tag.target).await?;
                 let mut yaml = String::new();
                 reader
                     .read_to_string(&mut yaml)
                     .await
                     .map_err(|err| Error::FileReadError(filename, err))?;
+                if let Some(rewriter) = &self.spec_rewriter {
+                    yaml = rewriter.rewrite_package(&pkg, yaml)?;
+                }
                 Spec::from_yaml(&yaml)
                     .map_err(|err| Error::InvalidPackageSpec(pkg.to_any_ident(), err.to_string()))
                     .map(Arc::new)
@@ -559,11 +2846,12 @@ impl Storage for SpfsRepository {
 
         self.caches
             .package
-            .insert(pkg.clone(), r.as_ref().cloned().into());
+            .insert(pkg.clone(), CacheEntry::new(r.as_ref().cloned().into()));
         r
     }
 
     async fn remove_embed_stub_from_storage(&self, pkg: &BuildIdent) -> Result<()> {
+        self.ensure_writable()?;
         self.with_build_spec_tag_for_pkg(pkg, |pkg, tag_spec, _| async move {
             match self.inner.remove_tag_stream(&tag_spec).await {
                 Err(spfs::Error::UnknownReference(_)) => {
@@ -571,7 +2859,7 @@ impl Storage for SpfsRepository {
                 }
                 Err(err) => Err(err.into()),
                 Ok(_) => {
-                    self.invalidate_caches();
+                    self.invalidate_caches_for(pkg.name());
                     Ok(())
                 }
             }
@@ -580,6 +2868,7 @@ impl Storage for SpfsRepository {
     }
 
     async fn remove_package_from_storage(&self, pkg: &BuildIdent) -> Result<()> {
+        self.ensure_writable()?;
         // The three things this method is responsible for deleting are:
         //
         // 1. Component build tags like: `spk/pkg/example/4.2.1/GMTG3CXY/build`.
@@ -640,7 +2929,7 @@ impl Storage for SpfsRepository {
 
         // Still invalidate caches in case some of individual deletions were
         // successful.
-        self.invalidate_caches();
+        self.invalidate_caches_for(pkg.name());
 
         // If any of the three sub-tasks successfully deleted something *and*
         // the only failures otherwise was `PackageNotFound`, then return
@@ -693,63 +2982,27 @@ impl crate::Repository for SpfsRepository {
         let path = relative_path::RelativePath::new("spk/spec");
         // XXX: infallible vs return type
         Ok(self
-            .ls_tags(path)
-            .await
-            .into_iter()
-            .filter_map(|entry| match entry {
-                Ok(EntryType::Folder(name)) => name.parse().ok(),
-                Ok(EntryType::Tag(_)) => None,
-                Ok(EntryType::Namespace { .. }) => None,
-                Err(_) => None,
+            .ls_tags_stream(path)
+            .filter_map(|entry| async move {
+                match entry {
+                    Ok(EntryType::Folder(name)) => name.parse().ok(),
+                    Ok(EntryType::Tag(_)) => None,
+                    Ok(EntryType::Namespace { .. }) => None,
+                    Err(_) => None,
+                }
             })
-            .collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+            .await)
     }
 
     async fn list_package_versions(&self, name: &PkgName) -> Result<Arc<Vec<Arc<Version>>>> {
-        if self.cached_result_permitted() {
-            if let Some(v) = self.caches.package_versions.get(name) {
-                return v.value().clone().into();
-            }
-        }
-        let r: Result<Arc<_>> = async {
-            let path = Self::build_spec_tag(&VersionIdent::new_zero(name).into_any_ident(None));
-            let versions: HashSet<_> = self
-                .ls_tags(&path)
-                .await
-                .into_iter()
-                .filter_map(|entry| match entry {
-                    // undo our encoding of the invalid '+' character in spfs tags
-                    Ok(EntryType::Folder(name)) => Some(name.replace("..", "+")),
-                    Ok(EntryType::Tag(name)) => Some(name.replace("..", "+")),
-                    Ok(EntryType::Namespace { .. }) => None,
-                    Err(_) => None,
-                })
-                .filter_map(|v| match parse_version(&v) {
-                    Ok(v) => Some(v),
-                    Err(_) => {
-                        tracing::warn!("Invalid version found in spfs tags: {}", v);
-                        None
-                    }
-                })
-                .collect();
-            let mut versions = versions.into_iter().map(Arc::new).collect_vec();
-            versions.sort();
-            // XXX: infallible vs return type
-            Ok(Arc::new(versions))
-        }
-        .await;
-
-        self.caches
-            .package_versions
-            .insert(name.to_owned(), r.as_ref().cloned().into());
-        r
+        let versions = self.list_all_package_versions(name).await;
+        self.hide_yanked_versions(name, versions).await
     }
 
     async fn list_build_components(&self, pkg: &BuildIdent) -> Result<Vec<Component>> {
-        if self.cached_result_permitted() {
-            if let Some(v) = self.caches.list_build_components.get(pkg) {
-                return v.value().clone().into();
-            }
+        if let Some(v) = self.cached(&self.caches.list_build_components, pkg) {
+            return v.into();
         }
 
         let r = if pkg.build().is_embedded() {
@@ -764,7 +3017,7 @@ impl crate::Repository for SpfsRepository {
 
         self.caches
             .list_build_components
-            .insert(pkg.to_owned(), r.as_ref().cloned().into());
+            .insert(pkg.to_owned(), CacheEntry::new(r.as_ref().cloned().into()));
         r
     }
 
@@ -783,14 +3036,12 @@ impl crate::Repository for SpfsRepository {
                 return Err(format!("Cannot read this ident as an embed stub: {pkg}").into());
             }
         };
-        if self.cached_result_permitted() {
-            if let Some(v) = self.caches.package.get(pkg) {
-                return v.value().clone().into();
-            }
+        if let Some(v) = self.cached(&self.caches.package, pkg) {
+            return v.into();
         }
         let r: Result<Arc<Spec>> = self
             .with_build_spec_tag_for_pkg(pkg, |pkg, _, tag| async move {
-                let (mut reader, _) = self.inner.open_payload(tag.target).await?;
+                let (mut reader, _) = self.open_payload_with_retries(tag.target).await?;
                 let mut yaml = String::new();
                 reader
                     .read_to_string(&mut yaml)
@@ -804,24 +3055,72 @@ impl crate::Repository for SpfsRepository {
 
         self.caches
             .package
-            .insert(pkg.clone(), r.as_ref().cloned().into());
+            .insert(pkg.clone(), CacheEntry::new(r.as_ref().cloned().into()));
         r
     }
 
+    async fn packages_exist(&self, builds: &[BuildIdent]) -> Result<HashMap<BuildIdent, bool>> {
+        // Only the spec tags are resolved, through the tag cache, so that
+        // a later read of any of the existing packages reuses the resolve.
+        futures::stream::iter(builds.iter().unique())
+            .map(|build| async move {
+                let found = match self
+                    .with_build_spec_tag_for_pkg(build, |_, _, _| async { Ok(()) })
+                    .await
+                {
+                    Ok(()) => true,
+                    Err(Error::PackageNotFound(_)) => false,
+                    Err(err) => return Err(err),
+                };
+                Ok((build.clone(), found))
+            })
+            .buffer_unordered(READ_PACKAGES_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    async fn read_packages(&self, builds: &[BuildIdent]) -> Vec<Result<Arc<Self::Package>>> {
+        // Each build is only fetched once, so that any repeated builds
+        // can be served from the cache entry made by the first read.
+        let mut unique = HashMap::with_capacity(builds.len());
+        for build in builds {
+            let next = unique.len();
+            unique.entry(build).or_insert(next);
+        }
+        let mut fetched = (0..unique.len()).map(|_| None).collect_vec();
+        let mut reads = futures::stream::iter(unique.iter())
+            .map(|(build, index)| async move { (*index, self.read_package(build).await) })
+            .buffer_unordered(READ_PACKAGES_CONCURRENCY);
+        while let Some((index, result)) = reads.next().await {
+            fetched[index] = Some(result);
+        }
+
+        let mut results = Vec::with_capacity(builds.len());
+        for build in builds {
+            let result = match fetched[unique[build]].take() {
+                Some(result) => result,
+                None => self.read_package(build).await,
+            };
+            results.push(result);
+        }
+        results
+    }
+
     async fn read_recipe(&self, pkg: &VersionIdent) -> Result<Arc<Self::Recipe>> {
-        if self.cached_result_permitted() {
-            if let Some(v) = self.caches.recipe.get(pkg) {
-                return v.value().clone().into();
-            }
+        if let Some(v) = self.cached(&self.caches.recipe, pkg) {
+            return v.into();
         }
         let r: Result<Arc<SpecRecipe>> = self
             .with_build_spec_tag_for_pkg(pkg, |pkg, _, tag| async move {
-                let (mut reader, _) = self.inner.open_payload(tag.target).await?;
+                let (mut reader, _) = self.open_payload_with_retries(tag.target).await?;
                 let mut yaml = String::new();
                 reader
                     .read_to_string(&mut yaml)
                     .await
                     .map_err(|err| Error::FileReadError(tag.target.to_string().into(), err))?;
+                if let Some(rewriter) = &self.spec_rewriter {
+                    yaml = rewriter.rewrite_recipe(&pkg, yaml)?;
+                }
                 SpecRecipe::from_yaml(yaml)
                     .map_err(|err| {
                         Error::InvalidPackageSpec(pkg.to_any_ident(None), err.to_string())
@@ -832,11 +3131,12 @@ impl crate::Repository for SpfsRepository {
 
         self.caches
             .recipe
-            .insert(pkg.clone(), r.as_ref().cloned().into());
+            .insert(pkg.clone(), CacheEntry::new(r.as_ref().cloned().into()));
         r
     }
 
     async fn remove_recipe(&self, pkg: &VersionIdent) -> Result<()> {
+        self.ensure_writable()?;
         self.with_build_spec_tag_for_pkg(pkg, |pkg, tag_spec, _| async move {
             match self.inner.remove_tag_stream(&tag_spec).await {
                 Err(spfs::Error::UnknownReference(_)) => {
@@ -844,7 +3144,7 @@ impl crate::Repository for SpfsRepository {
                 }
                 Err(err) => Err(err.into()),
                 Ok(_) => {
-                    self.invalidate_caches();
+                    self.invalidate_caches_for(pkg.name());
                     Ok(())
                 }
             }
@@ -853,77 +3153,53 @@ impl crate::Repository for SpfsRepository {
     }
 
     async fn upgrade(&self) -> Result<String> {
-        let target_version = Version::from_str(REPO_VERSION).unwrap();
-        let mut meta = self.read_metadata().await?;
-        if meta.version > target_version {
-            // for this particular upgrade (moving old-style tags to new)
-            // we allow it to be run again over the same repo since it's
-            // possible that some clients are still publishing the old way
-            // during the transition period
-            return Ok("Nothing to do.".to_string());
-        }
-        for name in self.list_packages().await? {
-            tracing::info!("Processing {name}...");
-            let mut pkg = VersionIdent::new_zero(&*name).into_any_ident(None);
-            for version in self.list_package_versions(&name).await?.iter() {
-                pkg.set_version((**version).clone());
-                for build in self.list_package_builds(pkg.as_version_ident()).await? {
-                    if build.is_embedded() {
-                        // XXX `lookup_package` isn't able to read embed stubs.
-                        // Should it be able to?
-                        continue;
-                    }
-                    let stored = with_cache_policy!(self, CachePolicy::BypassCache, {
-                        self.lookup_package(&build)
-                    })
-                    .await?;
+        let plan = self.upgrade_plan().await?;
+        self.apply_upgrade_plan(&plan).await
+    }
 
-                    // [Re-]create embedded stubs.
-                    if build.can_embed() {
-                        let spec = self.read_package(&build).await?;
-                        // spec is not mutated
-                        #[allow(clippy::mutable_key_type)]
-                        let providers = self.get_embedded_providers(&spec)?;
-                        if !providers.is_empty() {
-                            tracing::info!("Creating embedded stubs for {name}...");
-                            for (embedded, components) in providers.into_iter() {
-                                self.create_embedded_stub_for_spec(&spec, &embedded, components)
-                                    .await?
-                            }
-                        }
-                    }
+    /// The publish time is the time that the earliest of the build's
+    /// component tags was created. Embedded builds use the time that
+    /// their stub was created.
+    async fn get_package_publish_time(
+        &self,
+        pkg: &BuildIdent,
+    ) -> Result<chrono::DateTime<chrono::Utc>> {
+        if pkg.build().is_embedded() {
+            return self
+                .with_build_spec_tag_for_pkg(pkg, |_, _, tag| async move { Ok(tag.time) })
+                .await;
+        }
+        let package = self.lookup_package(pkg).await?;
+        let mut earliest: Option<chrono::DateTime<chrono::Utc>> = None;
+        for (_, tag_spec) in package.into_components() {
+            let tag = self.resolve_tag(|| pkg.to_any_ident(), &tag_spec).await?;
+            earliest = Some(earliest.map_or(tag.time, |time| time.min(tag.time)));
+        }
+        earliest.ok_or_else(|| Error::PackageNotFound(pkg.to_any_ident()))
+    }
 
-                    if stored.has_components() {
-                        continue;
-                    }
-                    tracing::info!("Replicating old tags for {name}...");
-                    let components = stored.into_components();
-                    for (name, tag_spec) in components.into_iter() {
-                        let tag = self.inner.resolve_tag(&tag_spec).await?;
-                        let new_tag_path = Self::build_package_tag(&build).join(name.to_string());
-                        let new_tag_spec = spfs::tracking::TagSpec::parse(&new_tag_path)?;
-
-                        // NOTE(rbottriell): this copying process feels annoying
-                        // and error prone. Ideally, there would be some set methods
-                        // on the tag for changing the org/name on an existing one
-                        let mut new_tag = spfs::tracking::Tag::new(
-                            new_tag_spec.org(),
-                            new_tag_spec.name(),
-                            tag.target,
-                        )?;
-                        new_tag.parent = tag.parent;
-                        new_tag.time = tag.time;
-                        new_tag.user = tag.user;
-
-                        self.insert_tag(&new_tag).await?;
-                    }
-                }
-            }
+    async fn component_digest(
+        &self,
+        pkg: &BuildIdent,
+        component: &Component,
+    ) -> Result<Option<spfs::encoding::Digest>> {
+        if pkg.build().is_embedded() {
+            let mut components = crate::Repository::read_components(self, pkg).await?;
+            return Ok(components.remove(component));
         }
-        meta.version = target_version;
-        self.write_metadata(&meta).await?;
-        // Note caches are already invalidated in `write_metadata`
-        Ok("Repo up to date".to_string())
+        let mut component_tags = self.lookup_package(pkg).await?.into_components();
+        let Some(tag_spec) = component_tags.remove(component) else {
+            return Ok(None);
+        };
+        let tag = self.resolve_tag(|| pkg.to_any_ident(), &tag_spec).await?;
+        Ok(Some(tag.target))
+    }
+
+    async fn is_yanked(&self, pkg: &VersionIdent) -> Result<bool> {
+        Ok(self
+            .yanked_versions(pkg.name())
+            .await?
+            .contains(pkg.version()))
     }
 
     fn set_cache_policy(&self, cache_policy: CachePolicy) -> CachePolicy {
@@ -932,8 +3208,30 @@ impl crate::Repository for SpfsRepository {
 }
 
 impl SpfsRepository {
-    fn cached_result_permitted(&self) -> bool {
-        self.cache_policy.load().cached_result_permitted()
+    fn cached_success_permitted(&self) -> bool {
+        self.cache_policy.load().cached_success_permitted()
+    }
+
+    /// Look up a cached value, if caching is permitted and the
+    /// entry has not outlived the configured cache ttl.
+    ///
+    /// Cached successes are only returned if the cache policy
+    /// permits them, see [`CachePolicy::CacheErrorsOnly`].
+    fn cached<K, Q, T>(&self, cache: &DashMap<K, CacheEntry<T>>, key: &Q) -> Option<T>
+    where
+        K: Eq + std::hash::Hash + std::borrow::Borrow<Q>,
+        Q: Eq + std::hash::Hash + ?Sized,
+        T: Cacheable + Clone,
+    {
+        let policy = **self.cache_policy.load();
+        if !policy.cached_result_permitted() {
+            return None;
+        }
+        cache
+            .get(key)
+            .filter(|entry| entry.is_fresh(self.cache_ttl))
+            .filter(|entry| policy.cached_success_permitted() || entry.value.is_cached_error())
+            .map(|entry| entry.value.clone())
     }
 
     async fn has_tag<F>(&self, for_pkg: F, tag: &tracking::TagSpec) -> bool
@@ -947,11 +3245,48 @@ impl SpfsRepository {
     /// Invalidate (clear) all cached results.
     fn invalidate_caches(&self) {
         self.caches.ls_tags.clear();
+        self.caches.metadata.store(None);
         self.caches.package_versions.clear();
         self.caches.recipe.clear();
+        self.caches.resolved_options.clear();
         self.caches.package.clear();
         self.caches.tag_spec.clear();
         self.caches.list_build_components.clear();
+        self.caches.yanked.clear();
+    }
+
+    /// Invalidate the cached results that refer to the named package.
+    ///
+    /// Tag listings of the folders that contain the package are also
+    /// invalidated, since the package may have been added to or
+    /// removed from them. The repository metadata is kept.
+    fn invalidate_caches_for(&self, name: &PkgName) {
+        let package_paths = [
+            RelativePathBuf::from("spk/spec").join(name.as_str()),
+            RelativePathBuf::from("spk/pkg").join(name.as_str()),
+            RelativePathBuf::from("spk/yanked").join(name.as_str()),
+        ];
+        let refers_to_package = |path: &relative_path::RelativePath| {
+            package_paths.iter().any(|package_path| {
+                path.starts_with(package_path) || package_path.starts_with(path)
+            })
+        };
+        self.caches
+            .ls_tags
+            .retain(|path, _| !refers_to_package(path));
+        self.caches
+            .tag_spec
+            .retain(|tag_spec, _| !refers_to_package(&tag_spec.path()));
+        self.caches.package_versions.remove(name);
+        self.caches.yanked.remove(name);
+        self.caches.recipe.retain(|pkg, _| pkg.name() != name);
+        self.caches
+            .resolved_options
+            .retain(|pkg, _| pkg.name() != name);
+        self.caches.package.retain(|pkg, _| pkg.name() != name);
+        self.caches
+            .list_build_components
+            .retain(|pkg, _| pkg.name() != name);
     }
 
     /// Return all the possible part lengths for a version that should be
@@ -961,8 +3296,8 @@ impl SpfsRepository {
     /// version, but we treat different amounts of trailing zeros as equal,
     /// e.g., 1.0 == 1.0.0. So first we normalize the provided version to
     /// remove any trailing zeros, but then we look in the repo for various
-    /// lengths of trailing zeros. This is capped at 5 to handle all known
-    /// existing packages (at SPI).
+    /// lengths of trailing zeros. This is capped at `max_parts`, which
+    /// defaults to 5 to handle all known existing packages (at SPI).
     ///
     /// Example:
     ///
@@ -981,16 +3316,17 @@ impl SpfsRepository {
     fn iter_possible_parts<I>(
         pkg: &I,
         legacy_spk_version_tags: bool,
+        max_parts: usize,
     ) -> impl Iterator<Item = I::Output> + '_
     where
         I: HasVersion + WithVersion,
     {
         let normalized_parts = pkg.version().parts.strip_trailing_zeros();
         let normalized_parts_len = normalized_parts.len();
-        (1..=5)
+        (1..=max_parts)
             // Handle all the part lengths that are bigger than the normalized
             // parts, except for the normalized parts length itself, which may
-            // be larger than 5 and not hit by this range.
+            // be larger than `max_parts` and not hit by this range.
             .filter(move |num_parts| legacy_spk_version_tags && *num_parts > normalized_parts_len)
             // Then, handle the normalized parts length itself, which is
             // skipped by the filter above so it isn't processed twice,
@@ -1055,7 +3391,11 @@ impl SpfsRepository {
         Fut: Future<Output = Result<R>>,
     {
         let mut first_resolve_err = None;
-        for pkg in Self::iter_possible_parts(pkg, self.legacy_spk_version_tags) {
+        for pkg in Self::iter_possible_parts(
+            pkg,
+            self.legacy_spk_version_tags,
+            self.version_part_probe_depth,
+        ) {
             let tag_path = tag_path(&pkg);
             let tag_spec = spfs::tracking::TagSpec::parse(tag_path.as_str())?;
             let tag = match self
@@ -1078,43 +3418,246 @@ impl SpfsRepository {
     }
 
     async fn ls_tags(&self, path: &relative_path::RelativePath) -> Vec<Result<EntryType>> {
-        if self.cached_result_permitted() {
-            if let Some(v) = self.caches.ls_tags.get(path) {
-                return v
-                    .value()
-                    .clone()
-                    .into_iter()
-                    .map(Ok)
-                    .collect::<Vec<Result<EntryType>>>();
-            }
+        if let Some(v) = self.cached(&self.caches.ls_tags, path) {
+            return v.into_iter().map(Ok).collect::<Vec<Result<EntryType>>>();
         }
-        let r: Vec<Result<EntryType>> = self
-            .inner
-            .ls_tags(path)
-            .map(|el| el.map_err(|err| err.into()))
-            .collect::<Vec<_>>()
-            .await;
+        let r: Vec<Result<EntryType>> = if self.retries_reads() {
+            let entries = self
+                .with_read_retries(|| self.inner.ls_tags(path).try_collect::<Vec<_>>())
+                .await;
+            match entries {
+                Ok(entries) => entries.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err.into())],
+            }
+        } else {
+            self.inner
+                .ls_tags(path)
+                .map(|el| el.map_err(|err| err.into()))
+                .collect::<Vec<_>>()
+                .await
+        };
 
         self.caches.ls_tags.insert(
             path.to_owned(),
-            r.iter().filter_map(|r| r.as_ref().ok()).cloned().collect(),
+            CacheEntry::new(r.iter().filter_map(|r| r.as_ref().ok()).cloned().collect()),
         );
         r
     }
 
+    /// True if reads from the underlying repository are retried, see
+    /// [`Self::with_retry_policy`].
+    fn retries_reads(&self) -> bool {
+        self.retry_policy.is_some() && Self::handle_kind(&self.inner) == "rpc"
+    }
+
+    /// Make a read from the underlying repository, retrying it while it
+    /// fails with a transient error if reads are retried.
+    async fn with_read_retries<T, F, Fut>(&self, mut read: F) -> spfs::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = spfs::Result<T>>,
+    {
+        let policy = match self.retry_policy {
+            Some(policy) if self.retries_reads() => policy,
+            _ => return read().await,
+        };
+        tokio_retry::RetryIf::spawn(policy.delays(), read, |err: &spfs::Error| {
+            let transient = err.is_transient();
+            if transient {
+                tracing::warn!(
+                    "Transient error reading from repository {}, retrying: {err}",
+                    self.name
+                );
+            }
+            transient
+        })
+        .await
+    }
+
+    /// Open a payload of the underlying repository, see
+    /// [`Self::with_read_retries`].
+    async fn open_payload_with_retries(
+        &self,
+        digest: spfs::encoding::Digest,
+    ) -> spfs::Result<(
+        std::pin::Pin<Box<dyn spfs::tracking::BlobRead>>,
+        std::path::PathBuf,
+    )> {
+        self.with_read_retries(|| self.inner.open_payload(digest))
+            .await
+    }
+
+    /// List the entries of a tag folder as they are read, for callers
+    /// that only need to scan through them once.
+    ///
+    /// Unlike [`Self::ls_tags`], the entries are not collected before
+    /// they are returned. They still come from the cache when possible,
+    /// and are added to the cache once the stream is read to the end.
+    fn ls_tags_stream(
+        &self,
+        path: &relative_path::RelativePath,
+    ) -> futures::stream::BoxStream<'_, Result<EntryType>> {
+        if let Some(v) = self.cached(&self.caches.ls_tags, path) {
+            return futures::stream::iter(v.into_iter().map(Ok)).boxed();
+        }
+        if self.retries_reads() {
+            // a listing can only be retried as a whole
+            let path = path.to_owned();
+            return futures::stream::once(async move { self.ls_tags(&path).await })
+                .flat_map(futures::stream::iter)
+                .boxed();
+        }
+        let entries = self.inner.ls_tags(path).map_err(Error::from);
+        futures::stream::unfold(
+            (entries, Vec::new(), path.to_owned()),
+            move |(mut entries, mut listed, path)| async move {
+                match entries.next().await {
+                    Some(entry) => {
+                        if let Ok(entry) = &entry {
+                            listed.push(entry.clone());
+                        }
+                        Some((entry, (entries, listed, path)))
+                    }
+                    None => {
+                        self.caches.ls_tags.insert(path, CacheEntry::new(listed));
+                        None
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    /// List the versions of a package, including any yanked versions.
+    async fn list_all_package_versions(&self, name: &PkgName) -> Result<ArcVecArcVersion> {
+        if let Some(v) = self.cached(&self.caches.package_versions, name) {
+            return v.into();
+        }
+        let (versions, skipped) = self.scan_package_versions(name).await;
+        for entry in skipped {
+            tracing::warn!("Invalid version found in spfs tags: {}", entry.tag);
+        }
+        // XXX: infallible vs return type
+        let r: Result<Arc<_>> = Ok(Arc::new(versions));
+
+        self.caches
+            .package_versions
+            .insert(name.to_owned(), CacheEntry::new(r.as_ref().cloned().into()));
+        r
+    }
+
+    /// List every version of the named package, including those that
+    /// have been yanked, along with the tags that could not be parsed
+    /// as a version.
+    async fn scan_package_versions(
+        &self,
+        name: &PkgName,
+    ) -> (Vec<Arc<Version>>, Vec<SkippedEntry>) {
+        let path = Self::build_spec_tag(&VersionIdent::new_zero(name).into_any_ident(None));
+        let mut versions = HashSet::new();
+        let mut skipped = Vec::new();
+        for entry in self.ls_tags(&path).await {
+            let entry = match entry {
+                Ok(EntryType::Folder(entry)) => entry,
+                Ok(EntryType::Tag(entry)) => entry,
+                Ok(EntryType::Namespace { .. }) => continue,
+                Err(_) => continue,
+            };
+            // undo our encoding of the invalid '+' character in spfs tags
+            match parse_version(entry.replace("..", "+")) {
+                Ok(v) => {
+                    versions.insert(v);
+                }
+                Err(err) => skipped.push(SkippedEntry {
+                    tag: path.join(entry),
+                    message: err.to_string(),
+                }),
+            }
+        }
+        let mut versions = versions.into_iter().map(Arc::new).collect_vec();
+        versions.sort();
+        // a version can have both a recipe tag and a folder of builds
+        skipped.sort_by(|a, b| a.tag.cmp(&b.tag));
+        skipped.dedup_by(|a, b| a.tag == b.tag);
+        (versions, skipped)
+    }
+
+    /// The versions of the named package that have been yanked.
+    async fn yanked_versions(&self, name: &PkgName) -> Result<Arc<BTreeSet<Version>>> {
+        if let Some(v) = self.cached(&self.caches.yanked, name) {
+            return v.into();
+        }
+        let path = Self::build_yanked_tag(&VersionIdent::new_zero(name).into_any_ident(None));
+        let mut versions = BTreeSet::new();
+        for entry in self.ls_tags(&path).await {
+            let Ok(EntryType::Tag(name)) = entry else {
+                continue;
+            };
+            // undo our encoding of the invalid '+' character in spfs tags
+            match parse_version(name.replace("..", "+")) {
+                Ok(version) => {
+                    versions.insert(version);
+                }
+                Err(_) => tracing::warn!("Invalid yanked version found in spfs tags: {name}"),
+            }
+        }
+        let versions = Arc::new(versions);
+        self.caches.yanked.insert(
+            name.to_owned(),
+            CacheEntry::new(CacheValue::Success(Arc::clone(&versions))),
+        );
+        Ok(versions)
+    }
+
+    /// Remove the yanked versions from the listed versions of the named
+    /// package, if this repository hides them.
+    async fn hide_yanked_versions(
+        &self,
+        name: &PkgName,
+        versions: Result<ArcVecArcVersion>,
+    ) -> Result<ArcVecArcVersion> {
+        let versions = versions?;
+        if !self.hide_yanked {
+            return Ok(versions);
+        }
+        let yanked = self.yanked_versions(name).await?;
+        if yanked.is_empty() {
+            return Ok(versions);
+        }
+        Ok(Arc::new(
+            versions
+                .iter()
+                .filter(|version| !yanked.contains(&***version))
+                .cloned()
+                .collect(),
+        ))
+    }
+
     /// Read the metadata for this spk repository.
     ///
     /// The repo metadata contains information about
     /// how this particular spfs repository has been setup
     /// with spk. Namely, version and compatibility information.
     pub async fn read_metadata(&self) -> Result<RepositoryMetadata> {
+        if self.cached_success_permitted() {
+            let cached = self.caches.metadata.load_full();
+            if let Some(entry) = cached.filter(|entry| entry.is_fresh(self.cache_ttl)) {
+                return Ok(entry.value.clone());
+            }
+        }
         let tag_spec = spfs::tracking::TagSpec::parse(REPO_METADATA_TAG).unwrap();
         let digest = match self.inner.resolve_tag(&tag_spec).await {
             Ok(tag) => tag.target,
-            Err(spfs::Error::UnknownReference(_)) => return Ok(Default::default()),
+            Err(spfs::Error::UnknownReference(_)) => {
+                let meta = RepositoryMetadata::default();
+                self.caches
+                    .metadata
+                    .store(Some(Arc::new(CacheEntry::new(meta.clone()))));
+                return Ok(meta);
+            }
             Err(err) => return Err(err.into()),
         };
-        let (mut reader, _) = self.inner.open_payload(digest).await?;
+        let (mut reader, _) = self.open_payload_with_retries(digest).await?;
         let mut yaml = String::new();
         reader
             .read_to_string(&mut yaml)
@@ -1122,9 +3665,101 @@ impl SpfsRepository {
             .map_err(|err| Error::FileReadError(digest.to_string().into(), err))?;
         let meta: RepositoryMetadata =
             serde_yaml::from_str(&yaml).map_err(Error::InvalidRepositoryMetadata)?;
+        self.caches
+            .metadata
+            .store(Some(Arc::new(CacheEntry::new(meta.clone()))));
         Ok(meta)
     }
 
+    /// Check that this client understands the storage format of this
+    /// repository, according to the version in its metadata.
+    ///
+    /// Repositories without any metadata are always compatible.
+    ///
+    /// # Errors:
+    /// - [`Error::RepositoryTooNew`]: if the repository was written with a
+    ///   newer major version of the storage format than this client supports
+    pub async fn check_compatibility(&self) -> Result<()> {
+        let client_version = Version::from_str(REPO_VERSION).unwrap();
+        let meta = self.read_metadata().await?;
+        if meta.version.major() > client_version.major() {
+            return Err(Error::RepositoryTooNew {
+                repo_version: meta.version,
+                client_version,
+            });
+        }
+        Ok(())
+    }
+
+    /// Mark this repository as being set up for the current version
+    /// of spk, by writing its metadata if it has none.
+    ///
+    /// Returns true if the metadata was written, and false if the
+    /// repository already had metadata, which is left unchanged.
+    pub async fn initialize(&self) -> Result<bool> {
+        self.ensure_writable()?;
+        let tag_spec = spfs::tracking::TagSpec::parse(REPO_METADATA_TAG).unwrap();
+        if self.inner.has_tag(&tag_spec).await {
+            return Ok(false);
+        }
+        let meta = RepositoryMetadata {
+            version: Version::from_str(REPO_VERSION).unwrap(),
+            ..Default::default()
+        };
+        self.write_metadata(&meta).await?;
+        Ok(true)
+    }
+
+    /// Find the changes that [`crate::Repository::upgrade`] would make
+    /// to this repository, without making any of them.
+    ///
+    /// The returned plan can be reported, and then given to
+    /// [`Self::apply_upgrade_plan`] to make the changes.
+    pub async fn upgrade_plan(&self) -> Result<UpgradePlan> {
+        let target_version = Version::from_str(REPO_VERSION).unwrap();
+        let meta = self.read_metadata().await?;
+        if meta.version > target_version {
+            // for this particular upgrade (moving old-style tags to new)
+            // we allow it to be run again over the same repo since it's
+            // possible that some clients are still publishing the old way
+            // during the transition period
+            return Ok(UpgradePlan::default());
+        }
+        let packages = futures::stream::iter(self.list_packages().await?)
+            .map(|name| async move {
+                let actions = self.plan_package_upgrade(&name).await?;
+                Ok::<_, Error>((name, actions))
+            })
+            .buffer_unordered(self.upgrade_concurrency)
+            .try_filter(|(_, actions)| futures::future::ready(!actions.is_empty()))
+            .try_collect()
+            .await?;
+        Ok(UpgradePlan {
+            target_version: Some(target_version),
+            packages,
+        })
+    }
+
+    /// Make the changes described by an upgrade plan.
+    ///
+    /// See [`Self::upgrade_plan`].
+    pub async fn apply_upgrade_plan(&self, plan: &UpgradePlan) -> Result<String> {
+        let Some(target_version) = &plan.target_version else {
+            return Ok("Nothing to do.".to_string());
+        };
+        self.ensure_writable()?;
+        let mut upgrades = futures::stream::iter(plan.packages.iter())
+            .map(|(name, actions)| self.apply_package_upgrade(name, actions))
+            .buffer_unordered(self.upgrade_concurrency);
+        while upgrades.try_next().await?.is_some() {}
+
+        let mut meta = self.read_metadata().await?;
+        meta.version = target_version.clone();
+        self.write_metadata(&meta).await?;
+        // Note caches are already invalidated in `write_metadata`
+        Ok("Repo up to date".to_string())
+    }
+
     async fn resolve_tag<F>(
         &self,
         for_pkg: F,
@@ -1133,28 +3768,27 @@ impl SpfsRepository {
     where
         F: Fn() -> AnyIdent,
     {
-        if self.cached_result_permitted() {
-            if let Some(v) = self.caches.tag_spec.get(tag_spec) {
-                return v.value().clone().into();
-            }
+        if let Some(v) = self.cached(&self.caches.tag_spec, tag_spec) {
+            return v.into();
         }
         let r = self
-            .inner
-            .resolve_tag(tag_spec)
+            .with_read_retries(|| self.inner.resolve_tag(tag_spec))
             .await
             .map_err(|err| match err {
                 spfs::Error::UnknownReference(_) => Error::PackageNotFound(for_pkg()),
                 err => err.into(),
             });
 
-        self.caches
-            .tag_spec
-            .insert(tag_spec.clone(), r.as_ref().cloned().into());
+        self.caches.tag_spec.insert(
+            tag_spec.clone(),
+            CacheEntry::new(r.as_ref().cloned().into()),
+        );
         r
     }
 
     /// Update the metadata for this spk repository.
     async fn write_metadata(&self, meta: &RepositoryMetadata) -> Result<()> {
+        self.ensure_writable()?;
         let tag_spec = spfs::tracking::TagSpec::parse(REPO_METADATA_TAG).unwrap();
         let yaml = serde_yaml::to_string(meta).map_err(Error::InvalidRepositoryMetadata)?;
         let digest = self
@@ -1166,12 +3800,111 @@ impl SpfsRepository {
         Ok(())
     }
 
+    /// Find the changes that upgrading makes to a single package, as
+    /// part of [`Self::upgrade_plan`].
+    async fn plan_package_upgrade(&self, name: &PkgName) -> Result<Vec<UpgradeAction>> {
+        tracing::info!("Processing {name}...");
+        let mut actions = Vec::new();
+        let mut pkg = VersionIdent::new_zero(name).into_any_ident(None);
+        for version in self.list_package_versions(name).await?.iter() {
+            pkg.set_version((**version).clone());
+            for build in self.list_package_builds(pkg.as_version_ident()).await? {
+                if build.is_embedded() {
+                    // XXX `lookup_package` isn't able to read embed stubs.
+                    // Should it be able to?
+                    continue;
+                }
+                let stored = with_cache_policy!(self, CachePolicy::BypassCache, {
+                    self.lookup_package(&build)
+                })
+                .await?;
+
+                // [Re-]create embedded stubs.
+                if build.can_embed() {
+                    let spec = self.read_package(&build).await?;
+                    // spec is not mutated
+                    #[allow(clippy::mutable_key_type)]
+                    let providers = self.get_embedded_providers(&spec)?;
+                    for (embedded, components) in providers.into_iter() {
+                        actions.push(UpgradeAction::CreateEmbeddedStub {
+                            parent: Arc::clone(&spec),
+                            embedded,
+                            components,
+                        });
+                    }
+                }
+
+                if stored.has_components() {
+                    continue;
+                }
+                let mut tags = Vec::new();
+                for (name, tag_spec) in stored.into_components().into_iter() {
+                    let tag = self.inner.resolve_tag(&tag_spec).await?;
+                    let new_tag_path = Self::build_package_tag(&build).join(name.to_string());
+                    let new_tag_spec = spfs::tracking::TagSpec::parse(&new_tag_path)?;
+
+                    // NOTE(rbottriell): this copying process feels annoying
+                    // and error prone. Ideally, there would be some set methods
+                    // on the tag for changing the org/name on an existing one
+                    let mut new_tag = spfs::tracking::Tag::new(
+                        new_tag_spec.org(),
+                        new_tag_spec.name(),
+                        tag.target,
+                    )?;
+                    new_tag.parent = tag.parent;
+                    new_tag.time = tag.time;
+                    new_tag.user = tag.user;
+                    tags.push(new_tag);
+                }
+                actions.push(UpgradeAction::ReplicateTags { build, tags });
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Make the upgrade changes to a single package, as part of
+    /// [`Self::apply_upgrade_plan`].
+    ///
+    /// Only the caches of the packages that are written to are
+    /// invalidated, so that packages being upgraded at the same time
+    /// can continue to reuse their own cached reads.
+    async fn apply_package_upgrade(&self, name: &PkgName, actions: &[UpgradeAction]) -> Result<()> {
+        for action in actions.iter() {
+            match action {
+                UpgradeAction::ReplicateTags { build, tags } => {
+                    tracing::info!("Replicating old tags for {build}...");
+                    for tag in tags.iter() {
+                        self.insert_tag(tag).await?;
+                    }
+                }
+                UpgradeAction::CreateEmbeddedStub {
+                    parent,
+                    embedded,
+                    components,
+                } => {
+                    tracing::info!(
+                        "Creating embedded stub for {} in {name}...",
+                        embedded.ident()
+                    );
+                    self.create_embedded_stub_for_spec(parent, embedded, components.clone())
+                        .await?;
+                }
+            }
+        }
+        self.invalidate_caches_for(name);
+        Ok(())
+    }
+
     /// Find a package stored in this repo in either the new or old way of tagging
     ///
     /// (with or without package components)
     async fn lookup_package(&self, pkg: &BuildIdent) -> Result<StoredPackage> {
         let mut first_resolve_err = None;
-        for pkg in Self::iter_possible_parts(pkg, self.legacy_spk_version_tags) {
+        for pkg in Self::iter_possible_parts(
+            pkg,
+            self.legacy_spk_version_tags,
+            self.version_part_probe_depth,
+        ) {
             let tag_path = verbatim_build_package_tag_if_enabled!(self, &pkg);
             let tag_specs: HashMap<Component, TagSpec> = self
                 .ls_tags(&tag_path)
@@ -1200,6 +3933,175 @@ impl SpfsRepository {
         Err(first_resolve_err.unwrap_or_else(|| Error::PackageNotFound(pkg.to_any_ident())))
     }
 
+    /// Find the concrete builds of a package, along with the tags that
+    /// could not be parsed as a build.
+    async fn scan_concrete_package_builds(
+        &self,
+        pkg: &VersionIdent,
+    ) -> Result<(
+        HashMap<BuildIdent, Option<RelativePathBuf>>,
+        Vec<SkippedEntry>,
+    )> {
+        // It is possible for a `spk/spec/pkgname/1.0.0/BUILDKEY` tag to
+        // exist without a corresponding `spk/spk/pkgname/1.0.0/BUILDKEY`
+        // tag. In this scenario, "pkgname" will appear in the results of
+        // `list_packages` and `list_package_versions`, because those look at
+        // the `spk/spec/...` spfs tag tree, i.e., this package will appear
+        // in the output of `spk ls`. In order to make it possible to locate
+        // the build spec, e.g., for `spk rm pkgname/1.0.0` to work, this
+        // method needs to return a union of all the build tags of both the
+        // `spk/spec/` and `spk/pkg/` tag trees.
+
+        let mut set = JoinSet::new();
+        for pkg in Self::iter_possible_parts(
+            pkg,
+            self.legacy_spk_version_tags,
+            self.version_part_probe_depth,
+        ) {
+            let repo = self.clone();
+            set.spawn(async move {
+                let spec_base = verbatim_build_spec_tag_if_enabled!(repo, &pkg);
+                let package_base = verbatim_build_package_tag_if_enabled!(repo, &pkg);
+
+                let spec_tags = repo.ls_tags(&spec_base);
+                let package_tags = repo.ls_tags(&package_base);
+
+                let (spec_tags, package_tags) = tokio::join!(spec_tags, package_tags);
+
+                let mut builds = HashMap::new();
+                let mut skipped = Vec::new();
+                let entries = spec_tags
+                    .into_iter()
+                    .map(|tag| (&spec_base, tag))
+                    .chain(package_tags.into_iter().map(|tag| (&package_base, tag)))
+                    .filter_map(|(base, entry)| match entry {
+                        Ok(EntryType::Tag(name))
+                            if !name.starts_with(EmbeddedSourcePackage::EMBEDDED_BY_PREFIX) =>
+                        {
+                            Some((base, name))
+                        }
+                        Ok(EntryType::Tag(_)) => None,
+                        Ok(EntryType::Folder(name)) => Some((base, name)),
+                        Ok(EntryType::Namespace { .. }) => None,
+                        Err(_) => None,
+                    });
+                // Because of the `chain` order above, this is intended to
+                // keep the tag spec of the package instead of the spec, in
+                // the case where both may exist.
+                for (base, b) in entries {
+                    match parse_build(&b) {
+                        Ok(v) => {
+                            builds.insert(pkg.to_build_ident(v), Some(base.join(b)));
+                        }
+                        Err(err) => skipped.push(SkippedEntry {
+                            tag: base.join(b),
+                            message: err.to_string(),
+                        }),
+                    }
+                }
+                (builds, skipped)
+            });
+        }
+
+        let mut builds = HashMap::new();
+        let mut skipped = Vec::new();
+        while let Some(b) = set.join_next().await {
+            let (b, s) = b.map_err(|err| Error::String(format!("Tokio join error: {err}")))?;
+            builds.extend(b);
+            skipped.extend(s);
+        }
+        skipped.sort_by(|a, b| a.tag.cmp(&b.tag));
+
+        Ok((builds, skipped))
+    }
+
+    /// List the embedded builds of a package, along with any embed
+    /// stub tags that could not be decoded.
+    ///
+    /// A warning is logged for each stub that could not be decoded.
+    async fn list_embedded_package_builds(
+        &self,
+        pkg: &VersionIdent,
+    ) -> Result<(
+        HashMap<BuildIdent, Option<RelativePathBuf>>,
+        Vec<EmbedStubDecodeError>,
+    )> {
+        let mut builds = HashMap::new();
+        let mut errors = Vec::new();
+
+        let pkg = pkg.to_any_ident(Some(Build::Source));
+        for pkg in Self::iter_possible_parts(
+            &pkg,
+            self.legacy_spk_version_tags,
+            self.version_part_probe_depth,
+        ) {
+            let mut base = verbatim_build_spec_tag_if_enabled!(self, &pkg);
+            // the package tag contains the name and build, but we need to
+            // remove the trailing build in order to list the containing 'folder'
+            // eg: pkg/1.0.0/src => pkg/1.0.0
+            base.pop();
+
+            for entry in self.ls_tags(&base).await {
+                let Ok(EntryType::Tag(name)) = entry else {
+                    continue;
+                };
+                match Self::try_decode_embed_stub_tag(&name) {
+                    None => {}
+                    Some(Ok(build)) => {
+                        builds.insert(pkg.to_build_ident(build), Some(base.join(name)));
+                    }
+                    Some(Err(message)) => {
+                        let tag = base.join(name);
+                        tracing::warn!("Invalid embed stub found in spfs tags: {tag}: {message}");
+                        errors.push(EmbedStubDecodeError { tag, message });
+                    }
+                }
+            }
+        }
+
+        Ok((builds, errors))
+    }
+
+    /// Decode the build of an embed stub from the name of its spec tag.
+    ///
+    /// Returns `None` if the name is not an embed stub tag or if it
+    /// cannot be decoded.
+    fn decode_embed_stub_tag(name: &str) -> Option<Build> {
+        Self::try_decode_embed_stub_tag(name).and_then(|r| r.ok())
+    }
+
+    /// Decode the build of an embed stub from the name of its spec tag.
+    ///
+    /// Returns `None` if the name is not an embed stub tag, and an
+    /// error describing the problem if it cannot be decoded.
+    fn try_decode_embed_stub_tag(name: &str) -> Option<std::result::Result<Build, String>> {
+        let encoded_ident = name.strip_prefix(EmbeddedSourcePackage::EMBEDDED_BY_PREFIX)?;
+        let decoded = data_encoding::BASE32_NOPAD
+            .decode(encoded_ident.as_bytes())
+            .map_err(|err| format!("invalid BASE32 encoding: {err}"))
+            .and_then(|bytes| {
+                String::from_utf8(bytes).map_err(|err| format!("invalid UTF-8: {err}"))
+            })
+            .and_then(|ident_str| {
+                // The decoded BASE32 value will look something like this:
+                //
+                //     "embedded[embed-projection:run/1.0/3I42H3S6]"
+                //
+                // The `embedded_source_package` parser knows how to
+                // parse the "[...]" part and return the type we want,
+                // but we need to strip the "embedded" prefix.
+                let Some(ident_str) = ident_str.strip_prefix("embedded") else {
+                    return Err(format!("not an embedded package: {ident_str}"));
+                };
+                use nom::combinator::all_consuming;
+
+                all_consuming(embedded_source_package::<(_, nom::error::ErrorKind)>)(ident_str)
+                    .map(|(_, ident_with_components)| Build::Embedded(ident_with_components))
+                    .map_err(|err| format!("invalid embedded package {ident_str}: {err}"))
+            });
+        Some(decoded)
+    }
+
     /// Construct an spfs tag string to represent a binary package layer.
     fn build_package_tag<T>(pkg: &T) -> RelativePathBuf
     where
@@ -1212,6 +4114,18 @@ impl SpfsRepository {
         tag
     }
 
+    /// Construct an spfs tag string to mark a version as yanked.
+    fn build_yanked_tag<T>(pkg: &T) -> RelativePathBuf
+    where
+        T: TagPath,
+    {
+        let mut tag = RelativePathBuf::from("spk");
+        tag.push("yanked");
+        tag.push(pkg.tag_path());
+
+        tag
+    }
+
     /// Construct an spfs tag string to represent a spec file blob.
     fn build_spec_tag<T>(pkg: &T) -> RelativePathBuf
     where
@@ -1256,17 +4170,45 @@ impl SpfsRepository {
         tag
     }
 
-    pub fn flush(&self) -> Result<()> {
+    /// Change the cache policy of this repository until the returned
+    /// guard is dropped, when the previous policy is restored.
+    ///
+    /// Unlike [`crate::with_cache_policy`], the previous policy is
+    /// restored on every exit from the enclosing scope, including early
+    /// returns.
+    pub fn cache_policy_scope(&self, cache_policy: CachePolicy) -> CachePolicyGuard<'_> {
+        let previous = crate::Repository::set_cache_policy(self, cache_policy);
+        CachePolicyGuard {
+            repo: self,
+            previous,
+        }
+    }
+
+    /// Ensure that everything written to this repository is persisted.
+    ///
+    /// Tar archives are written out to their file, and the object and
+    /// payload storage of filesystem repositories is synced to disk.
+    /// Rpc repositories have nothing to flush because the server
+    /// persists each write before responding, so this is a no-op for
+    /// them and the other repository types.
+    pub async fn flush(&self) -> Result<()> {
         match &*self.inner {
             spfs::storage::RepositoryHandle::Tar(tar) => Ok(tar.flush()?),
+            spfs::storage::RepositoryHandle::FS(fs) => Ok(fs.sync_to_disk().await?),
             _ => Ok(()),
         }
     }
 }
 
-#[derive(Deserialize, Serialize, Default, Debug, PartialEq, Eq)]
+#[derive(Clone, Deserialize, Serialize, Default, Debug, PartialEq, Eq)]
 pub struct RepositoryMetadata {
     version: Version,
+    /// Fields that are not known to this version of spk.
+    ///
+    /// These are kept so that writing metadata back to the repository,
+    /// eg: during an upgrade, does not drop fields added by newer clients.
+    #[serde(flatten)]
+    extra: serde_yaml::Mapping,
 }
 
 /// A simple enum that allows us to represent both the old and new form
@@ -1291,12 +4233,21 @@ impl StoredPackage {
         }
     }
 
+    /// True if a legacy package tag is for a source package.
+    ///
+    /// The name goes through the component parser, so that source tags
+    /// stored under a different case or a legacy alias are recognized.
+    fn is_legacy_source_tag(tag: &spfs::tracking::TagSpec) -> bool {
+        Component::parse_with_aliases(tag.name().to_ascii_lowercase(), &ComponentAliases::legacy())
+            .is_ok_and(|component| component.is_source())
+    }
+
     /// Return the mapped component tags for this package, converting
     /// from the legacy storage format if needed.
     fn into_components(self) -> HashMap<Component, spfs::tracking::TagSpec> {
         match self {
             Self::WithComponents(cmpts) => cmpts,
-            Self::WithoutComponents(tag) if tag.name() == "src" => {
+            Self::WithoutComponents(tag) if Self::is_legacy_source_tag(&tag) => {
                 vec![(Component::Source, tag)].into_iter().collect()
             }
             Self::WithoutComponents(tag) => {
@@ -1308,20 +4259,94 @@ impl StoredPackage {
     }
 }
 
+/// Replace the package name in the `pkg` field of a spec's yaml.
+fn rename_spec_yaml(yaml: &str, from: &PkgName, to: &PkgName) -> Result<String> {
+    let mut value: serde_yaml::Value = serde_yaml::from_str(yaml)
+        .map_err(|err| Error::String(format!("Failed to parse spec for {from}: {err}")))?;
+    let Some(serde_yaml::Value::String(pkg)) = value.get_mut("pkg") else {
+        return Err(Error::String(format!(
+            "Failed to rename spec for {from}, it has no pkg field"
+        )));
+    };
+    let renamed = match pkg.strip_prefix(from.as_str()) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{to}{rest}"),
+        _ => {
+            return Err(Error::String(format!(
+                "Failed to rename spec for {from}, it is for {pkg}"
+            )));
+        }
+    };
+    *pkg = renamed;
+    serde_yaml::to_string(&value)
+        .map_err(|err| Error::SpkSpecError(spk_schema::Error::SpecEncodingError(err)))
+}
+
+/// Serialize a spec with its `pkg` field replaced.
+fn replace_spec_pkg_yaml<T: Serialize>(spec: &T, pkg: &str) -> Result<String> {
+    let mut value = serde_yaml::to_value(spec)
+        .map_err(|err| Error::SpkSpecError(spk_schema::Error::SpecEncodingError(err)))?;
+    let Some(field) = value.get_mut("pkg") else {
+        return Err(Error::String(format!(
+            "Failed to create spec for {pkg}, the original has no pkg field"
+        )));
+    };
+    *field = serde_yaml::Value::String(pkg.to_string());
+    serde_yaml::to_string(&value)
+        .map_err(|err| Error::SpkSpecError(spk_schema::Error::SpecEncodingError(err)))
+}
+
+/// Rewrite a repository address into a canonical form, so that
+/// addresses for the same store compare equal.
+///
+/// Trailing slashes, ports that are the default for the scheme and
+/// the order of query pairs are all ignored. The query pairs
+/// themselves, such as the `when` of a pinned repository, are kept.
+pub fn normalize_repository_address(address: &url::Url) -> url::Url {
+    let mut address = address.clone();
+
+    let default_port = match address.scheme() {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        _ => None,
+    };
+    if address.port().is_some() && address.port() == default_port {
+        // only fails for urls that cannot have a port, which this has
+        let _ = address.set_port(None);
+    }
+
+    let path = address.path();
+    if path.len() > 1 && path.ends_with('/') {
+        let trimmed = match path.trim_end_matches('/') {
+            "" => "/".to_string(),
+            trimmed => trimmed.to_string(),
+        };
+        address.set_path(&trimmed);
+    }
+
+    let mut pairs = address
+        .query_pairs()
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect_vec();
+    // stable, so repeated keys keep their relative order
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    address.set_query(None);
+    if !pairs.is_empty() {
+        address.query_pairs_mut().extend_pairs(pairs);
+    }
+
+    address
+}
+
 /// Return the local packages repository used for development.
 pub async fn local_repository() -> Result<SpfsRepository> {
     let config = spfs::get_config()?;
     let repo = config.get_local_repository().await?;
     let inner: spfs::prelude::RepositoryHandle = repo.into();
-    let address = inner.address().into_owned();
-    Ok(SpfsRepository {
-        caches: CachesForAddress::new(&address),
-        address,
-        name: "local".try_into()?,
-        inner: Arc::new(inner),
-        cache_policy: Arc::new(ArcSwap::new(Arc::new(CachePolicy::CacheOk))),
-        legacy_spk_version_tags: cfg!(feature = "legacy-spk-version-tags"),
-    })
+    let address = normalize_repository_address(&inner.address());
+    let caches = CachesForAddress::new(&address);
+    let repo = SpfsRepository::from_parts("local".try_into()?, inner, caches);
+    repo.check_compatibility().await?;
+    Ok(repo)
 }
 
 /// Return the remote repository of the given name.
@@ -1330,13 +4355,9 @@ pub async fn local_repository() -> Result<SpfsRepository> {
 pub async fn remote_repository<S: AsRef<str>>(name: S) -> Result<SpfsRepository> {
     let config = spfs::get_config()?;
     let inner = config.get_remote(&name).await?;
-    let address = inner.address().into_owned();
-    Ok(SpfsRepository {
-        caches: CachesForAddress::new(&address),
-        address,
-        name: name.as_ref().try_into()?,
-        inner: Arc::new(inner),
-        cache_policy: Arc::new(ArcSwap::new(Arc::new(CachePolicy::CacheOk))),
-        legacy_spk_version_tags: cfg!(feature = "legacy-spk-version-tags"),
-    })
+    let address = normalize_repository_address(&inner.address());
+    let caches = CachesForAddress::new(&address);
+    let repo = SpfsRepository::from_parts(name.as_ref().try_into()?, inner, caches);
+    repo.check_compatibility().await?;
+    Ok(repo)
 }
@@ -5,15 +5,37 @@
 use std::convert::TryFrom;
 use std::str::FromStr;
 
+use futures::{StreamExt, TryStreamExt};
 use rstest::rstest;
 use spfs::prelude::*;
-use spk_schema::BuildIdent;
 use spk_schema::foundation::fixtures::*;
+use spk_schema::foundation::ident_component::Component;
+use spk_schema::foundation::spec_ops::Named;
 use spk_schema::foundation::version::Version;
+use spk_schema::ident::AsVersionIdent;
+use spk_schema::{BuildIdent, Package, Recipe, recipe, spec};
 
 use super::SpfsRepository;
-use crate::NameAndRepository;
+use crate::fixtures::{RepoKind, empty_layer_digest, make_repo};
 use crate::storage::{CachePolicy, Repository};
+use crate::{NameAndRepository, RepositoryHandle};
+
+/// Create a new spfs repository named `test-repo` in the given directory.
+async fn make_spfs_repo(root: &std::path::Path) -> SpfsRepository {
+    SpfsRepository::try_from(NameAndRepository::new(
+        "test-repo",
+        spfs::storage::fs::FsRepository::create(root).await.unwrap(),
+    ))
+    .unwrap()
+}
+
+/// Create a new spfs repository, as with [`make_spfs_repo`], that bypasses
+/// the cache so that every read sees the latest writes.
+async fn make_uncached_spfs_repo(root: &std::path::Path) -> SpfsRepository {
+    let repo = make_spfs_repo(root).await;
+    repo.set_cache_policy(CachePolicy::BypassCache);
+    repo
+}
 
 #[rstest]
 fn test_repo_meta_tag_is_valid() {
@@ -32,13 +54,7 @@ fn test_repo_version_is_valid() {
 async fn test_metadata_io(tmpdir: tempfile::TempDir) {
     init_logging();
     let repo_root = tmpdir.path();
-    let repo = SpfsRepository::try_from(NameAndRepository::new(
-        "test-repo",
-        spfs::storage::fs::FsRepository::create(repo_root)
-            .await
-            .unwrap(),
-    ))
-    .unwrap();
+    let repo = make_spfs_repo(repo_root).await;
 
     let meta = super::RepositoryMetadata::default();
     repo.write_metadata(&meta).await.unwrap();
@@ -46,19 +62,164 @@ async fn test_metadata_io(tmpdir: tempfile::TempDir) {
     assert_eq!(actual, meta, "should return metadata as it was stored");
 }
 
+#[rstest]
+#[tokio::test]
+async fn test_repository_too_new(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo_root = tmpdir.path();
+    let repo = make_spfs_repo(repo_root).await;
+    repo.check_compatibility()
+        .await
+        .expect("a repository without metadata should be compatible");
+
+    let newer_minor = super::RepositoryMetadata {
+        version: Version::from_str("1.5.0").unwrap(),
+        ..Default::default()
+    };
+    repo.write_metadata(&newer_minor).await.unwrap();
+    repo.check_compatibility()
+        .await
+        .expect("a newer minor version should be compatible");
+
+    let newer_major = super::RepositoryMetadata {
+        version: Version::from_str("2.0.0").unwrap(),
+        ..Default::default()
+    };
+    repo.write_metadata(&newer_major).await.unwrap();
+    let res = repo.check_compatibility().await;
+    assert!(
+        matches!(res, Err(crate::Error::RepositoryTooNew { .. })),
+        "a newer major version should not be compatible, got {res:?}"
+    );
+    let res =
+        SpfsRepository::new_unshared("test-repo", &format!("file://{}", repo_root.display())).await;
+    assert!(
+        matches!(res, Err(crate::Error::RepositoryTooNew { .. })),
+        "opening a repository that is too new should fail"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_metadata_preserves_unknown_fields(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    // metadata written by a newer client, with a field that is not known here
+    let yaml = "version: 1.0.0\nfrom_the_future:\n  enabled: true\n";
+    let digest = repo
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(yaml.as_bytes().to_vec())))
+        .await
+        .unwrap();
+    let tag_spec = spfs::tracking::TagSpec::parse(super::REPO_METADATA_TAG).unwrap();
+    repo.inner().push_tag(&tag_spec, &digest).await.unwrap();
+
+    let mut meta = repo.read_metadata().await.unwrap();
+    meta.version = Version::from_str("1.1.0").unwrap();
+    repo.write_metadata(&meta).await.unwrap();
+
+    let digest = repo.inner().resolve_tag(&tag_spec).await.unwrap().target;
+    let (mut reader, _) = repo.inner().open_payload(digest).await.unwrap();
+    let mut written = String::new();
+    tokio::io::AsyncReadExt::read_to_string(&mut reader, &mut written)
+        .await
+        .unwrap();
+    let written: serde_yaml::Mapping = serde_yaml::from_str(&written).unwrap();
+    assert_eq!(
+        written.get("from_the_future"),
+        Some(&serde_yaml::from_str("enabled: true").unwrap()),
+        "unknown fields should be written back as they were read"
+    );
+    assert_eq!(
+        written.get("version"),
+        Some(&serde_yaml::Value::from("1.1.0"))
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_initialize_writes_metadata_once(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    assert_eq!(
+        repo.read_metadata().await.unwrap().version,
+        Version::default(),
+        "a new repository should not have any metadata"
+    );
+
+    assert!(
+        repo.initialize().await.unwrap(),
+        "metadata should be written"
+    );
+    assert_eq!(
+        repo.read_metadata().await.unwrap().version,
+        Version::from_str(super::REPO_VERSION).unwrap()
+    );
+
+    let older = super::RepositoryMetadata {
+        version: Version::from_str("0.5.0").unwrap(),
+        ..Default::default()
+    };
+    repo.write_metadata(&older).await.unwrap();
+    assert!(
+        !repo.initialize().await.unwrap(),
+        "existing metadata should not be written again"
+    );
+    assert_eq!(repo.read_metadata().await.unwrap(), older);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_metadata_is_cached(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let original = super::RepositoryMetadata {
+        version: Version::from_str("1.0.0").unwrap(),
+        ..Default::default()
+    };
+    repo.write_metadata(&original).await.unwrap();
+    assert_eq!(repo.read_metadata().await.unwrap(), original);
+
+    // change the metadata without going through this repository
+    let changed = super::RepositoryMetadata {
+        version: Version::from_str("2.0.0").unwrap(),
+        ..Default::default()
+    };
+    let yaml = serde_yaml::to_string(&changed).unwrap();
+    let digest = repo
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(yaml.into_bytes())))
+        .await
+        .unwrap();
+    let tag_spec = spfs::tracking::TagSpec::parse(super::REPO_METADATA_TAG).unwrap();
+    repo.inner().push_tag(&tag_spec, &digest).await.unwrap();
+
+    assert_eq!(
+        repo.read_metadata().await.unwrap(),
+        original,
+        "metadata should be served from the cache"
+    );
+    repo.set_cache_policy(CachePolicy::BypassCache);
+    assert_eq!(repo.read_metadata().await.unwrap(), changed);
+    repo.set_cache_policy(CachePolicy::CacheOk);
+
+    repo.write_metadata(&original).await.unwrap();
+    assert_eq!(
+        repo.read_metadata().await.unwrap(),
+        original,
+        "writing metadata should invalidate the cache"
+    );
+}
+
 #[rstest]
 #[tokio::test]
 async fn test_upgrade_sets_version(tmpdir: tempfile::TempDir) {
     init_logging();
     let current_version = Version::from_str(super::REPO_VERSION).unwrap();
     let repo_root = tmpdir.path();
-    let repo = SpfsRepository::try_from(NameAndRepository::new(
-        "test-repo",
-        spfs::storage::fs::FsRepository::create(repo_root)
-            .await
-            .unwrap(),
-    ))
-    .unwrap();
+    let repo = make_spfs_repo(repo_root).await;
 
     assert_eq!(
         repo.read_metadata().await.unwrap().version,
@@ -113,3 +274,2338 @@ async fn test_upgrade_changes_tags(tmpdir: tempfile::TempDir) {
     .unwrap();
     assert!(matches!(pkg, super::StoredPackage::WithComponents(_)));
 }
+
+#[rstest]
+#[tokio::test]
+async fn test_upgrade_plan_makes_no_changes(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo_root = tmpdir.path();
+    let spfs_repo = spfs::storage::fs::FsRepository::create(repo_root)
+        .await
+        .unwrap();
+    let repo = SpfsRepository::new("test-repo", &format!("file://{}", repo_root.display()))
+        .await
+        .unwrap();
+
+    let ident = BuildIdent::from_str("mypkg/1.0.0/src").unwrap();
+    for path in [
+        SpfsRepository::build_package_tag(&ident),
+        SpfsRepository::build_spec_tag(&ident),
+    ] {
+        let tag_spec = spfs::tracking::TagSpec::from_str(path.as_str()).unwrap();
+        spfs_repo
+            .push_tag(&tag_spec, &spfs::encoding::EMPTY_DIGEST.into())
+            .await
+            .unwrap();
+    }
+
+    let plan = repo.upgrade_plan().await.unwrap();
+    assert_eq!(plan.replicated_builds(), 1);
+    assert_eq!(plan.embedded_stubs(), 0);
+    assert_eq!(
+        plan.target_version,
+        Some(Version::from_str(super::REPO_VERSION).unwrap())
+    );
+    let pkg = crate::with_cache_policy!(repo, CachePolicy::BypassCache, {
+        repo.lookup_package(&ident)
+    })
+    .await
+    .unwrap();
+    assert!(
+        matches!(pkg, super::StoredPackage::WithoutComponents(_)),
+        "planning an upgrade should not change any tags"
+    );
+    assert_eq!(
+        repo.read_metadata().await.unwrap().version,
+        Version::default(),
+        "planning an upgrade should not change the repo version"
+    );
+
+    repo.apply_upgrade_plan(&plan).await.unwrap();
+    let pkg = repo.lookup_package(&ident).await.unwrap();
+    assert!(matches!(pkg, super::StoredPackage::WithComponents(_)));
+    assert!(
+        repo.upgrade_plan().await.unwrap().packages.is_empty(),
+        "nothing should be left to upgrade"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_upgrade_many_packages_concurrently(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo_root = tmpdir.path();
+    let spfs_repo = spfs::storage::fs::FsRepository::create(repo_root)
+        .await
+        .unwrap();
+    let repo = SpfsRepository::new("test-repo", &format!("file://{}", repo_root.display()))
+        .await
+        .unwrap()
+        .with_upgrade_concurrency(3);
+
+    // publish more "old style" packages than are upgraded at once
+    let idents = (0..5)
+        .map(|i| BuildIdent::from_str(&format!("mypkg-{i}/1.0.0/src")).unwrap())
+        .collect::<Vec<_>>();
+    for ident in idents.iter() {
+        for path in [
+            SpfsRepository::build_package_tag(ident),
+            SpfsRepository::build_spec_tag(ident),
+        ] {
+            let tag_spec = spfs::tracking::TagSpec::from_str(path.as_str()).unwrap();
+            spfs_repo
+                .push_tag(&tag_spec, &spfs::encoding::EMPTY_DIGEST.into())
+                .await
+                .unwrap();
+        }
+    }
+
+    repo.upgrade()
+        .await
+        .expect("upgrading many packages at once should succeed");
+
+    for ident in idents.iter() {
+        let pkg = repo.lookup_package(ident).await.unwrap();
+        assert!(
+            matches!(pkg, super::StoredPackage::WithComponents(_)),
+            "{ident} should have been upgraded"
+        );
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_lazy_components_resolve_on_access(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![
+        (Component::Run, spfs::encoding::EMPTY_DIGEST.into()),
+        (Component::Build, spfs::encoding::EMPTY_DIGEST.into()),
+    ]
+    .into_iter()
+    .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    let lazy = repo.read_components_lazy(spec.ident()).await.unwrap();
+    assert_eq!(lazy.len(), 2);
+    assert!(
+        repo.caches.tag_spec.is_empty(),
+        "no component tags should be resolved up front"
+    );
+
+    let run = lazy.get(&Component::Run).await.unwrap();
+    assert_eq!(run, Some(spfs::encoding::EMPTY_DIGEST.into()));
+    assert_eq!(
+        repo.caches.tag_spec.len(),
+        1,
+        "only the accessed component tag should be resolved"
+    );
+    assert_eq!(lazy.get(&Component::Source).await.unwrap(), None);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_lenient_components_skip_corrupt_tags(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![
+        (Component::Run, spfs::encoding::EMPTY_DIGEST.into()),
+        (Component::Build, spfs::encoding::EMPTY_DIGEST.into()),
+    ]
+    .into_iter()
+    .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+    std::fs::write(
+        tmpdir
+            .path()
+            .join("tags/spk/pkg/my-pkg/1.0.0/3I42H3S6/build.tag"),
+        b"not a tag",
+    )
+    .unwrap();
+
+    repo.read_components(spec.ident())
+        .await
+        .expect_err("a corrupt component tag should fail by default");
+
+    let lenient = repo.with_lenient_components(true);
+    let components = lenient.read_components(spec.ident()).await.unwrap();
+    assert_eq!(
+        components.into_keys().collect::<Vec<_>>(),
+        vec![Component::Run],
+        "only the healthy component should be returned"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_version_part_probe_depth(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo_root = tmpdir.path();
+    let spfs_repo = spfs::storage::fs::FsRepository::create(repo_root)
+        .await
+        .unwrap();
+    let mut repo = SpfsRepository::new("test-repo", &format!("file://{}", repo_root.display()))
+        .await
+        .unwrap();
+    repo.set_legacy_spk_version_tags(true);
+
+    // a build that was published with six version parts
+    let ident = BuildIdent::from_str("mypkg/1.2.0.0.0.0/3I42H3S6").unwrap();
+    let tag = spfs::tracking::TagSpec::from_str(
+        SpfsRepository::build_package_verbatim_tag(&ident).as_str(),
+    )
+    .unwrap();
+    spfs_repo
+        .push_tag(&tag, &spfs::encoding::EMPTY_DIGEST.into())
+        .await
+        .unwrap();
+
+    let version = spk_schema::ident::VersionIdent::from_str("mypkg/1.2").unwrap();
+    let builds = repo.list_package_builds(&version).await.unwrap();
+    assert!(
+        builds.is_empty(),
+        "six version parts should not be probed by default"
+    );
+
+    let repo = repo.with_version_part_probe_depth(6);
+    let builds = crate::with_cache_policy!(repo, CachePolicy::BypassCache, {
+        repo.list_package_builds(&version)
+    })
+    .await
+    .unwrap();
+    assert_eq!(builds.len(), 1, "the build should be found at a depth of 6");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_cache_ttl_expires_entries(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo_root = tmpdir.path();
+    let spfs_repo = spfs::storage::fs::FsRepository::create(repo_root)
+        .await
+        .unwrap();
+    let repo = SpfsRepository::new("test-repo", &format!("file://{}", repo_root.display()))
+        .await
+        .unwrap()
+        .with_cache_ttl(Some(std::time::Duration::from_secs(1)));
+    let name = spk_schema::foundation::name::PkgName::new("mypkg").unwrap();
+
+    let push_version = |version: &str| {
+        let tag = spfs::tracking::TagSpec::from_str(&format!("spk/spec/mypkg/{version}")).unwrap();
+        let spfs_repo = &spfs_repo;
+        async move {
+            spfs_repo
+                .push_tag(&tag, &spfs::encoding::EMPTY_DIGEST.into())
+                .await
+                .unwrap();
+        }
+    };
+
+    push_version("1.0.0").await;
+    assert_eq!(repo.list_package_versions(name).await.unwrap().len(), 1);
+    push_version("2.0.0").await;
+
+    // the version was published behind the back of the spk repo, so
+    // the cached result is still valid until it expires
+    assert_eq!(
+        repo.list_package_versions(name).await.unwrap().len(),
+        1,
+        "a fresh cache entry should be reused"
+    );
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert_eq!(
+        repo.list_package_versions(name).await.unwrap().len(),
+        2,
+        "an expired cache entry should be fetched again"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_cache_errors_only(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+    repo.read_recipe(recipe.ident()).await.unwrap();
+
+    // change the recipe behind the back of the spk repo, so that only
+    // a fetch can notice
+    let tag = spfs::tracking::TagSpec::parse("spk/spec/my-pkg/1.0.0").unwrap();
+    let stored = repo.inner().resolve_tag(&tag).await.unwrap();
+    repo.inner().remove_tag_stream(&tag).await.unwrap();
+
+    repo.set_cache_policy(CachePolicy::CacheErrorsOnly);
+    assert!(
+        repo.read_recipe(recipe.ident())
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "a cached success should be fetched again"
+    );
+
+    repo.inner().push_tag(&tag, &stored.target).await.unwrap();
+    assert!(
+        repo.read_recipe(recipe.ident())
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "a cached error should be reused"
+    );
+
+    repo.set_cache_policy(CachePolicy::BypassCache);
+    repo.read_recipe(recipe.ident()).await.unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_cache_policy_scope_restores_on_early_return(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    async fn read_missing(repo: &SpfsRepository) -> crate::Result<()> {
+        let _guard = repo.cache_policy_scope(CachePolicy::BypassCache);
+        assert!(matches!(
+            **repo.cache_policy.load(),
+            CachePolicy::BypassCache
+        ));
+        let pkg = spk_schema::ident::VersionIdent::from_str("missing/1.0.0").unwrap();
+        repo.read_recipe(&pkg).await?;
+        Ok(())
+    }
+
+    read_missing(&repo)
+        .await
+        .expect_err("the missing recipe should not be found");
+    assert!(
+        matches!(**repo.cache_policy.load(), CachePolicy::CacheOk),
+        "the previous cache policy should be restored"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_describe_pinned_namespaced_repo(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let mut spfs_repo = spfs::storage::fs::FsRepository::create(tmpdir.path())
+        .await
+        .unwrap();
+    spfs_repo.set_tag_namespace(Some(spfs::storage::TagNamespaceBuf::new("sandbox")));
+    let mut repo =
+        SpfsRepository::try_from(NameAndRepository::new("test-repo", spfs_repo)).unwrap();
+
+    let description = repo.describe();
+    assert_eq!(description.kind, "fs");
+    assert!(description.pinned_at.is_none());
+
+    let ts = spfs::tracking::TimeSpec::parse("~1d").unwrap();
+    repo.pin_at_time(&ts);
+    repo.set_cache_policy(CachePolicy::BypassCache);
+
+    let description = repo.describe();
+    assert_eq!(description.name.to_string(), "test-repo");
+    assert_eq!(description.kind, "fs", "should report the pinned repo kind");
+    assert!(
+        description.pinned_at.is_some(),
+        "should report the pin time"
+    );
+    assert!(matches!(description.cache_policy, CachePolicy::BypassCache));
+    assert_eq!(
+        description
+            .tag_namespace
+            .map(|ns| ns.to_string())
+            .as_deref(),
+        Some("sandbox")
+    );
+    assert!(
+        description.address.as_str().contains("when="),
+        "address should include the pin"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_with_tag_namespace(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    let namespaced = SpfsRepository::try_from(NameAndRepository::new(
+        "test-repo",
+        spfs::storage::fs::FsRepository::open(tmpdir.path())
+            .await
+            .unwrap(),
+    ))
+    .unwrap()
+    .with_tag_namespace(Some(spfs::storage::TagNamespaceBuf::new("experiment")))
+    .unwrap();
+    assert_ne!(
+        repo.address(),
+        namespaced.address(),
+        "the namespace should be reflected in the address"
+    );
+
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    namespaced.publish_recipe(&recipe).await.unwrap();
+    namespaced
+        .read_recipe(recipe.ident())
+        .await
+        .expect("the recipe should be readable in its namespace");
+    assert!(
+        repo.list_packages().await.unwrap().is_empty(),
+        "packages in a namespace should not be visible outside of it"
+    );
+    assert!(
+        repo.read_recipe(recipe.ident())
+            .await
+            .unwrap_err()
+            .is_package_not_found()
+    );
+
+    let root = namespaced.with_tag_namespace(None).unwrap();
+    assert_eq!(root.address(), repo.address());
+    assert!(root.list_packages().await.unwrap().is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_promote_namespace(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let namespace = spfs::storage::TagNamespaceBuf::new("sandbox");
+    let mut sandbox_repo = spfs::storage::fs::FsRepository::create(tmpdir.path())
+        .await
+        .unwrap();
+    sandbox_repo.set_tag_namespace(Some(namespace.clone()));
+    let sandbox =
+        SpfsRepository::try_from(NameAndRepository::new("sandbox", sandbox_repo)).unwrap();
+    let root = SpfsRepository::try_from(NameAndRepository::new(
+        "root",
+        spfs::storage::fs::FsRepository::open(tmpdir.path())
+            .await
+            .unwrap(),
+    ))
+    .unwrap();
+    // both repos share the same address, and so the same caches
+    sandbox.set_cache_policy(CachePolicy::BypassCache);
+    root.set_cache_policy(CachePolicy::BypassCache);
+
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, spfs::encoding::EMPTY_DIGEST.into())]
+        .into_iter()
+        .collect();
+    sandbox.publish_recipe(&recipe).await.unwrap();
+    sandbox.publish_package(&spec, &components).await.unwrap();
+
+    let in_root = root.read_recipe(recipe.ident()).await;
+    assert!(
+        matches!(in_root, Err(crate::Error::PackageNotFound(_))),
+        "package should only be visible in the sandbox before promotion"
+    );
+
+    sandbox
+        .promote_namespace(recipe.name(), &namespace, None)
+        .await
+        .expect("promoting a new package should succeed");
+
+    root.read_recipe(recipe.ident())
+        .await
+        .expect("recipe should be visible in the root after promotion");
+    root.read_package(spec.ident())
+        .await
+        .expect("package should be visible in the root after promotion");
+    let in_sandbox = sandbox.read_recipe(recipe.ident()).await;
+    assert!(
+        matches!(in_sandbox, Err(crate::Error::PackageNotFound(_))),
+        "package should be removed from the sandbox after promotion"
+    );
+
+    // promoting again should not clobber the existing package
+    sandbox.publish_recipe(&recipe).await.unwrap();
+    sandbox
+        .promote_namespace(recipe.name(), &namespace, None)
+        .await
+        .expect_err("should fail when the target already has the package");
+    sandbox
+        .read_recipe(recipe.ident())
+        .await
+        .expect("a failed promotion should leave the sandbox untouched");
+
+    let mut pinned = sandbox.clone();
+    pinned.pin_at_datetime(chrono::Utc::now());
+    assert!(matches!(
+        pinned
+            .promote_namespace(recipe.name(), &namespace, None)
+            .await,
+        Err(crate::Error::RepositoryPinnedReadOnly(_))
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_move_all_to_namespace(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let namespace = spfs::storage::TagNamespaceBuf::new("isolated");
+    let root = SpfsRepository::try_from(NameAndRepository::new(
+        "root",
+        spfs::storage::fs::FsRepository::create(tmpdir.path())
+            .await
+            .unwrap(),
+    ))
+    .unwrap();
+    let mut isolated_repo = spfs::storage::fs::FsRepository::open(tmpdir.path())
+        .await
+        .unwrap();
+    isolated_repo.set_tag_namespace(Some(namespace.clone()));
+    let isolated =
+        SpfsRepository::try_from(NameAndRepository::new("isolated", isolated_repo)).unwrap();
+    // both repos share the same address, and so the same caches
+    root.set_cache_policy(CachePolicy::BypassCache);
+    isolated.set_cache_policy(CachePolicy::BypassCache);
+
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let other = recipe!({"pkg": "other-pkg/2.0.0"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    root.publish_recipe(&recipe).await.unwrap();
+    root.publish_package(&spec, &components).await.unwrap();
+    root.publish_recipe(&other).await.unwrap();
+    let published = root.get_package_publish_time(spec.ident()).await.unwrap();
+
+    root.move_all_to_namespace(&namespace).await.unwrap();
+
+    assert!(
+        root.list_packages().await.unwrap().is_empty(),
+        "all packages should be moved out of the root namespace"
+    );
+    assert_eq!(isolated.list_packages().await.unwrap().len(), 2);
+    isolated.read_recipe(other.ident()).await.unwrap();
+    assert_eq!(
+        isolated.read_components(spec.ident()).await.unwrap(),
+        components
+    );
+    assert_eq!(
+        isolated
+            .get_package_publish_time(spec.ident())
+            .await
+            .unwrap(),
+        published,
+        "the original tag times should be preserved"
+    );
+
+    // simulate an interrupted move, where the original was not removed
+    root.publish_recipe(&recipe).await.unwrap();
+    root.move_all_to_namespace(&namespace)
+        .await
+        .expect("moving again should skip the tags that were already moved");
+    assert!(root.list_packages().await.unwrap().is_empty());
+    isolated.read_recipe(recipe.ident()).await.unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_rename_package(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+
+    let recipe = recipe!({"pkg": "my-pkgg/1.0.0"});
+    let spec = spec!({"pkg": "my-pkgg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_recipe(&recipe).await.unwrap();
+    repo.publish_package(&spec, &components).await.unwrap();
+    let published = repo.get_package_publish_time(spec.ident()).await.unwrap();
+
+    let from = recipe.name();
+    let to = spk_schema::foundation::name::PkgName::new("my-pkg").unwrap();
+    repo.rename_package(from, to).await.unwrap();
+
+    let renamed = spk_schema::ident::parse_build_ident("my-pkg/1.0.0/3I42H3S6").unwrap();
+    let renamed_recipe = repo
+        .read_recipe(renamed.as_version_ident())
+        .await
+        .expect("the recipe should be readable under the new name");
+    assert_eq!(renamed_recipe.name(), to);
+    let renamed_spec = repo.read_package(&renamed).await.unwrap();
+    assert_eq!(renamed_spec.ident(), &renamed);
+    assert_eq!(repo.read_components(&renamed).await.unwrap(), components);
+    assert_eq!(
+        repo.get_package_publish_time(&renamed).await.unwrap(),
+        published,
+        "the original tag times should be preserved"
+    );
+    assert!(
+        repo.read_package(spec.ident())
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "the original package should be removed"
+    );
+
+    repo.publish_recipe(&recipe).await.unwrap();
+    repo.rename_package(from, to)
+        .await
+        .expect_err("should fail when the new name already exists");
+    repo.read_recipe(recipe.ident())
+        .await
+        .expect("a failed rename should leave the original untouched");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_clone_version(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+
+    let recipe = recipe!({"pkg": "my-pkg/1.2.0-rc.1"});
+    let spec = spec!({"pkg": "my-pkg/1.2.0-rc.1/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_recipe(&recipe).await.unwrap();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    let to = spk_schema::ident::parse_version_ident("my-pkg/1.2.0").unwrap();
+    repo.clone_version(recipe.ident(), &to).await.unwrap();
+
+    let cloned_recipe = repo.read_recipe(&to).await.unwrap();
+    assert_eq!(cloned_recipe.ident(), &to);
+    let cloned = spk_schema::ident::parse_build_ident("my-pkg/1.2.0/3I42H3S6").unwrap();
+    let cloned_spec = repo.read_package(&cloned).await.unwrap();
+    assert_eq!(cloned_spec.ident(), &cloned);
+    assert_eq!(
+        repo.read_components(&cloned).await.unwrap(),
+        components,
+        "the cloned build should use the same payloads"
+    );
+    repo.read_package(spec.ident())
+        .await
+        .expect("the original build should be kept");
+
+    let err = repo.clone_version(recipe.ident(), &to).await.unwrap_err();
+    assert!(
+        matches!(err, crate::Error::VersionExists(_)),
+        "cloning over an existing version should fail, got {err}"
+    );
+
+    let versioned = spec!({
+        "pkg": "other-pkg/2.0.0-rc.1/3I42H3S6",
+        "build": {"options": [{"var": "release/2.0.0-rc.1"}]},
+    });
+    repo.publish_package(&versioned, &components).await.unwrap();
+    let to = spk_schema::ident::parse_version_ident("other-pkg/2.0.0").unwrap();
+    repo.clone_version(versioned.ident().as_version_ident(), &to)
+        .await
+        .expect_err("a build with the version in its options should not be cloned");
+    assert!(
+        repo.list_package_builds(&to).await.unwrap().is_empty(),
+        "nothing should be cloned when a build must be rebuilt"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_yank_version(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    for version in ["1.0.0", "1.1.0"] {
+        repo.publish_recipe(&recipe!({"pkg": format!("my-pkg/{version}")}))
+            .await
+            .unwrap();
+    }
+    let yanked = spk_schema::ident::parse_version_ident("my-pkg/1.0.0").unwrap();
+    assert!(!repo.is_yanked(&yanked).await.unwrap());
+
+    repo.yank(&yanked).await.unwrap();
+    assert!(repo.is_yanked(&yanked).await.unwrap());
+    assert_eq!(
+        repo.list_package_versions(yanked.name())
+            .await
+            .unwrap()
+            .len(),
+        2,
+        "yanked versions are listed by default"
+    );
+    let hiding = repo.clone().with_hide_yanked(true);
+    let versions = hiding.list_package_versions(yanked.name()).await.unwrap();
+    assert_eq!(
+        versions.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+        vec!["1.1.0"]
+    );
+    hiding
+        .read_recipe(&yanked)
+        .await
+        .expect("yanked versions should still be readable");
+
+    repo.unyank(&yanked).await.unwrap();
+    assert!(!repo.is_yanked(&yanked).await.unwrap());
+    assert_eq!(
+        hiding
+            .list_package_versions(yanked.name())
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let missing = spk_schema::ident::parse_version_ident("my-pkg/2.0.0").unwrap();
+    assert!(
+        repo.yank(&missing)
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "only published versions can be yanked"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_prewarm_populates_caches(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+    let name = spec.name().to_owned();
+    let missing = spk_schema::foundation::name::PkgName::new("missing-pkg")
+        .unwrap()
+        .to_owned();
+
+    repo.invalidate_caches();
+    repo.set_cache_policy(CachePolicy::BypassCache);
+    repo.prewarm(&[name.clone()]).await.unwrap();
+    assert!(
+        !repo.caches.package_versions.contains_key(&name),
+        "nothing should be cached when bypassing the cache"
+    );
+
+    repo.set_cache_policy(CachePolicy::CacheOk);
+    repo.prewarm(&[missing, name.clone()])
+        .await
+        .expect("a missing package should not fail the prewarm");
+    assert!(repo.caches.package_versions.contains_key(&name));
+    let version_folder = SpfsRepository::build_spec_tag(spec.ident().as_version_ident());
+    assert!(
+        repo.caches.ls_tags.contains_key(&version_folder),
+        "the builds of each version should be cached"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_publish_with_legacy_component(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path())
+        .await
+        .with_legacy_component(Some(Component::Build));
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let build_digest: spfs::encoding::Digest = spfs::encoding::EMPTY_DIGEST.into();
+    let components = vec![
+        (Component::Run, empty_layer_digest()),
+        (Component::Build, build_digest),
+    ]
+    .into_iter()
+    .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    let legacy_tag =
+        spfs::tracking::TagSpec::parse(SpfsRepository::build_package_tag(spec.ident())).unwrap();
+    let tag = repo.inner.resolve_tag(&legacy_tag).await.unwrap();
+    assert_eq!(
+        tag.target, build_digest,
+        "the legacy tag should point to the configured component"
+    );
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/DZROCWKT"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components)
+        .await
+        .expect_err("publishing without the legacy component should fail");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_ls_tags_stream_caches_when_consumed(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    for name in ["pkg-a", "pkg-b"] {
+        repo.publish_recipe(&recipe!({"pkg": format!("{name}/1.0.0")}))
+            .await
+            .unwrap();
+    }
+    repo.invalidate_caches();
+    let path = relative_path::RelativePath::new("spk/spec");
+
+    let first = repo.ls_tags_stream(path).next().await;
+    assert!(first.is_some());
+    assert!(
+        !repo.caches.ls_tags.contains_key(path),
+        "a partially read listing should not be cached"
+    );
+
+    let entries: Vec<_> = repo.ls_tags_stream(path).try_collect().await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(
+        repo.caches.ls_tags.contains_key(path),
+        "a fully read listing should be cached"
+    );
+    let cached: Vec<_> = repo.ls_tags_stream(path).try_collect().await.unwrap();
+    assert_eq!(cached, entries);
+}
+
+#[rstest]
+fn test_retry_policy_delays_double() {
+    let policy = super::RetryPolicy {
+        max_attempts: 4,
+        base_backoff: std::time::Duration::from_millis(100),
+    };
+    assert_eq!(
+        policy.delays().collect::<Vec<_>>(),
+        vec![
+            std::time::Duration::from_millis(100),
+            std::time::Duration::from_millis(200),
+            std::time::Duration::from_millis(400),
+        ],
+        "there should be one fewer retry than attempts"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_retry_policy_ignored_for_local_repos(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path())
+        .await
+        .with_retry_policy(Some(super::RetryPolicy::default()));
+    assert!(
+        !repo.retries_reads(),
+        "only reads from an spfs server should be retried"
+    );
+
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+    repo.read_recipe(recipe.ident()).await.unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_publish_without_legacy_tags(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path())
+        .await
+        .with_write_legacy_tags(false);
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    let legacy_tag =
+        spfs::tracking::TagSpec::parse(SpfsRepository::build_package_tag(spec.ident())).unwrap();
+    assert!(
+        !repo.inner.has_tag(&legacy_tag).await,
+        "the legacy tag should not be written"
+    );
+    assert_eq!(
+        repo.read_components(spec.ident()).await.unwrap(),
+        components,
+        "the package should still be readable from its component tags"
+    );
+
+    repo.remove_package(spec.ident())
+        .await
+        .expect("a package without a legacy tag should be removable");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_find_and_remove_orphaned_builds(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let healthy = spec!({"pkg": "healthy/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&healthy, &components).await.unwrap();
+
+    let spec_only = BuildIdent::from_str("spec-only/1.0.0/3I42H3S6").unwrap();
+    let tag_spec =
+        spfs::tracking::TagSpec::parse(SpfsRepository::build_spec_tag(&spec_only)).unwrap();
+    repo.inner
+        .push_tag(&tag_spec, &spfs::encoding::EMPTY_DIGEST.into())
+        .await
+        .unwrap();
+
+    let package_only = BuildIdent::from_str("package-only/1.0.0/3I42H3S6").unwrap();
+    let tag_spec = spfs::tracking::TagSpec::parse(
+        SpfsRepository::build_package_tag(&package_only).join("run"),
+    )
+    .unwrap();
+    repo.inner
+        .push_tag(&tag_spec, &empty_layer_digest())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        repo.find_orphaned_specs().await.unwrap(),
+        vec![spec_only.clone()]
+    );
+    assert_eq!(
+        repo.find_orphaned_packages().await.unwrap(),
+        vec![package_only.clone()]
+    );
+
+    assert_eq!(repo.remove_orphaned_specs().await.unwrap(), vec![spec_only]);
+    assert_eq!(
+        repo.remove_orphaned_packages().await.unwrap(),
+        vec![package_only]
+    );
+    assert!(repo.find_orphaned_specs().await.unwrap().is_empty());
+    assert!(repo.find_orphaned_packages().await.unwrap().is_empty());
+    repo.read_package(healthy.ident())
+        .await
+        .expect("builds with both tags should not be removed");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_package_object_closure(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let blob = repo
+        .inner
+        .commit_blob(Box::pin(std::io::Cursor::new(b"contents".to_vec())))
+        .await
+        .unwrap();
+    let mut manifest = spfs::tracking::Manifest::<()>::default();
+    let file = manifest.mkfile("file").unwrap();
+    file.kind = spfs::tracking::EntryKind::Blob(8);
+    file.object = blob;
+    let manifest = manifest.to_graph_manifest();
+    repo.inner.write_object(&manifest).await.unwrap();
+    let layer = spfs::graph::Layer::new(manifest.digest().unwrap());
+    repo.inner.write_object(&layer).await.unwrap();
+    let layer = layer.digest().unwrap();
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, layer), (Component::Build, layer)]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    let closure = repo.package_object_closure(spec.ident()).await.unwrap();
+    assert_eq!(
+        closure,
+        std::collections::HashSet::from([layer, manifest.digest().unwrap(), blob]),
+        "the closure should hold the layer, its manifest and its blob"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_listing_with_diagnostics(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+    for junk in [
+        "spk/spec/my-pkg/not-a-version",
+        "spk/spec/my-pkg/1.0.0/not-a-build",
+    ] {
+        let tag_spec = spfs::tracking::TagSpec::parse(junk).unwrap();
+        repo.inner
+            .push_tag(&tag_spec, &spfs::encoding::EMPTY_DIGEST.into())
+            .await
+            .unwrap();
+    }
+    repo.invalidate_caches();
+
+    let name = spec.name();
+    let (versions, skipped) = repo
+        .list_package_versions_with_diagnostics(name)
+        .await
+        .unwrap();
+    assert_eq!(
+        *versions,
+        vec![std::sync::Arc::new(Version::from_str("1.0.0").unwrap())]
+    );
+    assert_eq!(
+        skipped.iter().map(|e| e.tag.as_str()).collect::<Vec<_>>(),
+        vec!["spk/spec/my-pkg/not-a-version"]
+    );
+    assert_eq!(
+        repo.list_package_versions(name).await.unwrap(),
+        versions,
+        "the plain listing should skip the same entries"
+    );
+
+    let (builds, skipped) = repo
+        .get_concrete_package_builds_with_diagnostics(spec.ident().base())
+        .await
+        .unwrap();
+    assert_eq!(
+        builds,
+        std::collections::HashSet::from([spec.ident().clone()])
+    );
+    assert_eq!(
+        skipped.iter().map(|e| e.tag.as_str()).collect::<Vec<_>>(),
+        vec!["spk/spec/my-pkg/1.0.0/not-a-build"]
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_read_resolved_options_matches_full_parse(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let specs = [
+        spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"}),
+        spec!({
+            "pkg": "my-pkg/1.0.0/7CI5R7Y4",
+            "build": {"options": [{"var": "debug/on"}, {"pkg": "python/3.9"}]},
+        }),
+        spec!({
+            "pkg": "my-pkg/1.0.0/QYB6QLCN",
+            "build": {"options": [{"var": "debug/off"}, {"var": "os/linux"}]},
+        }),
+    ];
+    let components = vec![(Component::Run, spfs::encoding::EMPTY_DIGEST.into())]
+        .into_iter()
+        .collect();
+    for spec in specs.iter() {
+        repo.publish_package(spec, &components).await.unwrap();
+    }
+
+    for spec in specs.iter() {
+        let full = repo.read_package(spec.ident()).await.unwrap();
+        let partial = repo.read_resolved_options(spec.ident()).await.unwrap();
+        assert_eq!(
+            partial,
+            full.option_values(),
+            "partial parse should match the full spec for {}",
+            spec.ident()
+        );
+    }
+    assert_eq!(repo.caches.resolved_options.len(), specs.len());
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_pin_at_datetime(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let mut repo = make_uncached_spfs_repo(tmpdir.path()).await;
+
+    let before_publish = chrono::Utc::now() - chrono::Duration::seconds(10);
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+
+    repo.pin_at_datetime(before_publish);
+    assert_eq!(repo.describe().pinned_at, Some(before_publish));
+    assert!(
+        repo.address()
+            .query_pairs()
+            .any(|(k, v)| k == "when" && v == before_publish.to_rfc3339()),
+        "address should include the absolute pin time: {}",
+        repo.address()
+    );
+    assert!(
+        matches!(
+            repo.read_recipe(recipe.ident()).await,
+            Err(crate::Error::PackageNotFound(_))
+        ),
+        "recipe published after the pin should not be visible"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_unpin_restores_live_view(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let mut repo = make_uncached_spfs_repo(tmpdir.path()).await;
+    let live_address = repo.address().clone();
+
+    // unpinning a repo that was never pinned does nothing
+    repo.unpin();
+    assert_eq!(repo.address(), &live_address);
+
+    let before_publish = chrono::Utc::now() - chrono::Duration::seconds(10);
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+
+    repo.pin_at_datetime(before_publish);
+    assert!(repo.read_recipe(recipe.ident()).await.is_err());
+
+    repo.unpin();
+    assert!(repo.describe().pinned_at.is_none());
+    assert_eq!(
+        repo.address(),
+        &live_address,
+        "the pin should be removed from the address"
+    );
+    repo.read_recipe(recipe.ident())
+        .await
+        .expect("recipe should be visible once unpinned");
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_pinned_repo_is_read_only(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let mut repo = make_uncached_spfs_repo(tmpdir.path()).await;
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+
+    repo.pin_at_datetime(chrono::Utc::now());
+    assert!(repo.is_pinned());
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    assert!(matches!(
+        repo.publish_recipe(&recipe).await,
+        Err(crate::Error::RepositoryPinnedReadOnly(_))
+    ));
+    assert!(matches!(
+        repo.publish_package(&spec, &components).await,
+        Err(crate::Error::RepositoryPinnedReadOnly(_))
+    ));
+    assert!(matches!(
+        repo.remove_recipe(recipe.ident()).await,
+        Err(crate::Error::RepositoryPinnedReadOnly(_))
+    ));
+
+    repo.unpin();
+    assert!(!repo.is_pinned());
+    repo.publish_package(&spec, &components)
+        .await
+        .expect("an unpinned repository should be writable again");
+}
+
+#[derive(Debug, Default)]
+struct RenameSummaryRewriter {
+    calls: std::sync::atomic::AtomicUsize,
+}
+
+impl super::SpecRewriter for RenameSummaryRewriter {
+    fn rewrite_recipe(
+        &self,
+        _pkg: &spk_schema::ident::VersionIdent,
+        yaml: String,
+    ) -> crate::Result<String> {
+        self.calls
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(yaml.replace("summary:", "description:"))
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_spec_rewriter_migrates_recipes(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let rewriter = std::sync::Arc::new(RenameSummaryRewriter::default());
+    let repo = make_spfs_repo(tmpdir.path())
+        .await
+        .with_spec_rewriter(Some(rewriter.clone()));
+
+    // write a spec that uses a legacy field directly into storage,
+    // as if it had been published by an older version of spk
+    let legacy_yaml = "pkg: my-pkg/1.0.0\nmeta:\n  summary: a legacy description\n";
+    let digest = repo
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(
+            legacy_yaml.as_bytes().to_vec(),
+        )))
+        .await
+        .unwrap();
+    let tag = spfs::tracking::TagSpec::parse("spk/spec/my-pkg/1.0.0").unwrap();
+    repo.inner().push_tag(&tag, &digest).await.unwrap();
+
+    let ident = spk_schema::ident::parse_version_ident("my-pkg/1.0.0").unwrap();
+    let recipe = repo.read_recipe(&ident).await.unwrap();
+    assert_eq!(
+        recipe.metadata().description.as_deref(),
+        Some("a legacy description"),
+        "the legacy field should be migrated by the rewriter"
+    );
+
+    // the rewritten recipe is cached, so it is not rewritten again
+    let again = repo.read_recipe(&ident).await.unwrap();
+    assert_eq!(again.metadata().description, recipe.metadata().description);
+    assert_eq!(rewriter.calls.load(std::sync::atomic::Ordering::Relaxed), 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_spec_rewriter_does_not_share_caches(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let plain = make_spfs_repo(tmpdir.path()).await;
+    let rewriting = plain
+        .clone()
+        .with_spec_rewriter(Some(std::sync::Arc::new(RenameSummaryRewriter::default())));
+
+    let legacy_yaml = "pkg: my-pkg/1.0.0\nmeta:\n  summary: a legacy description\n";
+    let digest = plain
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(
+            legacy_yaml.as_bytes().to_vec(),
+        )))
+        .await
+        .unwrap();
+    let tag = spfs::tracking::TagSpec::parse("spk/spec/my-pkg/1.0.0").unwrap();
+    plain.inner().push_tag(&tag, &digest).await.unwrap();
+
+    let ident = spk_schema::ident::parse_version_ident("my-pkg/1.0.0").unwrap();
+    let rewritten = rewriting.read_recipe(&ident).await.unwrap();
+    assert!(rewritten.metadata().description.is_some());
+    let original = plain.read_recipe(&ident).await.unwrap();
+    assert!(
+        original.metadata().description.is_none(),
+        "a repository without the rewriter should not see rewritten specs"
+    );
+}
+
+/// Migrates a hypothetical v1 recipe, which splits the package
+/// name and version into separate fields.
+#[derive(Debug)]
+struct SplitPkgFieldMigration;
+
+impl super::RecipeMigration for SplitPkgFieldMigration {
+    fn target_version(&self) -> &str {
+        "v0/package"
+    }
+
+    fn migrate(
+        &self,
+        _pkg: &spk_schema::ident::VersionIdent,
+        mut recipe: serde_yaml::Value,
+    ) -> crate::Result<serde_yaml::Value> {
+        let mapping = recipe.as_mapping_mut().unwrap();
+        let name = mapping.remove("name").unwrap();
+        let version = mapping.remove("version").unwrap();
+        mapping.insert(
+            "pkg".into(),
+            format!("{}/{}", name.as_str().unwrap(), version.as_str().unwrap()).into(),
+        );
+        Ok(recipe)
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_read_recipe_migrated(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let migrations = super::RecipeMigrations::new()
+        .with_migration("v1/package", std::sync::Arc::new(SplitPkgFieldMigration));
+    let repo = make_spfs_repo(tmpdir.path())
+        .await
+        .with_recipe_migrations(migrations);
+    repo.set_cache_policy(CachePolicy::BypassCache);
+
+    let v1_yaml = "api: v1/package\nname: my-pkg\nversion: 1.0.0\n";
+    let digest = repo
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(v1_yaml.as_bytes().to_vec())))
+        .await
+        .unwrap();
+    let tag = spfs::tracking::TagSpec::parse("spk/spec/my-pkg/1.0.0").unwrap();
+    repo.inner().push_tag(&tag, &digest).await.unwrap();
+
+    let ident = spk_schema::ident::parse_version_ident("my-pkg/1.0.0").unwrap();
+    repo.read_recipe(&ident)
+        .await
+        .expect_err("a v1 recipe should not be readable without migrating it");
+    let recipe = repo
+        .read_recipe_migrated(&ident)
+        .await
+        .expect("a v1 recipe should be migrated on read");
+    assert_eq!(recipe.ident(), &ident);
+
+    // the stored recipe is left as it was
+    let stored = repo.inner().resolve_tag(&tag).await.unwrap();
+    assert_eq!(stored.target, digest);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_unshared_caches_are_isolated(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let shared = SpfsRepository::try_from(NameAndRepository::new(
+        "shared",
+        spfs::storage::fs::FsRepository::create(tmpdir.path())
+            .await
+            .unwrap(),
+    ))
+    .unwrap();
+    let unshared = SpfsRepository::try_from(
+        NameAndRepository::new(
+            "unshared",
+            spfs::storage::fs::FsRepository::open(tmpdir.path())
+                .await
+                .unwrap(),
+        )
+        .unshared(),
+    )
+    .unwrap();
+    assert_eq!(shared.address(), unshared.address());
+
+    let name = spk_schema::foundation::name::PkgName::new("my-pkg").unwrap();
+    assert!(
+        unshared
+            .list_package_versions(name)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // publishing through the shared repo only invalidates the shared
+    // caches, so the unshared repo keeps its own cached view
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    shared.publish_recipe(&recipe).await.unwrap();
+    assert!(
+        unshared
+            .list_package_versions(name)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(unshared.caches.package_versions.contains_key(name));
+
+    unshared.set_cache_policy(CachePolicy::BypassCache);
+    assert_eq!(unshared.list_package_versions(name).await.unwrap().len(), 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_content_fingerprint(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let empty = repo.content_fingerprint().await.unwrap();
+
+    repo.publish_recipe(&recipe!({"pkg": "my-pkg/1.0.0"}))
+        .await
+        .unwrap();
+    let published = repo.content_fingerprint().await.unwrap();
+    assert_ne!(empty, published, "a publish should change the fingerprint");
+    assert_eq!(
+        published,
+        repo.content_fingerprint().await.unwrap(),
+        "the fingerprint should be stable when nothing changes"
+    );
+
+    let changed = recipe!({"pkg": "my-pkg/1.0.0", "meta": {"description": "changed"}});
+    repo.force_publish_recipe(&changed).await.unwrap();
+    assert_ne!(
+        published,
+        repo.content_fingerprint().await.unwrap(),
+        "retargeting an existing tag should change the fingerprint"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_list_healthy_builds(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let good = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let bad = spec!({"pkg": "my-pkg/1.0.0/7CI5R7Y4"});
+    let components = vec![(Component::Run, spfs::encoding::EMPTY_DIGEST.into())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&good, &components).await.unwrap();
+    repo.publish_package(&bad, &components).await.unwrap();
+
+    // replace the spec of one build with something that cannot be parsed
+    let digest = repo
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(b"{not: [valid".to_vec())))
+        .await
+        .unwrap();
+    let tag_spec =
+        spfs::tracking::TagSpec::parse(SpfsRepository::build_spec_tag(bad.ident())).unwrap();
+    repo.inner().push_tag(&tag_spec, &digest).await.unwrap();
+    repo.set_cache_policy(CachePolicy::BypassCache);
+
+    let (healthy, broken) = repo
+        .list_healthy_builds(good.ident().as_version_ident())
+        .await
+        .unwrap();
+    assert_eq!(healthy, vec![good.ident().clone()]);
+    assert_eq!(broken.len(), 1);
+    assert_eq!(&broken[0].0, bad.ident());
+    assert!(
+        matches!(broken[0].1, crate::Error::InvalidPackageSpec(..)),
+        "expected a spec parsing error, got {:?}",
+        broken[0].1
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_copy_package_between_repos() {
+    init_logging();
+    let src = make_repo(RepoKind::Spfs).await;
+    let dest_root = tempfile::TempDir::new().unwrap();
+    let dest = SpfsRepository::try_from(NameAndRepository::new(
+        "dest",
+        spfs::storage::fs::FsRepository::create(dest_root.path())
+            .await
+            .unwrap(),
+    ))
+    .unwrap();
+    let RepositoryHandle::SPFS(src_repo) = &*src.repo else {
+        panic!("expected an spfs repository");
+    };
+
+    let spec = spec!({
+        "pkg": "my-pkg/1.0.0/3I42H3S6",
+        "install": {
+            "embedded": [
+                {"pkg": "my-embedded-pkg/1.0.0/embedded"}
+            ]
+        }
+    });
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    src_repo.publish_package(&spec, &components).await.unwrap();
+
+    src_repo.copy_package(spec.ident(), &dest).await.unwrap();
+
+    let copied = dest.read_package(spec.ident()).await.unwrap();
+    assert_eq!(copied.ident(), spec.ident());
+    assert_eq!(
+        dest.read_components(spec.ident()).await.unwrap(),
+        components
+    );
+    assert!(
+        dest.inner().has_object(empty_layer_digest()).await,
+        "the component layers should be synced to the destination"
+    );
+    assert!(
+        dest.list_packages()
+            .await
+            .unwrap()
+            .iter()
+            .any(|pkg| pkg == "my-embedded-pkg"),
+        "the embed stub should be recreated in the destination"
+    );
+
+    // copying again replaces the existing build, like a normal publish
+    src_repo.copy_package(spec.ident(), &dest).await.unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_list_packages_matching(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    for recipe in [
+        recipe!({"pkg": "a-pkg/1.0.0"}),
+        recipe!({"pkg": "ab-pkg/1.0.0"}),
+        recipe!({"pkg": "b-pkg/2.0.0"}),
+    ] {
+        repo.publish_recipe(&recipe).await.unwrap();
+    }
+
+    let names = |names: Vec<spk_schema::foundation::name::PkgNameBuf>| {
+        names.iter().map(|n| n.to_string()).collect::<Vec<_>>()
+    };
+    assert_eq!(
+        names(repo.list_packages_matching("a").await.unwrap()),
+        vec!["a-pkg", "ab-pkg"]
+    );
+    assert_eq!(
+        names(repo.list_packages_matching("ab-pkg/1").await.unwrap()),
+        vec!["ab-pkg"]
+    );
+    assert!(
+        repo.caches
+            .ls_tags
+            .contains_key(relative_path::RelativePath::new("spk/spec/ab-pkg")),
+        "only the subtree of the named package should be listed"
+    );
+    assert!(
+        repo.list_packages_matching("ab-pkg/2")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(repo.list_packages_matching("c").await.unwrap().is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_packages_without_builds(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    for recipe in [
+        recipe!({"pkg": "recipe-only/1.0.0"}),
+        recipe!({"pkg": "recipe-only/2.0.0"}),
+        recipe!({"pkg": "has-builds/1.0.0"}),
+        recipe!({"pkg": "has-builds/2.0.0"}),
+    ] {
+        repo.publish_recipe(&recipe).await.unwrap();
+    }
+    repo.publish_package(&spec!({"pkg": "has-builds/1.0.0/3I42H3S6"}), &components)
+        .await
+        .unwrap();
+
+    let buildless = repo.packages_without_builds().await.unwrap();
+    assert_eq!(
+        buildless
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["recipe-only"]
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_list_package_versions_with_build_counts(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    for recipe in [
+        recipe!({"pkg": "my-pkg/1.0.0"}),
+        recipe!({"pkg": "my-pkg/2.0.0"}),
+    ] {
+        repo.publish_recipe(&recipe).await.unwrap();
+    }
+    for spec in [
+        spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"}),
+        spec!({"pkg": "my-pkg/1.0.0/7CI5R7Y4"}),
+    ] {
+        repo.publish_package(&spec, &components).await.unwrap();
+    }
+
+    let name = spk_schema::foundation::name::PkgName::new("my-pkg").unwrap();
+    let counts = repo
+        .list_package_versions_with_build_counts(name)
+        .await
+        .unwrap();
+    assert_eq!(
+        counts
+            .iter()
+            .map(|(version, count)| (version.to_string(), *count))
+            .collect::<Vec<_>>(),
+        vec![("1.0.0".to_string(), 2), ("2.0.0".to_string(), 0)],
+        "versions without builds should still be listed"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_get_package_publish_time(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![
+        (Component::Build, empty_layer_digest()),
+        (Component::Run, empty_layer_digest()),
+    ]
+    .into_iter()
+    .collect();
+
+    // tags only record time to the microsecond
+    let before = chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 6);
+    repo.publish_package(&spec, &components).await.unwrap();
+    let after = chrono::Utc::now();
+
+    let published = repo.get_package_publish_time(spec.ident()).await.unwrap();
+    assert!(
+        before <= published && published <= after,
+        "expected a publish time between {before} and {after}, got {published}"
+    );
+
+    let missing = BuildIdent::from_str("my-pkg/1.0.0/7CI5R7Y4").unwrap();
+    assert!(
+        repo.get_package_publish_time(&missing)
+            .await
+            .unwrap_err()
+            .is_package_not_found()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_package_tag_history(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    repo.publish_package(&spec, &components).await.unwrap();
+    let republished = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6", "deprecated": true});
+    repo.publish_package(&republished, &components)
+        .await
+        .unwrap();
+
+    let history = repo.package_tag_history(spec.ident()).await.unwrap();
+    assert_eq!(history.len(), 2, "expected both publishes, got {history:?}");
+    assert!(history[0].time >= history[1].time, "newest should be first");
+    assert_ne!(history[0].target, history[1].target);
+
+    let tag = spfs::tracking::TagSpec::parse("spk/spec/my-pkg/1.0.0/3I42H3S6").unwrap();
+    let current = repo.inner().resolve_tag(&tag).await.unwrap();
+    assert_eq!(history[0].target, current.target);
+}
+
+#[rstest]
+#[case::src("spk/pkg/my-pkg/1.0.0/src", &[Component::Source])]
+#[case::uppercase_src("spk/pkg/my-pkg/1.0.0/SRC", &[Component::Source])]
+#[case::source_alias("spk/pkg/my-pkg/1.0.0/source", &[Component::Source])]
+#[case::binary("spk/pkg/my-pkg/1.0.0/3I42H3S6", &[Component::Build, Component::Run])]
+fn test_legacy_stored_package_components(#[case] tag: &str, #[case] expected: &[Component]) {
+    let tag_spec = spfs::tracking::TagSpec::parse(tag).unwrap();
+    let components = super::StoredPackage::WithoutComponents(tag_spec.clone()).into_components();
+    assert_eq!(
+        components
+            .keys()
+            .cloned()
+            .collect::<std::collections::BTreeSet<_>>(),
+        expected.iter().cloned().collect(),
+    );
+    assert!(
+        components.values().all(|spec| *spec == tag_spec),
+        "every component should map to the legacy tag"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_packages_exist(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    let missing = BuildIdent::from_str("my-pkg/1.0.0/7CI5R7Y4").unwrap();
+    let exists = repo
+        .packages_exist(&[spec.ident().clone(), missing.clone()])
+        .await
+        .unwrap();
+    assert_eq!(exists.len(), 2);
+    assert!(exists[spec.ident()]);
+    assert!(!exists[&missing]);
+
+    let spec_tag = spfs::tracking::TagSpec::parse("spk/spec/my-pkg/1.0.0/3I42H3S6").unwrap();
+    assert!(
+        repo.caches.tag_spec.contains_key(&spec_tag),
+        "the resolved spec tag should be cached for later reads"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_any_build_exists_stops_at_first_match(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    for spec in [
+        spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"}),
+        spec!({"pkg": "my-pkg/2.0.0/3I42H3S6"}),
+        spec!({"pkg": "my-pkg/3.0.0/3I42H3S6"}),
+    ] {
+        repo.publish_package(&spec, &components).await.unwrap();
+    }
+    repo.publish_recipe(&recipe!({"pkg": "my-pkg/4.0.0"}))
+        .await
+        .unwrap();
+
+    let name = spk_schema::foundation::name::PkgName::new("my-pkg").unwrap();
+    let range = |r: &str| spk_schema::foundation::version_range::parse_version_range(r).unwrap();
+    assert!(
+        repo.any_build_exists(name, &range(">=2.0.0"))
+            .await
+            .unwrap()
+    );
+    let listed = repo
+        .caches
+        .ls_tags
+        .iter()
+        .map(|entry| entry.key().to_string())
+        .filter(|path| {
+            path.starts_with("spk/pkg/my-pkg/2") || path.starts_with("spk/spec/my-pkg/2")
+        })
+        .collect::<Vec<_>>();
+    assert!(
+        listed.is_empty(),
+        "older versions should not be listed once a build is found: {listed:?}"
+    );
+
+    assert!(
+        !repo
+            .any_build_exists(name, &range(">=4.0.0"))
+            .await
+            .unwrap(),
+        "a version with only a recipe has no builds"
+    );
+    assert!(!repo.any_build_exists(name, &range("<1.0.0")).await.unwrap());
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_iter_all_builds(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    let specs = [
+        spec!({"pkg": "a-pkg/1.0.0/3I42H3S6"}),
+        spec!({"pkg": "a-pkg/1.0.0/7CI5R7Y4"}),
+        spec!({"pkg": "a-pkg/2.0.0/3I42H3S6"}),
+        spec!({"pkg": "b-pkg/1.0.0/3I42H3S6"}),
+    ];
+    for spec in specs.iter() {
+        repo.publish_package(spec, &components).await.unwrap();
+    }
+    // a package folder that is not a valid package name
+    let tag = spfs::tracking::TagSpec::from_str("spk/spec/Not_A_Pkg/1.0.0").unwrap();
+    repo.inner()
+        .push_tag(&tag, &spfs::encoding::EMPTY_DIGEST.into())
+        .await
+        .unwrap();
+
+    let mut builds: Vec<BuildIdent> = repo.iter_all_builds().try_collect().await.unwrap();
+    builds.sort();
+    let mut expected = specs
+        .iter()
+        .map(|spec| spec.ident().clone())
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(builds, expected);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_publish_with_component_push_concurrency(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path())
+        .await
+        .with_component_push_concurrency(2);
+    repo.set_cache_policy(CachePolicy::BypassCache);
+    assert_eq!(repo.component_push_concurrency(), 2);
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = [
+        Component::Run,
+        Component::Build,
+        Component::Named("dev".into()),
+        Component::Named("docs".into()),
+        Component::Named("tests".into()),
+    ]
+    .into_iter()
+    .map(|component| (component, empty_layer_digest()))
+    .collect();
+    let result = repo.publish_package(&spec, &components).await.unwrap();
+    // the legacy tag, one tag per component, and the spec tag
+    assert_eq!(result.tags_written.len(), components.len() + 2);
+    assert_eq!(
+        result.tags_written.last(),
+        Some(
+            &spfs::tracking::TagSpec::parse(SpfsRepository::build_spec_tag(spec.ident())).unwrap()
+        ),
+        "the spec tag should still be written last"
+    );
+    assert_eq!(
+        repo.read_components(spec.ident()).await.unwrap(),
+        components
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_publish_package_with_embeds(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+
+    let embedded = spk_schema::ident::VersionIdent::from_str("my-embedded-pkg/1.0.0").unwrap();
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    let embeds = |spec: &spk_schema::Spec| {
+        spec.embedded_as_packages()
+            .unwrap()
+            .into_iter()
+            .map(|(embed, component)| {
+                (
+                    embed,
+                    std::iter::once(component.unwrap_or(Component::Run))
+                        .collect::<std::collections::BTreeSet<_>>(),
+                )
+            })
+            .collect::<std::collections::HashMap<_, _>>()
+    };
+
+    // a file where the stub's tag folder should be will fail its publish
+    let blocker = tmpdir.path().join("tags/spk/spec/my-embedded-pkg");
+    std::fs::create_dir_all(blocker.parent().unwrap()).unwrap();
+    std::fs::write(&blocker, "").unwrap();
+    let spec = spec!({
+        "pkg": "my-pkg/1.0.0/3I42H3S6",
+        "install": {
+            "embedded": [
+                {"pkg": "my-embedded-pkg/1.0.0/embedded"}
+            ]
+        }
+    });
+    repo.publish_package_with_embeds(&spec, &components, &embeds(&spec))
+        .await
+        .expect_err("the stub should fail to publish");
+    std::fs::remove_file(&blocker).unwrap();
+    assert!(
+        repo.read_package(spec.ident())
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "the provider should be removed when a stub fails to publish"
+    );
+    assert!(
+        repo.list_package_builds(&embedded)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    repo.publish_package_with_embeds(&spec, &components, &embeds(&spec))
+        .await
+        .unwrap();
+    repo.read_package(spec.ident())
+        .await
+        .expect("the provider should be published");
+    let stubs = repo.list_package_builds(&embedded).await.unwrap();
+    assert_eq!(stubs.len(), 1, "the stub should be published: {stubs:?}");
+    assert!(stubs[0].is_embedded());
+
+    // the existing build could not be restored if a stub failed to
+    // publish, and so it is never replaced
+    let res = repo
+        .publish_package_with_embeds(&spec, &components, &embeds(&spec))
+        .await;
+    assert!(
+        matches!(res, Err(crate::Error::VersionExists(_))),
+        "an existing build should not be overwritten, got {res:?}"
+    );
+    repo.read_package(spec.ident())
+        .await
+        .expect("the existing build should be left in place");
+    assert_eq!(repo.list_package_builds(&embedded).await.unwrap(), stubs);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_find_stray_component_tags(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    repo.publish_package(
+        &spec,
+        &vec![(Component::Run, empty_layer_digest())]
+            .into_iter()
+            .collect(),
+    )
+    .await
+    .unwrap();
+    let name = spec.ident().name();
+    assert!(
+        repo.find_stray_component_tags(name)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // a component tag that the spec does not declare
+    let tag = spfs::tracking::TagSpec::parse("spk/pkg/my-pkg/1.0.0/3I42H3S6/extra").unwrap();
+    repo.inner()
+        .push_tag(&tag, &empty_layer_digest())
+        .await
+        .unwrap();
+    let expected = vec![(spec.ident().clone(), Component::Named("extra".into()))];
+    assert_eq!(
+        repo.find_stray_component_tags(name).await.unwrap(),
+        expected
+    );
+
+    assert_eq!(
+        repo.remove_stray_component_tags(name).await.unwrap(),
+        expected
+    );
+    assert!(!repo.inner().has_tag(&tag).await);
+    assert!(
+        repo.find_stray_component_tags(name)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        repo.list_build_components(spec.ident()).await.unwrap(),
+        vec![Component::Run],
+        "declared components should not be removed"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_migrate_storage_root() {
+    init_logging();
+    let src = make_repo(RepoKind::Spfs).await;
+    let RepositoryHandle::SPFS(src_repo) = &*src.repo else {
+        panic!("expected an spfs repository");
+    };
+    let recipe = recipe!({"pkg": "my-pkg/1.0.0"});
+    src_repo.publish_recipe(&recipe).await.unwrap();
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    src_repo.publish_package(&spec, &components).await.unwrap();
+
+    let new_root = tempfile::TempDir::new().unwrap();
+    let mut repo = src_repo.clone();
+    repo.migrate_storage_root(
+        new_root.path(),
+        spfs::sync::reporter::SyncReporters::silent(),
+    )
+    .await
+    .unwrap();
+
+    assert_ne!(repo.address(), src_repo.address());
+    assert!(
+        repo.address()
+            .path()
+            .contains(&*new_root.path().canonicalize().unwrap().to_string_lossy()),
+        "the repository should point at the new root, got {}",
+        repo.address()
+    );
+    repo.read_recipe(recipe.ident()).await.unwrap();
+    repo.read_package(spec.ident()).await.unwrap();
+    assert_eq!(
+        repo.read_components(spec.ident()).await.unwrap(),
+        components
+    );
+    assert!(repo.inner().has_object(empty_layer_digest()).await);
+
+    // the original root is left untouched and still usable
+    src_repo.read_package(spec.ident()).await.unwrap();
+
+    // migrating again picks up where the last migration left off
+    let mut again = src_repo.clone();
+    again
+        .migrate_storage_root(
+            new_root.path(),
+            spfs::sync::reporter::SyncReporters::silent(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(again.address(), repo.address());
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_migrate_storage_root_keeps_cache_mode(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let mut repo = SpfsRepository::try_from(
+        NameAndRepository::new(
+            "test-repo",
+            spfs::storage::fs::FsRepository::create(tmpdir.path())
+                .await
+                .unwrap(),
+        )
+        .unshared(),
+    )
+    .unwrap();
+
+    let new_root = tempfile::TempDir::new().unwrap();
+    let mut pinned = repo.clone();
+    pinned.pin_at_datetime(chrono::Utc::now());
+    assert!(matches!(
+        pinned
+            .migrate_storage_root(
+                new_root.path(),
+                spfs::sync::reporter::SyncReporters::silent(),
+            )
+            .await,
+        Err(crate::Error::RepositoryPinnedReadOnly(_))
+    ));
+
+    repo.migrate_storage_root(
+        new_root.path(),
+        spfs::sync::reporter::SyncReporters::silent(),
+    )
+    .await
+    .unwrap();
+    assert!(
+        repo.caches.registration.is_none(),
+        "an unshared repository should keep its own caches after migrating"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_verify_package(tmpdir: tempfile::TempDir) {
+    use super::VerificationIssue;
+
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![
+        (Component::Build, spfs::encoding::EMPTY_DIGEST.into()),
+        (Component::Run, spfs::encoding::EMPTY_DIGEST.into()),
+    ]
+    .into_iter()
+    .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+    assert_eq!(repo.verify_package(spec.ident()).await.unwrap(), vec![]);
+
+    let tag = |path: &str| spfs::tracking::TagSpec::parse(path).unwrap();
+    let legacy_tag = tag("spk/pkg/my-pkg/1.0.0/3I42H3S6");
+    let build_tag = tag("spk/pkg/my-pkg/1.0.0/3I42H3S6/build");
+    repo.inner().remove_tag_stream(&legacy_tag).await.unwrap();
+    repo.inner().remove_tag_stream(&build_tag).await.unwrap();
+    let digest = repo
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(b"not-a-stub".to_vec())))
+        .await
+        .unwrap();
+    let stub_tag = tag("spk/spec/my-pkg/1.0.0/embedded-by-NOTBASE32");
+    repo.inner().push_tag(&stub_tag, &digest).await.unwrap();
+
+    let issues = repo.verify_package(spec.ident()).await.unwrap();
+    assert_eq!(
+        issues,
+        vec![
+            VerificationIssue::MissingLegacyTag {
+                expected: legacy_tag
+            },
+            VerificationIssue::MissingComponentTag {
+                component: Component::Build,
+                expected: build_tag,
+            },
+            VerificationIssue::UndecodableEmbedStub { tag: stub_tag },
+        ]
+    );
+
+    // a spec that was published without any package tags
+    let orphan = spec!({"pkg": "my-pkg/1.0.0/7CI5R7Y4"});
+    let yaml = serde_yaml::to_string(&orphan).unwrap();
+    let digest = repo
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(yaml.into_bytes())))
+        .await
+        .unwrap();
+    let spec_tag = tag("spk/spec/my-pkg/1.0.0/7CI5R7Y4");
+    repo.inner().push_tag(&spec_tag, &digest).await.unwrap();
+    let issues = repo.verify_package(orphan.ident()).await.unwrap();
+    assert!(
+        issues.contains(&VerificationIssue::SpecWithoutPackage { spec_tag }),
+        "expected the missing package to be reported, got {issues:?}"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_truncated_embed_stubs_are_reported(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![(Component::Run, spfs::encoding::EMPTY_DIGEST.into())]
+        .into_iter()
+        .collect();
+    repo.publish_package(&spec, &components).await.unwrap();
+
+    let digest = repo
+        .inner()
+        .commit_blob(Box::pin(std::io::Cursor::new(b"truncated".to_vec())))
+        .await
+        .unwrap();
+    // a BASE32 encoded embed stub name that has been cut short
+    let stub_tag =
+        spfs::tracking::TagSpec::parse("spk/spec/my-pkg/1.0.0/embedded-by-MVWWEZLEMRSWIW3NP")
+            .unwrap();
+    repo.inner().push_tag(&stub_tag, &digest).await.unwrap();
+
+    let version = spec.ident().clone().to_version_ident();
+    assert!(
+        repo.get_embedded_package_builds(&version)
+            .await
+            .expect("undecodable stubs should be skipped")
+            .is_empty()
+    );
+
+    let (builds, errors) = repo
+        .get_embedded_package_builds_verbose(&version)
+        .await
+        .unwrap();
+    assert!(builds.is_empty());
+    assert_eq!(errors.len(), 1, "expected one decode error, got {errors:?}");
+    assert_eq!(
+        errors[0].tag.as_str(),
+        "spk/spec/my-pkg/1.0.0/embedded-by-MVWWEZLEMRSWIW3NP"
+    );
+
+    let builds = repo.list_package_builds(&version).await.unwrap();
+    assert_eq!(builds, vec![spec.ident().clone()]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_publish_result_lists_written_tags(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let spec = spec!({"pkg": "my-pkg/1.0.0/3I42H3S6"});
+    let components = vec![
+        (Component::Build, spfs::encoding::EMPTY_DIGEST.into()),
+        (Component::Run, spfs::encoding::EMPTY_DIGEST.into()),
+    ]
+    .into_iter()
+    .collect();
+    let result = repo.publish_package(&spec, &components).await.unwrap();
+
+    assert_eq!(&result.ident, spec.ident());
+    assert_eq!(result.components, components);
+    // the legacy tag, one tag per component and the spec tag
+    assert_eq!(result.tags_written.len(), 4);
+    for tag in result.tags_written.iter() {
+        assert!(
+            repo.inner().has_tag(tag).await,
+            "reported tag should exist in the repo: {tag}"
+        );
+    }
+    let legacy_tag = result.legacy_tag.expect("spfs writes a legacy tag");
+    assert!(result.tags_written.contains(&legacy_tag));
+
+    let spec_tag =
+        spfs::tracking::TagSpec::parse(SpfsRepository::build_spec_tag(spec.ident())).unwrap();
+    assert!(result.tags_written.contains(&spec_tag));
+    let resolved = repo.inner().resolve_tag(&spec_tag).await.unwrap();
+    assert_eq!(result.spec_digest, Some(resolved.target));
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_check_name_collisions(tmpdir: tempfile::TempDir) {
+    use super::NameCollision;
+
+    init_logging();
+    let repo = make_uncached_spfs_repo(tmpdir.path()).await;
+
+    // package names are always lowercase, so the other casing can
+    // only be created by writing the tags directly
+    let tag = spfs::tracking::TagSpec::parse("spk/spec/Foo/1.0.0").unwrap();
+    repo.inner()
+        .push_tag(&tag, &spfs::encoding::EMPTY_DIGEST.into())
+        .await
+        .unwrap();
+    assert_eq!(repo.check_name_collisions().await.unwrap(), vec![]);
+
+    let recipe = recipe!({"pkg": "foo/1.0.0"});
+    repo.publish_recipe(&recipe).await.unwrap();
+    assert_eq!(
+        repo.check_name_collisions().await.unwrap(),
+        vec![NameCollision {
+            names: vec!["Foo".to_string(), "foo".to_string()]
+        }]
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_publish_only_invalidates_caches_for_package(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+
+    let foo = recipe!({"pkg": "foo/1.0.0"});
+    let bar = recipe!({"pkg": "bar/1.0.0"});
+    for recipe in [&foo, &bar] {
+        repo.publish_recipe(recipe).await.unwrap();
+        repo.read_recipe(recipe.ident()).await.unwrap();
+        repo.list_package_versions(recipe.name()).await.unwrap();
+    }
+
+    let bar2 = recipe!({"pkg": "bar/2.0.0"});
+    repo.publish_recipe(&bar2).await.unwrap();
+
+    assert!(repo.caches.recipe.contains_key(foo.ident()));
+    assert!(repo.caches.package_versions.contains_key(foo.name()));
+    assert!(!repo.caches.recipe.contains_key(bar.ident()));
+    assert!(!repo.caches.package_versions.contains_key(bar.name()));
+    assert_eq!(
+        repo.list_package_versions(bar.name()).await.unwrap().len(),
+        2,
+        "the new version should be visible after publishing"
+    );
+    assert_eq!(
+        repo.list_packages().await.unwrap().len(),
+        2,
+        "the package listing should be unaffected"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_address_caches_released_on_drop(tmpdir: tempfile::TempDir) {
+    init_logging();
+    spfs::storage::fs::FsRepository::create(tmpdir.path())
+        .await
+        .unwrap();
+
+    let now = chrono::Utc::now();
+    let mut addresses = Vec::new();
+    for i in 0..100 {
+        let inner = spfs::storage::RepositoryHandle::from(
+            spfs::storage::fs::FsRepository::open(tmpdir.path())
+                .await
+                .unwrap(),
+        )
+        .into_pinned(now - chrono::Duration::seconds(i));
+        let repo = SpfsRepository::try_from(NameAndRepository::new("test-repo", inner)).unwrap();
+        let clone = repo.clone();
+        drop(repo);
+        let address = clone.address().to_string();
+        assert!(
+            super::CACHES_FOR_ADDRESS
+                .lock()
+                .unwrap()
+                .contains_key(&address),
+            "caches should stay registered while a repository is open"
+        );
+        drop(clone);
+        addresses.push(address);
+    }
+
+    let registry = super::CACHES_FOR_ADDRESS.lock().unwrap();
+    let leaked = addresses
+        .iter()
+        .filter(|address| registry.contains_key(*address))
+        .collect::<Vec<_>>();
+    assert!(
+        leaked.is_empty(),
+        "caches of dropped repositories should be removed: {leaked:?}"
+    );
+}
+
+#[rstest]
+fn test_purge_unused_address_caches() {
+    let address = "test-purge://unused".to_string();
+    super::CACHES_FOR_ADDRESS.lock().unwrap().insert(
+        address.clone(),
+        super::RegisteredCaches {
+            caches: super::CachesForAddress::new_unshared(),
+            registration: std::sync::Weak::new(),
+        },
+    );
+
+    assert!(super::purge_unused_address_caches() >= 1);
+    assert!(
+        !super::CACHES_FOR_ADDRESS
+            .lock()
+            .unwrap()
+            .contains_key(&address),
+        "caches without any open repository should be purged"
+    );
+}
+
+#[rstest]
+#[case::trailing_slash("file:///tmp/spk-repo/", "file:///tmp/spk-repo")]
+#[case::root_path("http://example.com/", "http://example.com/")]
+#[case::default_port("https://example.com:443/repo", "https://example.com/repo")]
+#[case::other_port("https://example.com:8443/repo", "https://example.com:8443/repo")]
+#[case::query_order(
+    "grpc://example.com:9876?tag_namespace=ns&lazy=true",
+    "grpc://example.com:9876?lazy=true&tag_namespace=ns"
+)]
+#[case::empty_query("file:///tmp/spk-repo?", "file:///tmp/spk-repo")]
+#[case::pinned(
+    "file:///tmp/spk-repo/?when=2024-01-01T00%3A00%3A00%2B00%3A00",
+    "file:///tmp/spk-repo?when=2024-01-01T00%3A00%3A00%2B00%3A00"
+)]
+fn test_normalize_repository_address(#[case] address: &str, #[case] expected: &str) {
+    let address = url::Url::parse(address).unwrap();
+    let normalized = super::normalize_repository_address(&address);
+    assert_eq!(normalized.as_str(), expected);
+    assert_eq!(
+        super::normalize_repository_address(&normalized),
+        normalized,
+        "normalizing should be idempotent"
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_read_version_bundle(tmpdir: tempfile::TempDir) {
+    init_logging();
+    let repo = make_spfs_repo(tmpdir.path()).await;
+    let recipe = recipe!({
+        "pkg": "my-pkg/1.0.0",
+        "install": {"embedded": [{"pkg": "my-embedded-pkg/1.0.0"}]}
+    });
+    repo.publish_recipe(&recipe).await.unwrap();
+    let components = vec![(Component::Run, empty_layer_digest())]
+        .into_iter()
+        .collect();
+    let first = spec!({
+        "pkg": "my-pkg/1.0.0/3I42H3S6",
+        "install": {"embedded": [{"pkg": "my-embedded-pkg/1.0.0/embedded"}]}
+    });
+    let second = spec!({"pkg": "my-pkg/1.0.0/7CI5R7Y4"});
+    repo.publish_package(&first, &components).await.unwrap();
+    repo.publish_package(&second, &components).await.unwrap();
+
+    let bundle = repo.read_version_bundle(recipe.ident()).await.unwrap();
+    assert_eq!(*bundle.recipe, recipe);
+    assert_eq!(bundle.builds.len(), 2, "embed stubs should not be included");
+    assert_eq!(*bundle.builds[first.ident()], first);
+    assert_eq!(*bundle.builds[second.ident()], second);
+    assert!(repo.caches.recipe.contains_key(recipe.ident()));
+    assert!(
+        repo.caches.package.contains_key(second.ident()),
+        "builds should be left in the package cache"
+    );
+
+    let embedded = spk_schema::VersionIdent::from_str("my-embedded-pkg/1.0.0").unwrap();
+    assert!(
+        repo.read_version_bundle(&embedded)
+            .await
+            .unwrap_err()
+            .is_package_not_found(),
+        "a version with only embed stubs has no recipe"
+    );
+}